@@ -0,0 +1,22 @@
+//! Regenerate `src/grpc/apache.rocketmq.v2.rs` from the protos in `proto/`
+//!
+//! Run with `cargo run --manifest-path codegen/Cargo.toml` from the repository root.
+use std::path::Path;
+
+fn main() -> std::io::Result<()> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let out_dir = std::env::args()
+        .nth(1)
+        .map(Into::into)
+        .unwrap_or_else(|| root.join("src/grpc"));
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    let proto_dir = root.join("proto");
+    tonic_build::configure()
+        .build_server(false)
+        .emit_rerun_if_changed(false)
+        .out_dir(out_dir)
+        .compile_protos(
+            &[proto_dir.join("apache/rocketmq/v2/service.proto")],
+            &[proto_dir],
+        )
+}
@@ -6,7 +6,7 @@ async fn main() {
     let mut options = ProducerOptions::default();
     options.set_name_server(vec!["localhost:9876".to_string()]);
     let producer = Producer::with_options(options).unwrap();
-    producer.start();
+    producer.start().await.unwrap();
     let msg = Message::new(
         "TEST_TOPIC".to_string(),
         String::new(),
@@ -0,0 +1,232 @@
+//! Derive macros for the RocketMQ Rust client
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Expr, ExprLit, Fields, GenericArgument, Lit, Meta, Path,
+    PathArguments, Type,
+};
+
+/// Derive `EncodeRequestHeader` and `DecodeRequestHeader` of a request header struct
+///
+/// Fields are encoded as ext fields named after the camelCase field name, unless
+/// overridden with `#[rename = "name"]`. `Option` fields are omitted when `None` and
+/// `#[skip_if = "path"]` omits a field when `path(&value)` returns true.
+///
+/// A missing field decodes to `#[default = "expr"]` when given, to `Default::default()`
+/// for `skip_if` fields and is an error otherwise.
+#[proc_macro_derive(RequestHeader, attributes(rename, skip_if, default))]
+pub fn derive_request_header(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct HeaderField {
+    ident: syn::Ident,
+    name: String,
+    skip_if: Option<Path>,
+    default: Option<Expr>,
+    optional: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "RequestHeader requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "RequestHeader can only be derived for structs",
+            ))
+        }
+    };
+    let fields = fields
+        .iter()
+        .map(|field| {
+            let ident = field.ident.clone().expect("named field");
+            let mut name = to_camel_case(&ident.to_string());
+            let mut skip_if = None;
+            let mut default = None;
+            for attr in &field.attrs {
+                if attr.path().is_ident("rename") {
+                    name = attr_str(&attr.meta)?.value();
+                } else if attr.path().is_ident("skip_if") {
+                    skip_if = Some(attr_str(&attr.meta)?.parse()?);
+                } else if attr.path().is_ident("default") {
+                    default = Some(attr_str(&attr.meta)?.parse()?);
+                }
+            }
+            Ok(HeaderField {
+                ident,
+                name,
+                skip_if,
+                default,
+                optional: is_option(&field.ty),
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let capacity = fields.len();
+    let encode_fields = fields.iter().map(|field| {
+        let HeaderField { ident, name, .. } = field;
+        let insert = |value: TokenStream2| {
+            quote! {
+                map.insert(#name.to_string(), crate::protocol::request::HeaderValue::to_header_value(#value));
+            }
+        };
+        if field.optional {
+            let insert = insert(quote!(value));
+            quote! {
+                if let Some(value) = self.#ident {
+                    #insert
+                }
+            }
+        } else if let Some(skip_if) = &field.skip_if {
+            let insert = insert(quote!(self.#ident));
+            quote! {
+                if !#skip_if(&self.#ident) {
+                    #insert
+                }
+            }
+        } else {
+            insert(quote!(self.#ident))
+        }
+    });
+    let decode_fields = fields.iter().map(|field| {
+        let HeaderField { ident, name, .. } = field;
+        let parse = quote! {
+            crate::protocol::request::HeaderValue::from_header_value(value).ok_or_else(|| {
+                crate::Error::InvalidHeader(format!("invalid value of {}: {}", #name, value))
+            })
+        };
+        if field.optional {
+            quote! {
+                #ident: match fields.get(#name) {
+                    Some(value) => Some(#parse?),
+                    None => None,
+                }
+            }
+        } else if let Some(default) = &field.default {
+            quote! {
+                #ident: match fields.get(#name) {
+                    Some(value) => #parse?,
+                    None => #default,
+                }
+            }
+        } else if field.skip_if.is_some() {
+            quote! {
+                #ident: match fields.get(#name) {
+                    Some(value) => #parse?,
+                    None => Default::default(),
+                }
+            }
+        } else {
+            quote! {
+                #ident: match fields.get(#name) {
+                    Some(value) => #parse?,
+                    None => {
+                        return Err(crate::Error::InvalidHeader(format!("missing {}", #name)))
+                    }
+                }
+            }
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics crate::protocol::request::EncodeRequestHeader for #ident #ty_generics #where_clause {
+            fn encode(self) -> ::std::collections::HashMap<String, String> {
+                let mut map = ::std::collections::HashMap::with_capacity(#capacity);
+                #(#encode_fields)*
+                map
+            }
+        }
+
+        impl #impl_generics crate::protocol::request::DecodeRequestHeader for #ident #ty_generics #where_clause {
+            fn decode(
+                fields: &::std::collections::HashMap<String, String>,
+            ) -> Result<Self, crate::Error> {
+                Ok(Self {
+                    #(#decode_fields,)*
+                })
+            }
+        }
+    })
+}
+
+/// String value of a `#[name = "value"]` attribute
+fn attr_str(meta: &Meta) -> syn::Result<syn::LitStr> {
+    if let Meta::NameValue(meta) = meta {
+        if let Expr::Lit(ExprLit {
+            lit: Lit::Str(value),
+            ..
+        }) = &meta.value
+        {
+            return Ok(value.clone());
+        }
+    }
+    Err(syn::Error::new_spanned(
+        meta,
+        "expected a string value like `#[rename = \"name\"]`",
+    ))
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(ty) => ty
+            .path
+            .segments
+            .last()
+            .map(|segment| {
+                segment.ident == "Option"
+                    && matches!(
+                        &segment.arguments,
+                        PathArguments::AngleBracketed(args)
+                            if matches!(args.args.first(), Some(GenericArgument::Type(_)))
+                    )
+            })
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn to_camel_case(name: &str) -> String {
+    let mut camel = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = !camel.is_empty();
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+#[cfg(test)]
+mod test {
+    use super::to_camel_case;
+
+    #[test]
+    fn test_to_camel_case() {
+        assert_eq!("topic", to_camel_case("topic"));
+        assert_eq!("producerGroup", to_camel_case("producer_group"));
+        assert_eq!(
+            "defaultTopicQueueNums",
+            to_camel_case("default_topic_queue_nums")
+        );
+        assert_eq!("private", to_camel_case("_private"));
+    }
+}
@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+
+use crate::client::model::TopicConfigTable;
+use crate::client::{Client, ClientOptions, Credentials, MQClientManager};
+use crate::consumer::RETRY_GROUP_TOPIC_PREFIX;
+use crate::error::Error;
+use crate::message::MessageQueue;
+use crate::namespace::wrap_namespace;
+use crate::permission::Permission;
+use crate::protocol::request::{
+    ResetOffsetRequestHeader, SearchOffsetRequestHeader, UpdateConsumerOffsetRequestHeader,
+};
+use crate::protocol::ResponseCode;
+use crate::resolver::{HttpResolver, PassthroughResolver, Resolver};
+use crate::route::{BrokerData, ClusterInfo, TopicList, TopicRouteData, MASTER_ID};
+
+pub use crate::client::model::{
+    ClientConnection, ConsumeStats, ConsumerConnection, OffsetWrapper, SubscriptionData,
+    SubscriptionGroupConfig, TopicConfig, TopicOffset, TopicStatsTable,
+};
+
+/// RocketMQ admin client options
+#[derive(Debug, Clone)]
+pub struct AdminOptions {
+    client_options: ClientOptions,
+    resolver: Resolver,
+}
+
+impl Default for AdminOptions {
+    fn default() -> Self {
+        Self {
+            client_options: ClientOptions::new("admin_ext_group"),
+            resolver: Resolver::Http(HttpResolver::new("DEFAULT".to_string())),
+        }
+    }
+}
+
+impl AdminOptions {
+    pub fn new() -> Self {
+        AdminOptions::default()
+    }
+
+    pub fn with_client_options(client_options: ClientOptions) -> Self {
+        Self {
+            client_options,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_credentials(&mut self, credentials: Credentials) -> &mut Self {
+        self.client_options.set_credentials(credentials);
+        self
+    }
+
+    pub fn set_resolver(&mut self, resolver: Resolver) -> &mut Self {
+        self.resolver = resolver;
+        self
+    }
+
+    pub fn set_name_server(&mut self, addrs: Vec<String>) -> &mut Self {
+        self.resolver = Resolver::PassthroughHttp(PassthroughResolver::new(
+            addrs,
+            HttpResolver::new("DEFAULT".to_string()),
+        ));
+        self
+    }
+
+    pub fn set_name_server_domain(&mut self, url: &str) -> &mut Self {
+        self.resolver = Resolver::Http(HttpResolver::with_domain(
+            "DEFAULT".to_string(),
+            url.to_string(),
+        ));
+        self
+    }
+}
+
+/// RocketMQ admin client managing topics and consumer groups on brokers and name servers
+#[derive(Debug)]
+pub struct MqAdmin {
+    options: AdminOptions,
+    client: Client<Resolver>,
+}
+
+impl MqAdmin {
+    pub fn new() -> Result<Self, Error> {
+        Self::with_options(AdminOptions::default())
+    }
+
+    pub fn with_options(mut options: AdminOptions) -> Result<Self, Error> {
+        options.client_options.make_instance_name_unique();
+        let client = MQClientManager::get_or_create_client(
+            options.client_options.clone(),
+            options.resolver.clone(),
+        )?;
+        Ok(Self { options, client })
+    }
+
+    /// Start the admin client, fails if no name server address can be resolved
+    pub async fn start(&self) -> Result<(), Error> {
+        MQClientManager::register_client(&self.client, &self.options.resolver)?;
+        self.client.start().await
+    }
+
+    pub fn shutdown(&self) {
+        self.client.shutdown();
+    }
+
+    /// Create `topic` on the master brokers of a cluster, or of a single broker
+    /// if `broker_or_cluster` isn't a cluster name, updating it where it exists
+    pub async fn create_topic(
+        &self,
+        mut topic: TopicConfig,
+        broker_or_cluster: &str,
+    ) -> Result<(), Error> {
+        topic.topic_name = self.wrap_namespace(&topic.topic_name);
+        let mut last_err = None;
+        for addr in self.find_master_addrs(broker_or_cluster).await? {
+            if let Err(err) = self.client.update_and_create_topic(&addr, &topic).await {
+                last_err = Some(err);
+            }
+        }
+        match last_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// All topics registered in the name servers, of every namespace
+    pub async fn topic_list(&self) -> Result<TopicList, Error> {
+        self.client.name_server.get_all_topic_list().await
+    }
+
+    /// Delete `topic` from all master brokers and then from the name servers, like
+    /// `create_topic` brokers failing to delete it don't stop the others and the last
+    /// error is returned
+    pub async fn delete_topic(&self, topic: &str) -> Result<(), Error> {
+        let topic = self.wrap_namespace(topic);
+        let cluster_info = self.client.name_server.get_broker_cluster_info().await?;
+        let mut last_err = None;
+        for addr in master_addrs(&cluster_info, None) {
+            if let Err(err) = self.client.delete_topic_in_broker(&addr, &topic).await {
+                last_err = Some(err);
+            }
+        }
+        if let Err(err) = self.client.name_server.delete_topic(&topic).await {
+            last_err = Some(err);
+        }
+        self.client.name_server.remove_topic_route(&topic);
+        match last_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Update the permission of `topic` on every broker serving it, keeping the rest of
+    /// its config on each broker like queue numbers and order
+    ///
+    /// Brokers failing to update it don't stop the others and the last error is returned.
+    pub async fn update_topic_permission(
+        &self,
+        topic: &str,
+        permission: Permission,
+    ) -> Result<(), Error> {
+        let topic = self.wrap_namespace(topic);
+        let route_data = self
+            .client
+            .name_server
+            .query_topic_route_info(&topic)
+            .await?;
+        let addrs = topic_master_addrs(&route_data);
+        if addrs.is_empty() {
+            return Err(Error::EmptyRouteData);
+        }
+        let mut last_err = None;
+        for addr in addrs {
+            if let Err(err) = self
+                .update_topic_permission_in_broker(&addr, &topic, permission)
+                .await
+            {
+                last_err = Some(err);
+            }
+        }
+        match last_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    async fn update_topic_permission_in_broker(
+        &self,
+        addr: &str,
+        topic: &str,
+        permission: Permission,
+    ) -> Result<(), Error> {
+        let configs = self.client.get_all_topic_config(addr).await?;
+        let config = topic_config_with_permission(configs, topic, permission)
+            .ok_or_else(|| Error::TopicNotExist(topic.to_string()))?;
+        self.client.update_and_create_topic(addr, &config).await
+    }
+
+    /// Remove the write permission of `broker_name` in the name servers so that producers
+    /// stop sending to it, e.g. before taking it down for maintenance
+    ///
+    /// Returns the number of topics affected by name server address, or the error of
+    /// the name servers which failed, the others are wiped regardless.
+    pub async fn wipe_write_perm_of_broker(
+        &self,
+        broker_name: &str,
+    ) -> Result<HashMap<String, Result<i32, Error>>, Error> {
+        self.client
+            .name_server
+            .wipe_write_perm_of_broker(broker_name)
+            .await
+    }
+
+    /// Put a KV config to all name servers, e.g. in the `ORDER_TOPIC_CONFIG` namespace
+    pub async fn put_kv_config(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        self.client
+            .name_server
+            .put_kv_config(namespace, key, value)
+            .await
+    }
+
+    /// Get a KV config from the name servers, `None` if it doesn't exist
+    pub async fn get_kv_config(&self, namespace: &str, key: &str) -> Result<Option<String>, Error> {
+        self.client.name_server.get_kv_config(namespace, key).await
+    }
+
+    /// Delete a KV config from all name servers
+    pub async fn delete_kv_config(&self, namespace: &str, key: &str) -> Result<(), Error> {
+        self.client
+            .name_server
+            .delete_kv_config(namespace, key)
+            .await
+    }
+
+    /// All KV configs of `namespace`
+    pub async fn kv_list_by_namespace(
+        &self,
+        namespace: &str,
+    ) -> Result<HashMap<String, String>, Error> {
+        self.client
+            .name_server
+            .get_kv_list_by_namespace(namespace)
+            .await
+    }
+
+    /// Min and max offsets of every queue of `topic`, collected from the master brokers
+    /// serving it
+    ///
+    /// Every master broker is asked even if some of them fail, returns the offsets or
+    /// the error by broker address.
+    pub async fn topic_stats(
+        &self,
+        topic: &str,
+    ) -> Result<HashMap<String, Result<TopicStatsTable, Error>>, Error> {
+        let topic = self.wrap_namespace(topic);
+        let route_data = self
+            .client
+            .name_server
+            .query_topic_route_info(&topic)
+            .await?;
+        let mut results = HashMap::new();
+        for broker_data in &route_data.broker_datas {
+            if let Some(addr) = broker_data.broker_addrs.get(&MASTER_ID) {
+                let res = self.client.get_topic_stats_info(addr, &topic).await;
+                results.insert(addr.clone(), res);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Consume progress of `group` on `topic`, or on all topics it subscribes to if `None`,
+    /// collected from the master brokers serving the group
+    ///
+    /// Every master broker is asked even if some of them fail, returns the progress or
+    /// the error by broker address.
+    pub async fn consume_stats(
+        &self,
+        group: &str,
+        topic: Option<&str>,
+    ) -> Result<HashMap<String, Result<ConsumeStats, Error>>, Error> {
+        // every broker the group consumes from has its retry topic
+        let retry_topic = self.wrap_namespace(&format!("{}{}", RETRY_GROUP_TOPIC_PREFIX, group));
+        let group = self.wrap_namespace(group);
+        let topic = topic.map(|topic| self.wrap_namespace(topic));
+        let route_data = self
+            .client
+            .name_server
+            .query_topic_route_info(&retry_topic)
+            .await?;
+        let mut results = HashMap::new();
+        for broker_data in &route_data.broker_datas {
+            if let Some(addr) = broker_data.broker_addrs.get(&MASTER_ID) {
+                let res = self
+                    .client
+                    .get_consume_stats(addr, &group, topic.as_deref())
+                    .await;
+                results.insert(addr.clone(), res);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Client ids, addresses and subscriptions of the online consumers of `group`,
+    /// asked from the first master broker of the group which responds
+    ///
+    /// Fails with the `ConsumerNotOnline` response code if no consumer is online.
+    pub async fn consumer_connections(&self, group: &str) -> Result<ConsumerConnection, Error> {
+        let retry_topic = self.wrap_namespace(&format!("{}{}", RETRY_GROUP_TOPIC_PREFIX, group));
+        let group = self.wrap_namespace(group);
+        let route_data = self
+            .client
+            .name_server
+            .query_topic_route_info(&retry_topic)
+            .await?;
+        let mut last_err = Error::EmptyRouteData;
+        for broker_data in &route_data.broker_datas {
+            if let Some(addr) = broker_data.broker_addrs.get(&MASTER_ID) {
+                match self.client.get_consumer_connection_list(addr, &group).await {
+                    Ok(connection) => return Ok(connection),
+                    Err(err) => last_err = err,
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Reset the offsets of `group` on `topic` to the first messages stored at or after
+    /// `timestamp` in milliseconds, brokers push the new offsets to the online consumers
+    ///
+    /// Offsets are only moved backwards unless `force` is set. When no consumer of the
+    /// group is online, the offsets stored by the brokers are updated instead. Every
+    /// master broker is reset even if some of them fail, returns the new offset of
+    /// every queue or the error by broker address.
+    pub async fn reset_offset(
+        &self,
+        topic: &str,
+        group: &str,
+        timestamp: i64,
+        force: bool,
+    ) -> Result<HashMap<String, Result<HashMap<MessageQueue, i64>, Error>>, Error> {
+        let topic = self.wrap_namespace(topic);
+        let group = self.wrap_namespace(group);
+        let route_data = self
+            .client
+            .name_server
+            .query_topic_route_info(&topic)
+            .await?;
+        let mut results = HashMap::new();
+        for broker_data in &route_data.broker_datas {
+            if let Some(addr) = broker_data.broker_addrs.get(&MASTER_ID) {
+                let header = ResetOffsetRequestHeader {
+                    topic: topic.clone(),
+                    group: group.clone(),
+                    timestamp,
+                    is_force: force,
+                };
+                let res = match self
+                    .client
+                    .invoke_broker_to_reset_offset(addr, header)
+                    .await
+                {
+                    Err(Error::ResponseError { code, .. })
+                        if code == ResponseCode::ConsumerNotOnline =>
+                    {
+                        self.reset_offset_in_broker(addr, &topic, &group, timestamp, force)
+                            .await
+                    }
+                    res => res,
+                };
+                results.insert(addr.clone(), res);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Reset the offsets of `group` stored by the broker at `addr` queue by queue, for
+    /// groups without online consumers
+    async fn reset_offset_in_broker(
+        &self,
+        addr: &str,
+        topic: &str,
+        group: &str,
+        timestamp: i64,
+        force: bool,
+    ) -> Result<HashMap<MessageQueue, i64>, Error> {
+        let stats = self
+            .client
+            .get_consume_stats(addr, group, Some(topic))
+            .await?;
+        let mut offsets = HashMap::new();
+        for (mq, offset) in stats.offset_table {
+            if mq.topic != topic {
+                continue;
+            }
+            let header = SearchOffsetRequestHeader {
+                topic: mq.topic.clone(),
+                queue_id: mq.queue_id,
+                timestamp,
+            };
+            let timestamp_offset = self.client.search_offset(addr, header).await?;
+            let new_offset = reset_queue_offset(offset.consumer_offset, timestamp_offset, force);
+            if new_offset != offset.consumer_offset {
+                let header = UpdateConsumerOffsetRequestHeader {
+                    consumer_group: group.to_string(),
+                    topic: mq.topic.clone(),
+                    queue_id: mq.queue_id,
+                    commit_offset: new_offset,
+                };
+                self.client.update_consumer_offset(addr, header).await?;
+            }
+            offsets.insert(mq, new_offset);
+        }
+        Ok(offsets)
+    }
+
+    /// Create the consumer group `config` on the master brokers of a cluster, or of a
+    /// single broker if `broker_or_cluster` isn't a cluster name, updating it where it exists
+    pub async fn create_subscription_group(
+        &self,
+        mut config: SubscriptionGroupConfig,
+        broker_or_cluster: &str,
+    ) -> Result<(), Error> {
+        config.group_name = self.wrap_namespace(&config.group_name);
+        let mut last_err = None;
+        for addr in self.find_master_addrs(broker_or_cluster).await? {
+            if let Err(err) = self
+                .client
+                .update_and_create_subscription_group(&addr, &config)
+                .await
+            {
+                last_err = Some(err);
+            }
+        }
+        match last_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Delete the consumer group `group` from the master brokers of a cluster, or of a
+    /// single broker if `broker_or_cluster` isn't a cluster name
+    pub async fn delete_subscription_group(
+        &self,
+        group: &str,
+        broker_or_cluster: &str,
+    ) -> Result<(), Error> {
+        let group = self.wrap_namespace(group);
+        let mut last_err = None;
+        for addr in self.find_master_addrs(broker_or_cluster).await? {
+            if let Err(err) = self.client.delete_subscription_group(&addr, &group).await {
+                last_err = Some(err);
+            }
+        }
+        match last_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Master addresses of a cluster or broker, fails if there is none
+    async fn find_master_addrs(&self, broker_or_cluster: &str) -> Result<Vec<String>, Error> {
+        let cluster_info = self.client.name_server.get_broker_cluster_info().await?;
+        let addrs = master_addrs(&cluster_info, Some(broker_or_cluster));
+        if addrs.is_empty() {
+            return Err(Error::BrokerNotFound(broker_or_cluster.to_string()));
+        }
+        Ok(addrs)
+    }
+
+    fn wrap_namespace(&self, resource: &str) -> String {
+        wrap_namespace(&self.options.client_options.resource_namespace(), resource)
+    }
+}
+
+/// Master addresses of the brokers in a cluster or of a single broker, of all
+/// brokers if `broker_or_cluster` is `None`
+fn master_addrs(cluster_info: &ClusterInfo, broker_or_cluster: Option<&str>) -> Vec<String> {
+    let brokers: Vec<&BrokerData> = match broker_or_cluster {
+        Some(name) if cluster_info.cluster_addr_table.contains_key(name) => {
+            cluster_info.cluster_brokers(name)
+        }
+        Some(name) => cluster_info
+            .broker_addr_table
+            .get(name)
+            .into_iter()
+            .collect(),
+        None => cluster_info.broker_addr_table.values().collect(),
+    };
+    let mut addrs: Vec<String> = brokers
+        .into_iter()
+        .filter_map(|broker| broker.broker_addrs.get(&MASTER_ID).cloned())
+        .collect();
+    addrs.sort();
+    addrs
+}
+
+/// Offset a queue is reset to, the same as brokers pushing it to online consumers
+fn reset_queue_offset(consumer_offset: i64, timestamp_offset: i64, force: bool) -> i64 {
+    if force || timestamp_offset < consumer_offset {
+        timestamp_offset
+    } else {
+        consumer_offset
+    }
+}
+
+/// Master addresses of the brokers serving `topic`
+fn topic_master_addrs(route_data: &TopicRouteData) -> Vec<String> {
+    route_data
+        .queue_datas
+        .iter()
+        .filter_map(|queue_data| {
+            route_data
+                .broker_datas
+                .iter()
+                .find(|broker| broker.broker_name == queue_data.broker_name)?
+                .broker_addrs
+                .get(&MASTER_ID)
+                .cloned()
+        })
+        .collect()
+}
+
+/// Current config of `topic` on a broker with only its permission changed
+fn topic_config_with_permission(
+    configs: TopicConfigTable,
+    topic: &str,
+    permission: Permission,
+) -> Option<TopicConfig> {
+    let mut config = configs.topic_config_table.get(topic)?.clone();
+    config.set_permission(permission);
+    Some(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        master_addrs, reset_queue_offset, topic_config_with_permission, topic_master_addrs,
+    };
+    use crate::client::model::{TopicConfigTable, TopicFilterType};
+    use crate::permission::Permission;
+    use crate::route::{ClusterInfo, TopicRouteData};
+
+    #[test]
+    fn test_master_addrs() {
+        let body = br#"{"brokerAddrTable":{"broker-a":{"brokerAddrs":{0:"10.0.0.1:10911",1:"10.0.0.2:10911"},"brokerName":"broker-a","cluster":"DefaultCluster"},"broker-b":{"brokerAddrs":{0:"10.0.0.3:10911"},"brokerName":"broker-b","cluster":"DefaultCluster"},"broker-c":{"brokerAddrs":{1:"10.0.0.4:10911"},"brokerName":"broker-c","cluster":"OtherCluster"}},"clusterAddrTable":{"DefaultCluster":["broker-a","broker-b"],"OtherCluster":["broker-c"]}}"#;
+        let info = ClusterInfo::from_bytes(body).unwrap();
+        assert_eq!(
+            vec!["10.0.0.1:10911", "10.0.0.3:10911"],
+            master_addrs(&info, Some("DefaultCluster"))
+        );
+        assert_eq!(
+            vec!["10.0.0.3:10911"],
+            master_addrs(&info, Some("broker-b"))
+        );
+        // slaves only
+        assert!(master_addrs(&info, Some("OtherCluster")).is_empty());
+        assert!(master_addrs(&info, Some("broker-d")).is_empty());
+        assert_eq!(2, master_addrs(&info, None).len());
+    }
+
+    #[test]
+    fn test_reset_queue_offset() {
+        // only moved backwards unless forced
+        assert_eq!(10, reset_queue_offset(20, 10, false));
+        assert_eq!(20, reset_queue_offset(20, 30, false));
+        assert_eq!(30, reset_queue_offset(20, 30, true));
+    }
+
+    #[test]
+    fn test_topic_master_addrs() {
+        let body = br#"{"queueDatas":[{"brokerName":"broker-a","readQueueNums":8,"writeQueueNums":4,"perm":6},{"brokerName":"broker-b","readQueueNums":4,"writeQueueNums":4,"perm":6}],"brokerDatas":[{"cluster":"DefaultCluster","brokerName":"broker-a","brokerAddrs":{0:"10.0.0.1:10911"}},{"cluster":"DefaultCluster","brokerName":"broker-b","brokerAddrs":{1:"10.0.0.2:10911"}}],"filterServerTable":{}}"#;
+        let route_data = TopicRouteData::from_bytes(body).unwrap();
+        // broker-b has no master
+        assert_eq!(vec!["10.0.0.1:10911"], topic_master_addrs(&route_data));
+    }
+
+    #[test]
+    fn test_topic_config_with_permission() {
+        let body = br#"{"dataVersion":{"counter":3,"timestamp":1700000000000},"topicConfigTable":{"ordered":{"order":true,"perm":6,"readQueueNums":8,"topicFilterType":"MULTI_TAG","topicName":"ordered","topicSysFlag":1,"writeQueueNums":4}}}"#;
+        let configs = TopicConfigTable::from_bytes(body).unwrap();
+        let config =
+            topic_config_with_permission(configs.clone(), "ordered", Permission::READ).unwrap();
+        assert_eq!("ordered", config.topic_name);
+        assert_eq!(Permission::READ, config.permission);
+        // the rest of the config is kept
+        assert!(config.order);
+        assert_eq!(8, config.read_queue_nums);
+        assert_eq!(4, config.write_queue_nums);
+        assert_eq!(1, config.topic_sys_flag);
+        assert_eq!(TopicFilterType::MultiTag, config.topic_filter_type);
+
+        assert!(topic_config_with_permission(configs, "missing", Permission::READ).is_none());
+    }
+}
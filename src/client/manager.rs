@@ -0,0 +1,185 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU8;
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tracing::info;
+
+use super::{Client, ClientOptions};
+use crate::namesrv::NameServer;
+use crate::resolver::Resolver;
+use crate::Error;
+
+// client id -> Client
+static CLIENTS: Lazy<Mutex<HashMap<String, SharedClient>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug)]
+struct SharedClient {
+    client: Client<Resolver>,
+    resolver: Resolver,
+}
+
+impl SharedClient {
+    fn check(
+        &self,
+        client_id: &str,
+        options: &ClientOptions,
+        resolver: &Resolver,
+    ) -> Result<(), Error> {
+        if &self.resolver != resolver || !self.client.options.same_credentials(options) {
+            return Err(Error::ClientIdConflict(client_id.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Shares `Client` instances between producers and consumers with the same client id
+///
+/// Clients are removed once shut down, those of the same client id must use the same
+/// name server resolver and credentials.
+#[derive(Debug)]
+pub(crate) struct MQClientManager;
+
+impl MQClientManager {
+    /// Get the client of the client id derived from `options`, create one if not exists
+    ///
+    /// Fails if a client of that id exists with another resolver or other credentials.
+    pub fn get_or_create_client(
+        options: ClientOptions,
+        resolver: Resolver,
+    ) -> Result<Client<Resolver>, Error> {
+        let client_id = options.client_id();
+        let mut clients = CLIENTS.lock();
+        if let Some(shared) = clients.get(&client_id) {
+            shared.check(&client_id, &options, &resolver)?;
+            return Ok(shared.client.clone());
+        }
+        info!(client_id = %client_id, "create new client");
+        let mut name_server = NameServer::new(resolver.clone(), None)?;
+        name_server
+            .set_credentials_provider(options.credentials.clone())
+            .set_serialize_type(options.serialize_type)
+            .set_trace_propagation(options.trace_propagation)
+            .set_identity(options.language, options.client_version)
+            .set_signature_method(options.signature_method)
+            .set_request_timeout(options.request_timeout)
+            .set_runtime(options.runtime.clone());
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &options.name_server_tls {
+            name_server.set_tls(tls.clone());
+        }
+        name_server.set_route_ttl(options.route_ttl);
+        name_server.set_selection(options.name_server_selection);
+        if !options.name_server_weights.is_empty() {
+            name_server.set_weights(options.name_server_weights.clone());
+        }
+        let client = Client::new(options, name_server);
+        clients.insert(
+            client_id,
+            SharedClient {
+                client: client.clone(),
+                resolver,
+            },
+        );
+        Ok(client)
+    }
+
+    /// Share `client` again before restarting it after shutdown, fails if another
+    /// client of its id was created meanwhile
+    pub fn register_client(client: &Client<Resolver>, resolver: &Resolver) -> Result<(), Error> {
+        let client_id = client.id();
+        match CLIENTS.lock().entry(client_id) {
+            Entry::Occupied(entry) if Arc::ptr_eq(&entry.get().client.state, &client.state) => {
+                Ok(())
+            }
+            Entry::Occupied(entry) => Err(Error::ClientIdConflict(entry.key().clone())),
+            Entry::Vacant(entry) => {
+                entry.insert(SharedClient {
+                    client: client.clone(),
+                    resolver: resolver.clone(),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Remove the client of `client_id` on shutdown, if it's the one with `state`
+    pub fn remove_client(client_id: &str, state: &Arc<AtomicU8>) {
+        let mut clients = CLIENTS.lock();
+        if matches!(clients.get(client_id), Some(shared) if Arc::ptr_eq(&shared.client.state, state))
+        {
+            clients.remove(client_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::MQClientManager;
+    use crate::client::{ClientOptions, Credentials};
+    use crate::resolver::{Resolver, StaticResolver};
+    use crate::Error;
+
+    fn resolver() -> Resolver {
+        Resolver::Static(StaticResolver::new(vec!["localhost:9876".to_string()]))
+    }
+
+    #[test]
+    fn test_share_client_by_client_id() {
+        let mut options = ClientOptions::new("test_group");
+        options.instance_name = "test_share_client".to_string();
+        let client1 = MQClientManager::get_or_create_client(options.clone(), resolver()).unwrap();
+        options.group_name = "another_group".to_string();
+        let client2 = MQClientManager::get_or_create_client(options.clone(), resolver()).unwrap();
+        assert_eq!(client1.id(), client2.id());
+        assert!(Arc::ptr_eq(&client1.state, &client2.state));
+
+        options.instance_name = "test_share_client_2".to_string();
+        let client3 = MQClientManager::get_or_create_client(options, resolver()).unwrap();
+        assert!(!Arc::ptr_eq(&client1.state, &client3.state));
+    }
+
+    #[test]
+    fn test_reject_conflicting_client() {
+        let mut options = ClientOptions::new("test_group");
+        options
+            .set_instance_name("test_conflicting_client")
+            .set_credentials(Credentials::new("ak", "sk"));
+        let client1 = MQClientManager::get_or_create_client(options.clone(), resolver()).unwrap();
+        // equal credentials set separately
+        let mut same = options.clone();
+        same.set_credentials(Credentials::new("ak", "sk"));
+        let client2 = MQClientManager::get_or_create_client(same, resolver()).unwrap();
+        assert!(Arc::ptr_eq(&client1.state, &client2.state));
+
+        let mut other = options.clone();
+        other.set_credentials(Credentials::new("ak", "another_sk"));
+        let err = MQClientManager::get_or_create_client(other, resolver()).unwrap_err();
+        assert!(matches!(err, Error::ClientIdConflict(_)));
+        let other_resolver =
+            Resolver::Static(StaticResolver::new(vec!["localhost:9877".to_string()]));
+        let err = MQClientManager::get_or_create_client(options, other_resolver).unwrap_err();
+        assert!(matches!(err, Error::ClientIdConflict(_)));
+    }
+
+    #[tokio::test]
+    async fn test_remove_client_on_shutdown() {
+        let mut options = ClientOptions::new("test_group");
+        options.set_instance_name("test_remove_client");
+        let client1 = MQClientManager::get_or_create_client(options.clone(), resolver()).unwrap();
+        client1.start().await.unwrap();
+        client1.shutdown();
+
+        let client2 = MQClientManager::get_or_create_client(options, resolver()).unwrap();
+        assert!(!Arc::ptr_eq(&client1.state, &client2.state));
+        // the old client can't be shared again while the new one exists
+        let err = MQClientManager::register_client(&client1, &resolver()).unwrap_err();
+        assert!(matches!(err, Error::ClientIdConflict(_)));
+        MQClientManager::register_client(&client2, &resolver()).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::remoting::RemotingClient;
+
+/// Client metrics snapshot
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientMetrics {
+    /// Connections currently open to brokers and name servers
+    pub open_connections: usize,
+    /// Number of requests sent, by request code
+    pub requests: HashMap<i16, u64>,
+    /// Number of responses received, by response code
+    pub responses: HashMap<i16, u64>,
+    /// Number of failed topic route refreshes
+    pub route_refresh_failures: u64,
+    /// Number of failed heartbeats to brokers
+    pub heartbeat_failures: u64,
+    /// Connection metrics by broker or name server address
+    pub connections: HashMap<String, ConnectionMetrics>,
+}
+
+/// Metrics of the connections to one broker or name server address
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionMetrics {
+    /// Number of bytes received
+    pub bytes_received: u64,
+    /// Number of bytes sent
+    pub bytes_sent: u64,
+    /// Requests currently waiting for a response
+    pub in_flight: usize,
+    /// Number of connections opened after the first one
+    pub reconnects: u64,
+    /// Number of malformed frames received
+    pub decode_errors: u64,
+    /// Number of responses matching no pending request, like late responses to
+    /// requests which timed out
+    pub orphan_responses: u64,
+    /// Number of responses received
+    pub responses: u64,
+    /// Average time between sending a request and receiving its response
+    pub avg_latency: Duration,
+    /// Maximum time between sending a request and receiving its response
+    pub max_latency: Duration,
+}
+
+impl ClientMetrics {
+    pub(crate) fn add_remoting(&mut self, client: &RemotingClient) {
+        let stats = client.stats();
+        self.open_connections += stats.open_connections();
+        for (code, count) in stats.requests() {
+            *self.requests.entry(code).or_default() += count;
+        }
+        for (code, count) in stats.responses() {
+            *self.responses.entry(code).or_default() += count;
+        }
+        self.connections.extend(client.connection_metrics());
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ClientStats {
+    route_refresh_failures: AtomicU64,
+    heartbeat_failures: AtomicU64,
+}
+
+impl ClientStats {
+    pub fn record_route_refresh_failure(&self, topic: &str) {
+        self.route_refresh_failures.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "rocketmq_client_route_refresh_failures_total",
+            1,
+            "topic" => topic.to_string(),
+        );
+        #[cfg(not(feature = "metrics"))]
+        let _ = topic;
+    }
+
+    pub fn record_heartbeat_failure(&self, broker_addr: &str) {
+        self.heartbeat_failures.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "rocketmq_client_heartbeat_failures_total",
+            1,
+            "broker" => broker_addr.to_string(),
+        );
+        #[cfg(not(feature = "metrics"))]
+        let _ = broker_addr;
+    }
+
+    pub fn snapshot(&self) -> ClientMetrics {
+        ClientMetrics {
+            route_refresh_failures: self.route_refresh_failures.load(Ordering::Relaxed),
+            heartbeat_failures: self.heartbeat_failures.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ClientStats;
+    use crate::remoting::RemotingClient;
+
+    #[test]
+    fn test_client_metrics_snapshot() {
+        let stats = ClientStats::default();
+        stats.record_route_refresh_failure("test");
+        stats.record_heartbeat_failure("127.0.0.1:10911");
+        stats.record_heartbeat_failure("127.0.0.1:10911");
+
+        let broker = RemotingClient::new(None);
+        broker.stats().record_connected();
+        broker.stats().record_request(34);
+        broker.stats().record_response(0);
+        let name_server = RemotingClient::new(None);
+        name_server.stats().record_connected();
+        name_server.stats().record_request(34);
+        name_server
+            .stats()
+            .connection("127.0.0.1:9876")
+            .record_sent(10);
+
+        let mut metrics = stats.snapshot();
+        metrics.add_remoting(&broker);
+        metrics.add_remoting(&name_server);
+        assert_eq!(2, metrics.open_connections);
+        assert_eq!(Some(&2), metrics.requests.get(&34));
+        assert_eq!(Some(&1), metrics.responses.get(&0));
+        assert_eq!(1, metrics.route_refresh_failures);
+        assert_eq!(2, metrics.heartbeat_failures);
+        assert_eq!(10, metrics.connections["127.0.0.1:9876"].bytes_sent);
+    }
+}
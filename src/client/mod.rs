@@ -1,40 +1,71 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::io;
+use std::fmt;
 use std::net::IpAddr;
 use std::process;
 use std::sync::{
-    atomic::{AtomicU8, Ordering},
+    atomic::{AtomicU8, AtomicUsize, Ordering},
     Arc,
 };
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use async_trait::async_trait;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use parking_lot::Mutex;
-use tokio::sync::broadcast;
+use rand::Rng;
+use tokio::runtime::Handle;
+use tokio::sync::{broadcast, mpsc, Notify};
 use tokio::time;
-use tracing::{debug, error, info, info_span, warn};
-use tracing_futures::Instrument;
+use tracing::{debug, error, info, warn};
 
 use crate::consumer::ConsumerInner;
-use crate::message::MessageExt;
-use crate::namesrv::NameServer;
+use crate::error::ErrorKind;
+use crate::fastjson;
+use crate::message::{MessageExt, MessageQueue, Property};
+use crate::namespace::unit_namespace;
+use crate::namesrv::{FindBrokerResult, NameServer, NameServerSelection};
 use crate::producer::ProducerInner;
 use crate::protocol::{
     request::{
-        ConsumerSendMsgBackRequestHeader, CreateTopicRequestHeader, PullMessageRequestHeader,
-        UnregisterClientRequestHeader,
+        ConsumerSendMsgBackRequestHeader, CreateTopicRequestHeader,
+        DeleteSubscriptionGroupRequestHeader, DeleteTopicRequestHeader,
+        GetConsumeStatsRequestHeader, GetConsumerConnectionListRequestHeader,
+        GetEarliestMsgStoreTimeRequestHeader, GetMaxOffsetRequestHeader, GetMinOffsetRequestHeader,
+        GetTopicStatsInfoRequestHeader, PullMessageRequestHeader, QueryMessageRequestHeader,
+        ResetOffsetRequestHeader, SearchOffsetRequestHeader, UnregisterClientRequestHeader,
+        UpdateConsumerOffsetRequestHeader, ViewMessageRequestHeader,
     },
-    RemotingCommand, RequestCode, ResponseCode,
+    response::{
+        decode_response_header, GetEarliestMsgStoreTimeResponseHeader, GetMaxOffsetResponseHeader,
+        GetMinOffsetResponseHeader, SearchOffsetResponseHeader,
+    },
+    HeaderCodecType, LanguageCode, RemotingCommand, RequestCode, ResponseCode, DEFAULT_LANGUAGE,
+    DEFAULT_VERSION,
 };
-use crate::remoting::RemotingClient;
+#[cfg(feature = "test-util")]
+use crate::remoting::FaultInjection;
+#[cfg(feature = "tls")]
+use crate::remoting::TlsConfig;
+use crate::remoting::{ConnectionEvent, RemotingClient, RpcHook, SocketOptions};
 use crate::resolver::NsResolver;
 use crate::route::{TopicRouteData, MASTER_ID};
-use crate::utils::client_ip_addr;
+use crate::utils::{client_ip_addr, spawn};
 use crate::Error;
 
-mod model;
+mod manager;
+mod metrics;
+pub(crate) mod model;
+mod processor;
+mod retry;
 
-#[derive(Debug, Clone)]
+pub(crate) use manager::MQClientManager;
+use metrics::ClientStats;
+pub use metrics::{ClientMetrics, ConnectionMetrics};
+use processor::{CheckTransactionStateProcessor, ConsumerIdsChangedProcessor};
+pub use retry::{RetryOn, RetryPolicy};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Credentials {
     pub access_key: String,
     pub secret_key: String,
@@ -51,6 +82,47 @@ impl Credentials {
     }
 }
 
+/// Source of ACL credentials, asked before signing every request so that
+/// temporary credentials like STS security tokens can be refreshed without
+/// rebuilding clients
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync {
+    /// Current credentials, should be cheap as it's called for every request
+    async fn get(&self) -> Credentials;
+}
+
+#[async_trait]
+impl CredentialsProvider for Credentials {
+    async fn get(&self) -> Credentials {
+        self.clone()
+    }
+}
+
+impl fmt::Debug for dyn CredentialsProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CredentialsProvider")
+    }
+}
+
+/// HMAC algorithm of ACL request signatures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureMethod {
+    /// Understood by all ACL enabled brokers
+    #[default]
+    HmacSha1,
+    HmacSha256,
+}
+
+impl SignatureMethod {
+    /// Value of the `SignatureMethod` ext field
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureMethod::HmacSha1 => "HmacSHA1",
+            SignatureMethod::HmacSha256 => "HmacSHA256",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(i32)]
 pub enum PullStatus {
@@ -69,9 +141,9 @@ pub struct PullResult {
     pub status: PullStatus,
     pub suggest_which_broker_id: i64,
     pub message_exts: Vec<MessageExt>,
-    pub body: Vec<u8>,
 }
 
+/// Options shared by producers and consumers
 #[derive(Debug, Clone)]
 pub struct ClientOptions {
     pub(crate) group_name: String,
@@ -81,24 +153,336 @@ pub struct ClientOptions {
     pub(crate) unit_mode: bool,
     unit_name: String,
     vip_channel_enabled: bool,
-    retry_times: usize,
-    pub(crate) credentials: Option<Credentials>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) credentials: Option<Arc<dyn CredentialsProvider>>,
+    // credentials set by `set_credentials`, to tell if two clients use the same ones
+    static_credentials: Option<Credentials>,
+    pub(crate) signature_method: SignatureMethod,
     pub(crate) namespace: String,
+    pub(crate) persist_consumer_offset_interval: time::Duration,
+    pub(crate) route_ttl: Option<time::Duration>,
+    name_server_refresh_interval: time::Duration,
+    route_refresh_interval: time::Duration,
+    refresh_jitter: time::Duration,
+    health_check_interval: time::Duration,
+    shutdown_timeout: time::Duration,
+    pub(crate) request_timeout: time::Duration,
+    socket: SocketOptions,
+    connections_per_broker: usize,
+    multiplex_threshold: usize,
+    reconnect_failures: u32,
+    reconnect_cooldown: time::Duration,
+    pub(crate) name_server_weights: HashMap<String, u32>,
+    pub(crate) name_server_selection: NameServerSelection,
+    pub(crate) serialize_type: HeaderCodecType,
+    pub(crate) trace_propagation: bool,
+    pub(crate) language: LanguageCode,
+    pub(crate) client_version: i16,
+    pub(crate) runtime: Option<Handle>,
+    #[cfg(feature = "tls")]
+    pub(crate) name_server_tls: Option<TlsConfig>,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsConfig>,
 }
 
 impl ClientOptions {
     pub fn new(group: &str) -> Self {
         Self {
             group_name: group.to_string(),
-            name_server_addrs: Vec::new(),
-            client_ip: client_ip(),
-            instance_name: "DEFAULT".to_string(),
-            unit_mode: false,
-            unit_name: String::new(),
-            vip_channel_enabled: false,
-            retry_times: 3,
-            credentials: None,
-            namespace: String::new(),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_group_name(&mut self, group: &str) -> &mut Self {
+        self.group_name = group.to_string();
+        self
+    }
+
+    /// Override the client ip reported to brokers, it's detected from network interfaces by default
+    pub fn set_client_ip(&mut self, ip: IpAddr) -> &mut Self {
+        self.client_ip = format_client_ip(ip);
+        self
+    }
+
+    /// Instance name is part of the client id, clients with the same id share connections
+    ///
+    /// Producers and consumers using the default instance name get a unique one
+    /// so that they don't share a client by accident, set the same instance name
+    /// to share one.
+    pub fn set_instance_name(&mut self, name: &str) -> &mut Self {
+        self.instance_name = name.to_string();
+        self
+    }
+
+    /// Enable unit mode, which is reported in heartbeats and send requests
+    /// and marks topics created by this client as unit topics
+    ///
+    /// With a unit name set, topics and groups are also put under the unit,
+    /// e.g. `TopicTest` becomes `%UNIT%unit%TopicTest`.
+    pub fn set_unit_mode(&mut self, unit_mode: bool) -> &mut Self {
+        self.unit_mode = unit_mode;
+        self
+    }
+
+    /// Unit name is appended to the client id
+    pub fn set_unit_name(&mut self, name: &str) -> &mut Self {
+        self.unit_name = name.to_string();
+        self
+    }
+
+    /// Send messages through the broker VIP channel, whose port is the broker port minus 2
+    pub fn set_vip_channel_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.vip_channel_enabled = enabled;
+        self
+    }
+
+    /// Number of retries of idempotent requests, shorthand of updating the retry policy
+    pub fn set_retry_times(&mut self, times: usize) -> &mut Self {
+        self.retry_policy.set_max_attempts(times + 1);
+        self
+    }
+
+    /// Retry policy of idempotent requests like route, offset queries and heartbeats
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Set ACL credentials
+    pub fn set_credentials(&mut self, credentials: Credentials) -> &mut Self {
+        self.credentials = Some(Arc::new(credentials.clone()));
+        self.static_credentials = Some(credentials);
+        self
+    }
+
+    /// Get ACL credentials from `provider` before every request, e.g. to rotate
+    /// temporary security tokens
+    pub fn set_credentials_provider(
+        &mut self,
+        provider: Arc<dyn CredentialsProvider>,
+    ) -> &mut Self {
+        self.credentials = Some(provider);
+        self.static_credentials = None;
+        self
+    }
+
+    /// Algorithm of ACL request signatures, defaults to HMAC-SHA1
+    ///
+    /// Other algorithms are announced to brokers in the `SignatureMethod` ext field.
+    pub fn set_signature_method(&mut self, method: SignatureMethod) -> &mut Self {
+        self.signature_method = method;
+        self
+    }
+
+    pub fn set_namespace(&mut self, namespace: &str) -> &mut Self {
+        self.namespace = namespace.to_string();
+        self
+    }
+
+    /// Interval of persisting consumer offsets, defaults to 5 seconds
+    pub fn set_persist_consumer_offset_interval(&mut self, interval: time::Duration) -> &mut Self {
+        self.persist_consumer_offset_interval = interval;
+        self
+    }
+
+    /// Drop cached topic routes which were not refreshed within `ttl`, disabled by default
+    ///
+    /// Routes of topics in use are refreshed every route refresh interval, so `ttl` should be longer.
+    pub fn set_route_ttl(&mut self, ttl: time::Duration) -> &mut Self {
+        self.route_ttl = Some(ttl);
+        self
+    }
+
+    /// Interval of re-resolving name server addresses, defaults to 2 minutes
+    pub fn set_name_server_refresh_interval(&mut self, interval: time::Duration) -> &mut Self {
+        self.name_server_refresh_interval = interval;
+        self
+    }
+
+    /// Interval of refreshing topic routes, defaults to 30 seconds
+    pub fn set_route_refresh_interval(&mut self, interval: time::Duration) -> &mut Self {
+        self.route_refresh_interval = interval;
+        self
+    }
+
+    /// Maximum random delay added to the name server and route refresh intervals,
+    /// so that many clients don't poll name servers at the same moment, defaults to 5 seconds
+    pub fn set_refresh_jitter(&mut self, jitter: time::Duration) -> &mut Self {
+        self.refresh_jitter = jitter;
+        self
+    }
+
+    /// Interval of pinging broker connections which received nothing for that long,
+    /// connections failing to respond are closed and reconnected on next use so that
+    /// half-open connections don't fail user requests, defaults to 30 seconds and
+    /// zero disables health checks
+    pub fn set_health_check_interval(&mut self, interval: time::Duration) -> &mut Self {
+        self.health_check_interval = interval;
+        self
+    }
+
+    /// Time to wait on graceful shutdown for queued commands like oneway offset
+    /// commits to be written to brokers, defaults to 3 seconds
+    pub fn set_shutdown_timeout(&mut self, timeout: time::Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Timeout of requests to brokers and name servers which don't set their own,
+    /// long polling pulls wait for the broker suspend time on top of it, defaults to 3 seconds
+    pub fn set_request_timeout(&mut self, timeout: time::Duration) -> &mut Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on broker connections to send small requests without
+    /// delay, enabled by default
+    pub fn set_tcp_nodelay(&mut self, nodelay: bool) -> &mut Self {
+        self.socket.nodelay = nodelay;
+        self
+    }
+
+    /// Idle time of broker connections before TCP keepalive probes are sent,
+    /// `None` disables keepalive, defaults to 60 seconds
+    pub fn set_tcp_keepalive(&mut self, keepalive: Option<time::Duration>) -> &mut Self {
+        self.socket.keepalive = keepalive;
+        self
+    }
+
+    /// Timeout of connecting to a broker, defaults to 3 seconds
+    pub fn set_connect_timeout(&mut self, timeout: time::Duration) -> &mut Self {
+        self.socket.connect_timeout = timeout;
+        self
+    }
+
+    /// Socket send buffer size of broker connections, defaults to the OS default
+    pub fn set_send_buffer_size(&mut self, size: u32) -> &mut Self {
+        self.socket.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Socket receive buffer size of broker connections, defaults to the OS default
+    pub fn set_recv_buffer_size(&mut self, size: u32) -> &mut Self {
+        self.socket.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Inject faults into new broker connections, for testing only
+    #[cfg(feature = "test-util")]
+    pub fn set_fault_injection(&mut self, faults: FaultInjection) -> &mut Self {
+        self.socket.faults = Some(faults);
+        self
+    }
+
+    /// Maximum number of connections to one broker, defaults to 1
+    ///
+    /// Another connection is opened when the requests in flight on every open one
+    /// reach the multiplex threshold, so high throughput producers aren't limited
+    /// by a single TCP connection.
+    pub fn set_connections_per_broker(&mut self, connections: usize) -> &mut Self {
+        self.connections_per_broker = connections;
+        self
+    }
+
+    /// Requests in flight on a broker connection before requests spread to another
+    /// one, defaults to 64
+    pub fn set_multiplex_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.multiplex_threshold = threshold;
+        self
+    }
+
+    /// Run the background tasks and connections of the client on `runtime` instead
+    /// of the runtime it's started from, for example to isolate them on a dedicated
+    /// runtime
+    pub fn set_runtime(&mut self, runtime: Handle) -> &mut Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Stop connecting to a broker for `cooldown` after `failures` consecutive failed
+    /// attempts, requests to it fail fast meanwhile, defaults to 5 failures and 10
+    /// seconds and zero failures disables it
+    pub fn set_reconnect_circuit_breaker(
+        &mut self,
+        failures: u32,
+        cooldown: time::Duration,
+    ) -> &mut Self {
+        self.reconnect_failures = failures;
+        self.reconnect_cooldown = cooldown;
+        self
+    }
+
+    /// Selection weight of a name server relative to the others, which default to 1
+    ///
+    /// Requests go to a name server picked at random by weight, a weight of 0 only
+    /// uses the name server when all others fail.
+    pub fn set_name_server_weight(&mut self, addr: &str, weight: u32) -> &mut Self {
+        self.name_server_weights.insert(addr.to_string(), weight);
+        self
+    }
+
+    /// How requests are spread across name servers, defaults to round robin
+    ///
+    /// Sticky selection keeps reusing the connection to one name server until it fails.
+    pub fn set_name_server_selection(&mut self, selection: NameServerSelection) -> &mut Self {
+        self.name_server_selection = selection;
+        self
+    }
+
+    /// Header serialize type of outgoing requests, defaults to the RocketMQ binary encoding
+    ///
+    /// Responses are decoded with whichever serialize type the server used.
+    pub fn set_serialize_type(&mut self, serialize_type: HeaderCodecType) -> &mut Self {
+        self.serialize_type = serialize_type;
+        self
+    }
+
+    /// Inject a trace id and W3C `traceparent` into the ext fields of every request,
+    /// so requests can be correlated with broker side logs, disabled by default
+    ///
+    /// Requests sent within `TraceContext::scope` continue that trace.
+    pub fn set_trace_propagation(&mut self, enabled: bool) -> &mut Self {
+        self.trace_propagation = enabled;
+        self
+    }
+
+    /// Language reported to servers, defaults to `LanguageCode::RUST`
+    ///
+    /// Brokers older than the language code may reject it, `LanguageCode::OTHER`
+    /// is understood by all of them.
+    pub fn set_language(&mut self, language: LanguageCode) -> &mut Self {
+        self.language = language;
+        self
+    }
+
+    /// Client version reported to servers as an ordinal of the Java `MQVersion`,
+    /// which brokers use to gate features
+    pub fn set_client_version(&mut self, version: i16) -> &mut Self {
+        self.client_version = version;
+        self
+    }
+
+    /// Connect to brokers over TLS, with the CA bundle, server name and verification
+    /// settings of `tls`
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: TlsConfig) -> &mut Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Connect to name servers over TLS, independently of broker connections
+    #[cfg(feature = "tls")]
+    pub fn set_name_server_tls(&mut self, tls: TlsConfig) -> &mut Self {
+        self.name_server_tls = Some(tls);
+        self
+    }
+
+    /// `interval` plus a random delay of up to the refresh jitter
+    fn jittered(&self, interval: time::Duration) -> time::Duration {
+        if self.refresh_jitter.is_zero() {
+            interval
+        } else {
+            interval + rand::thread_rng().gen_range(time::Duration::ZERO..=self.refresh_jitter)
         }
     }
 }
@@ -113,23 +497,134 @@ impl Default for ClientOptions {
             unit_mode: false,
             unit_name: String::new(),
             vip_channel_enabled: false,
-            retry_times: 3,
+            retry_policy: RetryPolicy::default(),
             credentials: None,
+            static_credentials: None,
+            signature_method: SignatureMethod::default(),
             namespace: String::new(),
+            persist_consumer_offset_interval: time::Duration::from_secs(5),
+            route_ttl: None,
+            name_server_refresh_interval: time::Duration::from_secs(2 * 60),
+            route_refresh_interval: time::Duration::from_secs(30),
+            refresh_jitter: time::Duration::from_secs(5),
+            health_check_interval: time::Duration::from_secs(30),
+            shutdown_timeout: time::Duration::from_secs(3),
+            request_timeout: time::Duration::from_secs(3),
+            socket: SocketOptions::default(),
+            connections_per_broker: 1,
+            multiplex_threshold: 64,
+            reconnect_failures: 5,
+            reconnect_cooldown: time::Duration::from_secs(10),
+            name_server_weights: HashMap::new(),
+            name_server_selection: NameServerSelection::default(),
+            serialize_type: HeaderCodecType::default(),
+            trace_propagation: false,
+            language: DEFAULT_LANGUAGE,
+            client_version: DEFAULT_VERSION,
+            runtime: None,
+            #[cfg(feature = "tls")]
+            name_server_tls: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Replace the default instance name with one unique to this process and moment
+    pub(crate) fn make_instance_name_unique(&mut self) {
+        if self.instance_name == "DEFAULT" {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default();
+            let seq = INSTANCE_SEQ.fetch_add(1, Ordering::Relaxed);
+            self.instance_name = format!("{}#{}#{}", process::id(), nanos, seq);
+        }
+    }
+
+    /// Whether requests are signed with the same credentials as `other`
+    pub(crate) fn same_credentials(&self, other: &ClientOptions) -> bool {
+        match (&self.credentials, &other.credentials) {
+            (None, None) => true,
+            (Some(a), Some(b)) => {
+                Arc::ptr_eq(a, b)
+                    || matches!((&self.static_credentials, &other.static_credentials), (Some(a), Some(b)) if a == b)
+            }
+            _ => false,
+        }
+    }
+
+    /// Namespace topics and groups are wrapped with, put under the unit in unit mode
+    pub(crate) fn resource_namespace(&self) -> String {
+        if self.unit_mode && !self.unit_name.is_empty() {
+            unit_namespace(&self.unit_name, &self.namespace)
+        } else {
+            self.namespace.clone()
         }
     }
+
+    pub(crate) fn client_id(&self) -> String {
+        let mut client_id = format!("{}@{}", self.client_ip, self.instance_name);
+        if !self.unit_name.is_empty() {
+            client_id.push('@');
+            client_id.push_str(&self.unit_name);
+        }
+        client_id
+    }
+}
+
+/// Address of the broker VIP channel, which listens on the broker port minus 2
+fn broker_vip_channel(addr: &str) -> String {
+    match addr.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) if port > 2 => format!("{}:{}", host, port - 2),
+            _ => addr.to_string(),
+        },
+        None => addr.to_string(),
+    }
 }
 
+/// Whether a message found by a query has `key` as its unique key or among its keys
+fn matches_query_key(msg: &MessageExt, key: &str, unique_key: bool) -> bool {
+    if unique_key {
+        msg.message.unique_key() == Some(key)
+    } else {
+        msg.message
+            .keys()
+            .map(|keys| keys.split(Property::KEY_SEPARATOR).any(|k| k == key))
+            .unwrap_or(false)
+    }
+}
+
+/// Fails with `Error::ResponseError` unless `res` is a success response
+fn check_response(res: &RemotingCommand) -> Result<(), Error> {
+    if res.code() == ResponseCode::Success {
+        Ok(())
+    } else {
+        Err(Error::ResponseError {
+            code: res.code(),
+            message: res.header.remark.clone(),
+        })
+    }
+}
+
+static INSTANCE_SEQ: AtomicUsize = AtomicUsize::new(0);
+
 fn client_ip() -> String {
     client_ip_addr()
-        .map(|addr| match addr {
-            IpAddr::V4(v4) => v4.to_string(),
-            IpAddr::V6(v6) => format!("[{}]", v6),
-        })
+        .map(format_client_ip)
         .unwrap_or_else(|| "127.0.0.1".to_string())
 }
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive)]
+fn format_client_ip(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(v4) => v4.to_string(),
+        IpAddr::V6(v6) => format!("[{}]", v6),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum ClientState {
     Created = 0,
@@ -142,63 +637,131 @@ pub enum ClientState {
 pub struct Client<R: NsResolver + Clone> {
     options: ClientOptions,
     remote_client: RemotingClient,
-    consumers: Arc<Mutex<HashMap<String, Arc<Mutex<ConsumerInner>>>>>,
-    producers: Arc<Mutex<HashMap<String, Arc<Mutex<ProducerInner>>>>>,
+    consumers: Arc<Mutex<HashMap<String, Arc<ConsumerInner>>>>,
+    pub(crate) producers: Arc<Mutex<HashMap<String, Arc<Mutex<ProducerInner>>>>>,
     pub(crate) name_server: NameServer<R>,
     state: Arc<AtomicU8>,
+    start_lock: Arc<tokio::sync::Mutex<()>>,
+    stats: Arc<ClientStats>,
     shutdown_tx: Arc<Mutex<Option<broadcast::Sender<()>>>>,
+    rebalance_notify: Arc<Notify>,
+    // end transaction requests answering transaction state checks, by broker address
+    end_transactions: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<(String, RemotingCommand)>>>,
 }
 
 impl<R> Client<R>
 where
     R: NsResolver + Clone + Send + Sync + 'static,
 {
-    pub fn new(options: ClientOptions, name_server: NameServer<R>) -> Self {
-        let credentials = options.credentials.clone();
+    pub fn new(options: ClientOptions, mut name_server: NameServer<R>) -> Self {
+        let mut remote_client = RemotingClient::new(None);
+        remote_client
+            .set_credentials_provider(options.credentials.clone())
+            .set_serialize_type(options.serialize_type)
+            .set_trace_propagation(options.trace_propagation)
+            .set_identity(options.language, options.client_version)
+            .set_signature_method(options.signature_method)
+            .set_socket_options(options.socket.clone())
+            .set_connections_per_addr(options.connections_per_broker, options.multiplex_threshold)
+            .set_circuit_breaker(options.reconnect_failures, options.reconnect_cooldown)
+            .set_request_timeout(Some(options.request_timeout))
+            .set_runtime(options.runtime.clone());
+        #[cfg(feature = "tls")]
+        remote_client.set_tls(options.tls.clone());
+        name_server.share_connection_events(&remote_client);
+        let rebalance_notify = Arc::new(Notify::new());
+        remote_client.register_processor(
+            RequestCode::NotifyConsumerIdsChanged,
+            Arc::new(ConsumerIdsChangedProcessor::new(rebalance_notify.clone())),
+        );
+        let producers = Arc::new(Mutex::new(HashMap::new()));
+        let (end_transaction_tx, end_transaction_rx) = mpsc::unbounded_channel();
+        remote_client.register_processor(
+            RequestCode::CheckTransactionState,
+            Arc::new(CheckTransactionStateProcessor::new(
+                Arc::clone(&producers),
+                end_transaction_tx,
+            )),
+        );
         Self {
             options,
-            remote_client: RemotingClient::new(credentials),
+            remote_client,
             consumers: Arc::new(Mutex::new(HashMap::new())),
-            producers: Arc::new(Mutex::new(HashMap::new())),
+            producers,
             name_server,
             state: Arc::new(AtomicU8::new(ClientState::Created.into())),
+            start_lock: Arc::new(tokio::sync::Mutex::new(())),
+            stats: Arc::new(ClientStats::default()),
             shutdown_tx: Arc::new(Mutex::new(None)),
+            rebalance_notify,
+            end_transactions: Arc::new(tokio::sync::Mutex::new(end_transaction_rx)),
         }
     }
 
     /// Get Client ID
     pub fn id(&self) -> String {
-        let mut client_id = self.options.client_ip.clone() + "@";
-        if self.options.instance_name == "DEFAULT" {
-            client_id.push_str(&process::id().to_string());
-        } else {
-            client_id.push_str(&self.options.instance_name);
+        self.options.client_id()
+    }
+
+    /// Start the client and its scheduled tasks, does nothing if it's running already
+    ///
+    /// A client which was shut down can be started again, connections are
+    /// re-established lazily.
+    pub async fn start(&self) -> Result<(), Error> {
+        let _guard = self.start_lock.lock().await;
+        match self.state() {
+            ClientState::Running => return Ok(()),
+            ClientState::Created | ClientState::StartFailed | ClientState::Shutdown => {}
         }
-        if !self.options.unit_name.is_empty() {
-            client_id.push_str(&self.options.unit_name);
+        if let Err(err) = self.check_name_server().await {
+            error!("client start failed: {:?}", err);
+            self.state
+                .store(ClientState::StartFailed.into(), Ordering::SeqCst);
+            return Err(err);
         }
-        client_id
+        self.start_scheduled_tasks();
+        self.state
+            .store(ClientState::Running.into(), Ordering::SeqCst);
+        Ok(())
     }
 
-    pub fn start(&self) {
-        match ClientState::try_from(self.state.load(Ordering::SeqCst)).unwrap() {
-            ClientState::Created => {
-                self.state
-                    .store(ClientState::StartFailed.into(), Ordering::SeqCst);
-                let (shutdown_tx, mut shutdown_rx1) = broadcast::channel(1);
-                let mut shutdown_rx2 = shutdown_tx.subscribe();
-                let mut shutdown_rx3 = shutdown_tx.subscribe();
-                self.shutdown_tx.lock().replace(shutdown_tx);
-
-                // Schedule update name server address
-                let name_server = self.name_server.clone();
-                tokio::spawn(async move {
-                    let mut interval = time::interval(time::Duration::from_secs(2 * 60));
+    /// Make sure the resolver yields at least one name server address
+    async fn check_name_server(&self) -> Result<(), Error> {
+        self.name_server.update_name_server_address().await?;
+        if self.name_server.is_empty() {
+            return Err(Error::EmptyNameServers);
+        }
+        Ok(())
+    }
+
+    fn start_scheduled_tasks(&self) {
+        let (shutdown_tx, mut shutdown_rx1) = broadcast::channel(1);
+        let mut shutdown_rx2 = shutdown_tx.subscribe();
+        let mut shutdown_rx3 = shutdown_tx.subscribe();
+        let mut shutdown_rx4 = shutdown_tx.subscribe();
+        let mut shutdown_rx5 = shutdown_tx.subscribe();
+        let mut shutdown_rx6 = shutdown_tx.subscribe();
+        let mut shutdown_rx7 = shutdown_tx.subscribe();
+        self.shutdown_tx.lock().replace(shutdown_tx);
+        let runtime = self.options.runtime.as_ref();
+
+        // Schedule update name server address
+        let client = self.clone();
+        spawn(
+            runtime,
+            in_span!(
+                async move {
+                    let mut delay = time::Duration::ZERO;
                     loop {
                         tokio::select! {
-                            _ = interval.tick() => {
-                                match name_server.update_name_server_address().await {
-                                    Ok(_) => info!("name server addresses update succeed"),
+                            _ = time::sleep(delay) => {
+                                delay = client.options.jittered(client.options.name_server_refresh_interval);
+                                match client.name_server.update_name_server_address().await {
+                                    Ok(true) => {
+                                        info!("name server addresses changed, refresh topic route info");
+                                        client.update_topic_route_info().await;
+                                    }
+                                    Ok(false) => debug!("name server addresses unchanged"),
                                     Err(err) => error!("name server address update failed: {:?}", err),
                                 };
                             }
@@ -208,73 +771,216 @@ where
                             }
                         }
                     }
-                }.instrument(info_span!("update_name_server_address")));
-
-                // Schedule update route info
-                let client = self.clone();
-                tokio::spawn(
-                    async move {
-                        // time::delay_for(time::Duration::from_millis(10)).await;
-                        let mut interval = time::interval(time::Duration::from_secs(30));
-                        loop {
-                            tokio::select! {
-                                _ = interval.tick() => {
-                                    let _ = client.update_topic_route_info().await;
-                                }
-                                _ = shutdown_rx2.recv() => {
-                                    info!("client shutdown, stop updating topic route info");
-                                    break;
-                                }
+                },
+                tracing::info_span!("update_name_server_address"),
+            ),
+        );
+
+        // Schedule update route info
+        let client = self.clone();
+        spawn(
+            runtime,
+            in_span!(
+                async move {
+                    let mut delay = time::Duration::ZERO;
+                    loop {
+                        tokio::select! {
+                            _ = time::sleep(delay) => {
+                                delay = client.options.jittered(client.options.route_refresh_interval);
+                                let _ = client.update_topic_route_info().await;
+                                client.clean_offline_broker();
+                            }
+                            _ = shutdown_rx2.recv() => {
+                                info!("client shutdown, stop updating topic route info");
+                                break;
                             }
                         }
                     }
-                    .instrument(info_span!("update_topic_route_info")),
-                );
+                },
+                tracing::info_span!("update_topic_route_info"),
+            ),
+        );
 
-                // Schedule send heartbeat to all brokers
-                let client = self.clone();
-                tokio::spawn(
-                    async move {
-                        // time::delay_for(time::Duration::from_secs(1)).await;
-                        let mut interval = time::interval(time::Duration::from_secs(30));
-                        loop {
-                            tokio::select! {
-                                _ = interval.tick() => {
-                                    let _ = client.send_heartbeat_to_all_brokers().await;
-                                }
-                                _ = shutdown_rx3.recv() => {
-                                    info!("client shutdown, stop sending heartbeat to all brokers");
-                                    break;
-                                }
+        // Schedule send heartbeat to all brokers
+        let client = self.clone();
+        spawn(
+            runtime,
+            in_span!(
+                async move {
+                    // time::delay_for(time::Duration::from_secs(1)).await;
+                    let mut interval = time::interval(time::Duration::from_secs(30));
+                    loop {
+                        tokio::select! {
+                            _ = interval.tick() => {
+                                let _ = client.send_heartbeat_to_all_brokers().await;
+                            }
+                            _ = shutdown_rx3.recv() => {
+                                info!("client shutdown, stop sending heartbeat to all brokers");
+                                break;
                             }
                         }
                     }
-                    .instrument(info_span!("send_heartbeat_to_all_brokers")),
-                );
+                },
+                tracing::info_span!("send_heartbeat_to_all_brokers"),
+            ),
+        );
+
+        // Schedule persist consumer offsets
+        let client = self.clone();
+        spawn(
+            runtime,
+            in_span!(
+                async move {
+                    let mut interval =
+                        time::interval(client.options.persist_consumer_offset_interval);
+                    loop {
+                        tokio::select! {
+                            _ = interval.tick() => {
+                                client.persist_consumer_offset().await;
+                            }
+                            _ = shutdown_rx4.recv() => {
+                                info!("client shutdown, persist consumer offsets for the last time");
+                                client.persist_consumer_offset().await;
+                                break;
+                            }
+                        }
+                    }
+                },
+                tracing::info_span!("persist_consumer_offset"),
+            ),
+        );
 
-                // Persist offset
+        // Schedule rebalance
+        let client = self.clone();
+        spawn(
+            runtime,
+            in_span!(
+                async move {
+                    // consumers do the first rebalance on start
+                    let period = time::Duration::from_secs(20);
+                    let mut interval = time::interval_at(time::Instant::now() + period, period);
+                    loop {
+                        tokio::select! {
+                            _ = interval.tick() => {
+                                client.rebalance_immediately().await;
+                            }
+                            _ = client.rebalance_notify.notified() => {
+                                client.rebalance_immediately().await;
+                            }
+                            _ = shutdown_rx5.recv() => {
+                                info!("client shutdown, stop rebalancing");
+                                break;
+                            }
+                        }
+                    }
+                },
+                tracing::info_span!("rebalance"),
+            ),
+        );
 
-                // Rebalance
-                self.state
-                    .store(ClientState::Running.into(), Ordering::SeqCst);
-            }
-            _ => {}
+        // Send end transaction requests answering transaction state checks
+        let client = self.clone();
+        spawn(
+            runtime,
+            in_span!(
+                async move {
+                    let mut end_transactions = client.end_transactions.lock().await;
+                    loop {
+                        tokio::select! {
+                            Some((addr, cmd)) = end_transactions.recv() => {
+                                if let Err(err) = client.invoke_oneway(&addr, cmd).await {
+                                    warn!(broker = %addr, error = %err, "end transaction failed");
+                                }
+                            }
+                            _ = shutdown_rx7.recv() => {
+                                info!("client shutdown, stop ending checked transactions");
+                                break;
+                            }
+                        }
+                    }
+                },
+                tracing::info_span!("end_transaction"),
+            ),
+        );
+
+        // Schedule broker connection health checks
+        let period = self.options.health_check_interval;
+        if period.is_zero() {
+            return;
         }
+        let client = self.clone();
+        spawn(
+            runtime,
+            in_span!(
+                async move {
+                    let mut interval = time::interval_at(time::Instant::now() + period, period);
+                    loop {
+                        tokio::select! {
+                            _ = interval.tick() => {
+                                client
+                                    .remote_client
+                                    .check_connections(period, time::Duration::from_secs(3))
+                                    .await;
+                            }
+                            _ = shutdown_rx6.recv() => {
+                                info!("client shutdown, stop checking connections");
+                                break;
+                            }
+                        }
+                    }
+                },
+                tracing::info_span!("check_connections"),
+            ),
+        );
     }
 
+    /// Shutdown the client, does nothing while it is still used by producers or consumers
     pub fn shutdown(&self) {
-        match ClientState::try_from(
-            self.state
-                .swap(ClientState::Shutdown.into(), Ordering::Relaxed),
-        )
-        .unwrap()
-        {
-            ClientState::Shutdown => {} // shutdown already
+        if self.stop() {
+            self.remote_client.shutdown();
+        }
+    }
+
+    /// Like `shutdown`, but waits up to the shutdown timeout for the commands queued
+    /// on broker connections to be written before closing them
+    pub async fn shutdown_gracefully(&self) {
+        if self.stop() {
+            self.remote_client
+                .shutdown_gracefully(self.options.shutdown_timeout)
+                .await;
+        }
+    }
+
+    /// Mark the client shut down and stop its scheduled tasks, returns whether its
+    /// connections should be closed
+    fn stop(&self) -> bool {
+        if !self.consumers.lock().is_empty() || !self.producers.lock().is_empty() {
+            debug!("client is still in use, skip shutting down");
+            return false;
+        }
+        // held so that a concurrent restart can't install its scheduled tasks in between
+        let mut shutdown_tx = self.shutdown_tx.lock();
+        let state = self.state.load(Ordering::SeqCst);
+        match ClientState::try_from(state).unwrap() {
+            ClientState::Created | ClientState::Shutdown => false, // not started or shutdown already
             _ => {
-                if let Some(tx) = &*self.shutdown_tx.lock() {
-                    tx.send(()).unwrap();
+                if self
+                    .state
+                    .compare_exchange(
+                        state,
+                        ClientState::Shutdown.into(),
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    )
+                    .is_err()
+                {
+                    return false;
                 }
-                self.remote_client.shutdown();
+                if let Some(tx) = shutdown_tx.take() {
+                    let _ = tx.send(());
+                }
+                MQClientManager::remove_client(&self.id(), &self.state);
+                true
             }
         }
     }
@@ -284,11 +990,30 @@ where
             .unwrap_or(ClientState::StartFailed)
     }
 
+    /// Register a hook invoked around every request to brokers and name servers
+    pub fn register_rpc_hook(&self, hook: impl RpcHook + 'static) {
+        let hook: Arc<dyn RpcHook> = Arc::new(hook);
+        self.remote_client.register_rpc_hook(Arc::clone(&hook));
+        self.name_server.register_rpc_hook(hook);
+    }
+
+    /// Get a snapshot of the client metrics
+    pub fn metrics(&self) -> ClientMetrics {
+        let mut metrics = self.stats.snapshot();
+        metrics.add_remoting(&self.remote_client);
+        metrics.add_remoting(self.name_server.remoting_client());
+        metrics
+    }
+
+    /// Send a request and wait for its response, idempotent requests are retried
+    /// according to the retry policy
     #[inline]
     pub async fn invoke(&self, addr: &str, cmd: RemotingCommand) -> Result<RemotingCommand, Error> {
-        Ok(self.remote_client.invoke(addr, cmd).await?)
+        self.invoke_with_retry(addr, cmd, Some(self.options.request_timeout))
+            .await
     }
 
+    /// Like `invoke`, with `timeout` applied to every attempt
     #[inline]
     pub async fn invoke_timeout(
         &self,
@@ -296,14 +1021,131 @@ where
         cmd: RemotingCommand,
         timeout: time::Duration,
     ) -> Result<RemotingCommand, Error> {
-        time::timeout(timeout, self.remote_client.invoke(addr, cmd))
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))?
+        self.invoke_with_retry(addr, cmd, Some(timeout)).await
+    }
+
+    async fn invoke_with_retry(
+        &self,
+        addr: &str,
+        cmd: RemotingCommand,
+        timeout: Option<time::Duration>,
+    ) -> Result<RemotingCommand, Error> {
+        let res = self.invoke_attempts(addr, cmd, timeout).await?;
+        self.name_server
+            .update_broker_version(addr, res.version().into());
+        Ok(res)
+    }
+
+    async fn invoke_attempts(
+        &self,
+        addr: &str,
+        cmd: RemotingCommand,
+        timeout: Option<time::Duration>,
+    ) -> Result<RemotingCommand, Error> {
+        let addr = self.channel_addr(addr, &cmd);
+        let policy = &self.options.retry_policy;
+        if !retry::is_idempotent(cmd.code()) || policy.max_attempts() <= 1 {
+            return self.invoke_once(&addr, cmd, timeout).await;
+        }
+        let mut retry = 0;
+        loop {
+            let res = self.invoke_once(&addr, cmd.clone(), timeout).await;
+            // requests are retried on the same address, which doesn't help when the
+            // broker stepped down as the leader
+            let retriable = match &res {
+                Ok(res) => {
+                    res.code() != ResponseCode::NotLeaderForQueue
+                        && policy.is_retriable_response(res.code())
+                }
+                Err(err) => err.kind() != ErrorKind::NotLeader && policy.is_retriable_error(err),
+            };
+            retry += 1;
+            if !retriable || retry >= policy.max_attempts() {
+                return res;
+            }
+            let delay = policy.backoff(retry as u32);
+            debug!(
+                code = cmd.code(),
+                addr = %addr,
+                retry = retry,
+                "request failed, retry in {:?}",
+                delay
+            );
+            time::sleep(delay).await;
+        }
+    }
+
+    async fn invoke_once(
+        &self,
+        addr: &str,
+        cmd: RemotingCommand,
+        timeout: Option<time::Duration>,
+    ) -> Result<RemotingCommand, Error> {
+        match timeout {
+            Some(timeout) => self.remote_client.invoke_timeout(addr, cmd, timeout).await,
+            None => self.remote_client.invoke(addr, cmd).await,
+        }
+    }
+
+    /// Version of the broker at `addr` reported in its last response, if known
+    pub fn broker_version(&self, addr: &str) -> Option<i32> {
+        self.name_server.broker_version(addr)
     }
 
     #[inline]
     pub async fn invoke_oneway(&self, addr: &str, cmd: RemotingCommand) -> Result<(), Error> {
-        Ok(self.remote_client.invoke_oneway(addr, cmd).await?)
+        let addr = self.channel_addr(addr, &cmd);
+        self.remote_client.invoke_oneway(&addr, cmd).await
+    }
+
+    /// Route send message requests to the broker VIP channel if enabled
+    fn channel_addr<'a>(&self, addr: &'a str, cmd: &RemotingCommand) -> Cow<'a, str> {
+        let is_send = matches!(
+            RequestCode::try_from(cmd.code()),
+            Ok(RequestCode::SendMessage
+                | RequestCode::SendMessageV2
+                | RequestCode::SendBatchMessage)
+        );
+        if self.options.vip_channel_enabled && is_send {
+            Cow::Owned(broker_vip_channel(addr))
+        } else {
+            Cow::Borrowed(addr)
+        }
+    }
+
+    /// Connect to the master brokers of `topic` ahead of traffic, including their
+    /// VIP channels if enabled
+    pub async fn warm_up(&self, topic: &str) -> Result<(), Error> {
+        let (route_data, _) = self.name_server.update_topic_route_info(topic).await?;
+        let mut addrs = Vec::new();
+        for broker_data in &route_data.broker_datas {
+            if let Some(addr) = broker_data.broker_addrs.get(&MASTER_ID) {
+                if self.options.vip_channel_enabled {
+                    addrs.push(broker_vip_channel(addr));
+                }
+                addrs.push(addr.clone());
+            }
+        }
+        self.remote_client.pre_connect(&addrs).await
+    }
+
+    /// The first of `candidates` which can be connected to, for read-only requests
+    /// which slaves serve as well when the master is unreachable
+    pub(crate) async fn connect_any(
+        &self,
+        candidates: Vec<FindBrokerResult>,
+    ) -> Result<FindBrokerResult, Error> {
+        let mut last_err = Error::EmptyRouteData;
+        for candidate in candidates {
+            match self.remote_client.get_connection(&candidate.addr).await {
+                Ok(_) => return Ok(candidate),
+                Err(err) => {
+                    warn!(broker = %candidate.addr, "connect to broker failed, try the next address: {:?}", err);
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err)
     }
 
     pub async fn pull_message(
@@ -311,9 +1153,14 @@ where
         addr: &str,
         request: PullMessageRequestHeader,
     ) -> Result<PullResult, Error> {
+        // the broker holds long polling requests for up to the suspend timeout
+        let timeout = request.suspend_timeout_millis + self.options.request_timeout;
         let cmd = RemotingCommand::with_header(RequestCode::PullMessage, request, Vec::new());
-        let res = self.remote_client.invoke(addr, cmd).await?;
-        let status = match ResponseCode::from_code(res.code())? {
+        let res = self
+            .remote_client
+            .invoke_timeout(addr, cmd, timeout)
+            .await?;
+        let status = match ResponseCode::from_code(res.code()) {
             ResponseCode::Success => PullStatus::Found,
             ResponseCode::PullNotFound => PullStatus::NoNewMsg,
             ResponseCode::PullRetryImmediately => PullStatus::NoMsgMatched,
@@ -329,63 +1176,259 @@ where
                 });
             }
         };
-        let ext_fields = &res.header.ext_fields;
-        let max_offset = ext_fields
-            .get("maxOffset")
-            .and_then(|s| s.parse::<i64>().ok())
-            .unwrap_or_default();
-        let min_offset = ext_fields
-            .get("minOffset")
-            .and_then(|s| s.parse::<i64>().ok())
-            .unwrap_or_default();
-        let next_begin_offset = ext_fields
-            .get("nextBeginOffset")
-            .and_then(|s| s.parse::<i64>().ok())
-            .unwrap_or_default();
-        let suggest_which_broker_id = ext_fields
-            .get("suggestWhichBrokerId")
-            .and_then(|s| s.parse::<i64>().ok())
-            .unwrap_or_default();
+        let max_offset = res.ext_i64("maxOffset").unwrap_or_default();
+        let min_offset = res.ext_i64("minOffset").unwrap_or_default();
+        let next_begin_offset = res.ext_i64("nextBeginOffset").unwrap_or_default();
+        let suggest_which_broker_id = res.ext_i64("suggestWhichBrokerId").unwrap_or_default();
+        let message_exts = if status == PullStatus::Found {
+            MessageExt::decode(&res.body)
+        } else {
+            Vec::new()
+        };
         Ok(PullResult {
             next_begin_offset,
             min_offset,
             max_offset,
             suggest_which_broker_id,
             status,
-            message_exts: Vec::new(),
-            body: res.body,
+            message_exts,
         })
     }
 
-    pub(crate) fn register_consumer(&self, group: &str, consumer: Arc<Mutex<ConsumerInner>>) {
+    /// Query messages of a broker by key, a not found response yields no messages
+    pub async fn query_message(
+        &self,
+        addr: &str,
+        header: QueryMessageRequestHeader,
+        timeout: time::Duration,
+    ) -> Result<Vec<MessageExt>, Error> {
+        let cmd = RemotingCommand::with_header(RequestCode::QueryMessage, header, Vec::new());
+        let res = self.invoke_timeout(addr, cmd, timeout).await?;
+        match ResponseCode::from_code(res.code()) {
+            ResponseCode::Success => Ok(MessageExt::decode(&res.body)),
+            ResponseCode::QueryNotFound => Ok(Vec::new()),
+            _ => Err(Error::ResponseError {
+                code: res.code(),
+                message: res.header.remark,
+            }),
+        }
+    }
+
+    /// Query messages of `topic` by key from all of its brokers
+    ///
+    /// With `unique_key` the key is matched against message ids, otherwise against message keys.
+    pub async fn query_message_by_key(
+        &self,
+        topic: &str,
+        key: &str,
+        max_num: i32,
+        begin_timestamp: i64,
+        end_timestamp: i64,
+        unique_key: bool,
+    ) -> Result<Vec<MessageExt>, Error> {
+        let (route_data, _) = self.name_server.update_topic_route_info(topic).await?;
+        let mut messages = Vec::new();
+        let mut last_error = None;
+        for broker_data in &route_data.broker_datas {
+            let addr = match broker_data
+                .broker_addrs
+                .get(&MASTER_ID)
+                .or_else(|| broker_data.broker_addrs.values().next())
+            {
+                Some(addr) => addr,
+                None => continue,
+            };
+            let header = QueryMessageRequestHeader {
+                topic: topic.to_string(),
+                key: key.to_string(),
+                max_num,
+                begin_timestamp,
+                end_timestamp,
+                unique_key_query: unique_key,
+            };
+            match self
+                .query_message(addr, header, time::Duration::from_secs(3))
+                .await
+            {
+                Ok(found) => messages.extend(found),
+                Err(err) => {
+                    warn!(topic = %topic, broker_addr = %addr, "query message error: {:?}", err);
+                    last_error = Some(err);
+                }
+            }
+        }
+        if messages.is_empty() {
+            if let Some(err) = last_error {
+                return Err(err);
+            }
+        }
+        // the broker index may return hash collisions
+        messages.retain(|msg| matches_query_key(msg, key, unique_key));
+        Ok(messages)
+    }
+
+    /// Get the max offset of a message queue
+    pub async fn get_max_offset(
+        &self,
+        addr: &str,
+        header: GetMaxOffsetRequestHeader,
+    ) -> Result<i64, Error> {
+        let cmd = RemotingCommand::with_header(RequestCode::GetMaxOffset, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        let header: GetMaxOffsetResponseHeader = decode_response_header(&res)?;
+        Ok(header.offset)
+    }
+
+    /// Get the min offset of a message queue
+    pub async fn get_min_offset(
+        &self,
+        addr: &str,
+        header: GetMinOffsetRequestHeader,
+    ) -> Result<i64, Error> {
+        let cmd = RemotingCommand::with_header(RequestCode::GetMinOffset, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        let header: GetMinOffsetResponseHeader = decode_response_header(&res)?;
+        Ok(header.offset)
+    }
+
+    /// Get the store timestamp in milliseconds of the earliest message of a message queue
+    pub async fn get_earliest_msg_store_time(
+        &self,
+        addr: &str,
+        header: GetEarliestMsgStoreTimeRequestHeader,
+    ) -> Result<i64, Error> {
+        let cmd =
+            RemotingCommand::with_header(RequestCode::GetEarliestMsgStoreTime, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        let header: GetEarliestMsgStoreTimeResponseHeader = decode_response_header(&res)?;
+        Ok(header.timestamp)
+    }
+
+    /// Search the offset of the first message stored at or after `timestamp` in a message queue
+    pub async fn search_offset(
+        &self,
+        addr: &str,
+        header: SearchOffsetRequestHeader,
+    ) -> Result<i64, Error> {
+        let cmd =
+            RemotingCommand::with_header(RequestCode::SearchOffsetByTimestamp, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        let header: SearchOffsetResponseHeader = decode_response_header(&res)?;
+        Ok(header.offset)
+    }
+
+    /// Fetch a message by its offset message id from the broker which stored it
+    pub async fn view_message(&self, offset_msg_id: &str) -> Result<MessageExt, Error> {
+        let (addr, offset) = MessageExt::decode_message_id(offset_msg_id)?;
+        let header = ViewMessageRequestHeader { offset };
+        let cmd = RemotingCommand::with_header(RequestCode::ViewMessageById, header, Vec::new());
+        let res = self
+            .invoke_timeout(&addr.to_string(), cmd, time::Duration::from_secs(3))
+            .await?;
+        if res.code() != ResponseCode::Success {
+            return Err(Error::ResponseError {
+                code: res.code(),
+                message: res.header.remark,
+            });
+        }
+        MessageExt::decode(&res.body)
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::ResponseError {
+                code: res.code(),
+                message: format!("message {} not found in response", offset_msg_id),
+            })
+    }
+
+    /// Register a consumer, fails if another consumer of the same group is registered
+    pub(crate) fn register_consumer(
+        &self,
+        group: &str,
+        consumer: Arc<ConsumerInner>,
+    ) -> Result<(), Error> {
         let mut consumers = self.consumers.lock();
-        consumers.entry(group.to_string()).or_insert(consumer);
+        match consumers.get(group) {
+            Some(existing) if Arc::ptr_eq(existing, &consumer) => Ok(()),
+            Some(_) => Err(Error::DuplicateGroup(group.to_string())),
+            None => {
+                consumers.insert(group.to_string(), consumer);
+                Ok(())
+            }
+        }
     }
 
-    pub(crate) fn unregister_consumer(&self, group: &str) {
+    /// Unregister `consumer`, returns false if it is not the registered one of its group
+    pub(crate) fn unregister_consumer(&self, group: &str, consumer: &ConsumerInner) -> bool {
         let mut consumers = self.consumers.lock();
-        consumers.remove(group);
+        match consumers.get(group) {
+            Some(existing) if std::ptr::eq(Arc::as_ptr(existing), consumer) => {
+                consumers.remove(group);
+                true
+            }
+            _ => false,
+        }
     }
 
-    pub(crate) fn register_producer(&self, group: &str, producer: Arc<Mutex<ProducerInner>>) {
+    /// Register a producer, fails if another producer of the same group is registered
+    pub(crate) fn register_producer(
+        &self,
+        group: &str,
+        producer: Arc<Mutex<ProducerInner>>,
+    ) -> Result<(), Error> {
         let mut producers = self.producers.lock();
-        producers.entry(group.to_string()).or_insert(producer);
+        match producers.get(group) {
+            Some(existing) if Arc::ptr_eq(existing, &producer) => Ok(()),
+            Some(_) => Err(Error::DuplicateGroup(group.to_string())),
+            None => {
+                producers.insert(group.to_string(), producer);
+                Ok(())
+            }
+        }
     }
 
-    pub(crate) fn unregister_producer(&self, group: &str) {
+    /// Unregister `producer`, returns false if it is not the registered one of its group
+    pub(crate) fn unregister_producer(
+        &self,
+        group: &str,
+        producer: &Arc<Mutex<ProducerInner>>,
+    ) -> bool {
         let mut producers = self.producers.lock();
-        producers.remove(group);
+        match producers.get(group) {
+            Some(existing) if Arc::ptr_eq(existing, producer) => {
+                producers.remove(group);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) async fn rebalance_immediately(&self) {
+        let consumers: Vec<Arc<ConsumerInner>> = self.consumers.lock().values().cloned().collect();
+        for consumer in consumers {
+            if let Err(err) = consumer.rebalance().await {
+                warn!("rebalance failed: {:?}", err);
+            }
+        }
     }
 
-    fn rebalance_immediately(&self) {
-        let consumers = self.consumers.lock();
-        for consumer in consumers.values() {
-            consumer.lock().rebalance();
+    async fn persist_consumer_offset(&self) {
+        let consumers: Vec<Arc<ConsumerInner>> = self.consumers.lock().values().cloned().collect();
+        for consumer in consumers {
+            consumer.persist_consumer_offset().await;
         }
     }
 
-    async fn send_heartbeat_to_all_brokers(&self) {
-        use model::{HeartbeatData, ProducerData};
+    /// Send heartbeat to all brokers, fails only if no broker accepted it
+    pub(crate) async fn send_heartbeat_to_all_brokers(&self) -> Result<(), Error> {
+        use model::{ConsumerData, HeartbeatData, ProducerData};
 
         let producer_data_set: Vec<ProducerData> = self
             .producers
@@ -395,26 +1438,33 @@ where
                 group_name: group_name.clone(),
             })
             .collect();
-        // FIXME
-        let consumer_data_set = Vec::new();
+        let consumer_data_set: Vec<ConsumerData> = self
+            .consumers
+            .lock()
+            .values()
+            .map(|consumer| consumer.consumer_data())
+            .collect();
         if producer_data_set.is_empty() && consumer_data_set.is_empty() {
             debug!("sending heartbeat, but no producer and no consumer found");
-            return;
+            return Ok(());
         }
         let broker_address_map = self.name_server.broker_address_map();
         if broker_address_map.is_empty() {
             debug!("sending heartbeat, but no brokers found");
-            return;
+            return Ok(());
         }
         let heartbeat_data = HeartbeatData {
             client_id: self.id(),
             producer_data_set,
             consumer_data_set,
         };
-        let hb_bytes = serde_json::to_vec(&heartbeat_data).unwrap();
+        let send_to_slaves = !heartbeat_data.consumer_data_set.is_empty();
+        let heartbeat = heartbeat_data.into_command()?;
+        let mut last_error = None;
+        let mut success = false;
         for (broker_name, broker_data) in broker_address_map {
             for (id, addr) in &broker_data.broker_addrs {
-                if heartbeat_data.consumer_data_set.is_empty() && *id != 0 {
+                if !send_to_slaves && *id != 0 {
                     continue;
                 }
                 debug!(
@@ -423,26 +1473,15 @@ where
                     broker_addr = %addr,
                     "try to send heart beat to broker",
                 );
-                let cmd = RemotingCommand::new(
-                    RequestCode::Heartbeat.into(),
-                    0,
-                    String::new(),
-                    HashMap::new(),
-                    hb_bytes.clone(),
-                );
-                match time::timeout(
-                    time::Duration::from_secs(3),
-                    self.remote_client.invoke(addr, cmd),
-                )
-                .await
+                match self
+                    .invoke_timeout(addr, heartbeat.clone(), time::Duration::from_secs(3))
+                    .await
                 {
-                    Ok(Ok(res)) => match ResponseCode::try_from(res.code()) {
-                        Ok(ResponseCode::Success) => {
-                            self.name_server.add_broker_version(
-                                &broker_name,
-                                addr,
-                                res.header.version as i32,
-                            );
+                    Ok(res) => match HeartbeatData::broker_version(&res) {
+                        Ok(version) => {
+                            success = true;
+                            self.name_server
+                                .add_broker_version(&broker_name, addr, version);
                             info!(
                                 broker_name = %broker_name,
                                 broker_id = id,
@@ -450,7 +1489,7 @@ where
                                 "send heart beat to broker success",
                             );
                         }
-                        _ => {
+                        Err(err) => {
                             warn!(
                                 broker_name = %broker_name,
                                 broker_id = id,
@@ -458,13 +1497,22 @@ where
                                 code = res.code(),
                                 "send heart beat to broker failed",
                             );
+                            self.stats.record_heartbeat_failure(addr);
+                            last_error = Some(err);
                         }
                     },
-                    Ok(Err(err)) => warn!("send heart beat to broker {} error {:?}", id, err),
-                    Err(_) => warn!("send heart beat to broker {} timed out", id),
+                    Err(err) => {
+                        warn!("send heart beat to broker {} error {:?}", id, err);
+                        self.stats.record_heartbeat_failure(addr);
+                        last_error = Some(err);
+                    }
                 }
             }
         }
+        match last_error {
+            Some(err) if !success => Err(err),
+            _ => Ok(()),
+        }
     }
 
     pub fn update_publish_info(&self, topic: &str, data: TopicRouteData, changed: bool) {
@@ -490,6 +1538,25 @@ where
         }
     }
 
+    fn update_subscribe_info(&self, topic: &str, data: &TopicRouteData) {
+        let consumers: Vec<Arc<ConsumerInner>> = self.consumers.lock().values().cloned().collect();
+        for consumer in consumers {
+            consumer.update_topic_subscribe_info(topic, data);
+        }
+    }
+
+    /// Subscribe to topic route changes, topics are prefixed with the namespace if any
+    pub fn subscribe_route_changes(&self) -> broadcast::Receiver<(String, TopicRouteData)> {
+        self.name_server.subscribe_route_changes()
+    }
+
+    /// Subscribe to connection events of broker and name server addresses
+    pub fn subscribe_connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.remote_client.subscribe_connection_events()
+    }
+
+    /// Refresh routes of topics published by producers and subscribed by consumers,
+    /// including the retry topics of consumer groups
     async fn update_topic_route_info(&self) {
         let mut topics = HashSet::new();
         {
@@ -498,6 +1565,12 @@ where
                 topics.extend(producer.lock().publish_topic_list());
             }
         }
+        {
+            let consumers = self.consumers.lock();
+            for consumer in consumers.values() {
+                topics.extend(consumer.subscribed_topics());
+            }
+        }
         if topics.is_empty() {
             debug!("updating topic route info, but no topics found");
             return;
@@ -507,14 +1580,30 @@ where
             match self.name_server.update_topic_route_info(topic).await {
                 Ok((route_data, changed)) => {
                     info!(route_data = ?route_data, changed = changed, "topic route info updated");
+                    self.update_subscribe_info(topic, &route_data);
                     self.update_publish_info(topic, route_data, changed);
                 }
-                Err(err) => error!("update topic {} route info failed: {:?}", topic, err),
+                Err(err) => {
+                    error!("update topic {} route info failed: {:?}", topic, err);
+                    self.stats.record_route_refresh_failure(topic);
+                }
             }
         }
     }
 
-    async fn unregister_client(&self, producer_group: &str, consumer_group: &str) {
+    /// Remove expired topic routes and brokers no longer present in any topic route,
+    /// and close connections to removed brokers
+    fn clean_offline_broker(&self) {
+        let expired = self.name_server.remove_expired_routes();
+        if !expired.is_empty() {
+            info!(topics = ?expired, "remove expired topic routes");
+        }
+        for addr in self.name_server.clean_offline_broker() {
+            self.remote_client.close_connection(&addr);
+        }
+    }
+
+    pub(crate) async fn unregister_client(&self, producer_group: &str, consumer_group: &str) {
         for broker_data in self.name_server.broker_address_map().values() {
             for broker_addr in broker_data.broker_addrs.values() {
                 let header = UnregisterClientRequestHeader {
@@ -551,27 +1640,10 @@ where
         for broker_data in broker_datas {
             if let Some(addr) = broker_data.broker_addrs.get(&MASTER_ID) {
                 for _ in 0..5usize {
-                    let header = CreateTopicRequestHeader {
-                        topic: new_topic.topic_name.clone(),
-                        default_topic: "TBW102".to_string(), // FIXME
-                        read_queue_nums: new_topic.read_queue_nums,
-                        write_queue_nums: new_topic.write_queue_nums,
-                        permission: new_topic.permission.bits(),
-                        topic_filter_type: new_topic.topic_filter_type.to_string(),
-                        topic_sys_flag: new_topic.topic_sys_flag,
-                        order: new_topic.order,
-                    };
-                    let cmd = RemotingCommand::with_header(
-                        RequestCode::UpdateAndCreateTopic,
-                        header,
-                        Vec::new(),
-                    );
-                    match self.remote_client.invoke(addr, cmd).await {
-                        Ok(res) => {
-                            if res.code() == ResponseCode::Success {
-                                create_ok_at_least_once = true;
-                                break;
-                            }
+                    match self.update_and_create_topic(addr, new_topic).await {
+                        Ok(()) => {
+                            create_ok_at_least_once = true;
+                            break;
                         }
                         Err(err) => last_error = Some(err),
                     }
@@ -586,16 +1658,183 @@ where
         Ok(())
     }
 
+    /// Create `topic` on the broker at `addr`, or update its config if it exists
+    pub async fn update_and_create_topic(
+        &self,
+        addr: &str,
+        topic: &model::TopicConfig,
+    ) -> Result<(), Error> {
+        let header = CreateTopicRequestHeader {
+            topic: topic.topic_name.clone(),
+            default_topic: "TBW102".to_string(), // FIXME
+            read_queue_nums: topic.read_queue_nums,
+            write_queue_nums: topic.write_queue_nums,
+            permission: topic.permission.bits(),
+            topic_filter_type: topic.topic_filter_type.to_string(),
+            topic_sys_flag: topic.topic_sys_flag
+                | model::build_topic_sys_flag(self.options.unit_mode, false),
+            order: topic.order,
+        };
+        let cmd =
+            RemotingCommand::with_header(RequestCode::UpdateAndCreateTopic, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        check_response(&res)
+    }
+
+    /// Delete `topic` from the broker at `addr`
+    pub async fn delete_topic_in_broker(&self, addr: &str, topic: &str) -> Result<(), Error> {
+        let header = DeleteTopicRequestHeader {
+            topic: topic.to_string(),
+        };
+        let cmd =
+            RemotingCommand::with_header(RequestCode::DeleteTopicInBroker, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        check_response(&res)
+    }
+
+    /// Configs of all topics on the broker at `addr`
+    pub async fn get_all_topic_config(&self, addr: &str) -> Result<model::TopicConfigTable, Error> {
+        let cmd = RemotingCommand::new(
+            RequestCode::GetAllTopicConfig,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let res = self.invoke(addr, cmd).await?;
+        check_response(&res)?;
+        model::TopicConfigTable::from_bytes(&res.body)
+    }
+
+    /// Offsets of the queues of `topic` on the broker at `addr`
+    pub async fn get_topic_stats_info(
+        &self,
+        addr: &str,
+        topic: &str,
+    ) -> Result<model::TopicStatsTable, Error> {
+        let header = GetTopicStatsInfoRequestHeader {
+            topic: topic.to_string(),
+        };
+        let cmd = RemotingCommand::with_header(RequestCode::GetTopicStatsInfo, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        check_response(&res)?;
+        model::TopicStatsTable::from_bytes(&res.body)
+    }
+
+    /// Consume progress of `group` on the broker at `addr`, of all its topics if `topic` is `None`
+    pub async fn get_consume_stats(
+        &self,
+        addr: &str,
+        group: &str,
+        topic: Option<&str>,
+    ) -> Result<model::ConsumeStats, Error> {
+        let header = GetConsumeStatsRequestHeader {
+            consumer_group: group.to_string(),
+            topic: topic.map(ToString::to_string),
+        };
+        let cmd = RemotingCommand::with_header(RequestCode::GetConsumeStats, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        check_response(&res)?;
+        model::ConsumeStats::from_bytes(&res.body)
+    }
+
+    /// Online consumers of `group` connected to the broker at `addr`
+    pub async fn get_consumer_connection_list(
+        &self,
+        addr: &str,
+        group: &str,
+    ) -> Result<model::ConsumerConnection, Error> {
+        let header = GetConsumerConnectionListRequestHeader {
+            consumer_group: group.to_string(),
+        };
+        let cmd = RemotingCommand::with_header(
+            RequestCode::GetConsumerConnectionList,
+            header,
+            Vec::new(),
+        );
+        let res = self.invoke(addr, cmd).await?;
+        check_response(&res)?;
+        model::ConsumerConnection::from_bytes(&res.body)
+    }
+
+    /// Reset consumer offsets on the broker at `addr`, which pushes them to the online
+    /// consumers of the group, returns the new offset of every queue
+    pub async fn invoke_broker_to_reset_offset(
+        &self,
+        addr: &str,
+        header: ResetOffsetRequestHeader,
+    ) -> Result<HashMap<MessageQueue, i64>, Error> {
+        let cmd = RemotingCommand::with_header(
+            RequestCode::InvokeBrokerToResetOffset,
+            header,
+            Vec::new(),
+        );
+        let res = self.invoke(addr, cmd).await?;
+        check_response(&res)?;
+        Ok(model::ResetOffsetBody::from_bytes(&res.body)?.offset_table)
+    }
+
+    /// Update the offset of a consumer group stored by the broker at `addr`
+    pub async fn update_consumer_offset(
+        &self,
+        addr: &str,
+        header: UpdateConsumerOffsetRequestHeader,
+    ) -> Result<(), Error> {
+        let cmd =
+            RemotingCommand::with_header(RequestCode::UpdateConsumerOffset, header, Vec::new());
+        let res = self.invoke(addr, cmd).await?;
+        check_response(&res)
+    }
+
+    /// Create the consumer group `config` on the broker at `addr`, or update it if it exists
+    pub async fn update_and_create_subscription_group(
+        &self,
+        addr: &str,
+        config: &model::SubscriptionGroupConfig,
+    ) -> Result<(), Error> {
+        let cmd = RemotingCommand::new(
+            RequestCode::UpdateAndCreateSubscriptionGroup,
+            String::new(),
+            HashMap::new(),
+            fastjson::to_vec(config)?,
+        );
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        check_response(&res)
+    }
+
+    /// Delete the consumer group `group` from the broker at `addr`
+    pub async fn delete_subscription_group(&self, addr: &str, group: &str) -> Result<(), Error> {
+        let header = DeleteSubscriptionGroupRequestHeader {
+            group_name: group.to_string(),
+        };
+        let cmd =
+            RemotingCommand::with_header(RequestCode::DeleteSubscriptionGroup, header, Vec::new());
+        let res = self
+            .invoke_timeout(addr, cmd, time::Duration::from_secs(3))
+            .await?;
+        check_response(&res)
+    }
+
     pub async fn send_message_back(
         &self,
         broker_addr: &str,
+        group: &str,
         msg: &MessageExt,
         delay_level: i32,
         max_reconsume_times: i32,
     ) -> Result<(), Error> {
         let header = ConsumerSendMsgBackRequestHeader {
             offset: msg.commit_log_offset,
-            group: self.options.group_name.clone(),
+            group: group.to_string(),
             delay_level,
             origin_msg_id: msg.msg_id.clone(),
             origin_topic: msg.message.topic.clone(),
@@ -618,7 +1857,11 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::{model::TopicConfig, Client, ClientOptions};
+    use super::{
+        broker_vip_channel, matches_query_key, model, model::TopicConfig, Client, ClientOptions,
+        ClientState, Credentials,
+    };
+    use crate::message::{MessageExt, Property};
     use crate::namesrv::NameServer;
     use crate::resolver::{Resolver, StaticResolver};
 
@@ -626,7 +1869,7 @@ mod test {
         let options = ClientOptions::default();
         let name_server = NameServer::new(
             Resolver::Static(StaticResolver::new(vec!["localhost:9876".to_string()])),
-            options.credentials.clone(),
+            None,
         )
         .unwrap();
         Client::new(options, name_server)
@@ -640,4 +1883,109 @@ mod test {
             .await
             .unwrap();
     }
+
+    #[test]
+    fn test_client_options_setters() {
+        let mut options = ClientOptions::new("test_group");
+        options
+            .set_instance_name("instance")
+            .set_unit_name("unit")
+            .set_namespace("ns")
+            .set_credentials(Credentials::new("ak", "sk"));
+        assert!(options.client_id().ends_with("@instance@unit"));
+        assert!(!options.unit_mode);
+        options.set_unit_mode(true);
+        assert!(options.unit_mode);
+        assert_eq!(1, model::build_topic_sys_flag(options.unit_mode, false));
+        assert_eq!(3, model::build_topic_sys_flag(true, true));
+        options.set_retry_times(5);
+        assert_eq!(6, options.retry_policy.max_attempts());
+        assert_eq!("ns", options.namespace);
+        assert_eq!("%UNIT%unit%ns", options.resource_namespace());
+        options.set_unit_mode(false);
+        assert_eq!("ns", options.resource_namespace());
+        let credentials = futures::executor::block_on(options.credentials.unwrap().get());
+        assert_eq!("ak", credentials.access_key);
+    }
+
+    #[test]
+    fn test_refresh_jitter() {
+        use std::time::Duration;
+
+        let mut options = ClientOptions::default();
+        let interval = Duration::from_secs(30);
+        options.set_refresh_jitter(Duration::from_secs(1));
+        for _ in 0..10 {
+            let delay = options.jittered(interval);
+            assert!(delay >= interval && delay <= interval + Duration::from_secs(1));
+        }
+        options.set_refresh_jitter(Duration::ZERO);
+        assert_eq!(interval, options.jittered(interval));
+    }
+
+    #[tokio::test]
+    async fn test_client_start_without_name_servers() {
+        let options = ClientOptions::default();
+        let name_server =
+            NameServer::new(Resolver::Static(StaticResolver::new(Vec::new())), None).unwrap();
+        let client = Client::new(options, name_server);
+        assert!(client.start().await.is_err());
+        assert_eq!(ClientState::StartFailed, client.state());
+        // a failed client can be started again
+        assert!(client.start().await.is_err());
+        assert_eq!(ClientState::StartFailed, client.state());
+    }
+
+    #[test]
+    fn test_client_ip_and_unique_instance_name() {
+        let mut options = ClientOptions::default();
+        options.set_client_ip("fe80::1".parse().unwrap());
+        assert!(options.client_id().starts_with("[fe80::1]@"));
+        options.set_client_ip("10.0.0.1".parse().unwrap());
+        assert!(options.client_id().starts_with("10.0.0.1@"));
+
+        let mut another = options.clone();
+        options.make_instance_name_unique();
+        another.make_instance_name_unique();
+        assert_ne!(options.client_id(), another.client_id());
+
+        // explicit instance names are kept
+        options.set_instance_name("instance");
+        options.make_instance_name_unique();
+        assert_eq!("10.0.0.1@instance", options.client_id());
+    }
+
+    #[test]
+    fn test_broker_vip_channel() {
+        assert_eq!("127.0.0.1:10909", broker_vip_channel("127.0.0.1:10911"));
+        assert_eq!("[::1]:10909", broker_vip_channel("[::1]:10911"));
+        assert_eq!("127.0.0.1", broker_vip_channel("127.0.0.1"));
+        assert_eq!("127.0.0.1:1", broker_vip_channel("127.0.0.1:1"));
+    }
+
+    #[test]
+    fn test_matches_query_key() {
+        let mut msg = MessageExt::default();
+        msg.msg_id = "offset-id".to_string();
+        msg.message.set_unique_key("unique-id".to_string());
+        msg.message
+            .set_property(Property::KEYS.to_string(), "a b".to_string());
+        assert!(matches_query_key(&msg, "unique-id", true));
+        assert!(!matches_query_key(&msg, "offset-id", true));
+        assert!(matches_query_key(&msg, "b", false));
+        assert!(!matches_query_key(&msg, "unique-id", false));
+    }
+
+    #[tokio::test]
+    async fn test_client_restart_after_shutdown() {
+        let client = new_client();
+        client.start().await.unwrap();
+        assert_eq!(ClientState::Running, client.state());
+        client.shutdown();
+        assert_eq!(ClientState::Shutdown, client.state());
+        client.start().await.unwrap();
+        assert_eq!(ClientState::Running, client.state());
+        assert!(client.shutdown_tx.lock().is_some());
+        client.shutdown();
+    }
 }
@@ -1,9 +1,13 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
-use serde::Serialize;
+use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::fastjson;
+use crate::message::MessageQueue;
 use crate::permission::Permission;
+use crate::protocol::{LanguageCode, RemotingCommand, RequestCode, ResponseCode};
+use crate::Error;
 
 #[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct ProducerData {
@@ -11,7 +15,7 @@ pub struct ProducerData {
     pub group_name: String,
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SubscriptionData {
     #[serde(rename = "classFilterMode")]
     pub class_filter_mode: bool,
@@ -21,7 +25,7 @@ pub struct SubscriptionData {
     #[serde(rename = "tagsSet")]
     pub tags_set: HashSet<String>,
     #[serde(rename = "codeSet")]
-    pub code_set: HashSet<String>,
+    pub code_set: HashSet<i32>,
     #[serde(rename = "subVersion")]
     pub sub_version: i64,
     #[serde(rename = "expressionType")]
@@ -32,7 +36,7 @@ pub struct SubscriptionData {
 pub struct ConsumerData {
     #[serde(rename = "groupName")]
     pub group_name: String,
-    #[serde(rename = "consumerType")]
+    #[serde(rename = "consumeType")]
     pub consumer_type: String,
     #[serde(rename = "messageModel")]
     pub message_model: String,
@@ -54,9 +58,86 @@ pub struct HeartbeatData {
     pub consumer_data_set: Vec<ConsumerData>,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl HeartbeatData {
+    /// Build the heartbeat command, the body uses the field names of the Java client
+    pub fn into_command(self) -> Result<RemotingCommand, Error> {
+        Ok(RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            fastjson::to_vec(&self)?,
+        ))
+    }
+
+    /// Broker version carried by a heartbeat response
+    pub fn broker_version(res: &RemotingCommand) -> Result<i32, Error> {
+        if res.code() != ResponseCode::Success {
+            return Err(Error::ResponseError {
+                code: res.code(),
+                message: res.remark().to_string(),
+            });
+        }
+        Ok(res.version().into())
+    }
+}
+
+/// Request body of locking message queues on a broker for orderly consumption
+#[derive(Debug, Clone, Serialize)]
+pub struct LockBatchRequestBody {
+    #[serde(rename = "consumerGroup")]
+    pub consumer_group: String,
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    #[serde(rename = "mqSet")]
+    pub mq_set: HashSet<MessageQueue>,
+}
+
+/// Message queues successfully locked by a lock batch request
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LockBatchResponseBody {
+    #[serde(rename = "lockOKMQSet", default)]
+    pub lock_ok_mq_set: HashSet<MessageQueue>,
+}
+
+impl LockBatchResponseBody {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.is_empty() {
+            return Ok(Self::default());
+        }
+        fastjson::from_slice(bytes)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnlockBatchRequestBody {
+    #[serde(rename = "consumerGroup")]
+    pub consumer_group: String,
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    #[serde(rename = "mqSet")]
+    pub mq_set: HashSet<MessageQueue>,
+}
+
+const TOPIC_FLAG_UNIT: i32 = 0x1;
+const TOPIC_FLAG_UNIT_SUB: i32 = 0x1 << 1;
+
+/// Build the topic system flag from its unit flags
+pub fn build_topic_sys_flag(unit: bool, has_unit_sub: bool) -> i32 {
+    let mut sys_flag = 0;
+    if unit {
+        sys_flag |= TOPIC_FLAG_UNIT;
+    }
+    if has_unit_sub {
+        sys_flag |= TOPIC_FLAG_UNIT_SUB;
+    }
+    sys_flag
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
 pub enum TopicFilterType {
+    #[serde(rename = "SINGLE_TAG")]
     SingleTag,
+    #[serde(rename = "MULTI_TAG")]
     MultiTag,
 }
 
@@ -69,17 +150,29 @@ impl fmt::Display for TopicFilterType {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Topic created by `MqAdmin::create_topic`
+#[derive(Debug, Clone, Deserialize)]
 pub struct TopicConfig {
+    #[serde(rename = "topicName")]
     pub(crate) topic_name: String,
+    #[serde(rename = "readQueueNums")]
     pub(crate) read_queue_nums: u32,
+    #[serde(rename = "writeQueueNums")]
     pub(crate) write_queue_nums: u32,
+    #[serde(rename = "perm")]
     pub(crate) permission: Permission,
+    #[serde(rename = "topicFilterType", default = "default_topic_filter_type")]
     pub(crate) topic_filter_type: TopicFilterType,
+    #[serde(rename = "topicSysFlag", default)]
     pub(crate) topic_sys_flag: i32,
+    #[serde(default)]
     pub(crate) order: bool,
 }
 
+fn default_topic_filter_type() -> TopicFilterType {
+    TopicFilterType::SingleTag
+}
+
 impl TopicConfig {
     pub fn new<S: Into<String>>(topic_name: S) -> Self {
         Self {
@@ -92,4 +185,447 @@ impl TopicConfig {
             order: false,
         }
     }
+
+    /// Number of read and write queues on every broker, defaults to 16
+    pub fn set_queue_nums(&mut self, read_queue_nums: u32, write_queue_nums: u32) -> &mut Self {
+        self.read_queue_nums = read_queue_nums;
+        self.write_queue_nums = write_queue_nums;
+        self
+    }
+
+    /// Defaults to read and write
+    pub fn set_permission(&mut self, permission: Permission) -> &mut Self {
+        self.permission = permission;
+        self
+    }
+
+    /// Mark the topic as ordered, disabled by default
+    pub fn set_order(&mut self, order: bool) -> &mut Self {
+        self.order = order;
+        self
+    }
+}
+
+/// Topic configs of a broker by topic name
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TopicConfigTable {
+    #[serde(rename = "topicConfigTable", default)]
+    pub topic_config_table: HashMap<String, TopicConfig>,
+}
+
+impl TopicConfigTable {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fastjson::from_slice(bytes)
+    }
+}
+
+/// Consumer group created by `MqAdmin::create_subscription_group`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SubscriptionGroupConfig {
+    #[serde(rename = "groupName")]
+    pub(crate) group_name: String,
+    #[serde(rename = "consumeEnable")]
+    pub(crate) consume_enable: bool,
+    #[serde(rename = "consumeFromMinEnable")]
+    pub(crate) consume_from_min_enable: bool,
+    #[serde(rename = "consumeBroadcastEnable")]
+    pub(crate) consume_broadcast_enable: bool,
+    #[serde(rename = "retryQueueNums")]
+    pub(crate) retry_queue_nums: i32,
+    #[serde(rename = "retryMaxTimes")]
+    pub(crate) retry_max_times: i32,
+    #[serde(rename = "brokerId")]
+    pub(crate) broker_id: i64,
+    #[serde(rename = "whichBrokerWhenConsumeSlowly")]
+    pub(crate) which_broker_when_consume_slowly: i64,
+    #[serde(rename = "notifyConsumerIdsChangedEnable")]
+    pub(crate) notify_consumer_ids_changed_enable: bool,
+}
+
+impl SubscriptionGroupConfig {
+    pub fn new<S: Into<String>>(group_name: S) -> Self {
+        Self {
+            group_name: group_name.into(),
+            consume_enable: true,
+            consume_from_min_enable: true,
+            consume_broadcast_enable: true,
+            retry_queue_nums: 1,
+            retry_max_times: 16,
+            broker_id: 0,
+            which_broker_when_consume_slowly: 1,
+            notify_consumer_ids_changed_enable: true,
+        }
+    }
+
+    pub fn group_name(&self) -> &str {
+        &self.group_name
+    }
+
+    /// Allow the group to consume, enabled by default
+    pub fn set_consume_enable(&mut self, enable: bool) -> &mut Self {
+        self.consume_enable = enable;
+        self
+    }
+
+    /// Allow the group to start consuming from the minimum offset, enabled by default
+    pub fn set_consume_from_min_enable(&mut self, enable: bool) -> &mut Self {
+        self.consume_from_min_enable = enable;
+        self
+    }
+
+    /// Allow the group to consume in broadcasting mode, enabled by default
+    pub fn set_consume_broadcast_enable(&mut self, enable: bool) -> &mut Self {
+        self.consume_broadcast_enable = enable;
+        self
+    }
+
+    /// Number of queues of the retry topic, defaults to 1
+    pub fn set_retry_queue_nums(&mut self, nums: i32) -> &mut Self {
+        self.retry_queue_nums = nums;
+        self
+    }
+
+    /// Times a message is redelivered before it goes to the dead letter queue, defaults to 16
+    pub fn set_retry_max_times(&mut self, times: i32) -> &mut Self {
+        self.retry_max_times = times;
+        self
+    }
+
+    /// Broker to pull from, and the one to switch to when consuming falls behind,
+    /// defaults to the master and the first slave
+    pub fn set_broker_ids(&mut self, broker_id: i64, when_consume_slowly: i64) -> &mut Self {
+        self.broker_id = broker_id;
+        self.which_broker_when_consume_slowly = when_consume_slowly;
+        self
+    }
+
+    /// Notify consumers of the group when its members change, enabled by default
+    pub fn set_notify_consumer_ids_changed_enable(&mut self, enable: bool) -> &mut Self {
+        self.notify_consumer_ids_changed_enable = enable;
+        self
+    }
+}
+
+/// Offsets of a message queue on its broker
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub struct TopicOffset {
+    #[serde(rename = "minOffset")]
+    pub min_offset: i64,
+    #[serde(rename = "maxOffset")]
+    pub max_offset: i64,
+    /// Store timestamp in milliseconds of the last message, 0 if the queue is empty
+    #[serde(default, rename = "lastUpdateTimestamp")]
+    pub last_update_timestamp: i64,
+}
+
+/// Offsets of the queues of a topic, returned by `MqAdmin::topic_stats`
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct TopicStatsTable {
+    #[serde(
+        default,
+        rename = "offsetTable",
+        deserialize_with = "fastjson::deserialize_object_key_map"
+    )]
+    pub offset_table: HashMap<MessageQueue, TopicOffset>,
+}
+
+impl TopicStatsTable {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fastjson::from_slice_with_object_keys(bytes)
+    }
+
+    /// Number of messages stored in all queues
+    pub fn total_messages(&self) -> i64 {
+        self.offset_table
+            .values()
+            .map(|offset| offset.max_offset - offset.min_offset)
+            .sum()
+    }
+}
+
+/// Broker and consumer offsets of a message queue
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub struct OffsetWrapper {
+    #[serde(rename = "brokerOffset")]
+    pub broker_offset: i64,
+    #[serde(rename = "consumerOffset")]
+    pub consumer_offset: i64,
+    /// Store timestamp in milliseconds of the last consumed message, 0 if unknown
+    #[serde(default, rename = "lastTimestamp")]
+    pub last_timestamp: i64,
+}
+
+/// Consume progress of a consumer group, returned by `MqAdmin::consume_stats`
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct ConsumeStats {
+    #[serde(default, rename = "consumeTps")]
+    pub consume_tps: f64,
+    #[serde(
+        default,
+        rename = "offsetTable",
+        deserialize_with = "fastjson::deserialize_object_key_map"
+    )]
+    pub offset_table: HashMap<MessageQueue, OffsetWrapper>,
+}
+
+impl ConsumeStats {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fastjson::from_slice_with_object_keys(bytes)
+    }
+
+    /// Number of messages not consumed yet in all queues
+    pub fn total_diff(&self) -> i64 {
+        self.offset_table
+            .values()
+            .map(|offset| offset.broker_offset - offset.consumer_offset)
+            .sum()
+    }
+}
+
+/// Connection of an online client
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ClientConnection {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    #[serde(rename = "clientAddr")]
+    pub client_addr: String,
+    /// `OTHER` for languages unknown to this crate, like `NODE_JS` of newer clients
+    #[serde(deserialize_with = "deserialize_language")]
+    pub language: LanguageCode,
+    /// Ordinal of the client version
+    pub version: i32,
+}
+
+fn deserialize_language<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<LanguageCode, D::Error> {
+    let language = String::deserialize(deserializer)?;
+    Ok(language.parse().unwrap_or(LanguageCode::OTHER))
+}
+
+/// Online consumers of a group, returned by `MqAdmin::consumer_connections`
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ConsumerConnection {
+    #[serde(default, rename = "connectionSet")]
+    pub connection_set: Vec<ClientConnection>,
+    /// Subscriptions by topic
+    #[serde(default, rename = "subscriptionTable")]
+    pub subscription_table: HashMap<String, SubscriptionData>,
+    #[serde(rename = "consumeType")]
+    pub consume_type: String,
+    #[serde(rename = "messageModel")]
+    pub message_model: String,
+    #[serde(rename = "consumeFromWhere")]
+    pub consume_from_where: String,
+}
+
+impl ConsumerConnection {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fastjson::from_slice(bytes)
+    }
+}
+
+/// Response body of resetting consumer offsets, the new offset of every queue
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ResetOffsetBody {
+    #[serde(
+        default,
+        rename = "offsetTable",
+        deserialize_with = "fastjson::deserialize_object_key_map"
+    )]
+    pub offset_table: HashMap<MessageQueue, i64>,
+}
+
+impl ResetOffsetBody {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fastjson::from_slice_with_object_keys(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+    use crate::message::MessageQueue;
+
+    #[test]
+    fn test_lock_batch_body() {
+        let mq = MessageQueue {
+            topic: "topic".to_string(),
+            broker_name: "broker-a".to_string(),
+            queue_id: 1,
+        };
+        let body = LockBatchRequestBody {
+            consumer_group: "group".to_string(),
+            client_id: "client".to_string(),
+            mq_set: HashSet::from([mq.clone()]),
+        };
+        let json: serde_json::Value = serde_json::to_value(&body).unwrap();
+        assert_eq!("group", json["consumerGroup"]);
+        assert_eq!("client", json["clientId"]);
+        assert_eq!("broker-a", json["mqSet"][0]["brokerName"]);
+
+        let res = LockBatchResponseBody::from_bytes(
+            br#"{"lockOKMQSet":[{"brokerName":"broker-a","queueId":1,"topic":"topic"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(HashSet::from([mq]), res.lock_ok_mq_set);
+        assert!(LockBatchResponseBody::from_bytes(b"")
+            .unwrap()
+            .lock_ok_mq_set
+            .is_empty());
+        assert!(LockBatchResponseBody::from_bytes(b"{}")
+            .unwrap()
+            .lock_ok_mq_set
+            .is_empty());
+    }
+
+    #[test]
+    fn test_topic_stats_table() {
+        let body = br#"{"offsetTable":{{"brokerName":"broker-a","queueId":0,"topic":"topic"}:{"lastUpdateTimestamp":1700000000000,"maxOffset":10,"minOffset":2},{"brokerName":"broker-a","queueId":1,"topic":"topic"}:{"lastUpdateTimestamp":0,"maxOffset":0,"minOffset":0}}}"#;
+        let stats = TopicStatsTable::from_bytes(body).unwrap();
+        let mq = MessageQueue {
+            topic: "topic".to_string(),
+            broker_name: "broker-a".to_string(),
+            queue_id: 0,
+        };
+        assert_eq!(2, stats.offset_table.len());
+        assert_eq!(
+            TopicOffset {
+                min_offset: 2,
+                max_offset: 10,
+                last_update_timestamp: 1_700_000_000_000,
+            },
+            stats.offset_table[&mq]
+        );
+        assert_eq!(8, stats.total_messages());
+        assert!(TopicStatsTable::from_bytes(br#"{"offsetTable":{}}"#)
+            .unwrap()
+            .offset_table
+            .is_empty());
+    }
+
+    #[test]
+    fn test_consume_stats() {
+        let body = br#"{"consumeTps":1.5,"offsetTable":{{"brokerName":"broker-a","queueId":0,"topic":"topic"}:{"brokerOffset":10,"consumerOffset":4,"lastTimestamp":1700000000000},{"brokerName":"broker-b","queueId":0,"topic":"topic"}:{"brokerOffset":3,"consumerOffset":3,"lastTimestamp":0}}}"#;
+        let stats = ConsumeStats::from_bytes(body).unwrap();
+        assert_eq!(1.5, stats.consume_tps);
+        assert_eq!(2, stats.offset_table.len());
+        assert_eq!(6, stats.total_diff());
+        let mq = MessageQueue {
+            topic: "topic".to_string(),
+            broker_name: "broker-a".to_string(),
+            queue_id: 0,
+        };
+        assert_eq!(1_700_000_000_000, stats.offset_table[&mq].last_timestamp);
+    }
+
+    #[test]
+    fn test_reset_offset_body() {
+        let body =
+            br#"{"offsetTable":{{"brokerName":"broker-a","queueId":3,"topic":"topic"}:128}}"#;
+        let offsets = ResetOffsetBody::from_bytes(body).unwrap().offset_table;
+        let mq = MessageQueue {
+            topic: "topic".to_string(),
+            broker_name: "broker-a".to_string(),
+            queue_id: 3,
+        };
+        assert_eq!(HashMap::from([(mq, 128)]), offsets);
+    }
+
+    #[test]
+    fn test_consumer_connection() {
+        let body = br#"{"connectionSet":[{"clientAddr":"10.0.0.5:52344","clientId":"10.0.0.5@1","language":"JAVA","version":395}],"consumeFromWhere":"CONSUME_FROM_LAST_OFFSET","consumeType":"CONSUME_PASSIVELY","messageModel":"CLUSTERING","subscriptionTable":{"topic":{"classFilterMode":false,"codeSet":[2598919],"expressionType":"TAG","subString":"a","subVersion":1700000000000,"tagsSet":["a"],"topic":"topic"}}}"#;
+        let connection = ConsumerConnection::from_bytes(body).unwrap();
+        assert_eq!(
+            vec![ClientConnection {
+                client_id: "10.0.0.5@1".to_string(),
+                client_addr: "10.0.0.5:52344".to_string(),
+                language: LanguageCode::JAVA,
+                version: 395,
+            }],
+            connection.connection_set
+        );
+        let subscription = &connection.subscription_table["topic"];
+        assert_eq!("a", subscription.sub_string);
+
+        let body = br#"{"connectionSet":[{"clientAddr":"10.0.0.6:52344","clientId":"10.0.0.6@1","language":"NODE_JS","version":413}],"consumeFromWhere":"CONSUME_FROM_LAST_OFFSET","consumeType":"CONSUME_PASSIVELY","messageModel":"CLUSTERING","subscriptionTable":{}}"#;
+        let connection = ConsumerConnection::from_bytes(body).unwrap();
+        assert_eq!(LanguageCode::OTHER, connection.connection_set[0].language);
+        assert!(subscription.tags_set.contains("a"));
+        assert_eq!("CLUSTERING", connection.message_model);
+    }
+
+    #[test]
+    fn test_subscription_group_config() {
+        let mut config = SubscriptionGroupConfig::new("group");
+        config
+            .set_retry_max_times(3)
+            .set_consume_broadcast_enable(false);
+        let json: serde_json::Value = serde_json::to_value(&config).unwrap();
+        assert_eq!("group", json["groupName"]);
+        assert_eq!(3, json["retryMaxTimes"]);
+        assert_eq!(false, json["consumeBroadcastEnable"]);
+        assert_eq!(1, json["whichBrokerWhenConsumeSlowly"]);
+        assert_eq!(
+            config,
+            crate::fastjson::from_slice(&crate::fastjson::to_vec(&config).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_command() {
+        let heartbeat = HeartbeatData {
+            client_id: "127.0.0.1@1".to_string(),
+            producer_data_set: vec![ProducerData {
+                group_name: "producer".to_string(),
+            }],
+            consumer_data_set: vec![ConsumerData {
+                group_name: "consumer".to_string(),
+                consumer_type: "CONSUME_PASSIVELY".to_string(),
+                message_model: "CLUSTERING".to_string(),
+                consume_from_where: "CONSUME_FROM_LAST_OFFSET".to_string(),
+                subscription_data_set: vec![SubscriptionData {
+                    class_filter_mode: false,
+                    topic: "topic".to_string(),
+                    sub_string: "*".to_string(),
+                    tags_set: HashSet::new(),
+                    code_set: HashSet::new(),
+                    sub_version: 1,
+                    expression_type: "TAG".to_string(),
+                }],
+                unit_mode: false,
+            }],
+        };
+        let cmd = heartbeat.into_command().unwrap();
+        assert_eq!(RequestCode::Heartbeat as i16, cmd.code());
+        let json: serde_json::Value = serde_json::from_slice(cmd.body()).unwrap();
+        assert_eq!("127.0.0.1@1", json["clientID"]);
+        assert_eq!("producer", json["producerDataSet"][0]["groupName"]);
+        let consumer = &json["consumerDataSet"][0];
+        assert_eq!("CONSUME_PASSIVELY", consumer["consumeType"]);
+        assert_eq!("CLUSTERING", consumer["messageModel"]);
+        assert_eq!("TAG", consumer["subscriptionDataSet"][0]["expressionType"]);
+        assert_eq!(false, consumer["unitMode"]);
+    }
+
+    #[test]
+    fn test_heartbeat_broker_version() {
+        let mut res = RemotingCommand::new_response(
+            ResponseCode::Success,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        res.header.version = 397;
+        assert_eq!(397, HeartbeatData::broker_version(&res).unwrap());
+        let res = RemotingCommand::new_response(
+            ResponseCode::SystemError,
+            "error".to_string(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        assert!(HeartbeatData::broker_version(&res).is_err());
+    }
 }
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tokio::sync::{mpsc, Notify};
+use tracing::{info, warn};
+
+use crate::message::{MessageExt, Property};
+use crate::producer::{LocalTransactionState, ProducerInner};
+use crate::protocol::request::{
+    CheckTransactionStateRequestHeader, DecodeRequestHeader, EndTransactionRequestHeader,
+};
+use crate::protocol::RemotingCommand;
+use crate::remoting::RequestProcessor;
+
+/// Rebalance consumers right away when a broker notifies that the consumers of
+/// a group changed, instead of waiting for the next scheduled rebalance
+pub(crate) struct ConsumerIdsChangedProcessor {
+    rebalance: Arc<Notify>,
+}
+
+impl ConsumerIdsChangedProcessor {
+    pub fn new(rebalance: Arc<Notify>) -> Self {
+        Self { rebalance }
+    }
+}
+
+#[async_trait]
+impl RequestProcessor for ConsumerIdsChangedProcessor {
+    async fn process(&self, addr: &str, request: RemotingCommand) -> Option<RemotingCommand> {
+        info!(
+            broker = %addr,
+            group = request.ext_fields().get("consumerGroup").map(String::as_str).unwrap_or_default(),
+            "consumer ids changed, rebalance immediately"
+        );
+        self.rebalance.notify_one();
+        None
+    }
+}
+
+/// Answer transaction state checks of brokers with the checker of the producer
+/// group of the prepared message
+///
+/// The end transaction requests are sent by the client along with the broker address.
+pub(crate) struct CheckTransactionStateProcessor {
+    producers: Arc<Mutex<HashMap<String, Arc<Mutex<ProducerInner>>>>>,
+    end_transactions: mpsc::UnboundedSender<(String, RemotingCommand)>,
+}
+
+impl CheckTransactionStateProcessor {
+    pub fn new(
+        producers: Arc<Mutex<HashMap<String, Arc<Mutex<ProducerInner>>>>>,
+        end_transactions: mpsc::UnboundedSender<(String, RemotingCommand)>,
+    ) -> Self {
+        Self {
+            producers,
+            end_transactions,
+        }
+    }
+}
+
+#[async_trait]
+impl RequestProcessor for CheckTransactionStateProcessor {
+    async fn process(&self, addr: &str, request: RemotingCommand) -> Option<RemotingCommand> {
+        let header = match CheckTransactionStateRequestHeader::decode(request.ext_fields()) {
+            Ok(header) => header,
+            Err(err) => {
+                warn!(broker = %addr, error = %err, "invalid transaction state check");
+                return None;
+            }
+        };
+        let msg = match MessageExt::decode(&request.body).into_iter().next() {
+            Some(msg) => msg,
+            None => {
+                warn!(broker = %addr, "transaction state check without a message");
+                return None;
+            }
+        };
+        let group = msg
+            .message
+            .get_property(Property::PRODUCER_GROUP)
+            .cloned()
+            .unwrap_or_default();
+        let producer = self.producers.lock().get(&group).cloned();
+        let checker = match producer.and_then(|producer| producer.lock().transaction_checker()) {
+            Some(checker) => checker,
+            None => {
+                warn!(broker = %addr, group = %group, "no transaction checker of producer group");
+                return None;
+            }
+        };
+        let (msg, state) = match tokio::task::spawn_blocking(move || {
+            let state = checker(&msg);
+            (msg, state)
+        })
+        .await
+        {
+            Ok(res) => res,
+            Err(err) => {
+                warn!("transaction checker panicked: {:?}", err);
+                return None;
+            }
+        };
+        if state == LocalTransactionState::Unknown {
+            return None;
+        }
+        let header = EndTransactionRequestHeader {
+            producer_group: group,
+            tran_state_table_offset: msg.queue_offset,
+            commit_log_offset: msg.commit_log_offset,
+            commit_or_rollback: state.sys_flag(),
+            from_transaction_check: true,
+            msg_id: msg.message.unique_key().unwrap_or(&msg.msg_id).to_string(),
+            transaction_id: Some(header.transaction_id),
+        };
+        let _ = self.end_transactions.send((
+            addr.to_string(),
+            header.into_command("checked by the transaction checker".to_string()),
+        ));
+        None
+    }
+}
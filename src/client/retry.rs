@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use bitflags::bitflags;
+use rand::Rng;
+
+use crate::error::ErrorKind;
+use crate::protocol::RequestCode;
+use crate::Error;
+
+bitflags! {
+    /// Error classes retried by a `RetryPolicy`
+    pub struct RetryOn: u8 {
+        /// Failed to connect or the connection was lost
+        const CONNECTION = 0x1 << 0;
+        /// Request timed out
+        const TIMEOUT = 0x1 << 1;
+        /// Server responded with system busy or service not available
+        const BUSY = 0x1 << 2;
+        /// Broker is no longer the leader of the queue, only retried by sends which
+        /// pick a queue again from the refreshed route
+        const NOT_LEADER = 0x1 << 3;
+    }
+}
+
+/// Retry policy of idempotent requests like route, offset queries and heartbeats,
+/// and of message sends
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    retry_on: RetryOn,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            jitter: true,
+            retry_on: RetryOn::all(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy which never retries
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Maximum number of attempts including the first one, defaults to 3
+    pub fn set_max_attempts(&mut self, attempts: usize) -> &mut Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Delay before the first retry, doubled on every further retry, defaults to 100ms
+    pub fn set_base_delay(&mut self, delay: Duration) -> &mut Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Upper bound of the delay between retries, defaults to 2s
+    pub fn set_max_delay(&mut self, delay: Duration) -> &mut Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Randomize delays between half and all of the computed delay, enabled by default
+    pub fn set_jitter(&mut self, jitter: bool) -> &mut Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Error classes to retry on, defaults to all of them
+    pub fn set_retry_on(&mut self, retry_on: RetryOn) -> &mut Self {
+        self.retry_on = retry_on;
+        self
+    }
+
+    pub(crate) fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Delay before retry number `retry`, starting at 1
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if self.jitter && !delay.is_zero() {
+            let half = delay / 2;
+            half + rand::thread_rng().gen_range(Duration::ZERO..=delay - half)
+        } else {
+            delay
+        }
+    }
+
+    /// Errors retriable by `Error::is_retriable` whose class is enabled
+    fn is_retriable_kind(&self, kind: ErrorKind) -> bool {
+        match kind {
+            ErrorKind::Timeout => self.retry_on.contains(RetryOn::TIMEOUT),
+            ErrorKind::Disconnected => self.retry_on.contains(RetryOn::CONNECTION),
+            ErrorKind::BrokerBusy => self.retry_on.contains(RetryOn::BUSY),
+            ErrorKind::NotLeader => self.retry_on.contains(RetryOn::NOT_LEADER),
+            ErrorKind::Fatal => false,
+        }
+    }
+
+    pub(crate) fn is_retriable_error(&self, err: &Error) -> bool {
+        self.is_retriable_kind(err.kind())
+    }
+
+    pub(crate) fn is_retriable_response(&self, code: i16) -> bool {
+        self.is_retriable_kind(ErrorKind::from_response_code(code))
+    }
+}
+
+/// Whether a request can be sent again without side effects
+pub(crate) fn is_idempotent(code: i16) -> bool {
+    matches!(
+        RequestCode::try_from(code),
+        Ok(RequestCode::GetRouteInfoByTopic
+            | RequestCode::GetBrokerClusterInfo
+            | RequestCode::QueryConsumerOffset
+            | RequestCode::QueryBrokerOffset
+            | RequestCode::GetMaxOffset
+            | RequestCode::GetMinOffset
+            | RequestCode::GetEarliestMsgStoreTime
+            | RequestCode::SearchOffsetByTimestamp
+            | RequestCode::GetConsumerListByGroup
+            | RequestCode::GetTopicStatsInfo
+            | RequestCode::GetAllTopicConfig
+            | RequestCode::GetConsumeStats
+            | RequestCode::GetConsumerConnectionList
+            | RequestCode::QueryMessage
+            | RequestCode::ViewMessageById
+            | RequestCode::Heartbeat)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{is_idempotent, RetryOn, RetryPolicy};
+    use crate::error::ConnectionError;
+    use crate::protocol::{RequestCode, ResponseCode};
+    use crate::Error;
+
+    #[test]
+    fn test_backoff() {
+        let mut policy = RetryPolicy::default();
+        policy
+            .set_base_delay(Duration::from_millis(100))
+            .set_max_delay(Duration::from_millis(300))
+            .set_jitter(false);
+        assert_eq!(Duration::from_millis(100), policy.backoff(1));
+        assert_eq!(Duration::from_millis(200), policy.backoff(2));
+        assert_eq!(Duration::from_millis(300), policy.backoff(3));
+        assert_eq!(Duration::from_millis(300), policy.backoff(40));
+
+        policy.set_jitter(true);
+        for _ in 0..10 {
+            let delay = policy.backoff(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn test_retriable() {
+        let mut policy = RetryPolicy::default();
+        assert!(policy.is_retriable_error(&Error::Connection(ConnectionError::Disconnected)));
+        assert!(policy.is_retriable_error(&Error::Io(io::ErrorKind::TimedOut.into())));
+        assert!(!policy.is_retriable_error(&Error::EmptyRouteData));
+        assert!(policy.is_retriable_response(2));
+        assert!(policy.is_retriable_response(ResponseCode::NotLeaderForQueue.into()));
+        assert!(!policy.is_retriable_response(0));
+        assert!(!policy.is_retriable_response(1));
+
+        policy.set_retry_on(RetryOn::CONNECTION);
+        assert!(!policy.is_retriable_error(&Error::Io(io::ErrorKind::TimedOut.into())));
+        assert!(!policy.is_retriable_error(&Error::Connection(ConnectionError::Timeout)));
+        assert!(!policy.is_retriable_response(2));
+        assert!(!policy.is_retriable_response(ResponseCode::NotLeaderForQueue.into()));
+
+        assert!(is_idempotent(RequestCode::Heartbeat.into()));
+        assert!(!is_idempotent(RequestCode::SendMessage.into()));
+    }
+}
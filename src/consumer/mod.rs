@@ -1,35 +1,58 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use parking_lot::Mutex;
-use tracing::error;
+use parking_lot::{Mutex, RwLock};
+use time::OffsetDateTime;
+use tokio::runtime::Handle;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, warn};
 
-use crate::client::{Client, ClientOptions};
-use crate::message::MessageQueue;
-use crate::namesrv::NameServer;
+use crate::client::model::{
+    ConsumerData, LockBatchRequestBody, LockBatchResponseBody, SubscriptionData,
+    UnlockBatchRequestBody,
+};
+use crate::client::{Client, ClientMetrics, ClientOptions, Credentials, MQClientManager};
+use crate::fastjson;
+use crate::message::{MessageExt, MessageQueue};
+use crate::namespace::{without_namespace, wrap_namespace};
+use crate::namesrv::FindBrokerResult;
 use crate::protocol::{
     request::{
-        GetConsumerListRequestHeader, GetMaxOffsetRequestHeader,
-        SearchOffsetByTimestampRequestHeader,
+        GetConsumerListRequestHeader, GetEarliestMsgStoreTimeRequestHeader,
+        GetMaxOffsetRequestHeader, GetMinOffsetRequestHeader, SearchOffsetRequestHeader,
     },
     RemotingCommand, RequestCode, ResponseCode,
 };
+use crate::remoting::{ConnectionEvent, RpcHook};
 use crate::resolver::{HttpResolver, PassthroughResolver, Resolver};
+use crate::route::TopicRouteData;
 use crate::Error;
 
 mod offset_store;
 mod process_queue;
 mod push;
+mod stats;
 /// Message queue allocation strategy
 pub mod strategy;
 
-use offset_store::{LocalFileOffsetStore, OffsetStorage, RemoteBrokerOffsetStore};
+use offset_store::{
+    LocalFileOffsetStore, OffsetStorage, OffsetStore, ReadType, RemoteBrokerOffsetStore,
+};
 use process_queue::ProcessQueue;
 pub use push::PushConsumer;
+use stats::ConsumerStats;
+pub use stats::{ConsumerLag, ConsumerMetrics, QueueMetrics, TopicMetrics};
 use strategy::{AllocateAveragely, AllocateStrategy};
 
+pub(crate) const RETRY_GROUP_TOPIC_PREFIX: &str = "%RETRY%";
+const SUBSCRIBE_ALL: &str = "*";
+const DEFAULT_CONSUMER_GROUP: &str = "DEFAULT_CONSUMER";
+/// Max messages of one broker returned by a unique key query
+const UNIQUE_KEY_QUERY_MAX_NUM: i32 = 32;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MessageModel {
     BroadCasting,
@@ -53,6 +76,27 @@ pub enum ConsumeFrom {
     Timestamp,
 }
 
+impl fmt::Display for ConsumeFrom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsumeFrom::LastOffset => write!(f, "CONSUME_FROM_LAST_OFFSET"),
+            ConsumeFrom::FirstOffset => write!(f, "CONSUME_FROM_FIRST_OFFSET"),
+            ConsumeFrom::Timestamp => write!(f, "CONSUME_FROM_TIMESTAMP"),
+        }
+    }
+}
+
+/// Message delivery semantics of push consumers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliverySemantics {
+    /// Commit offsets before dispatching messages to the listener,
+    /// messages failed to consume or lost in a crash are never redelivered
+    AtMostOnce,
+    /// Commit offsets after messages are consumed successfully,
+    /// failed messages are redelivered and a crash may cause duplicates
+    AtLeastOnce,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExpressionType {
     Sql92,
@@ -68,6 +112,37 @@ impl fmt::Display for ExpressionType {
     }
 }
 
+/// Message selector used when subscribing a topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageSelector {
+    pub(crate) expression_type: ExpressionType,
+    pub(crate) expression: String,
+}
+
+impl MessageSelector {
+    /// Select messages by tags, e.g. `TagA || TagB`, `*` selects all messages
+    pub fn tag<S: Into<String>>(expression: S) -> Self {
+        Self {
+            expression_type: ExpressionType::Tag,
+            expression: expression.into(),
+        }
+    }
+
+    /// Select messages by a SQL92 expression on message properties
+    pub fn sql92<S: Into<String>>(expression: S) -> Self {
+        Self {
+            expression_type: ExpressionType::Sql92,
+            expression: expression.into(),
+        }
+    }
+}
+
+impl Default for MessageSelector {
+    fn default() -> Self {
+        Self::tag(SUBSCRIBE_ALL)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ConsumerOptions {
     client_options: ClientOptions,
@@ -77,6 +152,13 @@ pub struct ConsumerOptions {
     message_model: MessageModel,
     consume_from: ConsumeFrom,
     auto_commit: bool,
+    pull_batch_size: i32,
+    pull_interval: Duration,
+    pull_threshold_for_queue: usize,
+    consume_message_batch_max_size: usize,
+    await_termination: Duration,
+    delivery_semantics: DeliverySemantics,
+    consume_orderly: bool,
 }
 
 impl Default for ConsumerOptions {
@@ -89,11 +171,105 @@ impl Default for ConsumerOptions {
             message_model: MessageModel::Clustering,
             consume_from: ConsumeFrom::LastOffset,
             auto_commit: true,
+            pull_batch_size: 32,
+            pull_interval: Duration::from_secs(0),
+            pull_threshold_for_queue: 1024,
+            consume_message_batch_max_size: 1,
+            await_termination: Duration::from_secs(5),
+            delivery_semantics: DeliverySemantics::AtLeastOnce,
+            consume_orderly: false,
         }
     }
 }
 
 impl ConsumerOptions {
+    pub fn new() -> Self {
+        ConsumerOptions::default()
+    }
+
+    pub fn with_client_options(client_options: ClientOptions) -> Self {
+        Self {
+            client_options,
+            ..Default::default()
+        }
+    }
+
+    pub fn group_name(&self) -> &str {
+        &self.client_options.group_name
+    }
+
+    /// Set ACL credentials
+    pub fn set_credentials(&mut self, credentials: Credentials) -> &mut Self {
+        self.client_options.set_credentials(credentials);
+        self
+    }
+
+    pub fn set_message_model(&mut self, message_model: MessageModel) -> &mut Self {
+        self.message_model = message_model;
+        self
+    }
+
+    pub fn set_consume_from(&mut self, consume_from: ConsumeFrom) -> &mut Self {
+        self.consume_from = consume_from;
+        self
+    }
+
+    pub fn set_max_reconsume_times(&mut self, times: i32) -> &mut Self {
+        self.max_reconsume_times = times;
+        self
+    }
+
+    pub fn set_pull_batch_size(&mut self, size: i32) -> &mut Self {
+        self.pull_batch_size = size;
+        self
+    }
+
+    pub fn set_pull_interval(&mut self, interval: Duration) -> &mut Self {
+        self.pull_interval = interval;
+        self
+    }
+
+    pub fn set_pull_threshold_for_queue(&mut self, threshold: usize) -> &mut Self {
+        self.pull_threshold_for_queue = threshold;
+        self
+    }
+
+    pub fn set_consume_message_batch_max_size(&mut self, size: usize) -> &mut Self {
+        self.consume_message_batch_max_size = size;
+        self
+    }
+
+    /// Defaults to `DeliverySemantics::AtLeastOnce`
+    pub fn set_delivery_semantics(&mut self, semantics: DeliverySemantics) -> &mut Self {
+        self.delivery_semantics = semantics;
+        self
+    }
+
+    /// Consume the messages of a queue one batch at a time in queue order, holding a
+    /// broker lock of the queue so that no other consumer of the group takes it over
+    ///
+    /// Only applies to the clustering model. Failed messages are consumed again
+    /// locally until `max_reconsume_times` is exceeded and then sent to the dead
+    /// letter queue, offsets are committed after messages are consumed regardless
+    /// of the delivery semantics.
+    pub fn set_consume_orderly(&mut self, orderly: bool) -> &mut Self {
+        self.consume_orderly = orderly;
+        self
+    }
+
+    /// Interval of persisting consumer offsets, defaults to 5 seconds
+    pub fn set_persist_consumer_offset_interval(&mut self, interval: Duration) -> &mut Self {
+        self.client_options
+            .set_persist_consumer_offset_interval(interval);
+        self
+    }
+
+    /// Max time to wait for in-flight message listeners when shutting down
+    pub fn set_await_termination(&mut self, timeout: Duration) -> &mut Self {
+        self.await_termination = timeout;
+        self
+    }
+
     pub fn set_resolver(&mut self, resolver: Resolver) -> &mut Self {
         self.resolver = resolver;
         self
@@ -149,64 +325,510 @@ impl fmt::Display for ConsumeType {
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct ConsumerInner {}
+pub(crate) type MessageListener = Arc<dyn Fn(&[MessageExt]) -> ConsumeResult + Send + Sync>;
 
-impl ConsumerInner {
-    pub fn rebalance(&self) {
-        todo!()
-    }
-}
-
-#[derive(Debug)]
-pub struct Consumer {
-    consumer_group: String,
-    inner: Arc<Mutex<ConsumerInner>>,
+pub(crate) struct ConsumerInner {
+    group: String,
     options: ConsumerOptions,
-    client: Client<Resolver>,
+    pub(crate) client: Client<Resolver>,
     storage: OffsetStorage,
     allocate: AllocateStrategy,
-    process_queue_map: HashMap<MessageQueue, ProcessQueue>,
+    // topic -> SubscriptionData
+    subscriptions: Mutex<HashMap<String, SubscriptionData>>,
+    // topic -> message listener
+    listeners: RwLock<HashMap<String, MessageListener>>,
+    // topic -> subscribed message queues
+    topic_subscribe_info: Mutex<HashMap<String, Vec<MessageQueue>>>,
+    process_queue_table: Mutex<HashMap<MessageQueue, Arc<ProcessQueue>>>,
+    stats: ConsumerStats,
+    // number of in-flight message listener invocations
+    inflight: AtomicUsize,
+    shutting_down: AtomicBool,
+    rebalance_lock: tokio::sync::Mutex<()>,
+    // serializes start and shutdown
+    lifecycle_lock: tokio::sync::Mutex<()>,
 }
 
-impl Consumer {
-    pub fn new() -> Result<Self, Error> {
-        Self::with_options(ConsumerOptions::default())
+impl fmt::Debug for ConsumerInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsumerInner")
+            .field("group", &self.group)
+            .field("options", &self.options)
+            .field("storage", &self.storage)
+            .field("allocate", &self.allocate)
+            .field("subscriptions", &self.subscriptions)
+            .finish()
     }
+}
 
-    pub fn with_options(options: ConsumerOptions) -> Result<Self, Error> {
-        let client_options = options.client_options.clone();
-        let inner = Arc::new(Mutex::new(ConsumerInner {}));
-        let name_server =
-            NameServer::new(options.resolver.clone(), client_options.credentials.clone())?;
-        let client = Client::new(client_options, name_server);
-        let consumer_group = &options.client_options.group_name;
-        let offset_store = match options.message_model {
-            MessageModel::Clustering => OffsetStorage::RemoteBroker(RemoteBrokerOffsetStore::new(
-                consumer_group,
-                client.clone(),
-            )),
+impl ConsumerInner {
+    fn new(mut options: ConsumerOptions) -> Result<Self, Error> {
+        options.client_options.make_instance_name_unique();
+        let client = MQClientManager::get_or_create_client(
+            options.client_options.clone(),
+            options.resolver.clone(),
+        )?;
+        let group = wrap_namespace(
+            &options.client_options.resource_namespace(),
+            &options.client_options.group_name,
+        );
+        let storage = match options.message_model {
+            MessageModel::Clustering => {
+                OffsetStorage::RemoteBroker(RemoteBrokerOffsetStore::new(&group, client.clone()))
+            }
             MessageModel::BroadCasting => {
-                OffsetStorage::LocalFile(LocalFileOffsetStore::new(consumer_group, &client.id()))
+                OffsetStorage::LocalFile(LocalFileOffsetStore::new(&group, &client.id()))
             }
         };
         Ok(Self {
-            consumer_group: consumer_group.clone(),
-            inner,
+            stats: ConsumerStats::new(&group),
+            group,
             options,
             client,
-            storage: offset_store,
+            storage,
             allocate: AllocateStrategy::Averagely(AllocateAveragely),
-            process_queue_map: HashMap::new(),
+            subscriptions: Mutex::new(HashMap::new()),
+            listeners: RwLock::new(HashMap::new()),
+            topic_subscribe_info: Mutex::new(HashMap::new()),
+            process_queue_table: Mutex::new(HashMap::new()),
+            inflight: AtomicUsize::new(0),
+            shutting_down: AtomicBool::new(false),
+            rebalance_lock: tokio::sync::Mutex::new(()),
+            lifecycle_lock: tokio::sync::Mutex::new(()),
         })
     }
 
-    pub fn start(&self) {
-        self.client.start();
+    fn retry_topic(&self) -> String {
+        format!("{}{}", RETRY_GROUP_TOPIC_PREFIX, self.group)
+    }
+
+    /// Whether message queues are locked and consumed in order
+    pub(crate) fn is_orderly(&self) -> bool {
+        self.options.consume_orderly && self.options.message_model == MessageModel::Clustering
     }
 
-    pub fn shutdown(&self) {
-        self.client.shutdown();
+    /// Runtime to run pulls and message listeners on, the current one if unset
+    pub(crate) fn runtime(&self) -> Option<&Handle> {
+        self.options.client_options.runtime.as_ref()
+    }
+
+    /// Prefix a topic or group with the namespace of this consumer
+    pub(crate) fn wrap_namespace(&self, resource: &str) -> String {
+        wrap_namespace(&self.options.client_options.resource_namespace(), resource)
+    }
+
+    /// Strip the namespace of this consumer off a topic or group
+    pub(crate) fn without_namespace(&self, resource: &str) -> String {
+        without_namespace(&self.options.client_options.resource_namespace(), resource)
+    }
+
+    fn wrap_message_queue(&self, mq: &MessageQueue) -> MessageQueue {
+        MessageQueue {
+            topic: self.wrap_namespace(&mq.topic),
+            ..mq.clone()
+        }
+    }
+
+    /// Strip the namespace of this consumer off the topic of a fetched message
+    fn message_without_namespace(&self, mut msg: MessageExt) -> MessageExt {
+        msg.message.topic = self.without_namespace(&msg.message.topic);
+        msg
+    }
+
+    fn message_queue_without_namespace(&self, mq: MessageQueue) -> MessageQueue {
+        MessageQueue {
+            topic: self.without_namespace(&mq.topic),
+            ..mq
+        }
+    }
+
+    /// Subscribe `topic` in the namespace of this consumer, returns the wrapped topic
+    pub(crate) fn subscribe(&self, topic: &str, selector: MessageSelector) -> String {
+        let topic = self.wrap_namespace(topic);
+        let sub = build_subscription_data(&topic, &selector);
+        self.subscriptions.lock().insert(topic.clone(), sub);
+        topic
+    }
+
+    pub(crate) fn subscribed_topics(&self) -> Vec<String> {
+        self.subscriptions.lock().keys().cloned().collect()
+    }
+
+    pub(crate) fn consumer_data(&self) -> ConsumerData {
+        ConsumerData {
+            group_name: self.group.clone(),
+            consumer_type: ConsumeType::Passively.to_string(),
+            message_model: self.options.message_model.to_string().to_uppercase(),
+            consume_from_where: self.options.consume_from.to_string(),
+            subscription_data_set: self.subscriptions.lock().values().cloned().collect(),
+            unit_mode: self.options.client_options.unit_mode,
+        }
+    }
+
+    /// Export the buffered messages and estimated lag of a topic
+    fn record_topic_state(&self, topic: &str) {
+        let process_queues = self.process_queue_table.lock();
+        self.stats.record_topic_state(topic, &process_queues);
+    }
+
+    pub(crate) fn metrics(&self) -> ConsumerMetrics {
+        let process_queues = self.process_queue_table.lock().clone();
+        let metrics = self.stats.snapshot(&process_queues);
+        if self.options.client_options.resource_namespace().is_empty() {
+            return metrics;
+        }
+        ConsumerMetrics {
+            topics: metrics
+                .topics
+                .into_iter()
+                .map(|(topic, m)| (self.without_namespace(&topic), m))
+                .collect(),
+            queues: metrics
+                .queues
+                .into_iter()
+                .map(|(mq, m)| (self.message_queue_without_namespace(mq), m))
+                .collect(),
+        }
+    }
+
+    /// Refresh the cached message queues of a subscribed topic
+    pub(crate) fn update_topic_subscribe_info(&self, topic: &str, route_data: &TopicRouteData) {
+        if self.subscriptions.lock().contains_key(topic) {
+            let mqs = route_data.to_subscribe_message_queues(topic);
+            self.topic_subscribe_info
+                .lock()
+                .insert(topic.to_string(), mqs);
+        }
+    }
+
+    async fn topic_subscribe_info(&self, topic: &str) -> Result<Vec<MessageQueue>, Error> {
+        if let Some(mqs) = self.topic_subscribe_info.lock().get(topic) {
+            return Ok(mqs.clone());
+        }
+        let mqs = self
+            .client
+            .name_server
+            .fetch_subscribe_message_queues(topic)
+            .await?;
+        self.topic_subscribe_info
+            .lock()
+            .insert(topic.to_string(), mqs.clone());
+        Ok(mqs)
+    }
+
+    /// Rebalance message queues of all subscribed topics, returns the last error if any
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "rebalance", skip_all, fields(group = %self.group))
+    )]
+    pub(crate) async fn rebalance(self: &Arc<Self>) -> Result<(), Error> {
+        let _guard = self.rebalance_lock.lock().await;
+        if self.is_shutting_down() {
+            return Ok(());
+        }
+        if self.is_orderly() {
+            self.lock_all().await;
+        }
+        let mut last_error = None;
+        for topic in self.subscribed_topics() {
+            let mut mqs = match self.topic_subscribe_info(&topic).await {
+                Ok(mqs) => mqs,
+                Err(err) => {
+                    warn!(consumer_group = %self.group, topic = %topic, "fetch subscribe message queues error: {:?}", err);
+                    // retry topic is created by broker on first consume failure
+                    if topic != self.retry_topic() {
+                        last_error = Some(err);
+                    }
+                    continue;
+                }
+            };
+            let allocated = match self.options.message_model {
+                MessageModel::BroadCasting => mqs,
+                MessageModel::Clustering => {
+                    let mut cids = match self.get_consumer_list(&topic).await {
+                        Ok(cids) => cids,
+                        Err(err) => {
+                            warn!(consumer_group = %self.group, topic = %topic, "get consumer list error: {:?}", err);
+                            last_error = Some(err);
+                            continue;
+                        }
+                    };
+                    if cids.is_empty() {
+                        warn!(consumer_group = %self.group, topic = %topic, "no consumer id found, skip rebalance");
+                        continue;
+                    }
+                    mqs.sort();
+                    cids.sort();
+                    let cid_all: Vec<&str> = cids.iter().map(|cid| &cid[..]).collect();
+                    self.allocate
+                        .allocate(&self.group, &self.client.id(), &mqs, &cid_all)
+                }
+            };
+            self.update_process_queue_table(&topic, allocated).await;
+        }
+        match last_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    async fn update_process_queue_table(
+        self: &Arc<Self>,
+        topic: &str,
+        allocated: Vec<MessageQueue>,
+    ) {
+        let removed: Vec<(MessageQueue, Arc<ProcessQueue>)> = {
+            let mut table = self.process_queue_table.lock();
+            let removed: Vec<MessageQueue> = table
+                .keys()
+                .filter(|mq| mq.topic == topic && !allocated.contains(mq))
+                .cloned()
+                .collect();
+            removed
+                .into_iter()
+                .filter_map(|mq| table.remove(&mq).map(|pq| (mq, pq)))
+                .inspect(|(_, pq)| pq.set_dropped(true))
+                .collect()
+        };
+        for (mq, _) in &removed {
+            info!(consumer_group = %self.group, message_queue = ?mq, "message queue is no longer allocated, drop it");
+            self.storage.persist(std::slice::from_ref(mq)).await;
+            self.storage.remove(mq);
+            self.stats.remove(mq);
+            self.record_topic_state(&mq.topic);
+        }
+        let locked: Vec<MessageQueue> = removed
+            .into_iter()
+            .filter(|(_, pq)| pq.is_locked())
+            .map(|(mq, _)| mq)
+            .collect();
+        if !locked.is_empty() {
+            self.unlock_all(locked).await;
+        }
+        for mq in allocated {
+            if self.process_queue_table.lock().contains_key(&mq) {
+                continue;
+            }
+            let orderly = self.is_orderly();
+            if orderly {
+                let mq_set = HashSet::from([mq.clone()]);
+                match self.lock_batch(&mq.broker_name, mq_set).await {
+                    Ok(locked) if locked.contains(&mq) => {}
+                    Ok(_) => {
+                        warn!(consumer_group = %self.group, message_queue = ?mq, "message queue is locked by another consumer, skip it");
+                        continue;
+                    }
+                    Err(err) => {
+                        warn!(consumer_group = %self.group, message_queue = ?mq, "lock message queue error: {:?}", err);
+                        continue;
+                    }
+                }
+            }
+            self.storage.remove(&mq);
+            let next_offset = self.compute_pull_from_where(&mq).await;
+            if next_offset < 0 {
+                warn!(consumer_group = %self.group, message_queue = ?mq, "compute pull offset failed, skip it");
+                continue;
+            }
+            let pq = Arc::new(ProcessQueue::new());
+            pq.set_locked(orderly);
+            self.process_queue_table
+                .lock()
+                .insert(mq.clone(), Arc::clone(&pq));
+            info!(consumer_group = %self.group, message_queue = ?mq, next_offset = next_offset, "start pulling message queue");
+            self.start_pull(mq, pq, next_offset);
+        }
+    }
+
+    async fn compute_pull_from_where(&self, mq: &MessageQueue) -> i64 {
+        let last_offset = self.storage.read(mq, ReadType::Store).await;
+        if last_offset >= 0 {
+            return last_offset;
+        }
+        let is_retry_topic = mq.topic.starts_with(RETRY_GROUP_TOPIC_PREFIX);
+        let res = match self.options.consume_from {
+            ConsumeFrom::FirstOffset => Ok(0),
+            ConsumeFrom::LastOffset if is_retry_topic => Ok(0),
+            ConsumeFrom::LastOffset => self.get_max_offset(mq).await,
+            ConsumeFrom::Timestamp if is_retry_topic => self.get_max_offset(mq).await,
+            ConsumeFrom::Timestamp => {
+                let timestamp = (OffsetDateTime::now_utc()
+                    - OffsetDateTime::UNIX_EPOCH
+                    - time::Duration::minutes(30))
+                .whole_milliseconds() as i64;
+                self.search_offset_by_timestamp(mq, timestamp).await
+            }
+        };
+        match res {
+            Ok(offset) => offset,
+            Err(err) => {
+                error!(consumer_group = %self.group, message_queue = ?mq, "compute pull offset error: {:?}", err);
+                -1
+            }
+        }
+    }
+
+    /// Drop all message queues, which stops pulling them
+    fn drop_all_process_queues(&self) -> HashMap<MessageQueue, Arc<ProcessQueue>> {
+        let table = std::mem::take(&mut *self.process_queue_table.lock());
+        for pq in table.values() {
+            pq.set_dropped(true);
+        }
+        table
+    }
+
+    /// Persist offsets of all message queues owned by this consumer
+    pub(crate) async fn persist_consumer_offset(&self) {
+        let mqs: Vec<MessageQueue> = self.process_queue_table.lock().keys().cloned().collect();
+        self.storage.persist(&mqs).await;
+    }
+
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    async fn shutdown(&self) {
+        let _guard = self.lifecycle_lock.lock().await;
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // pulling stops once `shutting_down` is set, wait for in-flight listeners
+        // so that their offsets get committed
+        let deadline = tokio::time::Instant::now() + self.options.await_termination;
+        while self.inflight.load(Ordering::SeqCst) > 0 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let inflight = self.inflight.load(Ordering::SeqCst);
+        if inflight > 0 {
+            warn!(consumer_group = %self.group, inflight = inflight, "timed out waiting for in-flight message listeners");
+        }
+        let process_queues = self.drop_all_process_queues();
+        let mqs: Vec<MessageQueue> = process_queues.keys().cloned().collect();
+        self.storage.persist(&mqs).await;
+        let locked: Vec<MessageQueue> = process_queues
+            .into_iter()
+            .filter(|(_, pq)| pq.is_locked())
+            .map(|(mq, _)| mq)
+            .collect();
+        self.unlock_all(locked).await;
+        if self.client.unregister_consumer(&self.group, self) {
+            self.client.unregister_client("", &self.group).await;
+        }
+        // offsets were committed oneway, make sure they are written
+        self.client.shutdown_gracefully().await;
+        info!(consumer_group = %self.group, "consumer shutdown");
+    }
+
+    /// Lock message queues of a broker for orderly consumption, returns the queues locked
+    ///
+    /// Process queues of the requested message queues are marked locked or unlocked accordingly.
+    pub(crate) async fn lock_batch(
+        &self,
+        broker_name: &str,
+        mq_set: HashSet<MessageQueue>,
+    ) -> Result<HashSet<MessageQueue>, Error> {
+        let broker_addr = self
+            .client
+            .name_server
+            .find_broker_addr_by_name(broker_name)
+            .ok_or(Error::EmptyRouteData)?;
+        let body = LockBatchRequestBody {
+            consumer_group: self.group.clone(),
+            client_id: self.client.id(),
+            mq_set: mq_set.clone(),
+        };
+        let cmd = RemotingCommand::new(
+            RequestCode::LockBatchMQ,
+            String::new(),
+            HashMap::new(),
+            fastjson::to_vec(&body)?,
+        );
+        let res = self
+            .client
+            .invoke_timeout(&broker_addr, cmd, Duration::from_secs(1))
+            .await?;
+        if res.code() != ResponseCode::Success {
+            return Err(Error::ResponseError {
+                code: res.code(),
+                message: res.remark().to_string(),
+            });
+        }
+        let locked = LockBatchResponseBody::from_bytes(res.body())?.lock_ok_mq_set;
+        let table = self.process_queue_table.lock();
+        for mq in &mq_set {
+            if let Some(pq) = table.get(mq) {
+                pq.set_locked(locked.contains(mq));
+            }
+        }
+        debug!(consumer_group = %self.group, broker_name = %broker_name, locked = locked.len(), requested = mq_set.len(), "lock message queues");
+        Ok(locked)
+    }
+
+    /// Renew the broker side locks of all orderly consumed message queues, queues
+    /// locked by other consumers in the meantime stop being consumed
+    async fn lock_all(&self) {
+        let mut broker_mqs: HashMap<String, HashSet<MessageQueue>> = HashMap::new();
+        for mq in self.process_queue_table.lock().keys() {
+            broker_mqs
+                .entry(mq.broker_name.clone())
+                .or_default()
+                .insert(mq.clone());
+        }
+        for (broker_name, mq_set) in broker_mqs {
+            if let Err(err) = self.lock_batch(&broker_name, mq_set).await {
+                warn!(consumer_group = %self.group, broker_name = %broker_name, "lock message queues error: {:?}", err);
+            }
+        }
+    }
+
+    /// Release the broker side locks of orderly consumed message queues
+    async fn unlock_all(&self, mqs: Vec<MessageQueue>) {
+        let mut broker_mqs: HashMap<String, HashSet<MessageQueue>> = HashMap::new();
+        for mq in mqs {
+            broker_mqs
+                .entry(mq.broker_name.clone())
+                .or_default()
+                .insert(mq);
+        }
+        for (broker_name, mq_set) in broker_mqs {
+            let broker_addr = match self
+                .client
+                .name_server
+                .find_broker_addr_by_name(&broker_name)
+            {
+                Some(addr) => addr,
+                None => {
+                    warn!(consumer_group = %self.group, broker_name = %broker_name, "unlock message queues failed, broker address not found");
+                    continue;
+                }
+            };
+            let body = UnlockBatchRequestBody {
+                consumer_group: self.group.clone(),
+                client_id: self.client.id(),
+                mq_set,
+            };
+            let cmd = RemotingCommand::new(
+                RequestCode::UnlockBatchMQ,
+                String::new(),
+                HashMap::new(),
+                fastjson::to_vec(&body).unwrap(),
+            );
+            match self
+                .client
+                .invoke_timeout(&broker_addr, cmd, Duration::from_secs(1))
+                .await
+            {
+                Ok(res) if res.code() == ResponseCode::Success => {
+                    info!(consumer_group = %self.group, broker_name = %broker_name, "unlock message queues success")
+                }
+                Ok(res) => {
+                    warn!(consumer_group = %self.group, broker_name = %broker_name, code = res.code(), "unlock message queues failed")
+                }
+                Err(err) => {
+                    warn!(consumer_group = %self.group, broker_name = %broker_name, "unlock message queues error: {:?}", err)
+                }
+            }
+        }
     }
 
     async fn get_broker_addr(&self, topic: &str) -> Result<String, Error> {
@@ -225,10 +847,49 @@ impl Consumer {
         }
     }
 
-    pub async fn get_consumer_list(&self, topic: &str) -> Result<Vec<String>, Error> {
+    async fn get_queue_broker_addr(&self, mq: &MessageQueue) -> Result<String, Error> {
+        if mq.broker_name.is_empty() {
+            return self.get_broker_addr(&mq.topic).await;
+        }
+        match self
+            .client
+            .name_server
+            .find_broker_addr_by_name(&mq.broker_name)
+        {
+            Some(addr) => Ok(addr),
+            None => {
+                self.client
+                    .name_server
+                    .update_topic_route_info(&mq.topic)
+                    .await?;
+                self.client
+                    .name_server
+                    .find_broker_addr_by_name(&mq.broker_name)
+                    .ok_or(Error::EmptyRouteData)
+            }
+        }
+    }
+
+    /// Find the broker address to pull `mq` from, a slave one if the broker suggested so
+    /// or the master is unavailable or can't be connected to
+    async fn get_pull_broker_addr(
+        &self,
+        mq: &MessageQueue,
+        prefer_slave: bool,
+    ) -> Result<FindBrokerResult, Error> {
+        let name_server = &self.client.name_server;
+        let mut candidates = name_server.find_broker_addrs(&mq.broker_name, prefer_slave);
+        if candidates.is_empty() {
+            name_server.update_topic_route_info(&mq.topic).await?;
+            candidates = name_server.find_broker_addrs(&mq.broker_name, prefer_slave);
+        }
+        self.client.connect_any(candidates).await
+    }
+
+    async fn get_consumer_list(&self, topic: &str) -> Result<Vec<String>, Error> {
         let broker_addr = self.get_broker_addr(topic).await?;
         let header = GetConsumerListRequestHeader {
-            consumer_group: self.consumer_group.clone(),
+            consumer_group: self.group.clone(),
         };
         let cmd =
             RemotingCommand::with_header(RequestCode::GetConsumerListByGroup, header, Vec::new());
@@ -241,7 +902,7 @@ impl Consumer {
                 if res.body.is_empty() {
                     return Ok(Vec::new());
                 }
-                let result: serde_json::Value = serde_json::from_slice(&res.body)?;
+                let result: serde_json::Value = fastjson::from_slice(&res.body)?;
                 if let Some(list) = result
                     .get("consumerIdList")
                     .and_then(|list| list.as_array())
@@ -256,83 +917,351 @@ impl Consumer {
                 }
             }
             Err(err) => {
-                error!(consumer_group = %self.consumer_group, broker = %broker_addr, "get consumer list of group from broker error: {:?}", err);
+                error!(consumer_group = %self.group, broker = %broker_addr, "get consumer list of group from broker error: {:?}", err);
                 Err(err)
             }
         }
     }
 
-    pub async fn get_max_offset(&self, mq: &MessageQueue) -> Result<i64, Error> {
-        let broker_addr = self.get_broker_addr(&mq.topic).await?;
+    async fn lag(&self, topic: &str) -> Result<ConsumerLag, Error> {
+        let mqs = self.topic_subscribe_info(topic).await?;
+        let mut lag = ConsumerLag::default();
+        for mq in mqs {
+            let max_offset = self.get_max_offset(&mq).await?;
+            let committed_offset = self.storage.read(&mq, ReadType::MemoryThenStore).await;
+            lag.add(
+                self.message_queue_without_namespace(mq),
+                max_offset,
+                committed_offset,
+            );
+        }
+        Ok(lag)
+    }
+
+    async fn get_max_offset(&self, mq: &MessageQueue) -> Result<i64, Error> {
+        let broker_addr = self.get_queue_broker_addr(mq).await?;
         let header = GetMaxOffsetRequestHeader {
             topic: mq.topic.clone(),
             queue_id: mq.queue_id,
         };
-        let cmd = RemotingCommand::with_header(RequestCode::GetMaxOffset, header, Vec::new());
-        let res = self
-            .client
-            .invoke_timeout(&broker_addr, cmd, Duration::from_secs(3))
-            .await?;
-        if res.code() == ResponseCode::Success {
-            let offset: i64 = res
-                .header
-                .ext_fields
-                .get("offset")
-                .and_then(|s| s.parse().ok())
-                .unwrap();
-            Ok(offset)
-        } else {
-            Err(Error::ResponseError {
-                code: res.code(),
-                message: res.header.remark,
-            })
-        }
+        self.client.get_max_offset(&broker_addr, header).await
     }
 
-    pub async fn search_offset_by_timestamp(
+    async fn get_min_offset(&self, mq: &MessageQueue) -> Result<i64, Error> {
+        let broker_addr = self.get_queue_broker_addr(mq).await?;
+        let header = GetMinOffsetRequestHeader {
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+        };
+        self.client.get_min_offset(&broker_addr, header).await
+    }
+
+    async fn get_earliest_msg_store_time(&self, mq: &MessageQueue) -> Result<i64, Error> {
+        let broker_addr = self.get_queue_broker_addr(mq).await?;
+        let header = GetEarliestMsgStoreTimeRequestHeader {
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+        };
+        self.client
+            .get_earliest_msg_store_time(&broker_addr, header)
+            .await
+    }
+
+    async fn search_offset_by_timestamp(
         &self,
         mq: &MessageQueue,
         timestamp: i64,
     ) -> Result<i64, Error> {
-        let broker_addr = self.get_broker_addr(&mq.topic).await?;
-        let header = SearchOffsetByTimestampRequestHeader {
+        let broker_addr = self.get_queue_broker_addr(mq).await?;
+        let header = SearchOffsetRequestHeader {
             topic: mq.topic.clone(),
             queue_id: mq.queue_id,
             timestamp,
         };
-        let cmd =
-            RemotingCommand::with_header(RequestCode::SearchOffsetByTimestamp, header, Vec::new());
-        let res = self
+        self.client.search_offset(&broker_addr, header).await
+    }
+}
+
+const MAX_GROUP_NAME_LEN: usize = 255;
+
+fn check_group_name(group: &str) -> Result<(), Error> {
+    if group.is_empty() {
+        return Err(Error::InvalidGroupName("group name is empty".to_string()));
+    }
+    if group == DEFAULT_CONSUMER_GROUP {
+        return Err(Error::InvalidGroupName(format!(
+            "group name can not be the default {}",
+            DEFAULT_CONSUMER_GROUP
+        )));
+    }
+    if group.len() > MAX_GROUP_NAME_LEN {
+        return Err(Error::InvalidGroupName(format!(
+            "group name is longer than {} characters",
+            MAX_GROUP_NAME_LEN
+        )));
+    }
+    if !group
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '%' | '|' | '_' | '-'))
+    {
+        return Err(Error::InvalidGroupName(format!(
+            "group name {} contains illegal characters",
+            group
+        )));
+    }
+    Ok(())
+}
+
+fn build_subscription_data(topic: &str, selector: &MessageSelector) -> SubscriptionData {
+    let expression = selector.expression.trim();
+    let sub_string = if expression.is_empty() {
+        SUBSCRIBE_ALL.to_string()
+    } else {
+        expression.to_string()
+    };
+    let mut tags_set = HashSet::new();
+    let mut code_set = HashSet::new();
+    if selector.expression_type == ExpressionType::Tag && sub_string != SUBSCRIBE_ALL {
+        for tag in sub_string.split("||") {
+            let tag = tag.trim();
+            if !tag.is_empty() {
+                code_set.insert(java_string_hash_code(tag));
+                tags_set.insert(tag.to_string());
+            }
+        }
+    }
+    SubscriptionData {
+        class_filter_mode: false,
+        topic: topic.to_string(),
+        sub_string,
+        tags_set,
+        code_set,
+        sub_version: (OffsetDateTime::now_utc() - OffsetDateTime::UNIX_EPOCH).whole_milliseconds()
+            as i64,
+        expression_type: selector.expression_type.to_string(),
+    }
+}
+
+/// Same as Java's `String.hashCode`, used by brokers to filter tags
+fn java_string_hash_code(s: &str) -> i32 {
+    s.encode_utf16()
+        .fold(0i32, |hash, c| hash.wrapping_mul(31).wrapping_add(c as i32))
+}
+
+#[derive(Debug)]
+pub struct Consumer {
+    inner: Arc<ConsumerInner>,
+}
+
+impl Consumer {
+    pub fn new() -> Result<Self, Error> {
+        Self::with_options(ConsumerOptions::default())
+    }
+
+    pub fn with_options(options: ConsumerOptions) -> Result<Self, Error> {
+        Ok(Self {
+            inner: Arc::new(ConsumerInner::new(options)?),
+        })
+    }
+
+    /// Start the consumer
+    ///
+    /// Verifies the group name, resolves name servers, fetches routes of the subscribed topics,
+    /// sends the first heartbeat and completes an initial rebalance before returning.
+    /// A consumer which was shut down can be started again.
+    pub async fn start(&self) -> Result<(), Error> {
+        let inner = &self.inner;
+        let _guard = inner.lifecycle_lock.lock().await;
+        // restart after shutdown
+        inner.shutting_down.store(false, Ordering::SeqCst);
+        check_group_name(&inner.group)?;
+        MQClientManager::register_client(&inner.client, &inner.options.resolver)?;
+        inner.client.start().await?;
+        let retry_topic = inner.retry_topic();
+        if inner.options.message_model == MessageModel::Clustering {
+            inner.subscribe(&retry_topic, MessageSelector::default());
+        }
+        for topic in inner.subscribed_topics() {
+            if let Err(err) = inner
+                .client
+                .name_server
+                .update_topic_route_info(&topic)
+                .await
+            {
+                if topic == retry_topic {
+                    debug!(consumer_group = %inner.group, topic = %topic, "retry topic route not found: {:?}", err);
+                } else {
+                    return Err(err);
+                }
+            }
+        }
+        inner
             .client
-            .invoke_timeout(&broker_addr, cmd, Duration::from_secs(3))
-            .await?;
-        if res.code() == ResponseCode::Success {
-            let offset: i64 = res
-                .header
-                .ext_fields
-                .get("offset")
-                .and_then(|s| s.parse().ok())
-                .unwrap();
-            Ok(offset)
-        } else {
-            Err(Error::ResponseError {
-                code: res.code(),
-                message: res.header.remark,
-            })
+            .register_consumer(&inner.group, Arc::clone(inner))?;
+        let res = async {
+            inner.client.send_heartbeat_to_all_brokers().await?;
+            inner.rebalance().await
         }
+        .await;
+        if let Err(err) = res {
+            error!(consumer_group = %inner.group, "consumer start failed: {:?}", err);
+            inner.client.unregister_consumer(&inner.group, inner);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Gracefully shutdown the consumer
+    ///
+    /// Stops pulling, waits for in-flight message listeners, persists offsets,
+    /// unlocks orderly consumed queues and unregisters from brokers.
+    pub async fn shutdown(&self) {
+        self.inner.shutdown().await;
+    }
+
+    /// Get a snapshot of the consumer metrics
+    pub fn metrics(&self) -> ConsumerMetrics {
+        self.inner.metrics()
+    }
+
+    /// Register a hook invoked around every request of the underlying client
+    pub fn register_rpc_hook(&self, hook: impl RpcHook + 'static) {
+        self.inner.client.register_rpc_hook(hook);
+    }
+
+    /// Get a snapshot of the metrics of the underlying client
+    pub fn client_metrics(&self) -> ClientMetrics {
+        self.inner.client.metrics()
+    }
+
+    /// Subscribe to topic route changes, topics are prefixed with the namespace if any
+    pub fn subscribe_route_changes(&self) -> broadcast::Receiver<(String, TopicRouteData)> {
+        self.inner.client.subscribe_route_changes()
+    }
+
+    /// Subscribe to connection events of broker and name server addresses
+    pub fn subscribe_connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.inner.client.subscribe_connection_events()
+    }
+
+    /// Connect to the master brokers of `topic` ahead of the first pulls
+    pub async fn warm_up(&self, topic: &str) -> Result<(), Error> {
+        let topic = self.inner.wrap_namespace(topic);
+        self.inner.client.warm_up(&topic).await
+    }
+
+    pub async fn get_consumer_list(&self, topic: &str) -> Result<Vec<String>, Error> {
+        let topic = self.inner.wrap_namespace(topic);
+        self.inner.get_consumer_list(&topic).await
+    }
+
+    /// Get the consume lag of every message queue of `topic` and the total lag,
+    /// computed from the broker max offsets and the committed offsets
+    pub async fn lag(&self, topic: &str) -> Result<ConsumerLag, Error> {
+        let topic = self.inner.wrap_namespace(topic);
+        self.inner.lag(&topic).await
+    }
+
+    pub async fn get_max_offset(&self, mq: &MessageQueue) -> Result<i64, Error> {
+        let mq = self.inner.wrap_message_queue(mq);
+        self.inner.get_max_offset(&mq).await
+    }
+
+    pub async fn get_min_offset(&self, mq: &MessageQueue) -> Result<i64, Error> {
+        let mq = self.inner.wrap_message_queue(mq);
+        self.inner.get_min_offset(&mq).await
+    }
+
+    /// Get the store timestamp in milliseconds of the earliest message of `mq`
+    pub async fn get_earliest_msg_store_time(&self, mq: &MessageQueue) -> Result<i64, Error> {
+        let mq = self.inner.wrap_message_queue(mq);
+        self.inner.get_earliest_msg_store_time(&mq).await
+    }
+
+    pub async fn search_offset_by_timestamp(
+        &self,
+        mq: &MessageQueue,
+        timestamp: i64,
+    ) -> Result<i64, Error> {
+        let mq = self.inner.wrap_message_queue(mq);
+        self.inner.search_offset_by_timestamp(&mq, timestamp).await
+    }
+
+    /// Query up to `max_num` messages of every broker of `topic` having `key` among
+    /// their keys and stored between the timestamps in milliseconds
+    pub async fn query_message(
+        &self,
+        topic: &str,
+        key: &str,
+        max_num: i32,
+        begin_timestamp: i64,
+        end_timestamp: i64,
+    ) -> Result<Vec<MessageExt>, Error> {
+        let topic = self.inner.wrap_namespace(topic);
+        let msgs = self
+            .inner
+            .client
+            .query_message_by_key(&topic, key, max_num, begin_timestamp, end_timestamp, false)
+            .await?;
+        Ok(msgs
+            .into_iter()
+            .map(|msg| self.inner.message_without_namespace(msg))
+            .collect())
+    }
+
+    /// Query the messages of `topic` sent with the unique message id `msg_id`,
+    /// more than one if the send was retried
+    pub async fn query_message_by_unique_key(
+        &self,
+        topic: &str,
+        msg_id: &str,
+    ) -> Result<Vec<MessageExt>, Error> {
+        let topic = self.inner.wrap_namespace(topic);
+        let msgs = self
+            .inner
+            .client
+            .query_message_by_key(&topic, msg_id, UNIQUE_KEY_QUERY_MAX_NUM, 0, i64::MAX, true)
+            .await?;
+        Ok(msgs
+            .into_iter()
+            .map(|msg| self.inner.message_without_namespace(msg))
+            .collect())
+    }
+
+    /// Fetch a message by its offset message id
+    pub async fn view_message(&self, offset_msg_id: &str) -> Result<MessageExt, Error> {
+        let msg = self.inner.client.view_message(offset_msg_id).await?;
+        Ok(self.inner.message_without_namespace(msg))
     }
 }
 
 impl Drop for Consumer {
     fn drop(&mut self) {
-        self.shutdown();
+        let inner = &self.inner;
+        if inner.is_shutting_down() {
+            return;
+        }
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let inner = Arc::clone(inner);
+                handle.spawn(async move { inner.shutdown().await });
+            }
+            Err(_) => {
+                inner.shutting_down.store(true, Ordering::SeqCst);
+                inner.drop_all_process_queues();
+                inner.client.unregister_consumer(&inner.group, inner);
+                inner.client.shutdown();
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Consumer, ConsumerOptions};
+    use super::{check_group_name, Consumer, ConsumerOptions, MessageSelector};
+    use crate::client::ClientOptions;
     use crate::message::MessageQueue;
+    use crate::Error;
 
     #[tokio::test]
     async fn test_get_consumer_list() {
@@ -373,4 +1302,73 @@ mod test {
         let offset = consumer.search_offset_by_timestamp(&mq, 0).await.unwrap();
         assert!(offset >= 0);
     }
+
+    #[tokio::test]
+    async fn test_lag() {
+        let mut options = ConsumerOptions::default();
+        options.set_name_server(vec!["localhost:9876".to_string()]);
+        let consumer = Consumer::with_options(options).unwrap();
+        let lag = consumer.lag("SELF_TEST_TOPIC").await.unwrap();
+        assert!(!lag.queues.is_empty());
+        assert_eq!(lag.total, lag.queues.values().sum::<i64>());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let mut options = ConsumerOptions::default();
+        options.set_name_server(vec!["localhost:9876".to_string()]);
+        let consumer = Consumer::with_options(options).unwrap();
+        consumer.shutdown().await;
+        assert!(consumer.inner.is_shutting_down());
+        assert!(consumer.inner.process_queue_table.lock().is_empty());
+        // shutdown twice is a no-op
+        consumer.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_namespace() {
+        let mut client_options = ClientOptions::new("test_group");
+        client_options.set_namespace("ns");
+        let mut options = ConsumerOptions::with_client_options(client_options);
+        options.set_name_server(vec!["localhost:9876".to_string()]);
+        let consumer = Consumer::with_options(options).unwrap();
+        let inner = &consumer.inner;
+        assert_eq!("ns%test_group", inner.group);
+        assert_eq!("%RETRY%ns%test_group", inner.retry_topic());
+        assert_eq!(
+            "ns%test",
+            inner.subscribe("test", MessageSelector::default())
+        );
+        inner.subscribe(&inner.retry_topic(), MessageSelector::default());
+        let mut topics = inner.subscribed_topics();
+        topics.sort();
+        assert_eq!(vec!["%RETRY%ns%test_group", "ns%test"], topics);
+        let data = inner.consumer_data();
+        assert_eq!("ns%test_group", data.group_name);
+        let mq = MessageQueue {
+            topic: "ns%test".to_string(),
+            broker_name: "broker".to_string(),
+            queue_id: 0,
+        };
+        assert_eq!("test", inner.message_queue_without_namespace(mq).topic);
+    }
+
+    #[test]
+    fn test_check_group_name() {
+        assert!(check_group_name("test_group-1").is_ok());
+        assert!(check_group_name("%RETRY%test|group").is_ok());
+        assert!(check_group_name("").is_err());
+        assert!(check_group_name("DEFAULT_CONSUMER").is_err());
+        assert!(check_group_name("test group").is_err());
+        assert!(check_group_name(&"a".repeat(256)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_start_with_invalid_group_name() {
+        let mut options = ConsumerOptions::default();
+        options.set_name_server(vec!["localhost:9876".to_string()]);
+        let consumer = Consumer::with_options(options).unwrap();
+        let err = consumer.start().await.unwrap_err();
+        assert!(matches!(err, Error::InvalidGroupName(_)));
+    }
 }
@@ -65,10 +65,18 @@ impl OffsetStore for OffsetStorage {
     }
 }
 
+// JSON object keys must be strings, so the offset table is stored as a list
 #[derive(Debug, Deserialize, Serialize)]
 struct OffsetTableWrapper {
     #[serde(rename = "offsetTable")]
-    offset_table: HashMap<MessageQueue, i64>,
+    offset_table: Vec<QueueOffset>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct QueueOffset {
+    #[serde(flatten)]
+    mq: MessageQueue,
+    offset: i64,
 }
 
 #[derive(Debug)]
@@ -81,13 +89,13 @@ pub struct LocalFileOffsetStore {
 impl LocalFileOffsetStore {
     pub fn new(group: &str, client_id: &str) -> Self {
         let store_path = env::var("rocketmq.client.localOffsetStoreDir")
-            .unwrap_or_else(|_| env::var("HOME").unwrap() + ".rocketmq_client_rust");
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| {
+                PathBuf::from(env::var("HOME").unwrap_or_default()).join(".rocketmq_client_rust")
+            });
         Self {
             group: group.to_string(),
-            path: PathBuf::from(store_path)
-                .join(client_id)
-                .join(group)
-                .join("offset.json"),
+            path: store_path.join(client_id).join(group).join("offset.json"),
             offset_table: Mutex::new(HashMap::new()),
         }
     }
@@ -113,7 +121,11 @@ impl LocalFileOffsetStore {
         };
         match serde_json::from_slice::<OffsetTableWrapper>(&data) {
             Ok(wrapper) => {
-                *self.offset_table.lock() = wrapper.offset_table;
+                *self.offset_table.lock() = wrapper
+                    .offset_table
+                    .into_iter()
+                    .map(|entry| (entry.mq, entry.offset))
+                    .collect();
             }
             Err(err) => {
                 warn!("deserialize local offset error: {:?}", err);
@@ -134,10 +146,28 @@ impl OffsetStore for LocalFileOffsetStore {
             return;
         }
         let wrapper = OffsetTableWrapper {
-            offset_table: self.offset_table.lock().clone(),
+            offset_table: self
+                .offset_table
+                .lock()
+                .iter()
+                .map(|(mq, offset)| QueueOffset {
+                    mq: mq.clone(),
+                    offset: *offset,
+                })
+                .collect(),
         };
         match serde_json::to_vec(&wrapper) {
             Ok(data) => {
+                if let Some(dir) = self.path.parent() {
+                    if let Err(err) = tokio::fs::create_dir_all(dir).await {
+                        error!(
+                            "create offset store dir {} failed: {:?}",
+                            dir.display(),
+                            err
+                        );
+                        return;
+                    }
+                }
                 if let Err(err) = tokio::fs::write(&self.path, data).await {
                     error!(
                         "persist offset to {} failed: {:?}",
@@ -219,25 +249,15 @@ impl RemoteBrokerOffsetStore {
     }
 
     async fn fetch_consumer_offset_from_broker(&self, mq: &MessageQueue) -> Result<i64, Error> {
-        let broker_addr = {
-            match self
-                .client
-                .name_server
-                .find_broker_addr_by_name(&mq.broker_name)
-            {
-                Some(addr) => Some(addr),
-                None => {
-                    self.client
-                        .name_server
-                        .update_topic_route_info(&mq.topic)
-                        .await?;
-                    self.client
-                        .name_server
-                        .find_broker_addr_by_name(&mq.broker_name)
-                }
-            }
-        };
-        if let Some(addr) = broker_addr {
+        let name_server = &self.client.name_server;
+        let mut candidates = name_server.find_broker_addrs(&mq.broker_name, false);
+        if candidates.is_empty() {
+            name_server.update_topic_route_info(&mq.topic).await?;
+            candidates = name_server.find_broker_addrs(&mq.broker_name, false);
+        }
+        // slaves keep a copy of consumer offsets, query them when the master is unreachable
+        if !candidates.is_empty() {
+            let addr = self.client.connect_any(candidates).await?.addr;
             let header = QueryConsumerOffsetRequestHeader {
                 consumer_group: self.group.clone(),
                 topic: mq.topic.clone(),
@@ -252,8 +272,7 @@ impl RemoteBrokerOffsetStore {
                     message: res.header.remark,
                 });
             }
-            let offset: i64 = res.header.ext_fields["offset"].parse().unwrap_or(-1);
-            return Ok(offset);
+            return res.ext_i64("offset");
         }
         Err(Error::EmptyRouteData)
     }
@@ -365,3 +384,33 @@ impl OffsetStore for RemoteBrokerOffsetStore {
             .or_insert(offset);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::{LocalFileOffsetStore, OffsetStore, ReadType};
+    use crate::message::MessageQueue;
+
+    #[tokio::test]
+    async fn test_local_file_offset_store_persist() {
+        let dir = env::temp_dir().join(format!("rocketmq_offset_test_{}", std::process::id()));
+        let mut store = LocalFileOffsetStore::new("test_group", "127.0.0.1@test");
+        store.path = dir.join("test_group").join("offset.json");
+        let mq = MessageQueue {
+            topic: "test".to_string(),
+            broker_name: "broker-a".to_string(),
+            queue_id: 0,
+        };
+        store.update(&mq, 10, true);
+        store.update(&mq, 5, true);
+        assert_eq!(10, store.read(&mq, ReadType::Memory).await);
+        store.persist(std::slice::from_ref(&mq)).await;
+
+        let mut loaded = LocalFileOffsetStore::new("test_group", "127.0.0.1@test");
+        loaded.path = store.path.clone();
+        assert_eq!(-1, loaded.read(&mq, ReadType::Memory).await);
+        assert_eq!(10, loaded.read(&mq, ReadType::Store).await);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
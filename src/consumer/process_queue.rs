@@ -1,18 +1,19 @@
 use std::collections::BTreeMap;
-use std::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering};
 
 use parking_lot::RwLock;
 use time::OffsetDateTime;
 
+use crate::message::{MessageExt, Property};
+
 #[derive(Debug)]
 pub struct ProcessQueue {
+    msg_cache: RwLock<BTreeMap<i64, MessageExt>>,
     msg_count: AtomicUsize,
     msg_size: AtomicUsize,
-    msg_acc_count: AtomicUsize,
-    queue_offset_max: i64,
+    msg_acc_count: AtomicI64,
+    queue_offset_max: AtomicI64,
     dropped: AtomicBool,
-    last_pull_timestamp: AtomicI64,
-    last_consume_timestamp: AtomicI64,
     locked: AtomicBool,
     last_lock_timestamp: AtomicI64,
     consuming: AtomicBool,
@@ -22,26 +23,181 @@ impl ProcessQueue {
     pub fn new() -> Self {
         let ts = OffsetDateTime::now_utc().unix_timestamp();
         Self {
+            msg_cache: RwLock::new(BTreeMap::new()),
             msg_count: AtomicUsize::new(0),
             msg_size: AtomicUsize::new(0),
-            msg_acc_count: AtomicUsize::new(0),
-            queue_offset_max: 0,
+            msg_acc_count: AtomicI64::new(0),
+            queue_offset_max: AtomicI64::new(0),
             dropped: AtomicBool::new(false),
-            last_pull_timestamp: AtomicI64::new(ts),
-            last_consume_timestamp: AtomicI64::new(ts),
             locked: AtomicBool::new(false),
             last_lock_timestamp: AtomicI64::new(ts),
             consuming: AtomicBool::new(false),
         }
     }
+
+    /// Put pulled messages into the cache, returns the number of newly cached messages
+    pub fn put_messages(&self, msgs: &[MessageExt]) -> usize {
+        let mut cache = self.msg_cache.write();
+        let mut count = 0;
+        for msg in msgs {
+            if cache.contains_key(&msg.queue_offset()) {
+                continue;
+            }
+            self.msg_size
+                .fetch_add(msg.message.body.len(), Ordering::SeqCst);
+            self.queue_offset_max
+                .store(msg.queue_offset(), Ordering::SeqCst);
+            cache.insert(msg.queue_offset(), msg.clone());
+            count += 1;
+        }
+        self.msg_count.fetch_add(count, Ordering::SeqCst);
+        if let Some(last) = msgs.last() {
+            if let Some(max_offset) = last
+                .message
+                .get_property(Property::MAX_OFFSET)
+                .and_then(|s| s.parse::<i64>().ok())
+            {
+                let acc = max_offset - last.queue_offset();
+                if acc > 0 {
+                    self.msg_acc_count.store(acc, Ordering::SeqCst);
+                }
+            }
+        }
+        count
+    }
+
+    /// Remove consumed messages from the cache, returns the offset that can be committed
+    /// or -1 if the cache was already empty
+    pub fn remove_messages(&self, msgs: &[MessageExt]) -> i64 {
+        let mut cache = self.msg_cache.write();
+        if cache.is_empty() {
+            return -1;
+        }
+        let mut result = self.queue_offset_max.load(Ordering::SeqCst) + 1;
+        for msg in msgs {
+            if let Some(removed) = cache.remove(&msg.queue_offset()) {
+                self.msg_count.fetch_sub(1, Ordering::SeqCst);
+                self.msg_size
+                    .fetch_sub(removed.message.body.len(), Ordering::SeqCst);
+            }
+        }
+        if let Some(first) = cache.keys().next() {
+            result = *first;
+        }
+        result
+    }
+
+    /// Up to `count` cached messages with the lowest offsets, kept in the cache
+    pub fn peek_messages(&self, count: usize) -> Vec<MessageExt> {
+        self.msg_cache
+            .read()
+            .values()
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// Number of messages pulled but not consumed yet
+    pub fn msg_count(&self) -> usize {
+        self.msg_count.load(Ordering::SeqCst)
+    }
+
+    /// Total body size of messages pulled but not consumed yet
+    pub fn msg_size(&self) -> usize {
+        self.msg_size.load(Ordering::SeqCst)
+    }
+
+    /// Messages remaining on the broker behind the last pulled message
+    pub fn msg_acc_count(&self) -> i64 {
+        self.msg_acc_count.load(Ordering::SeqCst)
+    }
+
+    pub fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    pub fn set_dropped(&self, dropped: bool) {
+        self.dropped.store(dropped, Ordering::SeqCst);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::SeqCst)
+    }
+
+    pub fn set_locked(&self, locked: bool) {
+        self.locked.store(locked, Ordering::SeqCst);
+        if locked {
+            self.last_lock_timestamp
+                .store(OffsetDateTime::now_utc().unix_timestamp(), Ordering::SeqCst);
+        }
+    }
+
+    /// Mark the queue consuming, returns false if it already was
+    pub fn start_consuming(&self) -> bool {
+        !self.consuming.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn set_consuming(&self, consuming: bool) {
+        self.consuming.store(consuming, Ordering::SeqCst);
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use bytes::Bytes;
+
     use super::ProcessQueue;
+    use crate::message::MessageExt;
+
+    fn message_ext(queue_offset: i64) -> MessageExt {
+        let mut msg = MessageExt::default();
+        msg.queue_offset = queue_offset;
+        msg.message.body = Bytes::from_static(b"hello");
+        msg
+    }
 
     #[test]
     fn test_process_queue() {
         let _pq = ProcessQueue::new();
     }
+
+    #[test]
+    fn test_process_queue_put_remove_messages() {
+        let pq = ProcessQueue::new();
+        let msgs: Vec<MessageExt> = (0..3).map(message_ext).collect();
+        assert_eq!(3, pq.put_messages(&msgs));
+        assert_eq!(0, pq.put_messages(&msgs[..1]));
+        assert_eq!(3, pq.msg_count());
+        assert_eq!(15, pq.msg_size());
+
+        assert_eq!(0, pq.remove_messages(&msgs[1..2]));
+        assert_eq!(2, pq.remove_messages(&msgs[..1]));
+        assert_eq!(1, pq.msg_count());
+        assert_eq!(3, pq.remove_messages(&msgs[2..]));
+        assert_eq!(0, pq.msg_count());
+        assert_eq!(0, pq.msg_size());
+        assert_eq!(-1, pq.remove_messages(&msgs));
+    }
+
+    #[test]
+    fn test_process_queue_peek_messages() {
+        let pq = ProcessQueue::new();
+        let msgs: Vec<MessageExt> = [2, 0, 1]
+            .iter()
+            .map(|offset| message_ext(*offset))
+            .collect();
+        pq.put_messages(&msgs);
+        let peeked: Vec<i64> = pq
+            .peek_messages(2)
+            .iter()
+            .map(|msg| msg.queue_offset)
+            .collect();
+        assert_eq!(vec![0, 1], peeked);
+        assert_eq!(3, pq.msg_count());
+
+        assert!(pq.start_consuming());
+        assert!(!pq.start_consuming());
+        pq.set_consuming(false);
+        assert!(pq.start_consuming());
+    }
 }
@@ -1,6 +1,53 @@
-use super::{Consumer, ConsumerOptions};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::time;
+use tracing::{debug, error, info, warn};
+
+use super::offset_store::{OffsetStore, ReadType};
+use super::process_queue::ProcessQueue;
+use super::{
+    ConsumeResult, Consumer, ConsumerInner, ConsumerMetrics, ConsumerOptions, DeliverySemantics,
+    MessageModel, MessageSelector, SUBSCRIBE_ALL,
+};
+use crate::client::{ClientMetrics, PullResult, PullStatus};
+use crate::message::{MessageExt, MessageQueue, Property};
+use crate::protocol::request::PullMessageRequestHeader;
+use crate::remoting::{ConnectionEvent, RpcHook};
+use crate::route::{TopicRouteData, MASTER_ID};
+use crate::utils::spawn;
 use crate::Error;
 
+const PULL_FLAG_COMMIT_OFFSET: i32 = 0x1;
+const PULL_FLAG_SUSPEND: i32 = 0x1 << 1;
+const PULL_FLAG_SUBSCRIPTION: i32 = 0x1 << 2;
+
+const BROKER_SUSPEND_MAX_TIME: Duration = Duration::from_secs(20);
+const PULL_TIMEOUT: Duration = Duration::from_secs(30);
+const PULL_DELAY_WHEN_EXCEPTION: Duration = Duration::from_secs(3);
+const PULL_DELAY_WHEN_FLOW_CONTROL: Duration = Duration::from_millis(50);
+const CONSUME_DELAY_WHEN_SEND_BACK_FAILED: Duration = Duration::from_secs(5);
+const CONSUME_DELAY_WHEN_SUSPENDED: Duration = Duration::from_secs(1);
+
+/// Tracks an in-flight message listener invocation
+struct InflightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InflightGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 #[derive(Debug)]
 pub struct PushConsumer {
     consumer: Consumer,
@@ -18,4 +65,608 @@ impl PushConsumer {
             consumer: Consumer::with_options(options)?,
         })
     }
+
+    /// Subscribe a topic, messages selected by `selector` are passed to `listener`
+    pub fn subscribe<F>(&self, topic: &str, selector: MessageSelector, listener: F)
+    where
+        F: Fn(&[MessageExt]) -> ConsumeResult + Send + Sync + 'static,
+    {
+        let inner = &self.consumer.inner;
+        let topic = inner.subscribe(topic, selector);
+        inner.listeners.write().insert(topic, Arc::new(listener));
+    }
+
+    pub async fn start(&self) -> Result<(), Error> {
+        self.consumer.start().await
+    }
+
+    pub async fn shutdown(&self) {
+        self.consumer.shutdown().await;
+    }
+
+    /// Get a snapshot of the per-queue and per-topic consume metrics
+    pub fn metrics(&self) -> ConsumerMetrics {
+        self.consumer.metrics()
+    }
+
+    /// Register a hook invoked around every request of the underlying client
+    pub fn register_rpc_hook(&self, hook: impl RpcHook + 'static) {
+        self.consumer.register_rpc_hook(hook);
+    }
+
+    /// Get a snapshot of the connection, request and failure metrics of the underlying client
+    pub fn client_metrics(&self) -> ClientMetrics {
+        self.consumer.client_metrics()
+    }
+
+    /// Subscribe to topic route changes, topics are prefixed with the namespace if any
+    pub fn subscribe_route_changes(&self) -> broadcast::Receiver<(String, TopicRouteData)> {
+        self.consumer.subscribe_route_changes()
+    }
+
+    /// Connect to the master brokers of `topic` ahead of the first pulls
+    pub async fn warm_up(&self, topic: &str) -> Result<(), Error> {
+        self.consumer.warm_up(topic).await
+    }
+
+    /// Subscribe to connection events of broker and name server addresses, e.g. to
+    /// track their availability
+    pub fn subscribe_connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.consumer.subscribe_connection_events()
+    }
+}
+
+impl ConsumerInner {
+    pub(crate) fn start_pull(
+        self: &Arc<Self>,
+        mq: MessageQueue,
+        pq: Arc<ProcessQueue>,
+        offset: i64,
+    ) {
+        let inner = Arc::clone(self);
+        spawn(self.runtime(), async move {
+            inner.pull_message(mq, pq, offset).await
+        });
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "pull",
+            skip_all,
+            fields(group = %self.group, topic = %mq.topic, broker = %mq.broker_name, queue_id = mq.queue_id)
+        )
+    )]
+    async fn pull_message(self: Arc<Self>, mq: MessageQueue, pq: Arc<ProcessQueue>, offset: i64) {
+        let mut next_offset = offset;
+        // whether the broker suggested pulling from a slave
+        let mut prefer_slave = false;
+        loop {
+            if pq.is_dropped() || self.is_shutting_down() {
+                info!(consumer_group = %self.group, message_queue = ?mq, "process queue is dropped, stop pulling");
+                break;
+            }
+            if self.is_orderly() && !pq.is_locked() {
+                // the lock is renewed on every rebalance
+                time::sleep(PULL_DELAY_WHEN_EXCEPTION).await;
+                continue;
+            }
+            if pq.msg_count() > self.options.pull_threshold_for_queue {
+                // flow control
+                time::sleep(PULL_DELAY_WHEN_FLOW_CONTROL).await;
+                continue;
+            }
+            let sub = match self.subscriptions.lock().get(&mq.topic).cloned() {
+                Some(sub) => sub,
+                None => {
+                    warn!(consumer_group = %self.group, message_queue = ?mq, "subscription not found, stop pulling");
+                    break;
+                }
+            };
+            let broker = match self.get_pull_broker_addr(&mq, prefer_slave).await {
+                Ok(broker) => broker,
+                Err(err) => {
+                    warn!(consumer_group = %self.group, message_queue = ?mq, "find broker address error: {:?}", err);
+                    time::sleep(PULL_DELAY_WHEN_EXCEPTION).await;
+                    continue;
+                }
+            };
+            let mut commit_offset = 0;
+            if self.options.message_model == MessageModel::Clustering {
+                commit_offset = self.storage.read(&mq, ReadType::Memory).await;
+            }
+            let mut sys_flag = PULL_FLAG_SUSPEND;
+            // offsets are only committed to masters
+            if commit_offset > 0 && !broker.slave {
+                sys_flag |= PULL_FLAG_COMMIT_OFFSET;
+            }
+            if sub.sub_string != SUBSCRIBE_ALL {
+                sys_flag |= PULL_FLAG_SUBSCRIPTION;
+            }
+            let header = PullMessageRequestHeader {
+                consumer_group: self.group.clone(),
+                topic: mq.topic.clone(),
+                queue_id: mq.queue_id as i32,
+                queue_offset: next_offset,
+                max_msg_nums: self.options.pull_batch_size,
+                sys_flag,
+                commit_offset,
+                suspend_timeout_millis: BROKER_SUSPEND_MAX_TIME,
+                sub_expression: sub.sub_string.clone(),
+                sub_version: sub.sub_version,
+                expression_type: sub.expression_type.clone(),
+            };
+            let result = match time::timeout(
+                PULL_TIMEOUT,
+                self.client.pull_message(&broker.addr, header),
+            )
+            .await
+            {
+                Ok(Ok(result)) => result,
+                Ok(Err(err)) => {
+                    warn!(consumer_group = %self.group, message_queue = ?mq, "pull message error: {:?}", err);
+                    time::sleep(PULL_DELAY_WHEN_EXCEPTION).await;
+                    continue;
+                }
+                Err(_) => {
+                    warn!(consumer_group = %self.group, message_queue = ?mq, "pull message timed out");
+                    continue;
+                }
+            };
+            if pq.is_dropped() || self.is_shutting_down() {
+                break;
+            }
+            prefer_slave = result.suggest_which_broker_id != MASTER_ID;
+            match result.status {
+                PullStatus::Found => {
+                    next_offset = result.next_begin_offset;
+                    let msgs = self.process_pull_result(&mq, result, &sub.tags_set);
+                    self.stats.record_pull(&mq, msgs.len());
+                    if msgs.is_empty() {
+                        continue;
+                    }
+                    pq.put_messages(&msgs);
+                    self.record_topic_state(&mq.topic);
+                    self.submit_consume(&mq, &pq, msgs);
+                }
+                PullStatus::NoNewMsg | PullStatus::NoMsgMatched => {
+                    next_offset = result.next_begin_offset;
+                    if pq.msg_count() == 0 {
+                        self.storage.update(&mq, next_offset, true);
+                    }
+                }
+                PullStatus::OffsetIllegal => {
+                    warn!(consumer_group = %self.group, message_queue = ?mq, next_offset = result.next_begin_offset, "pull offset illegal, drop the message queue");
+                    pq.set_dropped(true);
+                    self.storage.update(&mq, result.next_begin_offset, false);
+                    self.storage.persist(std::slice::from_ref(&mq)).await;
+                    self.storage.remove(&mq);
+                    self.process_queue_table.lock().remove(&mq);
+                    break;
+                }
+                PullStatus::BrokerTimeout => {
+                    time::sleep(PULL_DELAY_WHEN_EXCEPTION).await;
+                    continue;
+                }
+            }
+            if !self.options.pull_interval.is_zero() {
+                time::sleep(self.options.pull_interval).await;
+            }
+        }
+    }
+
+    fn process_pull_result(
+        &self,
+        mq: &MessageQueue,
+        result: PullResult,
+        tags: &HashSet<String>,
+    ) -> Vec<MessageExt> {
+        let min_offset = result.min_offset.to_string();
+        let max_offset = result.max_offset.to_string();
+        result
+            .message_exts
+            .into_iter()
+            .filter(|msg| {
+                tags.is_empty()
+                    || msg
+                        .message
+                        .tags()
+                        .map(|tag| tags.contains(tag))
+                        .unwrap_or(false)
+            })
+            .map(|mut msg| {
+                msg.message
+                    .set_property(Property::MIN_OFFSET.to_string(), min_offset.clone());
+                msg.message
+                    .set_property(Property::MAX_OFFSET.to_string(), max_offset.clone());
+                msg.message.queue = Some(mq.clone());
+                msg
+            })
+            .collect()
+    }
+
+    fn submit_consume(
+        self: &Arc<Self>,
+        mq: &MessageQueue,
+        pq: &Arc<ProcessQueue>,
+        msgs: Vec<MessageExt>,
+    ) {
+        if self.is_orderly() {
+            // the cached messages are taken in order by a single task per queue
+            if pq.start_consuming() {
+                let inner = Arc::clone(self);
+                let mq = mq.clone();
+                let pq = Arc::clone(pq);
+                spawn(self.runtime(), async move {
+                    inner.consume_orderly(mq, pq).await
+                });
+            }
+            return;
+        }
+        let batch_size = self.options.consume_message_batch_max_size.max(1);
+        for chunk in msgs.chunks(batch_size) {
+            let inner = Arc::clone(self);
+            let mq = mq.clone();
+            let pq = Arc::clone(pq);
+            let msgs = chunk.to_vec();
+            spawn(self.runtime(), async move {
+                inner.consume_messages(mq, pq, msgs).await
+            });
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "consume",
+            skip_all,
+            fields(group = %self.group, topic = %mq.topic, broker = %mq.broker_name, queue_id = mq.queue_id, count = msgs.len())
+        )
+    )]
+    async fn consume_messages(
+        self: Arc<Self>,
+        mq: MessageQueue,
+        pq: Arc<ProcessQueue>,
+        mut msgs: Vec<MessageExt>,
+    ) {
+        if pq.is_dropped() || self.is_shutting_down() {
+            return;
+        }
+        self.reset_retry_topic(&mut msgs);
+        let listener = match self.listeners.read().get(&msgs[0].message.topic).cloned() {
+            Some(listener) => listener,
+            None => {
+                warn!(consumer_group = %self.group, topic = %msgs[0].message.topic, "no message listener found for topic");
+                return;
+            }
+        };
+        let at_most_once = self.options.delivery_semantics == DeliverySemantics::AtMostOnce;
+        if at_most_once {
+            let offset = pq.remove_messages(&msgs);
+            if offset >= 0 {
+                self.storage.update(&mq, offset, true);
+                self.storage.persist(std::slice::from_ref(&mq)).await;
+            }
+            self.record_topic_state(&mq.topic);
+        }
+        let mut listener_msgs = msgs.clone();
+        for msg in &mut listener_msgs {
+            msg.message.topic = self.without_namespace(&msg.message.topic);
+        }
+        // held until the consume result is committed
+        let _inflight = InflightGuard::new(&self.inflight);
+        let result = tokio::task::spawn_blocking(move || listener(&listener_msgs))
+            .await
+            .unwrap_or_else(|err| {
+                error!("message listener panicked: {:?}", err);
+                ConsumeResult::RetryLater
+            });
+        if at_most_once {
+            match result {
+                ConsumeResult::Success | ConsumeResult::Commit => {
+                    self.stats.record_consume_ok(&mq, msgs.len());
+                }
+                _ => {
+                    self.stats.record_consume_failed(&mq, msgs.len());
+                    warn!(consumer_group = %self.group, message_queue = ?mq, "consume failed with at most once delivery, drop messages");
+                }
+            }
+            return;
+        }
+        if pq.is_dropped() {
+            warn!(consumer_group = %self.group, message_queue = ?mq, "process queue is dropped, discard consume result");
+            return;
+        }
+        let mut failed = Vec::new();
+        match result {
+            ConsumeResult::Success | ConsumeResult::Commit => {
+                self.stats.record_consume_ok(&mq, msgs.len());
+            }
+            _ => {
+                self.stats.record_consume_failed(&mq, msgs.len());
+                if self.options.message_model == MessageModel::Clustering {
+                    for msg in &msgs {
+                        if let Err(err) = self.send_message_back(&mq, msg).await {
+                            warn!(consumer_group = %self.group, msg_id = %msg.msg_id, "send message back error: {:?}", err);
+                            failed.push(msg.clone());
+                        }
+                    }
+                } else {
+                    warn!(consumer_group = %self.group, message_queue = ?mq, "broadcasting consume failed, drop messages");
+                }
+            }
+        }
+        let consumed: Vec<MessageExt> = msgs
+            .into_iter()
+            .filter(|msg| !failed.iter().any(|f| f.queue_offset == msg.queue_offset))
+            .collect();
+        let offset = pq.remove_messages(&consumed);
+        if offset >= 0 && !pq.is_dropped() {
+            self.storage.update(&mq, offset, true);
+        }
+        self.record_topic_state(&mq.topic);
+        if !failed.is_empty() {
+            for msg in &mut failed {
+                msg.reconsume_times += 1;
+            }
+            let inner = Arc::clone(&self);
+            spawn(self.runtime(), async move {
+                time::sleep(CONSUME_DELAY_WHEN_SEND_BACK_FAILED).await;
+                inner.submit_consume(&mq, &pq, failed);
+            });
+        }
+    }
+
+    /// Consume the cached messages of an orderly consumed queue batch by batch until
+    /// none is left, a failed batch is consumed again after a moment
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "consume_orderly",
+            skip_all,
+            fields(group = %self.group, topic = %mq.topic, broker = %mq.broker_name, queue_id = mq.queue_id)
+        )
+    )]
+    async fn consume_orderly(self: Arc<Self>, mq: MessageQueue, pq: Arc<ProcessQueue>) {
+        let batch_size = self.options.consume_message_batch_max_size.max(1);
+        // times the current batch failed to be consumed
+        let mut attempts = 0;
+        loop {
+            if pq.is_dropped() || self.is_shutting_down() {
+                break;
+            }
+            if !pq.is_locked() {
+                warn!(consumer_group = %self.group, message_queue = ?mq, "message queue is not locked, consume later");
+                time::sleep(PULL_DELAY_WHEN_EXCEPTION).await;
+                continue;
+            }
+            let mut msgs = pq.peek_messages(batch_size);
+            if msgs.is_empty() {
+                pq.set_consuming(false);
+                // messages cached right before the flag was cleared
+                if pq.msg_count() > 0 && pq.start_consuming() {
+                    continue;
+                }
+                return;
+            }
+            self.reset_retry_topic(&mut msgs);
+            let listener = match self.listeners.read().get(&msgs[0].message.topic).cloned() {
+                Some(listener) => listener,
+                None => {
+                    warn!(consumer_group = %self.group, topic = %msgs[0].message.topic, "no message listener found for topic");
+                    break;
+                }
+            };
+            let mut listener_msgs = msgs.clone();
+            for msg in &mut listener_msgs {
+                msg.message.topic = self.without_namespace(&msg.message.topic);
+                msg.reconsume_times += attempts;
+            }
+            let _inflight = InflightGuard::new(&self.inflight);
+            let result = tokio::task::spawn_blocking(move || listener(&listener_msgs))
+                .await
+                .unwrap_or_else(|err| {
+                    error!("message listener panicked: {:?}", err);
+                    ConsumeResult::SuspendCurrentQueueAMoment
+                });
+            if pq.is_dropped() {
+                warn!(consumer_group = %self.group, message_queue = ?mq, "process queue is dropped, discard consume result");
+                break;
+            }
+            let consumed = match result {
+                ConsumeResult::Success | ConsumeResult::Commit => {
+                    self.stats.record_consume_ok(&mq, msgs.len());
+                    true
+                }
+                _ => {
+                    self.stats.record_consume_failed(&mq, msgs.len());
+                    attempts += 1;
+                    let max_reconsume_times = self.options.max_reconsume_times;
+                    max_reconsume_times >= 0
+                        && attempts > max_reconsume_times
+                        && self.send_back_exhausted(&mq, &msgs).await
+                }
+            };
+            if !consumed {
+                time::sleep(CONSUME_DELAY_WHEN_SUSPENDED).await;
+                continue;
+            }
+            attempts = 0;
+            let offset = pq.remove_messages(&msgs);
+            if offset >= 0 {
+                self.storage.update(&mq, offset, true);
+            }
+            self.record_topic_state(&mq.topic);
+        }
+        pq.set_consuming(false);
+    }
+
+    /// Send orderly consumed messages failed too many times to the dead letter queue,
+    /// returns whether all of them were sent
+    async fn send_back_exhausted(&self, mq: &MessageQueue, msgs: &[MessageExt]) -> bool {
+        let broker_addr = match self.get_queue_broker_addr(mq).await {
+            Ok(addr) => addr,
+            Err(err) => {
+                warn!(consumer_group = %self.group, message_queue = ?mq, "find broker address error: {:?}", err);
+                return false;
+            }
+        };
+        for msg in msgs {
+            // a negative delay level sends the message to the dead letter queue
+            if let Err(err) = self
+                .client
+                .send_message_back(
+                    &broker_addr,
+                    &self.group,
+                    msg,
+                    -1,
+                    self.options.max_reconsume_times,
+                )
+                .await
+            {
+                warn!(consumer_group = %self.group, msg_id = %msg.msg_id, "send message to dead letter queue error: {:?}", err);
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn send_message_back(&self, mq: &MessageQueue, msg: &MessageExt) -> Result<(), Error> {
+        let broker_addr = self.get_queue_broker_addr(mq).await?;
+        self.client
+            .send_message_back(
+                &broker_addr,
+                &self.group,
+                msg,
+                0,
+                self.options.max_reconsume_times,
+            )
+            .await
+    }
+
+    fn reset_retry_topic(&self, msgs: &mut [MessageExt]) {
+        let retry_topic = self.retry_topic();
+        for msg in msgs {
+            if msg.message.topic == retry_topic {
+                if let Some(topic) = msg.message.get_property(Property::RETRY_TOPIC).cloned() {
+                    debug!(consumer_group = %self.group, "reset retry topic to {}", topic);
+                    msg.message.topic = topic;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use super::super::offset_store::{OffsetStore, ReadType};
+    use super::super::process_queue::ProcessQueue;
+    use super::super::{ConsumeResult, ConsumerInner, ConsumerOptions, DeliverySemantics};
+    use crate::client::ClientOptions;
+    use crate::message::{MessageExt, MessageQueue};
+
+    fn new_consumer(semantics: DeliverySemantics, result: ConsumeResult) -> Arc<ConsumerInner> {
+        let mut options = ConsumerOptions::with_client_options(ClientOptions::new("test_group"));
+        options
+            .set_name_server(vec!["localhost:9876".to_string()])
+            .set_delivery_semantics(semantics);
+        let inner = ConsumerInner::new(options).unwrap();
+        inner
+            .listeners
+            .write()
+            .insert("test".to_string(), Arc::new(move |_| result));
+        Arc::new(inner)
+    }
+
+    fn messages(mq: &MessageQueue) -> Vec<MessageExt> {
+        (0..2)
+            .map(|offset| {
+                let mut msg = MessageExt::default();
+                msg.message.topic = mq.topic.clone();
+                msg.queue_offset = offset;
+                msg
+            })
+            .collect()
+    }
+
+    async fn consume(inner: &Arc<ConsumerInner>) -> (i64, usize) {
+        let mq = MessageQueue {
+            topic: "test".to_string(),
+            broker_name: "no-such-broker".to_string(),
+            queue_id: 0,
+        };
+        let pq = Arc::new(ProcessQueue::new());
+        let msgs = messages(&mq);
+        pq.put_messages(&msgs);
+        Arc::clone(inner)
+            .consume_messages(mq.clone(), Arc::clone(&pq), msgs)
+            .await;
+        (
+            inner.storage.read(&mq, ReadType::Memory).await,
+            pq.msg_count(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_at_least_once_success() {
+        let inner = new_consumer(DeliverySemantics::AtLeastOnce, ConsumeResult::Success);
+        assert_eq!((2, 0), consume(&inner).await);
+    }
+
+    #[tokio::test]
+    async fn test_at_least_once_redelivers_failed_messages() {
+        let inner = new_consumer(DeliverySemantics::AtLeastOnce, ConsumeResult::RetryLater);
+        // sending back fails without a broker, offset is not advanced
+        // and messages stay in the process queue to be consumed again
+        assert_eq!((0, 2), consume(&inner).await);
+    }
+
+    #[tokio::test]
+    async fn test_at_most_once_drops_failed_messages() {
+        let inner = new_consumer(DeliverySemantics::AtMostOnce, ConsumeResult::RetryLater);
+        assert_eq!((2, 0), consume(&inner).await);
+    }
+
+    #[tokio::test]
+    async fn test_consume_orderly() {
+        let mut options = ConsumerOptions::with_client_options(ClientOptions::new("test_group"));
+        options
+            .set_name_server(vec!["localhost:9876".to_string()])
+            .set_consume_orderly(true);
+        let inner = ConsumerInner::new(options).unwrap();
+        let consumed = Arc::new(Mutex::new(Vec::new()));
+        let listener_consumed = Arc::clone(&consumed);
+        inner.listeners.write().insert(
+            "test".to_string(),
+            Arc::new(move |msgs: &[MessageExt]| {
+                listener_consumed
+                    .lock()
+                    .extend(msgs.iter().map(|msg| msg.queue_offset));
+                ConsumeResult::Success
+            }),
+        );
+        let inner = Arc::new(inner);
+        assert!(inner.is_orderly());
+
+        let mq = MessageQueue {
+            topic: "test".to_string(),
+            broker_name: "no-such-broker".to_string(),
+            queue_id: 0,
+        };
+        let pq = Arc::new(ProcessQueue::new());
+        pq.put_messages(&messages(&mq));
+        pq.set_locked(true);
+        assert!(pq.start_consuming());
+        Arc::clone(&inner)
+            .consume_orderly(mq.clone(), Arc::clone(&pq))
+            .await;
+        assert_eq!(vec![0, 1], *consumed.lock());
+        assert_eq!(2, inner.storage.read(&mq, ReadType::Memory).await);
+        assert_eq!(0, pq.msg_count());
+        assert!(pq.start_consuming());
+    }
 }
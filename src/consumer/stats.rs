@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use time::OffsetDateTime;
+
+use super::process_queue::ProcessQueue;
+use crate::message::MessageQueue;
+
+const WINDOW_SECS: usize = 60;
+
+/// Sliding one minute counter with one second resolution
+#[derive(Debug, Clone)]
+struct RateCounter {
+    buckets: [u64; WINDOW_SECS],
+    seconds: [i64; WINDOW_SECS],
+    total: u64,
+}
+
+impl RateCounter {
+    fn new() -> Self {
+        Self {
+            buckets: [0; WINDOW_SECS],
+            seconds: [-1; WINDOW_SECS],
+            total: 0,
+        }
+    }
+
+    fn add(&mut self, now: i64, n: u64) {
+        let index = now.rem_euclid(WINDOW_SECS as i64) as usize;
+        if self.seconds[index] != now {
+            self.seconds[index] = now;
+            self.buckets[index] = 0;
+        }
+        self.buckets[index] += n;
+        self.total += n;
+    }
+
+    /// Average per second rate over the last minute
+    fn rate(&self, now: i64) -> f64 {
+        let sum: u64 = self
+            .seconds
+            .iter()
+            .zip(self.buckets.iter())
+            .filter(|(sec, _)| **sec >= 0 && now - **sec < WINDOW_SECS as i64 && now >= **sec)
+            .map(|(_, count)| *count)
+            .sum();
+        sum as f64 / WINDOW_SECS as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+struct QueueStats {
+    pulled: RateCounter,
+    consumed: RateCounter,
+    failed: RateCounter,
+}
+
+impl QueueStats {
+    fn new() -> Self {
+        Self {
+            pulled: RateCounter::new(),
+            consumed: RateCounter::new(),
+            failed: RateCounter::new(),
+        }
+    }
+}
+
+/// Metrics of a single message queue
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueueMetrics {
+    /// Pulled messages per second over the last minute
+    pub pull_rate: f64,
+    /// Successfully consumed messages per second over the last minute
+    pub consume_rate: f64,
+    /// Failed messages per second over the last minute
+    pub failure_rate: f64,
+    /// Total pulled messages
+    pub pulled_total: u64,
+    /// Total successfully consumed messages
+    pub consumed_total: u64,
+    /// Total failed messages
+    pub failed_total: u64,
+    /// Messages pulled and buffered locally but not consumed yet
+    pub ready_messages: usize,
+    /// Total body size of the buffered messages
+    pub ready_bytes: usize,
+    /// Estimated number of messages not consumed yet, including buffered ones
+    pub lag: i64,
+}
+
+/// Metrics of a topic, aggregated over its message queues
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TopicMetrics {
+    pub pull_rate: f64,
+    pub consume_rate: f64,
+    pub failure_rate: f64,
+    pub pulled_total: u64,
+    pub consumed_total: u64,
+    pub failed_total: u64,
+    pub ready_messages: usize,
+    pub ready_bytes: usize,
+    pub lag: i64,
+}
+
+impl TopicMetrics {
+    fn add(&mut self, queue: &QueueMetrics) {
+        self.pull_rate += queue.pull_rate;
+        self.consume_rate += queue.consume_rate;
+        self.failure_rate += queue.failure_rate;
+        self.pulled_total += queue.pulled_total;
+        self.consumed_total += queue.consumed_total;
+        self.failed_total += queue.failed_total;
+        self.ready_messages += queue.ready_messages;
+        self.ready_bytes += queue.ready_bytes;
+        self.lag += queue.lag;
+    }
+}
+
+/// Consumer metrics snapshot
+#[derive(Debug, Clone, Default)]
+pub struct ConsumerMetrics {
+    pub topics: HashMap<String, TopicMetrics>,
+    pub queues: HashMap<MessageQueue, QueueMetrics>,
+}
+
+/// Consume lag of a topic
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConsumerLag {
+    /// Lag of each message queue
+    pub queues: HashMap<MessageQueue, i64>,
+    /// Total lag of all message queues
+    pub total: i64,
+}
+
+impl ConsumerLag {
+    /// Record the lag of a message queue from its broker max offset and committed offset,
+    /// a negative committed offset means nothing has been committed yet
+    pub(crate) fn add(&mut self, mq: MessageQueue, max_offset: i64, committed_offset: i64) {
+        let lag = (max_offset - committed_offset.max(0)).max(0);
+        self.total += lag;
+        self.queues.insert(mq, lag);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConsumerStats {
+    #[cfg(feature = "metrics")]
+    group: String,
+    queues: Mutex<HashMap<MessageQueue, QueueStats>>,
+}
+
+impl ConsumerStats {
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn new(group: &str) -> Self {
+        Self {
+            #[cfg(feature = "metrics")]
+            group: group.to_string(),
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn now() -> i64 {
+        OffsetDateTime::now_utc().unix_timestamp()
+    }
+
+    pub fn record_pull(&self, mq: &MessageQueue, count: usize) {
+        self.queues
+            .lock()
+            .entry(mq.clone())
+            .or_insert_with(QueueStats::new)
+            .pulled
+            .add(Self::now(), count as u64);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "rocketmq_consumer_pulled_messages_total",
+            count as u64,
+            "group" => self.group.clone(),
+            "topic" => mq.topic.clone(),
+        );
+    }
+
+    pub fn record_consume_ok(&self, mq: &MessageQueue, count: usize) {
+        self.queues
+            .lock()
+            .entry(mq.clone())
+            .or_insert_with(QueueStats::new)
+            .consumed
+            .add(Self::now(), count as u64);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "rocketmq_consumer_consumed_messages_total",
+            count as u64,
+            "group" => self.group.clone(),
+            "topic" => mq.topic.clone(),
+        );
+    }
+
+    pub fn record_consume_failed(&self, mq: &MessageQueue, count: usize) {
+        self.queues
+            .lock()
+            .entry(mq.clone())
+            .or_insert_with(QueueStats::new)
+            .failed
+            .add(Self::now(), count as u64);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "rocketmq_consumer_failed_messages_total",
+            count as u64,
+            "group" => self.group.clone(),
+            "topic" => mq.topic.clone(),
+        );
+    }
+
+    /// Export the buffered messages and estimated lag of a topic, summed over its message queues
+    pub fn record_topic_state(
+        &self,
+        topic: &str,
+        process_queues: &HashMap<MessageQueue, Arc<ProcessQueue>>,
+    ) {
+        #[cfg(feature = "metrics")]
+        {
+            let mut state = TopicMetrics::default();
+            for (_, pq) in process_queues.iter().filter(|(mq, _)| mq.topic == topic) {
+                state.ready_messages += pq.msg_count();
+                state.ready_bytes += pq.msg_size();
+                state.lag += Self::lag_of(pq);
+            }
+            let labels = [("group", self.group.clone()), ("topic", topic.to_string())];
+            metrics::gauge!(
+                "rocketmq_consumer_ready_messages",
+                state.ready_messages as f64,
+                &labels
+            );
+            metrics::gauge!(
+                "rocketmq_consumer_ready_bytes",
+                state.ready_bytes as f64,
+                &labels
+            );
+            metrics::gauge!("rocketmq_consumer_lag", state.lag as f64, &labels);
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = (topic, process_queues);
+    }
+
+    pub fn remove(&self, mq: &MessageQueue) {
+        self.queues.lock().remove(mq);
+    }
+
+    fn lag_of(pq: &ProcessQueue) -> i64 {
+        pq.msg_acc_count() + pq.msg_count() as i64
+    }
+
+    pub fn snapshot(
+        &self,
+        process_queues: &HashMap<MessageQueue, Arc<ProcessQueue>>,
+    ) -> ConsumerMetrics {
+        let now = Self::now();
+        let queues = self.queues.lock();
+        let mut metrics = ConsumerMetrics::default();
+        let mqs = process_queues.keys().chain(queues.keys());
+        for mq in mqs {
+            if metrics.queues.contains_key(mq) {
+                continue;
+            }
+            let mut queue_metrics = QueueMetrics::default();
+            if let Some(stats) = queues.get(mq) {
+                queue_metrics.pull_rate = stats.pulled.rate(now);
+                queue_metrics.consume_rate = stats.consumed.rate(now);
+                queue_metrics.failure_rate = stats.failed.rate(now);
+                queue_metrics.pulled_total = stats.pulled.total;
+                queue_metrics.consumed_total = stats.consumed.total;
+                queue_metrics.failed_total = stats.failed.total;
+            }
+            if let Some(pq) = process_queues.get(mq) {
+                queue_metrics.ready_messages = pq.msg_count();
+                queue_metrics.ready_bytes = pq.msg_size();
+                queue_metrics.lag = Self::lag_of(pq);
+            }
+            metrics
+                .topics
+                .entry(mq.topic.clone())
+                .or_default()
+                .add(&queue_metrics);
+            metrics.queues.insert(mq.clone(), queue_metrics);
+        }
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::{ConsumerLag, ConsumerStats, RateCounter};
+    use crate::consumer::process_queue::ProcessQueue;
+    use crate::message::MessageQueue;
+
+    #[test]
+    fn test_rate_counter() {
+        let mut counter = RateCounter::new();
+        counter.add(100, 30);
+        counter.add(101, 30);
+        assert_eq!(1.0, counter.rate(101));
+        assert_eq!(0.5, counter.rate(160));
+        assert_eq!(0.0, counter.rate(161));
+        // bucket reused after a full window
+        counter.add(160, 6);
+        assert_eq!(0.6, counter.rate(160));
+        assert_eq!(66, counter.total);
+    }
+
+    #[test]
+    fn test_consumer_stats_snapshot() {
+        let stats = ConsumerStats::new("test_group");
+        let mq1 = MessageQueue {
+            topic: "test".to_string(),
+            broker_name: "broker-a".to_string(),
+            queue_id: 0,
+        };
+        let mq2 = MessageQueue {
+            queue_id: 1,
+            ..mq1.clone()
+        };
+        stats.record_pull(&mq1, 10);
+        stats.record_pull(&mq2, 20);
+        stats.record_consume_ok(&mq1, 8);
+        stats.record_consume_failed(&mq2, 2);
+        let mut process_queues = HashMap::new();
+        process_queues.insert(mq1.clone(), Arc::new(ProcessQueue::new()));
+        let metrics = stats.snapshot(&process_queues);
+        assert_eq!(2, metrics.queues.len());
+        assert_eq!(10, metrics.queues[&mq1].pulled_total);
+        assert_eq!(8, metrics.queues[&mq1].consumed_total);
+        assert_eq!(2, metrics.queues[&mq2].failed_total);
+        let topic = &metrics.topics["test"];
+        assert_eq!(30, topic.pulled_total);
+        assert_eq!(8, topic.consumed_total);
+        assert_eq!(2, topic.failed_total);
+        assert_eq!(0.5, topic.pull_rate);
+
+        stats.remove(&mq2);
+        let metrics = stats.snapshot(&process_queues);
+        assert_eq!(1, metrics.queues.len());
+    }
+
+    #[test]
+    fn test_consumer_lag() {
+        let mq = |queue_id| MessageQueue {
+            topic: "test".to_string(),
+            broker_name: "broker-a".to_string(),
+            queue_id,
+        };
+        let mut lag = ConsumerLag::default();
+        lag.add(mq(0), 100, 80);
+        lag.add(mq(1), 50, -1);
+        lag.add(mq(2), 10, 20);
+        assert_eq!(20, lag.queues[&mq(0)]);
+        assert_eq!(50, lag.queues[&mq(1)]);
+        assert_eq!(0, lag.queues[&mq(2)]);
+        assert_eq!(70, lag.total);
+    }
+}
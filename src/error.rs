@@ -1,6 +1,8 @@
 use std::string::FromUtf8Error;
 use std::{error, fmt, io};
 
+use crate::protocol::ResponseCode;
+
 #[derive(Debug)]
 pub enum Error {
     Connection(ConnectionError),
@@ -10,11 +12,104 @@ pub enum Error {
     InvalidUtf8(FromUtf8Error),
     InvalidHeaderCodec,
     InvalidHeader(String),
+    Decode(DecodeError),
+    InvalidGroupName(String),
+    DuplicateGroup(String),
+    /// A running client of the same client id uses another resolver or other credentials
+    ClientIdConflict(String),
     EmptyNameServers,
+    InvalidNameServerAddress(String),
+    Resolve(String),
     EmptyRouteData,
+    EmptyRouteBody(String),
+    RouteDecode {
+        topic: String,
+        source: Box<Error>,
+    },
     EmptyBatchMessage,
+    InvalidMessageId(String),
     TopicNotExist(String),
-    ResponseError { code: i16, message: String },
+    /// Neither a cluster nor a broker of this name has a master registered
+    BrokerNotFound(String),
+    UnsupportedByBroker {
+        addr: String,
+        version: i32,
+        feature: &'static str,
+    },
+    ResponseError {
+        code: i16,
+        message: String,
+    },
+    /// Non-OK status in a response of a 5.x proxy
+    #[cfg(feature = "grpc")]
+    ProxyError {
+        code: i32,
+        message: String,
+    },
+    /// Message received from a proxy has no receipt handle to ack it with
+    #[cfg(feature = "grpc")]
+    MissingReceiptHandle(String),
+    #[cfg(feature = "grpc")]
+    Grpc(Box<tonic::Status>),
+    #[cfg(feature = "grpc")]
+    GrpcTransport(tonic::transport::Error),
+}
+
+/// Category of an error, deciding whether the failed request can be retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// No response arrived in time, the request may or may not have been processed
+    Timeout,
+    /// Failed to connect or the connection was lost
+    Disconnected,
+    /// Broker is busy or not available for now
+    BrokerBusy,
+    /// Broker is no longer the leader of the queue, retry after refreshing the route
+    NotLeader,
+    /// Retrying won't help
+    Fatal,
+}
+
+impl ErrorKind {
+    /// Category of a non-OK response code
+    pub(crate) fn from_response_code(code: i16) -> Self {
+        match ResponseCode::from_code(code) {
+            ResponseCode::SystemBusy | ResponseCode::ServiceNotAvailable => ErrorKind::BrokerBusy,
+            ResponseCode::NotLeaderForQueue => ErrorKind::NotLeader,
+            _ => ErrorKind::Fatal,
+        }
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Connection(ConnectionError::Timeout) => ErrorKind::Timeout,
+            // fail fast until the circuit breaker lets connection attempts through again
+            Error::Connection(ConnectionError::Shutdown | ConnectionError::CircuitOpen) => {
+                ErrorKind::Fatal
+            }
+            Error::Connection(_) => ErrorKind::Disconnected,
+            Error::Io(err) if err.kind() == io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            Error::Io(_) => ErrorKind::Disconnected,
+            Error::ResponseError { code, .. } => ErrorKind::from_response_code(*code),
+            #[cfg(feature = "grpc")]
+            Error::Grpc(status) => match status.code() {
+                tonic::Code::DeadlineExceeded => ErrorKind::Timeout,
+                tonic::Code::Unavailable => ErrorKind::Disconnected,
+                tonic::Code::ResourceExhausted => ErrorKind::BrokerBusy,
+                _ => ErrorKind::Fatal,
+            },
+            #[cfg(feature = "grpc")]
+            Error::GrpcTransport(_) => ErrorKind::Disconnected,
+            _ => ErrorKind::Fatal,
+        }
+    }
+
+    /// Whether the failed request may succeed when sent again
+    pub fn is_retriable(&self) -> bool {
+        self.kind() != ErrorKind::Fatal
+    }
 }
 
 impl fmt::Display for Error {
@@ -27,13 +122,62 @@ impl fmt::Display for Error {
             Error::InvalidUtf8(err) => err.fmt(f),
             Error::InvalidHeaderCodec => write!(f, "invalid header codec"),
             Error::InvalidHeader(ref err) => write!(f, "invalid header: {}", err),
+            Error::Decode(err) => err.fmt(f),
+            Error::InvalidGroupName(ref err) => write!(f, "invalid group name: {}", err),
+            Error::DuplicateGroup(ref group) => {
+                write!(f, "group {} is already registered in this client", group)
+            }
+            Error::ClientIdConflict(ref client_id) => write!(
+                f,
+                "client {} exists with another name server resolver or credentials, use another instance name",
+                client_id
+            ),
             Error::EmptyNameServers => write!(f, "name server addresses are empty"),
+            Error::InvalidNameServerAddress(ref addr) => {
+                write!(f, "invalid name server address: {}", addr)
+            }
+            Error::Resolve(ref err) => write!(f, "resolve name server addresses failed: {}", err),
             Error::EmptyRouteData => write!(f, "route data is empty"),
+            Error::EmptyRouteBody(ref topic) => {
+                write!(
+                    f,
+                    "name server returned empty route data of topic {}",
+                    topic
+                )
+            }
+            Error::RouteDecode { topic, source } => {
+                write!(f, "decode route data of topic {} failed: {}", topic, source)
+            }
             Error::EmptyBatchMessage => write!(f, "batch message is empty"),
+            Error::InvalidMessageId(ref id) => write!(f, "invalid offset message id: {}", id),
             Error::TopicNotExist(ref topic) => write!(f, "topic {} not exist", topic),
+            Error::BrokerNotFound(ref name) => {
+                write!(f, "no master broker found in cluster or broker {}", name)
+            }
+            Error::UnsupportedByBroker {
+                addr,
+                version,
+                feature,
+            } => write!(
+                f,
+                "broker {} of version {} does not support {}",
+                addr, version, feature
+            ),
             Error::ResponseError { code, message } => {
                 write!(f, "response error, code: {}, message: {}", code, message)
             }
+            #[cfg(feature = "grpc")]
+            Error::ProxyError { code, message } => {
+                write!(f, "proxy error, code: {}, message: {}", code, message)
+            }
+            #[cfg(feature = "grpc")]
+            Error::MissingReceiptHandle(ref id) => {
+                write!(f, "message {} has no receipt handle", id)
+            }
+            #[cfg(feature = "grpc")]
+            Error::Grpc(status) => status.fmt(f),
+            #[cfg(feature = "grpc")]
+            Error::GrpcTransport(err) => err.fmt(f),
         }
     }
 }
@@ -46,6 +190,12 @@ impl error::Error for Error {
             Error::Io(err) => Some(err),
             Error::Json(err) => Some(err),
             Error::InvalidUtf8(err) => Some(err),
+            Error::Decode(err) => Some(err),
+            Error::RouteDecode { source, .. } => Some(source.as_ref()),
+            #[cfg(feature = "grpc")]
+            Error::Grpc(status) => Some(status.as_ref()),
+            #[cfg(feature = "grpc")]
+            Error::GrpcTransport(err) => Some(err),
             _ => None,
         }
     }
@@ -56,6 +206,12 @@ pub enum ConnectionError {
     Disconnected,
     Canceled,
     Shutdown,
+    /// No response arrived within the request timeout
+    Timeout,
+    /// The connection was closed while waiting for the response
+    Closed,
+    /// Connecting is suspended after repeated failures
+    CircuitOpen,
 }
 
 impl fmt::Display for ConnectionError {
@@ -64,6 +220,11 @@ impl fmt::Display for ConnectionError {
             ConnectionError::Disconnected => write!(f, "disconnected"),
             ConnectionError::Canceled => write!(f, "canceled request"),
             ConnectionError::Shutdown => write!(f, "the connection was shut down"),
+            ConnectionError::Timeout => write!(f, "request timed out"),
+            ConnectionError::Closed => write!(f, "the connection was closed"),
+            ConnectionError::CircuitOpen => {
+                write!(f, "connecting suspended after repeated failures")
+            }
         }
     }
 }
@@ -89,6 +250,44 @@ impl fmt::Display for ClientError {
 
 impl error::Error for ClientError {}
 
+/// Malformed frame received from a server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeError {
+    /// Frame length is too small to hold the header length
+    InvalidFrameLength(i32),
+    /// Frame length exceeds the maximum frame size
+    FrameTooLarge { length: usize, max: usize },
+    /// Header length exceeds the frame length
+    InvalidHeaderLength { header_len: usize, frame_len: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidFrameLength(length) => write!(f, "invalid frame length {}", length),
+            DecodeError::FrameTooLarge { length, max } => {
+                write!(f, "frame length {} exceeds the maximum {}", length, max)
+            }
+            DecodeError::InvalidHeaderLength {
+                header_len,
+                frame_len,
+            } => write!(
+                f,
+                "header length {} exceeds the frame length {}",
+                header_len, frame_len
+            ),
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Self {
+        Self::Decode(err)
+    }
+}
+
 impl From<ConnectionError> for Error {
     fn from(err: ConnectionError) -> Self {
         Self::Connection(err)
@@ -112,3 +311,53 @@ impl From<FromUtf8Error> for Error {
         Self::InvalidUtf8(err)
     }
 }
+
+#[cfg(feature = "grpc")]
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Self::Grpc(Box::new(status))
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<tonic::transport::Error> for Error {
+    fn from(err: tonic::transport::Error) -> Self {
+        Self::GrpcTransport(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::{ConnectionError, Error, ErrorKind};
+
+    #[test]
+    fn test_error_kind() {
+        let response = |code| Error::ResponseError {
+            code,
+            message: String::new(),
+        };
+        assert_eq!(
+            ErrorKind::Timeout,
+            Error::Connection(ConnectionError::Timeout).kind()
+        );
+        assert_eq!(
+            ErrorKind::Timeout,
+            Error::Io(io::ErrorKind::TimedOut.into()).kind()
+        );
+        assert_eq!(
+            ErrorKind::Disconnected,
+            Error::Connection(ConnectionError::Closed).kind()
+        );
+        assert_eq!(ErrorKind::BrokerBusy, response(2).kind());
+        assert_eq!(ErrorKind::NotLeader, response(501).kind());
+        assert_eq!(ErrorKind::Fatal, response(17).kind());
+        assert!(!Error::EmptyBatchMessage.is_retriable());
+        assert!(!Error::Connection(ConnectionError::Shutdown).is_retriable());
+        assert_eq!(
+            ErrorKind::Fatal,
+            Error::Connection(ConnectionError::CircuitOpen).kind()
+        );
+    }
+}
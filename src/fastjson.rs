@@ -0,0 +1,250 @@
+//! JSON bodies in the dialect of fastjson, which the Java servers use
+//!
+//! fastjson writes non-string map keys unquoted, e.g. `{0:"127.0.0.1:10911"}` for
+//! broker addresses, which isn't valid JSON. Stats bodies even use objects like
+//! message queues as map keys.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+
+use crate::Error;
+
+/// Maximum number of nested objects and arrays, the same as `serde_json`
+const MAX_DEPTH: usize = 128;
+
+/// Deserialize a fastjson body, bodies which aren't valid JSON are fixed up first
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    match serde_json::from_slice(bytes) {
+        Ok(value) => Ok(value),
+        Err(_) => {
+            let s = String::from_utf8(bytes.to_vec())?;
+            let json = dirty_json::fix(&s);
+            Ok(serde_json::from_str(&json)?)
+        }
+    }
+}
+
+/// Deserialize a fastjson body whose maps may have object keys, such maps are read
+/// as lists of key and value pairs, see `deserialize_object_key_map`
+pub fn from_slice_with_object_keys<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let mut parser = Parser {
+        bytes,
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.parse_value()?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Deserialize a map of a body read by `from_slice_with_object_keys`
+pub fn deserialize_object_key_map<'de, D, K, V>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de> + Eq + Hash,
+    V: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Table<K, V> {
+        Entries(Vec<(K, V)>),
+        // an empty map, which is kept as an object
+        Empty {},
+    }
+
+    Ok(match Table::deserialize(deserializer)? {
+        Table::Entries(entries) => entries.into_iter().collect(),
+        Table::Empty {} => HashMap::new(),
+    })
+}
+
+/// Parser of fastjson documents, nested containers are parsed here and scalars by `serde_json`
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // number of containers being parsed
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn parse_value(&mut self) -> Result<Value, serde_json::Error> {
+        match self.peek() {
+            Some(b'{') => self.parse_nested(Self::parse_object),
+            Some(b'[') => self.parse_nested(Self::parse_array),
+            _ => self.parse_scalar(),
+        }
+    }
+
+    /// Parse a container, failing instead of overflowing the stack on deeply nested input
+    fn parse_nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Value, serde_json::Error>,
+    ) -> Result<Value, serde_json::Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("recursion limit exceeded"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_object(&mut self) -> Result<Value, serde_json::Error> {
+        self.pos += 1;
+        let mut entries = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(Map::new()));
+        }
+        loop {
+            let key = self.parse_value()?;
+            self.expect(b':')?;
+            let value = self.parse_value()?;
+            entries.push((key, value));
+            match self.next() {
+                Some(b',') => continue,
+                Some(b'}') => break,
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+        if entries
+            .iter()
+            .all(|(key, _)| !key.is_object() && !key.is_array())
+        {
+            return Ok(Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| match key {
+                        Value::String(key) => (key, value),
+                        key => (key.to_string(), value),
+                    })
+                    .collect(),
+            ));
+        }
+        Ok(Value::Array(
+            entries
+                .into_iter()
+                .map(|(key, value)| Value::Array(vec![key, value]))
+                .collect(),
+        ))
+    }
+
+    fn parse_array(&mut self) -> Result<Value, serde_json::Error> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            match self.next() {
+                Some(b',') => continue,
+                Some(b']') => return Ok(Value::Array(values)),
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_scalar(&mut self) -> Result<Value, serde_json::Error> {
+        let mut stream =
+            serde_json::Deserializer::from_slice(&self.bytes[self.pos..]).into_iter::<Value>();
+        let value = stream
+            .next()
+            .unwrap_or_else(|| Err(self.error("unexpected end of input")))?;
+        self.pos += stream.byte_offset();
+        Ok(value)
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), serde_json::Error> {
+        if self.next() == Some(byte) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", byte as char)))
+        }
+    }
+
+    /// Next byte after whitespaces, without consuming it
+    fn peek(&mut self) -> Option<u8> {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn error(&self, msg: &str) -> serde_json::Error {
+        de::Error::custom(format!("{} at byte {}", msg, self.pos))
+    }
+}
+
+/// Serialize a body, fastjson reads standard JSON
+pub fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    Ok(serde_json::to_vec(value)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use serde_json::Value;
+
+    use super::{
+        deserialize_object_key_map, from_slice, from_slice_with_object_keys, to_vec, MAX_DEPTH,
+    };
+    use crate::message::MessageQueue;
+
+    #[test]
+    fn test_from_slice() {
+        let map: HashMap<i64, String> = from_slice(br#"{0:"127.0.0.1:10911"}"#).unwrap();
+        assert_eq!("127.0.0.1:10911", map[&0]);
+        let map: HashMap<String, i32> = from_slice(br#"{"a":1}"#).unwrap();
+        assert_eq!(1, map["a"]);
+        assert!(from_slice::<HashMap<String, i32>>(b"\xff").is_err());
+        assert_eq!(b"{\"a\":1}".to_vec(), to_vec(&map).unwrap());
+    }
+
+    #[test]
+    fn test_from_slice_with_object_keys() {
+        #[derive(Deserialize)]
+        struct Table {
+            #[serde(deserialize_with = "deserialize_object_key_map")]
+            table: HashMap<MessageQueue, i64>,
+            addrs: HashMap<i64, String>,
+        }
+
+        let body = br#"{"table":{{"brokerName":"broker-a","queueId":1,"topic":"topic"}:42, {"brokerName":"broker-a","queueId":2,"topic":"topic"}:43},"addrs":{0:"127.0.0.1:10911"}}"#;
+        let table: Table = from_slice_with_object_keys(body).unwrap();
+        let mq = MessageQueue {
+            topic: "topic".to_string(),
+            broker_name: "broker-a".to_string(),
+            queue_id: 2,
+        };
+        assert_eq!(2, table.table.len());
+        assert_eq!(43, table.table[&mq]);
+        assert_eq!("127.0.0.1:10911", table.addrs[&0]);
+
+        let table: Table = from_slice_with_object_keys(br#"{"table":{},"addrs":{}}"#).unwrap();
+        assert!(table.table.is_empty());
+        assert!(from_slice_with_object_keys::<Table>(br#"{"table":{"#).is_err());
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(from_slice_with_object_keys::<Value>(nested(MAX_DEPTH).as_bytes()).is_ok());
+        assert!(from_slice_with_object_keys::<Value>(nested(MAX_DEPTH + 1).as_bytes()).is_err());
+        let deep = "{0:".repeat(100_000);
+        assert!(from_slice_with_object_keys::<Value>(deep.as_bytes()).is_err());
+        assert!(from_slice::<Value>(deep.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,32 @@
+//! Entry points of the fuzz targets in `fuzz/`, only built by `cargo fuzz`
+
+use bytes::{Bytes, BytesMut};
+use tokio_util::codec::Decoder;
+
+use crate::fastjson;
+use crate::message::MessageExt;
+use crate::protocol::{HeaderCodec, JsonHeaderCodec, MqCodec, RocketMQHeaderCodec};
+
+/// Decode frames of a remoting stream until it is exhausted or invalid
+pub fn decode_frames(data: &[u8]) {
+    let mut codec = MqCodec::new(64 * 1024);
+    let mut buf = BytesMut::from(data);
+    while let Ok(Some(_)) = codec.decode(&mut buf) {}
+}
+
+/// Decode a command header with both header codecs
+pub fn decode_header(data: &[u8]) {
+    let _ = JsonHeaderCodec.decode(data);
+    let _ = RocketMQHeaderCodec.decode(data);
+}
+
+/// Parse a fastjson body of a broker or name server
+pub fn decode_json(data: &[u8]) {
+    let _ = fastjson::from_slice::<serde_json::Value>(data);
+    let _ = fastjson::from_slice_with_object_keys::<serde_json::Value>(data);
+}
+
+/// Decode the messages of a pull response body
+pub fn decode_messages(data: &[u8]) {
+    let _ = MessageExt::decode(&Bytes::copy_from_slice(data));
+}
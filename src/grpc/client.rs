@@ -0,0 +1,309 @@
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, Endpoint};
+
+use super::v2::{self, messaging_service_client::MessagingServiceClient};
+use crate::client::ClientOptions;
+use crate::Error;
+
+const PROTOCOL_VERSION: &str = "v2";
+const LANGUAGE: &str = "RUST";
+
+/// Client of the `MessagingService` of RocketMQ 5.x proxies
+#[derive(Debug, Clone)]
+pub struct GrpcClient {
+    pub(crate) inner: MessagingServiceClient<Channel>,
+    client_id: String,
+    namespace: String,
+    pub(crate) request_timeout: Duration,
+    pub(crate) runtime: Option<Handle>,
+}
+
+impl GrpcClient {
+    /// Connect to the proxy at `endpoint`, e.g. `http://127.0.0.1:8081`
+    pub async fn connect(endpoint: &str, options: &ClientOptions) -> Result<Self, Error> {
+        let channel = Endpoint::from_shared(endpoint.to_string())?
+            .connect()
+            .await?;
+        Ok(Self::with_channel(channel, options))
+    }
+
+    /// Use an already configured channel, e.g. one with TLS or connect timeouts set
+    pub fn with_channel(channel: Channel, options: &ClientOptions) -> Self {
+        Self {
+            inner: MessagingServiceClient::new(channel),
+            client_id: options.client_id(),
+            namespace: options.namespace.clone(),
+            request_timeout: Duration::from_secs(3),
+            runtime: options.runtime.clone(),
+        }
+    }
+
+    /// Timeout of unary requests, defaults to 3s
+    pub fn set_request_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Resource `name` in the namespace of this client
+    pub fn resource(&self, name: &str) -> v2::Resource {
+        v2::Resource {
+            resource_namespace: self.namespace.clone(),
+            name: name.to_string(),
+        }
+    }
+
+    /// Wrap `message` in a request carrying the client metadata the proxy expects
+    pub(crate) fn request<T>(&self, message: T, timeout: Duration) -> tonic::Request<T> {
+        let mut request = self.stream_request(message);
+        request.set_timeout(timeout);
+        request
+    }
+
+    /// Like `request` but without a deadline, for long lived streams
+    pub(crate) fn stream_request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        let metadata = request.metadata_mut();
+        metadata.insert("x-mq-language", AsciiMetadataValue::from_static(LANGUAGE));
+        metadata.insert(
+            "x-mq-protocol",
+            AsciiMetadataValue::from_static(PROTOCOL_VERSION),
+        );
+        metadata.insert(
+            "x-mq-client-version",
+            AsciiMetadataValue::from_static(env!("CARGO_PKG_VERSION")),
+        );
+        if let Ok(client_id) = self.client_id.parse() {
+            metadata.insert("x-mq-client-id", client_id);
+        }
+        if !self.namespace.is_empty() {
+            if let Ok(namespace) = self.namespace.parse() {
+                metadata.insert("x-mq-namespace", namespace);
+            }
+        }
+        request
+    }
+
+    /// Message queues of `topic`
+    pub async fn query_route(&self, topic: &str) -> Result<Vec<v2::MessageQueue>, Error> {
+        let request = v2::QueryRouteRequest {
+            topic: Some(self.resource(topic)),
+            endpoints: None,
+        };
+        let res = self
+            .inner
+            .clone()
+            .query_route(self.request(request, self.request_timeout))
+            .await?
+            .into_inner();
+        check_status(res.status)?;
+        Ok(res.message_queues)
+    }
+
+    pub async fn heartbeat(
+        &self,
+        group: Option<&str>,
+        client_type: v2::ClientType,
+    ) -> Result<(), Error> {
+        let request = v2::HeartbeatRequest {
+            group: group.map(|group| self.resource(group)),
+            client_type: client_type.into(),
+        };
+        let res = self
+            .inner
+            .clone()
+            .heartbeat(self.request(request, self.request_timeout))
+            .await?
+            .into_inner();
+        check_status(res.status)
+    }
+
+    /// Send messages, returns one result entry per message
+    pub async fn send_message(
+        &self,
+        messages: Vec<v2::Message>,
+    ) -> Result<Vec<v2::SendResultEntry>, Error> {
+        let request = v2::SendMessageRequest { messages };
+        let res = self
+            .inner
+            .clone()
+            .send_message(self.request(request, self.request_timeout))
+            .await?
+            .into_inner();
+        check_status(res.status)?;
+        Ok(res.entries)
+    }
+
+    /// Receive messages, waiting up to the long polling timeout of `request` for them
+    pub async fn receive_message(
+        &self,
+        request: v2::ReceiveMessageRequest,
+    ) -> Result<Vec<v2::Message>, Error> {
+        let long_polling_timeout = request
+            .long_polling_timeout
+            .and_then(|timeout| Duration::try_from(timeout).ok())
+            .unwrap_or_default();
+        let timeout = self.request_timeout + long_polling_timeout;
+        let mut stream = self
+            .inner
+            .clone()
+            .receive_message(self.request(request, timeout))
+            .await?
+            .into_inner();
+        let mut messages = Vec::new();
+        while let Some(res) = stream.message().await? {
+            match res.content {
+                Some(v2::receive_message_response::Content::Message(message)) => {
+                    messages.push(message)
+                }
+                Some(v2::receive_message_response::Content::Status(status)) => {
+                    if status.code() != v2::Code::MessageNotFound {
+                        check_status(Some(status))?;
+                    }
+                }
+                Some(v2::receive_message_response::Content::DeliveryTimestamp(_)) | None => {}
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Acknowledge received messages, returns one result entry per message
+    pub async fn ack_message(
+        &self,
+        group: &str,
+        topic: &str,
+        entries: Vec<v2::AckMessageEntry>,
+    ) -> Result<Vec<v2::AckMessageResultEntry>, Error> {
+        let request = v2::AckMessageRequest {
+            group: Some(self.resource(group)),
+            topic: Some(self.resource(topic)),
+            entries,
+        };
+        let res = self
+            .inner
+            .clone()
+            .ack_message(self.request(request, self.request_timeout))
+            .await?
+            .into_inner();
+        check_status(res.status)?;
+        Ok(res.entries)
+    }
+
+    /// Change how long a received message stays invisible to other consumers,
+    /// returns the new receipt handle of the message
+    pub async fn change_invisible_duration(
+        &self,
+        group: &str,
+        topic: &str,
+        message_id: &str,
+        receipt_handle: &str,
+        invisible_duration: Duration,
+    ) -> Result<String, Error> {
+        let request = v2::ChangeInvisibleDurationRequest {
+            group: Some(self.resource(group)),
+            topic: Some(self.resource(topic)),
+            receipt_handle: receipt_handle.to_string(),
+            invisible_duration: prost_types::Duration::try_from(invisible_duration).ok(),
+            message_id: message_id.to_string(),
+        };
+        let res = self
+            .inner
+            .clone()
+            .change_invisible_duration(self.request(request, self.request_timeout))
+            .await?
+            .into_inner();
+        check_status(res.status)?;
+        Ok(res.receipt_handle)
+    }
+
+    /// Move a received message to the dead letter queue of `group`
+    pub async fn forward_message_to_dead_letter_queue(
+        &self,
+        group: &str,
+        topic: &str,
+        message_id: &str,
+        receipt_handle: &str,
+        delivery_attempt: i32,
+        max_delivery_attempts: i32,
+    ) -> Result<(), Error> {
+        let request = v2::ForwardMessageToDeadLetterQueueRequest {
+            group: Some(self.resource(group)),
+            topic: Some(self.resource(topic)),
+            receipt_handle: receipt_handle.to_string(),
+            message_id: message_id.to_string(),
+            delivery_attempt,
+            max_delivery_attempts,
+        };
+        let res = self
+            .inner
+            .clone()
+            .forward_message_to_dead_letter_queue(self.request(request, self.request_timeout))
+            .await?
+            .into_inner();
+        check_status(res.status)
+    }
+}
+
+/// Turn a non-OK proxy status into an error
+pub(crate) fn check_status(status: Option<v2::Status>) -> Result<(), Error> {
+    match status {
+        Some(status) if status.code() != v2::Code::Ok => Err(Error::ProxyError {
+            code: status.code,
+            message: status.message,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{check_status, GrpcClient};
+    use crate::client::ClientOptions;
+    use crate::grpc::v2;
+    use crate::Error;
+
+    #[test]
+    fn test_check_status() {
+        assert!(check_status(None).is_ok());
+        let ok = v2::Status {
+            code: v2::Code::Ok.into(),
+            message: "OK".to_string(),
+        };
+        assert!(check_status(Some(ok)).is_ok());
+        let not_found = v2::Status {
+            code: v2::Code::TopicNotFound.into(),
+            message: "topic not found".to_string(),
+        };
+        assert!(matches!(
+            check_status(Some(not_found)),
+            Err(Error::ProxyError { code: 40402, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_metadata() {
+        let mut options = ClientOptions::default();
+        options.set_namespace("ns");
+        let channel =
+            tonic::transport::Endpoint::from_static("http://127.0.0.1:8081").connect_lazy();
+        let client = GrpcClient::with_channel(channel, &options);
+        let request = client.request(v2::HeartbeatRequest::default(), Duration::from_secs(1));
+        let metadata = request.metadata();
+        assert_eq!("RUST", metadata.get("x-mq-language").unwrap());
+        assert_eq!("v2", metadata.get("x-mq-protocol").unwrap());
+        assert_eq!("ns", metadata.get("x-mq-namespace").unwrap());
+        assert_eq!(
+            client.client_id(),
+            metadata.get("x-mq-client-id").unwrap().to_str().unwrap()
+        );
+        assert_eq!("ns", client.resource("topic").resource_namespace);
+    }
+}
@@ -0,0 +1,14 @@
+#[allow(clippy::all)]
+pub mod v2 {
+    //! Protobuf types and the `MessagingService` client of `apache.rocketmq.v2`,
+    //! generated from `proto/` by `cargo run --manifest-path codegen/Cargo.toml`
+    include!("apache.rocketmq.v2.rs");
+}
+
+mod client;
+mod simple_consumer;
+mod telemetry;
+
+pub use client::GrpcClient;
+pub use simple_consumer::SimpleConsumer;
+pub use telemetry::{NoopTelemetryHandler, TelemetryHandler, TelemetrySession};
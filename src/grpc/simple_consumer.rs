@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use parking_lot::{Mutex, RwLock};
+
+use super::v2;
+use super::GrpcClient;
+use crate::consumer::{ExpressionType, MessageSelector};
+use crate::Error;
+
+/// Consumer of 5.x proxies which receives messages on demand and acks them one by one
+///
+/// Received messages stay invisible to other consumers of the group for the
+/// invisible duration, messages not acked in time are delivered again.
+#[derive(Debug)]
+pub struct SimpleConsumer {
+    client: GrpcClient,
+    group: String,
+    await_duration: Duration,
+    subscriptions: RwLock<HashMap<String, MessageSelector>>,
+    routes: Mutex<HashMap<String, Vec<v2::MessageQueue>>>,
+    // round robin position among the subscribed topics
+    topic_index: AtomicUsize,
+    // round robin position among the queues of every topic
+    queue_index: Mutex<HashMap<String, usize>>,
+}
+
+impl SimpleConsumer {
+    pub fn new(client: GrpcClient, group: &str) -> Self {
+        Self {
+            client,
+            group: group.to_string(),
+            await_duration: Duration::from_secs(30),
+            subscriptions: RwLock::new(HashMap::new()),
+            routes: Mutex::new(HashMap::new()),
+            topic_index: AtomicUsize::new(0),
+            queue_index: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Long polling timeout of `receive` when no message is available, defaults to 30s
+    pub fn set_await_duration(&mut self, duration: Duration) -> &mut Self {
+        self.await_duration = duration;
+        self
+    }
+
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+
+    pub fn subscribe(&self, topic: &str, selector: MessageSelector) {
+        self.subscriptions
+            .write()
+            .insert(topic.to_string(), selector);
+    }
+
+    pub fn unsubscribe(&self, topic: &str) {
+        self.subscriptions.write().remove(topic);
+        self.routes.lock().remove(topic);
+        self.queue_index.lock().remove(topic);
+    }
+
+    /// Receive up to `max_messages` messages of the subscribed topics, which are
+    /// invisible to other consumers for `invisible_duration`
+    pub async fn receive(
+        &self,
+        max_messages: i32,
+        invisible_duration: Duration,
+    ) -> Result<Vec<v2::Message>, Error> {
+        let (topic, selector) = match self.select_topic() {
+            Some(subscription) => subscription,
+            None => return Ok(Vec::new()),
+        };
+        let mq = self.select_queue(&topic).await?;
+        let request = v2::ReceiveMessageRequest {
+            group: Some(self.client.resource(&self.group)),
+            message_queue: Some(mq),
+            filter_expression: Some(filter_expression(&selector)),
+            batch_size: max_messages,
+            invisible_duration: prost_types::Duration::try_from(invisible_duration).ok(),
+            auto_renew: false,
+            long_polling_timeout: prost_types::Duration::try_from(self.await_duration).ok(),
+        };
+        let res = self.client.receive_message(request).await;
+        if res.is_err() {
+            self.routes.lock().remove(&topic);
+        }
+        res
+    }
+
+    /// Acknowledge a received message so it is not delivered again
+    pub async fn ack(&self, message: &v2::Message) -> Result<(), Error> {
+        let (topic, message_id, receipt_handle) = receipt(message)?;
+        let entry = v2::AckMessageEntry {
+            message_id: message_id.to_string(),
+            receipt_handle: receipt_handle.to_string(),
+        };
+        let entries = self
+            .client
+            .ack_message(&self.group, topic, vec![entry])
+            .await?;
+        for entry in entries {
+            super::client::check_status(entry.status)?;
+        }
+        Ok(())
+    }
+
+    /// Change how long a received message stays invisible, updating its receipt
+    /// handle for later acks
+    pub async fn change_invisible_duration(
+        &self,
+        message: &mut v2::Message,
+        invisible_duration: Duration,
+    ) -> Result<(), Error> {
+        let (topic, message_id, receipt_handle) = receipt(message)?;
+        let receipt_handle = self
+            .client
+            .change_invisible_duration(
+                &self.group,
+                topic,
+                message_id,
+                receipt_handle,
+                invisible_duration,
+            )
+            .await?;
+        if let Some(properties) = message.system_properties.as_mut() {
+            properties.receipt_handle = Some(receipt_handle);
+        }
+        Ok(())
+    }
+
+    /// Move a received message to the dead letter queue of the group
+    pub async fn forward_to_dead_letter_queue(
+        &self,
+        message: &v2::Message,
+        max_delivery_attempts: i32,
+    ) -> Result<(), Error> {
+        let (topic, message_id, receipt_handle) = receipt(message)?;
+        let delivery_attempt = message
+            .system_properties
+            .as_ref()
+            .and_then(|properties| properties.delivery_attempt)
+            .unwrap_or(1);
+        self.client
+            .forward_message_to_dead_letter_queue(
+                &self.group,
+                topic,
+                message_id,
+                receipt_handle,
+                delivery_attempt,
+                max_delivery_attempts,
+            )
+            .await
+    }
+
+    /// Pick a subscribed topic round robin
+    fn select_topic(&self) -> Option<(String, MessageSelector)> {
+        let subscriptions = self.subscriptions.read();
+        if subscriptions.is_empty() {
+            return None;
+        }
+        let index = self.topic_index.fetch_add(1, Ordering::Relaxed);
+        let mut topics: Vec<_> = subscriptions.iter().collect();
+        topics.sort_by(|a, b| a.0.cmp(b.0));
+        let (topic, selector) = topics[index % topics.len()];
+        Some((topic.clone(), selector.clone()))
+    }
+
+    /// Pick a readable queue of `topic` round robin, querying the route when not cached
+    async fn select_queue(&self, topic: &str) -> Result<v2::MessageQueue, Error> {
+        let cached = self.routes.lock().get(topic).cloned();
+        let queues = match cached {
+            Some(queues) => queues,
+            None => {
+                let queues: Vec<_> = self
+                    .client
+                    .query_route(topic)
+                    .await?
+                    .into_iter()
+                    .filter(is_readable)
+                    .collect();
+                self.routes.lock().insert(topic.to_string(), queues.clone());
+                queues
+            }
+        };
+        if queues.is_empty() {
+            self.routes.lock().remove(topic);
+            return Err(Error::TopicNotExist(topic.to_string()));
+        }
+        let index = {
+            let mut queue_index = self.queue_index.lock();
+            let next = queue_index.entry(topic.to_string()).or_default();
+            let index = *next;
+            *next = index.wrapping_add(1);
+            index
+        };
+        Ok(queues[index % queues.len()].clone())
+    }
+}
+
+fn is_readable(mq: &v2::MessageQueue) -> bool {
+    matches!(
+        mq.permission(),
+        v2::Permission::Read | v2::Permission::ReadWrite
+    )
+}
+
+fn filter_expression(selector: &MessageSelector) -> v2::FilterExpression {
+    let filter_type = match selector.expression_type {
+        ExpressionType::Tag => v2::FilterType::Tag,
+        ExpressionType::Sql92 => v2::FilterType::Sql,
+    };
+    v2::FilterExpression {
+        r#type: filter_type.into(),
+        expression: selector.expression.clone(),
+    }
+}
+
+/// Topic, message id and receipt handle of a received message
+fn receipt(message: &v2::Message) -> Result<(&str, &str, &str), Error> {
+    let topic = message
+        .topic
+        .as_ref()
+        .map(|topic| topic.name.as_str())
+        .unwrap_or_default();
+    let properties = message.system_properties.as_ref();
+    let message_id = properties
+        .map(|properties| properties.message_id.as_str())
+        .unwrap_or_default();
+    match properties.and_then(|properties| properties.receipt_handle.as_deref()) {
+        Some(receipt_handle) => Ok((topic, message_id, receipt_handle)),
+        None => Err(Error::MissingReceiptHandle(message_id.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{filter_expression, is_readable, receipt, SimpleConsumer};
+    use crate::client::ClientOptions;
+    use crate::consumer::MessageSelector;
+    use crate::grpc::{v2, GrpcClient};
+    use crate::Error;
+
+    #[test]
+    fn test_receipt() {
+        let mut message = v2::Message {
+            topic: Some(v2::Resource {
+                resource_namespace: String::new(),
+                name: "topic".to_string(),
+            }),
+            system_properties: Some(v2::SystemProperties {
+                message_id: "id".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            receipt(&message),
+            Err(Error::MissingReceiptHandle(id)) if id == "id"
+        ));
+        message.system_properties.as_mut().unwrap().receipt_handle = Some("handle".to_string());
+        assert_eq!(("topic", "id", "handle"), receipt(&message).unwrap());
+    }
+
+    #[test]
+    fn test_queue_and_filter() {
+        let mut mq = v2::MessageQueue {
+            permission: v2::Permission::Write.into(),
+            ..Default::default()
+        };
+        assert!(!is_readable(&mq));
+        mq.permission = v2::Permission::ReadWrite.into();
+        assert!(is_readable(&mq));
+
+        let filter = filter_expression(&MessageSelector::sql92("a > 1"));
+        assert_eq!(v2::FilterType::Sql, filter.r#type());
+        assert_eq!("a > 1", filter.expression);
+    }
+
+    #[tokio::test]
+    async fn test_select_every_queue_of_every_topic() {
+        let channel =
+            tonic::transport::Endpoint::from_static("http://127.0.0.1:8081").connect_lazy();
+        let client = GrpcClient::with_channel(channel, &ClientOptions::default());
+        let consumer = SimpleConsumer::new(client, "group");
+        for topic in ["a", "b"] {
+            consumer.subscribe(topic, MessageSelector::default());
+            let queues = (0..4)
+                .map(|id| v2::MessageQueue {
+                    topic: Some(v2::Resource {
+                        resource_namespace: String::new(),
+                        name: topic.to_string(),
+                    }),
+                    id,
+                    permission: v2::Permission::ReadWrite.into(),
+                    ..Default::default()
+                })
+                .collect();
+            consumer.routes.lock().insert(topic.to_string(), queues);
+        }
+        let mut selected = HashSet::new();
+        for _ in 0..8 {
+            let (topic, _) = consumer.select_topic().unwrap();
+            let mq = consumer.select_queue(&topic).await.unwrap();
+            selected.insert((topic, mq.id));
+        }
+        assert_eq!(8, selected.len());
+    }
+}
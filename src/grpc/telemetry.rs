@@ -0,0 +1,260 @@
+use std::backtrace::Backtrace;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, info, warn};
+
+use super::v2::{self, telemetry_command::Command};
+use super::GrpcClient;
+use crate::utils::spawn;
+use crate::Error;
+
+/// Handler of commands a proxy sends over the telemetry stream
+pub trait TelemetryHandler: Send + Sync + 'static {
+    /// Settings sent by the proxy, like the backoff policy and the publishing or
+    /// subscription config
+    fn on_settings(&self, _settings: &v2::Settings) {}
+
+    /// Check whether `message` can be consumed, the proxy asks for this when
+    /// verifying a consumer from the dashboard
+    fn verify_message(&self, _message: &v2::Message) -> v2::Status {
+        status(v2::Code::NotImplemented, "verify message is not supported")
+    }
+
+    /// Resolve an orphaned transaction of a transactional producer
+    fn recover_orphaned_transaction(&self, _message: v2::Message, _transaction_id: String) {}
+}
+
+/// Telemetry handler keeping the defaults of every callback, settings are only
+/// recorded by the session and message verification is not supported
+#[derive(Debug, Default)]
+pub struct NoopTelemetryHandler;
+
+impl TelemetryHandler for NoopTelemetryHandler {}
+
+/// Bidirectional telemetry stream with a proxy, closed on drop
+#[derive(Debug)]
+pub struct TelemetrySession {
+    tx: mpsc::Sender<v2::TelemetryCommand>,
+    settings: Arc<Mutex<Option<v2::Settings>>>,
+    task: JoinHandle<()>,
+}
+
+impl TelemetrySession {
+    /// Latest settings received from the proxy
+    pub fn settings(&self) -> Option<v2::Settings> {
+        self.settings.lock().clone()
+    }
+
+    /// Send updated client settings, e.g. after subscribing to another topic
+    pub async fn update_settings(&self, settings: v2::Settings) -> Result<(), Error> {
+        self.tx
+            .send(settings_command(settings))
+            .await
+            .map_err(|_| Error::Connection(crate::error::ConnectionError::Disconnected))
+    }
+}
+
+impl Drop for TelemetrySession {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl GrpcClient {
+    /// Client settings sent when opening a telemetry stream
+    pub fn settings(
+        &self,
+        client_type: v2::ClientType,
+        pub_sub: v2::settings::PubSub,
+    ) -> v2::Settings {
+        v2::Settings {
+            client_type: Some(client_type.into()),
+            access_point: None,
+            backoff_policy: None,
+            request_timeout: prost_types::Duration::try_from(self.request_timeout).ok(),
+            pub_sub: Some(pub_sub),
+            user_agent: Some(v2::Ua {
+                language: v2::Language::Rust.into(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                platform: std::env::consts::OS.to_string(),
+                hostname: self
+                    .client_id()
+                    .split('@')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            }),
+            metric: None,
+        }
+    }
+
+    /// Open the telemetry stream, sending `settings` first
+    ///
+    /// Commands from the proxy are answered by `handler` until the session is dropped.
+    pub async fn telemetry(
+        &self,
+        settings: v2::Settings,
+        handler: Arc<dyn TelemetryHandler>,
+    ) -> Result<TelemetrySession, Error> {
+        let (tx, rx) = mpsc::channel(16);
+        tx.send(settings_command(settings)).await.ok();
+        let request = self.stream_request(ReceiverStream::new(rx));
+        let mut stream = self.inner.clone().telemetry(request).await?.into_inner();
+        let received = Arc::new(Mutex::new(None));
+        let reply_tx = tx.clone();
+        let settings = Arc::clone(&received);
+        let task = spawn(self.runtime.as_ref(), async move {
+            loop {
+                match stream.message().await {
+                    Ok(Some(cmd)) => {
+                        if let Some(reply) = handle_command(cmd, handler.as_ref(), &settings) {
+                            if reply_tx.send(reply).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Ok(None) => {
+                        info!("telemetry stream closed by proxy");
+                        break;
+                    }
+                    Err(err) => {
+                        warn!(error = %err, "telemetry stream failed");
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(TelemetrySession {
+            tx,
+            settings: received,
+            task,
+        })
+    }
+}
+
+fn status(code: v2::Code, message: &str) -> v2::Status {
+    v2::Status {
+        code: code.into(),
+        message: message.to_string(),
+    }
+}
+
+fn settings_command(settings: v2::Settings) -> v2::TelemetryCommand {
+    v2::TelemetryCommand {
+        status: None,
+        command: Some(Command::Settings(settings)),
+    }
+}
+
+/// Handle a command from the proxy, returns the reply to send if any
+fn handle_command(
+    cmd: v2::TelemetryCommand,
+    handler: &dyn TelemetryHandler,
+    settings: &Mutex<Option<v2::Settings>>,
+) -> Option<v2::TelemetryCommand> {
+    let command = match cmd.command? {
+        Command::Settings(received) => {
+            debug!(settings = ?received, "received settings from proxy");
+            handler.on_settings(&received);
+            *settings.lock() = Some(received);
+            return None;
+        }
+        Command::PrintThreadStackTraceCommand(print) => {
+            Command::ThreadStackTrace(v2::ThreadStackTrace {
+                nonce: print.nonce,
+                thread_stack_trace: Some(Backtrace::force_capture().to_string()),
+            })
+        }
+        Command::VerifyMessageCommand(verify) => {
+            let message = verify.message.unwrap_or_default();
+            return Some(v2::TelemetryCommand {
+                status: Some(handler.verify_message(&message)),
+                command: Some(Command::VerifyMessageResult(v2::VerifyMessageResult {
+                    nonce: verify.nonce,
+                })),
+            });
+        }
+        Command::RecoverOrphanedTransactionCommand(recover) => {
+            if let Some(message) = recover.message {
+                handler.recover_orphaned_transaction(message, recover.transaction_id);
+            }
+            return None;
+        }
+        Command::ThreadStackTrace(_) | Command::VerifyMessageResult(_) => return None,
+    };
+    Some(v2::TelemetryCommand {
+        status: Some(status(v2::Code::Ok, "OK")),
+        command: Some(command),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use parking_lot::Mutex;
+
+    use super::{handle_command, NoopTelemetryHandler};
+    use crate::grpc::v2::{self, telemetry_command::Command};
+
+    fn command(command: Command) -> v2::TelemetryCommand {
+        v2::TelemetryCommand {
+            status: None,
+            command: Some(command),
+        }
+    }
+
+    #[test]
+    fn test_handle_command() {
+        let settings = Mutex::new(None);
+        let handler = NoopTelemetryHandler;
+
+        let received = v2::Settings {
+            backoff_policy: Some(v2::RetryPolicy {
+                max_attempts: 16,
+                strategy: None,
+            }),
+            ..Default::default()
+        };
+        assert!(handle_command(
+            command(Command::Settings(received.clone())),
+            &handler,
+            &settings
+        )
+        .is_none());
+        assert_eq!(Some(received), *settings.lock());
+
+        let print = v2::PrintThreadStackTraceCommand {
+            nonce: "nonce-1".to_string(),
+        };
+        let reply = handle_command(
+            command(Command::PrintThreadStackTraceCommand(print)),
+            &handler,
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(v2::Code::Ok, reply.status.unwrap().code());
+        assert!(matches!(
+            reply.command,
+            Some(Command::ThreadStackTrace(v2::ThreadStackTrace { nonce, .. })) if nonce == "nonce-1"
+        ));
+
+        let verify = v2::VerifyMessageCommand {
+            nonce: "nonce-2".to_string(),
+            message: Some(v2::Message::default()),
+        };
+        let reply = handle_command(
+            command(Command::VerifyMessageCommand(verify)),
+            &handler,
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(v2::Code::NotImplemented, reply.status.unwrap().code());
+        assert!(matches!(
+            reply.command,
+            Some(Command::VerifyMessageResult(v2::VerifyMessageResult { nonce })) if nonce == "nonce-2"
+        ));
+    }
+}
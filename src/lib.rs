@@ -1,8 +1,21 @@
+#[macro_use]
+mod macros;
+
+/// RocketMQ admin client
+pub mod admin;
 mod client;
 /// RocketMQ consumer
 pub mod consumer;
 mod error;
+mod fastjson;
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzzing;
+/// gRPC protocol of RocketMQ 5.x proxies
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod message;
+mod namespace;
 mod namesrv;
 mod permission;
 /// RocketMQ producer
@@ -14,7 +27,24 @@ pub mod resolver;
 mod route;
 mod utils;
 
+pub use admin::{
+    AdminOptions, ClientConnection, ConsumeStats, ConsumerConnection, MqAdmin, OffsetWrapper,
+    SubscriptionData, SubscriptionGroupConfig, TopicConfig, TopicOffset, TopicStatsTable,
+};
+pub use client::{
+    ClientMetrics, ClientOptions, ConnectionMetrics, Credentials, CredentialsProvider, RetryOn,
+    RetryPolicy, SignatureMethod,
+};
 pub use consumer::{ConsumerOptions, PushConsumer};
-pub use error::Error;
+pub use error::{Error, ErrorKind};
 pub use message::Message;
+pub use namesrv::NameServerSelection;
+pub use permission::Permission;
 pub use producer::{Producer, ProducerOptions};
+pub use protocol::{HeaderCodecType, HeaderValue, LanguageCode, RemotingCommand};
+#[cfg(feature = "test-util")]
+pub use remoting::FaultInjection;
+#[cfg(feature = "tls")]
+pub use remoting::TlsConfig;
+pub use remoting::{ConnectionEvent, DisconnectReason, RpcHook, TraceContext};
+pub use route::{BrokerData, ClusterInfo, QueueData, TopicList, TopicRouteData};
@@ -0,0 +1,15 @@
+/// Attach a span to a future, the span is compiled out without the `tracing` feature
+#[cfg(feature = "tracing")]
+macro_rules! in_span {
+    ($fut:expr, $span:expr $(,)?) => {
+        tracing_futures::Instrument::instrument($fut, $span)
+    };
+}
+
+/// Attach a span to a future, the span is compiled out without the `tracing` feature
+#[cfg(not(feature = "tracing"))]
+macro_rules! in_span {
+    ($fut:expr, $span:expr $(,)?) => {
+        $fut
+    };
+}
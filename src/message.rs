@@ -1,10 +1,11 @@
 use std::collections::HashMap;
-use std::io::{Cursor, Read, Write};
-use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::io::{self, Cursor, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::process;
 use std::time::SystemTime;
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Bytes;
 use flate2::read::ZlibDecoder;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use once_cell::sync::Lazy;
@@ -12,6 +13,8 @@ use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
 
+use tracing::warn;
+
 use crate::utils::client_ip_addr;
 use crate::Error;
 
@@ -75,10 +78,18 @@ pub enum MessageSysFlag {
     MultiTags = 0x2,
     TransactionPreparedType = 0x4,
     TransactionCommitType = 0x8,
-    TransactionRollbackType = 0x12,
+    TransactionRollbackType = 0xc,
+    /// Born host is a 16 byte IPv6 address
+    BornHostV6 = 0x10,
+    /// Store host is a 16 byte IPv6 address
+    StoreHostV6 = 0x20,
+    /// Body holds a batch of inner messages which must be unwrapped by the client
+    NeedUnwrap = 0x40,
+    /// Body holds a batch of inner messages stored as a single entry
+    InnerBatch = 0x80,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct MessageQueue {
     pub topic: String,
     #[serde(rename = "brokerName")]
@@ -87,13 +98,13 @@ pub struct MessageQueue {
     pub queue_id: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Message {
     pub(crate) topic: String,
     pub(crate) flag: i32,
     pub(crate) sys_flag: i32,
     properties: HashMap<String, String>,
-    pub(crate) body: Vec<u8>,
+    pub(crate) body: Bytes,
     transaction_id: String,
     pub(crate) batch: bool,
     pub(crate) queue: Option<MessageQueue>,
@@ -105,7 +116,7 @@ impl Message {
         tags: String,
         keys: String,
         flag: i32,
-        body: Vec<u8>,
+        body: impl Into<Bytes>,
         wait_store_msg_ok: bool,
     ) -> Message {
         let mut props = HashMap::new();
@@ -122,7 +133,7 @@ impl Message {
             topic,
             flag,
             sys_flag: 0,
-            body,
+            body: body.into(),
             properties: props,
             transaction_id: String::new(),
             batch: false,
@@ -167,6 +178,31 @@ impl Message {
         &self.topic
     }
 
+    #[inline]
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn tags(&self) -> Option<&str> {
+        self.properties.get(Property::TAGS).and_then(|val| {
+            if val.is_empty() {
+                None
+            } else {
+                Some(&val[..])
+            }
+        })
+    }
+
+    pub fn keys(&self) -> Option<&str> {
+        self.properties.get(Property::KEYS).and_then(|val| {
+            if val.is_empty() {
+                None
+            } else {
+                Some(&val[..])
+            }
+        })
+    }
+
     pub(crate) fn dump_properties(&self) -> String {
         let mut s = String::new();
         for (k, v) in &self.properties {
@@ -223,7 +259,7 @@ impl Message {
                 flag: 0,
                 sys_flag: 0,
                 properties: HashMap::new(),
-                body,
+                body: body.into(),
                 transaction_id: String::new(),
                 batch: true,
             })
@@ -234,146 +270,314 @@ impl Message {
 #[derive(Debug, Clone)]
 pub struct MessageExt {
     pub(crate) message: Message,
-    queue_id: i32,
+    pub(crate) queue_id: i32,
     store_size: i32,
-    queue_offset: i64,
+    pub(crate) queue_offset: i64,
     sys_flag: i32,
-    born_host: SocketAddrV4,
+    born_host: SocketAddr,
     born_timestamp: i64,
-    store_host: SocketAddrV4,
+    store_host: SocketAddr,
     store_timestamp: i64,
     pub(crate) msg_id: String,
     pub(crate) commit_log_offset: i64,
     body_crc: i32,
-    reconsume_times: i32,
+    pub(crate) reconsume_times: i32,
     prepared_transaction_offset: i64,
 }
 
+impl Default for MessageExt {
+    fn default() -> Self {
+        Self {
+            message: Message::default(),
+            queue_id: 0,
+            store_size: 0,
+            queue_offset: 0,
+            sys_flag: 0,
+            born_host: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            born_timestamp: 0,
+            store_host: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            store_timestamp: 0,
+            msg_id: String::new(),
+            commit_log_offset: 0,
+            body_crc: 0,
+            reconsume_times: 0,
+            prepared_transaction_offset: 0,
+        }
+    }
+}
+
 impl MessageExt {
-    pub fn decode(input: &[u8]) -> Vec<Self> {
+    /// Decode messages of a pull response, uncompressed bodies share `input` without copying
+    ///
+    /// Decoding stops at the first malformed message, returning the ones before it.
+    pub fn decode(input: &Bytes) -> Vec<Self> {
         let input_len = input.len() as u64;
-        let mut rdr = Cursor::new(input);
+        let mut rdr = Cursor::new(&input[..]);
         let mut msgs = Vec::new();
         while rdr.position() < input_len {
-            let store_size = rdr.read_i32::<BigEndian>().unwrap();
-            let magic_code = rdr.read_i32::<BigEndian>().unwrap();
-            if magic_code != -626843481 {
-                // TODO: check
-            }
-            let body_crc = rdr.read_i32::<BigEndian>().unwrap();
-            let queue_id = rdr.read_i32::<BigEndian>().unwrap();
-            let flag = rdr.read_i32::<BigEndian>().unwrap();
-            let queue_offset = rdr.read_i64::<BigEndian>().unwrap();
-            let physic_offset = rdr.read_i64::<BigEndian>().unwrap();
-            let sys_flag = rdr.read_i32::<BigEndian>().unwrap();
-            let born_timestamp = rdr.read_i64::<BigEndian>().unwrap();
-            let mut born_host_buf = [0u8; 4];
-            rdr.read_exact(&mut born_host_buf).unwrap();
-            let born_host_port = rdr.read_i32::<BigEndian>().unwrap();
-            let born_host = SocketAddrV4::new(
-                Ipv4Addr::new(
-                    born_host_buf[0],
-                    born_host_buf[1],
-                    born_host_buf[2],
-                    born_host_buf[3],
-                ),
-                born_host_port as u16,
-            );
-            let store_timestamp = rdr.read_i64::<BigEndian>().unwrap();
-            let mut store_host_buf = [0u8; 4];
-            rdr.read_exact(&mut store_host_buf).unwrap();
-            let store_host_port = rdr.read_i32::<BigEndian>().unwrap();
-            let store_host = SocketAddrV4::new(
-                Ipv4Addr::new(
-                    store_host_buf[0],
-                    store_host_buf[1],
-                    store_host_buf[2],
-                    store_host_buf[3],
-                ),
-                store_host_port as u16,
-            );
-
-            let reconsume_times = rdr.read_i32::<BigEndian>().unwrap();
-            let prepared_transaction_offset = rdr.read_i64::<BigEndian>().unwrap();
-
-            // Body
-            let body_len = rdr.read_i32::<BigEndian>().unwrap();
-            let body = {
-                if body_len > 0 {
-                    let mut body = vec![0; body_len as usize];
-                    rdr.read_exact(&mut body).unwrap();
-                    // decompress
-                    if false {
-                        let mut decoder = ZlibDecoder::new(&body[..]);
-                        let mut body_buf = Vec::new();
-                        decoder.read_to_end(&mut body_buf).unwrap();
-                        body_buf
-                    } else {
-                        body
-                    }
-                } else {
-                    Vec::new()
+            let msg_ex = match Self::decode_one(input, &mut rdr) {
+                Ok(msg_ex) => msg_ex,
+                Err(err) => {
+                    warn!(
+                        position = rdr.position(),
+                        error = %err,
+                        "decode pulled messages failed"
+                    );
+                    break;
                 }
             };
+            let batch_flags =
+                i32::from(MessageSysFlag::InnerBatch) | i32::from(MessageSysFlag::NeedUnwrap);
+            if msg_ex.sys_flag & batch_flags != 0 {
+                msgs.extend(msg_ex.decode_inner_batch());
+            } else {
+                msgs.push(msg_ex);
+            }
+        }
+        msgs
+    }
 
-            let topic_len = rdr.read_u8().unwrap();
-            let mut topic_buf = vec![0; topic_len as usize];
-            rdr.read_exact(&mut topic_buf).unwrap();
-            let topic = String::from_utf8(topic_buf).unwrap();
-
-            let properties_len = rdr.read_i16::<BigEndian>().unwrap();
-            let properties = {
-                if properties_len > 0 {
-                    let mut properties_buf = vec![0; properties_len as usize];
-                    rdr.read_exact(&mut properties_buf).unwrap();
-                    let properties_str = String::from_utf8(properties_buf).unwrap();
-                    Message::parse_properties(&properties_str)
+    fn decode_one(input: &Bytes, rdr: &mut Cursor<&[u8]>) -> io::Result<Self> {
+        let store_size = rdr.read_i32::<BigEndian>()?;
+        let magic_code = rdr.read_i32::<BigEndian>()?;
+        if magic_code != -626843481 {
+            // TODO: check
+        }
+        let body_crc = rdr.read_i32::<BigEndian>()?;
+        let queue_id = rdr.read_i32::<BigEndian>()?;
+        let flag = rdr.read_i32::<BigEndian>()?;
+        let queue_offset = rdr.read_i64::<BigEndian>()?;
+        let physic_offset = rdr.read_i64::<BigEndian>()?;
+        let sys_flag = rdr.read_i32::<BigEndian>()?;
+        let born_timestamp = rdr.read_i64::<BigEndian>()?;
+        let born_host = Self::read_host(rdr, sys_flag, MessageSysFlag::BornHostV6)?;
+        let store_timestamp = rdr.read_i64::<BigEndian>()?;
+        let store_host = Self::read_host(rdr, sys_flag, MessageSysFlag::StoreHostV6)?;
+
+        let reconsume_times = rdr.read_i32::<BigEndian>()?;
+        let prepared_transaction_offset = rdr.read_i64::<BigEndian>()?;
+
+        // Body
+        let body_len = rdr.read_i32::<BigEndian>()?;
+        let body = {
+            if body_len > 0 {
+                let start = rdr.position() as usize;
+                let end = start + body_len as usize;
+                if end > input.len() {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                let body = input.slice(start..end);
+                rdr.set_position(end as u64);
+                // decompress
+                let compressed_flag: i32 = MessageSysFlag::Compressed.into();
+                if sys_flag & compressed_flag == compressed_flag {
+                    let mut decoder = ZlibDecoder::new(&body[..]);
+                    let mut body_buf = Vec::new();
+                    decoder.read_to_end(&mut body_buf)?;
+                    Bytes::from(body_buf)
                 } else {
-                    HashMap::new()
+                    body
                 }
-            };
+            } else {
+                Bytes::new()
+            }
+        };
+
+        let topic_len = rdr.read_u8()?;
+        let mut topic_buf = vec![0; topic_len as usize];
+        rdr.read_exact(&mut topic_buf)?;
+        let topic = String::from_utf8(topic_buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let properties_len = rdr.read_i16::<BigEndian>()?;
+        let properties = {
+            if properties_len > 0 {
+                let mut properties_buf = vec![0; properties_len as usize];
+                rdr.read_exact(&mut properties_buf)?;
+                let properties_str = String::from_utf8(properties_buf)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                Message::parse_properties(&properties_str)
+            } else {
+                HashMap::new()
+            }
+        };
 
-            let message = Message {
-                topic,
-                flag,
-                sys_flag: 0,
-                properties,
-                body,
-                transaction_id: String::new(),
-                batch: false,
-                queue: None,
-            };
-            let msg_id = message
-                .unique_key()
-                .map(|key| key.to_string())
-                .unwrap_or_else(|| {
-                    Self::get_message_offset_id(store_host_buf, store_host_port, physic_offset)
-                });
-            let msg_ex = MessageExt {
-                message,
-                queue_id,
-                store_size,
-                queue_offset,
-                sys_flag,
-                born_host,
-                born_timestamp,
-                store_host,
-                store_timestamp,
-                msg_id,
-                commit_log_offset: physic_offset,
-                body_crc,
-                reconsume_times,
-                prepared_transaction_offset,
-            };
-            msgs.push(msg_ex);
+        let message = Message {
+            topic,
+            flag,
+            sys_flag: 0,
+            properties,
+            body,
+            transaction_id: String::new(),
+            batch: false,
+            queue: None,
+        };
+        let msg_id = message
+            .unique_key()
+            .map(|key| key.to_string())
+            .unwrap_or_else(|| Self::get_message_offset_id(store_host, physic_offset));
+        Ok(MessageExt {
+            message,
+            queue_id,
+            store_size,
+            queue_offset,
+            sys_flag,
+            born_host,
+            born_timestamp,
+            store_host,
+            store_timestamp,
+            msg_id,
+            commit_log_offset: physic_offset,
+            body_crc,
+            reconsume_times,
+            prepared_transaction_offset,
+        })
+    }
+
+    /// Unwrap the inner messages of a batch message, which take consecutive queue
+    /// offsets starting at the offset of the batch
+    fn decode_inner_batch(self) -> Vec<Self> {
+        let body = self.message.body.clone();
+        let mut rdr = Cursor::new(&body[..]);
+        let mut msgs = Vec::new();
+        while (rdr.position() as usize) < body.len() {
+            match self.decode_inner(&body, &mut rdr, msgs.len() as i64) {
+                Ok(msg) => msgs.push(msg),
+                Err(err) => {
+                    warn!(msg_id = %self.msg_id, error = %err, "decode inner batch messages failed");
+                    return vec![self];
+                }
+            }
         }
         msgs
     }
 
-    fn get_message_offset_id(store_host: [u8; 4], port: i32, commit_offset: i64) -> String {
+    fn decode_inner(&self, body: &Bytes, rdr: &mut Cursor<&[u8]>, index: i64) -> io::Result<Self> {
+        let _total_size = rdr.read_i32::<BigEndian>()?;
+        let _magic_code = rdr.read_i32::<BigEndian>()?;
+        let body_crc = rdr.read_i32::<BigEndian>()?;
+        let flag = rdr.read_i32::<BigEndian>()?;
+        let body_len = rdr.read_i32::<BigEndian>()?;
+        let start = rdr.position() as usize;
+        let end = usize::try_from(body_len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+            .filter(|end| *end <= body.len())
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        rdr.set_position(end as u64);
+        let properties_len = rdr.read_u16::<BigEndian>()?;
+        let mut properties_buf = vec![0; properties_len as usize];
+        rdr.read_exact(&mut properties_buf)?;
+
+        let mut msg = self.clone();
+        msg.message.flag = flag;
+        msg.message.body = body.slice(start..end);
+        msg.message.properties =
+            Message::parse_properties(&String::from_utf8_lossy(&properties_buf));
+        msg.body_crc = body_crc;
+        msg.queue_offset = self.queue_offset + index;
+        msg.sys_flag &=
+            !(i32::from(MessageSysFlag::InnerBatch) | i32::from(MessageSysFlag::NeedUnwrap));
+        if let Some(key) = msg.message.unique_key() {
+            msg.msg_id = key.to_string();
+        }
+        Ok(msg)
+    }
+
+    #[inline]
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    #[inline]
+    pub fn msg_id(&self) -> &str {
+        &self.msg_id
+    }
+
+    #[inline]
+    pub fn queue_id(&self) -> i32 {
+        self.queue_id
+    }
+
+    #[inline]
+    pub fn queue_offset(&self) -> i64 {
+        self.queue_offset
+    }
+
+    #[inline]
+    pub fn reconsume_times(&self) -> i32 {
+        self.reconsume_times
+    }
+
+    #[inline]
+    pub fn born_timestamp(&self) -> i64 {
+        self.born_timestamp
+    }
+
+    #[inline]
+    pub fn store_timestamp(&self) -> i64 {
+        self.store_timestamp
+    }
+
+    #[inline]
+    pub fn born_host(&self) -> SocketAddr {
+        self.born_host
+    }
+
+    #[inline]
+    pub fn store_host(&self) -> SocketAddr {
+        self.store_host
+    }
+
+    /// Decode the store host and commit log offset of an offset message id
+    pub fn decode_message_id(msg_id: &str) -> Result<(SocketAddr, i64), Error> {
+        let invalid = || Error::InvalidMessageId(msg_id.to_string());
+        let bytes = hex::decode(msg_id).map_err(|_| invalid())?;
+        let ip_len = match bytes.len() {
+            16 => 4,
+            28 => 16,
+            _ => return Err(invalid()),
+        };
+        let mut rdr = Cursor::new(&bytes[ip_len..]);
+        let port = rdr.read_i32::<BigEndian>()?;
+        let offset = rdr.read_i64::<BigEndian>()?;
+        let ip = if ip_len == 4 {
+            IpAddr::from(<[u8; 4]>::try_from(&bytes[..4]).unwrap())
+        } else {
+            IpAddr::from(<[u8; 16]>::try_from(&bytes[..16]).unwrap())
+        };
+        let port = u16::try_from(port).map_err(|_| invalid())?;
+        Ok((SocketAddr::new(ip, port), offset))
+    }
+
+    /// Read a host address, which is IPv6 when `v6_flag` is set in `sys_flag`
+    fn read_host(
+        rdr: &mut Cursor<&[u8]>,
+        sys_flag: i32,
+        v6_flag: MessageSysFlag,
+    ) -> io::Result<SocketAddr> {
+        let v6_flag = i32::from(v6_flag);
+        let ip = if sys_flag & v6_flag == v6_flag {
+            let mut buf = [0u8; 16];
+            rdr.read_exact(&mut buf)?;
+            IpAddr::from(buf)
+        } else {
+            let mut buf = [0u8; 4];
+            rdr.read_exact(&mut buf)?;
+            IpAddr::from(buf)
+        };
+        let port = rdr.read_i32::<BigEndian>()?;
+        Ok(SocketAddr::new(ip, port as u16))
+    }
+
+    fn get_message_offset_id(store_host: SocketAddr, commit_offset: i64) -> String {
         let mut wtr = Vec::new();
-        wtr.write_all(&store_host).unwrap();
-        wtr.write_i32::<BigEndian>(port).unwrap();
+        match store_host.ip() {
+            IpAddr::V4(ip) => wtr.write_all(&ip.octets()).unwrap(),
+            IpAddr::V6(ip) => wtr.write_all(&ip.octets()).unwrap(),
+        }
+        wtr.write_i32::<BigEndian>(store_host.port() as i32)
+            .unwrap();
         wtr.write_i64::<BigEndian>(commit_offset).unwrap();
         hex::encode(wtr)
     }
@@ -425,30 +629,26 @@ impl UniqueIdGenerator {
 
 #[cfg(test)]
 mod test {
-    use super::MessageExt;
-    use std::net::{Ipv4Addr, SocketAddrV4};
+    use super::{Message, MessageExt, MessageSysFlag};
+    use bytes::Bytes;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::net::SocketAddr;
 
     #[test]
     fn test_decode_message_ext() {
-        let bytes = [
-            0, 0, 0, 123, 218, 163, 32, 167, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 123, 0, 0, 0, 0, 0, 1, 226, 64, 0, 0, 0, 0, 0, 0, 1, 104, 106, 154, 142, 143, 127,
-            0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 168, 2, 248, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 104, 101, 108, 108, 111, 33, 113, 33, 3, 97, 98,
-            99, 0, 21, 97, 1, 49, 50, 51, 2, 98, 1, 104, 101, 108, 108, 111, 2, 99, 1, 51, 46, 49,
-            52, 2,
-        ];
-        let msgs = MessageExt::decode(&bytes[..]);
+        let bytes = Bytes::from_static(ENCODED_MESSAGE);
+        let msgs = MessageExt::decode(&bytes);
         assert_eq!(1, msgs.len());
         let msg = &msgs[0];
         assert_eq!("abc", msg.message.topic);
         assert_eq!(b"hello!q!", &msg.message.body[..]);
+        // the body is a view into the pull response
+        let body_pos = bytes.windows(8).position(|w| w == b"hello!q!").unwrap();
+        assert_eq!(bytes[body_pos..].as_ptr(), msg.message.body.as_ptr());
+        assert_eq!("127.0.0.1:0".parse::<SocketAddr>().unwrap(), msg.born_host);
         assert_eq!(
-            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0),
-            msg.born_host
-        );
-        assert_eq!(
-            SocketAddrV4::new(Ipv4Addr::new(192, 168, 2, 248), 0),
+            "192.168.2.248:0".parse::<SocketAddr>().unwrap(),
             msg.store_host
         );
         assert_eq!(123456, msg.commit_log_offset);
@@ -461,6 +661,145 @@ mod test {
         assert_eq!("3.14", &msg.message.properties["c"]);
     }
 
+    const ENCODED_MESSAGE: &[u8] = &[
+        0, 0, 0, 123, 218, 163, 32, 167, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        123, 0, 0, 0, 0, 0, 1, 226, 64, 0, 0, 0, 0, 0, 0, 1, 104, 106, 154, 142, 143, 127, 0, 0, 1,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 168, 2, 248, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 8, 104, 101, 108, 108, 111, 33, 113, 33, 3, 97, 98, 99, 0, 21, 97, 1,
+        49, 50, 51, 2, 98, 1, 104, 101, 108, 108, 111, 2, 99, 1, 51, 46, 49, 52, 2,
+    ];
+
+    proptest! {
+        #[test]
+        fn prop_decode_truncated(len in 0..ENCODED_MESSAGE.len()) {
+            let mut input = ENCODED_MESSAGE.to_vec();
+            input.extend_from_slice(&ENCODED_MESSAGE[..len]);
+            // the complete first message is kept, the truncated one dropped
+            prop_assert_eq!(1, MessageExt::decode(&Bytes::from(input)).len());
+        }
+
+        #[test]
+        fn prop_decode_arbitrary_bytes(data in vec(any::<u8>(), 0..512)) {
+            let _ = MessageExt::decode(&Bytes::from(data));
+        }
+
+        #[test]
+        fn prop_decode_corrupted(index in 0..ENCODED_MESSAGE.len(), byte in any::<u8>()) {
+            let mut input = ENCODED_MESSAGE.to_vec();
+            input[index] = byte;
+            prop_assert!(MessageExt::decode(&Bytes::from(input)).len() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_decode_ipv6_hosts() {
+        use byteorder::{BigEndian, WriteBytesExt};
+
+        let sys_flag =
+            i32::from(MessageSysFlag::BornHostV6) | i32::from(MessageSysFlag::StoreHostV6);
+        let mut buf = Vec::new();
+        buf.write_i32::<BigEndian>(0).unwrap(); // store size
+        buf.write_i32::<BigEndian>(-626843481).unwrap(); // magic
+        buf.write_i32::<BigEndian>(0).unwrap(); // body crc
+        buf.write_i32::<BigEndian>(1).unwrap(); // queue id
+        buf.write_i32::<BigEndian>(0).unwrap(); // flag
+        buf.write_i64::<BigEndian>(7).unwrap(); // queue offset
+        buf.write_i64::<BigEndian>(42).unwrap(); // physical offset
+        buf.write_i32::<BigEndian>(sys_flag).unwrap();
+        buf.write_i64::<BigEndian>(0).unwrap(); // born timestamp
+        buf.extend_from_slice(&"fe80::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        buf.write_i32::<BigEndian>(5000).unwrap();
+        buf.write_i64::<BigEndian>(0).unwrap(); // store timestamp
+        buf.extend_from_slice(&std::net::Ipv6Addr::LOCALHOST.octets());
+        buf.write_i32::<BigEndian>(10911).unwrap();
+        buf.write_i32::<BigEndian>(0).unwrap(); // reconsume times
+        buf.write_i64::<BigEndian>(0).unwrap(); // prepared transaction offset
+        buf.write_i32::<BigEndian>(2).unwrap();
+        buf.extend_from_slice(b"hi");
+        buf.write_u8(5).unwrap();
+        buf.extend_from_slice(b"topic");
+        buf.write_i16::<BigEndian>(0).unwrap();
+
+        let msgs = MessageExt::decode(&Bytes::from(buf));
+        assert_eq!(1, msgs.len());
+        let msg = &msgs[0];
+        assert_eq!(
+            "[fe80::1]:5000".parse::<SocketAddr>().unwrap(),
+            msg.born_host()
+        );
+        assert_eq!(
+            "[::1]:10911".parse::<SocketAddr>().unwrap(),
+            msg.store_host()
+        );
+        assert_eq!(b"hi", msg.message.body());
+        let (store_host, offset) = MessageExt::decode_message_id(msg.msg_id()).unwrap();
+        assert_eq!(msg.store_host(), store_host);
+        assert_eq!(42, offset);
+    }
+
+    #[test]
+    fn test_decode_inner_batch() {
+        let mut msgs = Vec::new();
+        for i in 0..3 {
+            let mut msg = Message::new(
+                "topic".to_string(),
+                String::new(),
+                String::new(),
+                i,
+                format!("body-{}", i),
+                false,
+            );
+            msg.set_unique_key(format!("key-{}", i));
+            msgs.push(msg);
+        }
+        let batch = Message::encode_batch(&msgs).unwrap();
+        let outer = MessageExt {
+            message: batch,
+            queue_offset: 10,
+            sys_flag: MessageSysFlag::InnerBatch.into(),
+            msg_id: "outer".to_string(),
+            ..Default::default()
+        };
+        let inner = outer.decode_inner_batch();
+        assert_eq!(3, inner.len());
+        for (i, msg) in inner.iter().enumerate() {
+            assert_eq!(format!("body-{}", i).as_bytes(), msg.message.body());
+            assert_eq!(10 + i as i64, msg.queue_offset());
+            assert_eq!(format!("key-{}", i), msg.msg_id());
+            assert_eq!(i as i32, msg.message.flag);
+            assert_eq!(0, msg.sys_flag);
+        }
+
+        let truncated = MessageExt {
+            message: Message::new(
+                "topic".to_string(),
+                String::new(),
+                String::new(),
+                0,
+                vec![0u8, 0, 0, 40, 0],
+                false,
+            ),
+            ..Default::default()
+        };
+        assert_eq!(1, truncated.decode_inner_batch().len());
+    }
+
+    #[test]
+    fn test_decode_message_id() {
+        let msg_id = MessageExt::get_message_offset_id("10.0.0.1:10911".parse().unwrap(), 123456);
+        let (addr, offset) = MessageExt::decode_message_id(&msg_id).unwrap();
+        assert_eq!("10.0.0.1:10911", addr.to_string());
+        assert_eq!(123456, offset);
+
+        let msg_id = format!("{:032x}{:08x}{:016x}", 1u128, 10911, 42);
+        let (addr, offset) = MessageExt::decode_message_id(&msg_id).unwrap();
+        assert_eq!("[::1]:10911", addr.to_string());
+        assert_eq!(42, offset);
+
+        assert!(MessageExt::decode_message_id("0A000001").is_err());
+        assert!(MessageExt::decode_message_id("not a message id").is_err());
+    }
+
     #[test]
     fn text_generate_uniq_id() {
         use super::UNIQ_ID_GENERATOR;
@@ -0,0 +1,127 @@
+const NAMESPACE_SEPARATOR: char = '%';
+const RETRY_PREFIX: &str = "%RETRY%";
+const DLQ_PREFIX: &str = "%DLQ%";
+const UNIT_PREFIX: &str = "%UNIT%";
+const SYSTEM_TOPIC_PREFIX: &str = "rmq_sys_";
+const SYSTEM_GROUP_PREFIX: &str = "CID_RMQ_SYS_";
+const SYSTEM_RESOURCES: &[&str] = &[
+    "TBW102",
+    "SCHEDULE_TOPIC_XXXX",
+    "BenchmarkTest",
+    "OFFSET_MOVED_EVENT",
+    "SELF_TEST_TOPIC",
+    "RMQ_SYS_TRANS_HALF_TOPIC",
+    "RMQ_SYS_TRACE_TOPIC",
+    "RMQ_SYS_TRANS_OP_HALF_TOPIC",
+    "TRANS_CHECK_MAX_TIME_TOPIC",
+    "DEFAULT_CONSUMER",
+    "DEFAULT_PRODUCER",
+    "CLIENT_INNER_PRODUCER",
+    "TOOLS_CONSUMER",
+];
+
+fn is_system_resource(resource: &str) -> bool {
+    SYSTEM_RESOURCES.contains(&resource)
+        || resource.starts_with(SYSTEM_TOPIC_PREFIX)
+        || resource.starts_with(SYSTEM_GROUP_PREFIX)
+}
+
+/// Split the `%RETRY%`/`%DLQ%` prefix off a resource
+fn split_prefix(resource: &str) -> (&str, &str) {
+    for prefix in [RETRY_PREFIX, DLQ_PREFIX] {
+        if let Some(rest) = resource.strip_prefix(prefix) {
+            return (prefix, rest);
+        }
+    }
+    ("", resource)
+}
+
+/// Prefix a topic or group with `namespace`
+///
+/// System resources and already wrapped resources are returned as is, retry and
+/// dead letter topics keep their prefix in front: `%RETRY%namespace%group`.
+pub(crate) fn wrap_namespace(namespace: &str, resource: &str) -> String {
+    if namespace.is_empty() || is_system_resource(resource) {
+        return resource.to_string();
+    }
+    let (prefix, name) = split_prefix(resource);
+    if is_system_resource(name) || is_wrapped(namespace, name) {
+        return resource.to_string();
+    }
+    format!("{}{}{}{}", prefix, namespace, NAMESPACE_SEPARATOR, name)
+}
+
+/// Namespace of the topics and groups of unit `unit_name`, which is put in front of
+/// `namespace`: `%UNIT%unit%namespace`
+pub(crate) fn unit_namespace(unit_name: &str, namespace: &str) -> String {
+    if namespace.is_empty() {
+        format!("{}{}", UNIT_PREFIX, unit_name)
+    } else {
+        format!(
+            "{}{}{}{}",
+            UNIT_PREFIX, unit_name, NAMESPACE_SEPARATOR, namespace
+        )
+    }
+}
+
+/// Strip `namespace` off a topic or group, the reverse of `wrap_namespace`
+pub(crate) fn without_namespace(namespace: &str, resource: &str) -> String {
+    if namespace.is_empty() {
+        return resource.to_string();
+    }
+    let (prefix, name) = split_prefix(resource);
+    if is_wrapped(namespace, name) {
+        format!("{}{}", prefix, &name[namespace.len() + 1..])
+    } else {
+        resource.to_string()
+    }
+}
+
+fn is_wrapped(namespace: &str, name: &str) -> bool {
+    name.len() > namespace.len()
+        && name.starts_with(namespace)
+        && name[namespace.len()..].starts_with(NAMESPACE_SEPARATOR)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{unit_namespace, without_namespace, wrap_namespace};
+
+    #[test]
+    fn test_wrap_namespace() {
+        assert_eq!("topic", wrap_namespace("", "topic"));
+        assert_eq!("ns%topic", wrap_namespace("ns", "topic"));
+        assert_eq!("ns%topic", wrap_namespace("ns", "ns%topic"));
+        assert_eq!("%RETRY%ns%group", wrap_namespace("ns", "%RETRY%group"));
+        assert_eq!("%DLQ%ns%group", wrap_namespace("ns", "%DLQ%ns%group"));
+        assert_eq!("TBW102", wrap_namespace("ns", "TBW102"));
+        assert_eq!("rmq_sys_topic", wrap_namespace("ns", "rmq_sys_topic"));
+    }
+
+    #[test]
+    fn test_without_namespace() {
+        assert_eq!("ns%topic", without_namespace("", "ns%topic"));
+        assert_eq!("topic", without_namespace("ns", "ns%topic"));
+        assert_eq!("topic", without_namespace("ns", "topic"));
+        assert_eq!("nstopic", without_namespace("ns", "nstopic"));
+        assert_eq!("%RETRY%group", without_namespace("ns", "%RETRY%ns%group"));
+        assert_eq!("TBW102", without_namespace("ns", "TBW102"));
+    }
+
+    #[test]
+    fn test_unit_namespace() {
+        assert_eq!("%UNIT%unit", unit_namespace("unit", ""));
+        let namespace = unit_namespace("unit", "ns");
+        assert_eq!("%UNIT%unit%ns", namespace);
+        assert_eq!("%UNIT%unit%ns%topic", wrap_namespace(&namespace, "topic"));
+        assert_eq!(
+            "%RETRY%%UNIT%unit%ns%group",
+            wrap_namespace(&namespace, "%RETRY%group")
+        );
+        assert_eq!(
+            "%RETRY%group",
+            without_namespace(&namespace, "%RETRY%%UNIT%unit%ns%group")
+        );
+        assert_eq!("TBW102", wrap_namespace(&namespace, "TBW102"));
+    }
+}
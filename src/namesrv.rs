@@ -1,28 +1,93 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use bytes::Bytes;
 use parking_lot::Mutex;
+use rand::distributions::WeightedIndex;
 use rand::prelude::*;
+use serde::Deserialize;
+use tokio::runtime::Handle;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
 
-use crate::client::Credentials;
+use crate::client::{Credentials, CredentialsProvider, SignatureMethod};
+use crate::fastjson;
 use crate::message::MessageQueue;
 use crate::protocol::{
-    request::GetRouteInfoRequestHeader, RemotingCommand, RequestCode, ResponseCode,
+    request::{
+        DeleteTopicRequestHeader, GetKvListByNamespaceRequestHeader, GetRouteInfoRequestHeader,
+        KvConfigRequestHeader, PutKvConfigRequestHeader, WipeWritePermOfBrokerRequestHeader,
+    },
+    response::{decode_response_header, WipeWritePermOfBrokerResponseHeader},
+    HeaderCodecType, LanguageCode, RemotingCommand, RequestCode, ResponseCode,
 };
-use crate::remoting::RemotingClient;
-use crate::resolver::NsResolver;
-use crate::route::{BrokerData, TopicRouteData, MASTER_ID};
+use crate::remoting::{RemotingClient, RpcHook};
+use crate::resolver::{normalize_addrs, NsResolver};
+use crate::route::{BrokerData, ClusterInfo, TopicList, TopicRouteData, MASTER_ID};
 use crate::Error;
 
+/// Capacity of the route change channel, slow receivers miss older changes
+const ROUTE_CHANGES_CAPACITY: usize = 64;
+
+/// How long a failed name server is skipped before being tried again
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Body of `GetKvListByNamespace` responses
+#[derive(Debug, Default, Deserialize)]
+struct KvTable {
+    #[serde(default)]
+    table: HashMap<String, String>,
+}
+
+impl KvTable {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fastjson::from_slice(bytes)
+    }
+}
+
+/// Broker address found by `NameServer::find_broker_addr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FindBrokerResult {
+    pub addr: String,
+    /// Whether `addr` is a slave broker
+    pub slave: bool,
+}
+
+/// How requests are spread across name servers
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NameServerSelection {
+    /// Rotate name servers on every request
+    #[default]
+    RoundRobin,
+    /// Keep using the last healthy name server, moving on to the next only on failures
+    Sticky,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ServerHealth {
+    // consecutive failures
+    failures: u32,
+    unhealthy_until: Instant,
+}
+
 #[derive(Debug)]
 struct NameServerInner {
     servers: Vec<String>,
     index: usize,
+    // name server address -> health, healthy servers are absent
+    health: HashMap<String, ServerHealth>,
+    // name server address -> selection weight, unlisted servers weigh 1
+    weights: HashMap<String, u32>,
+    selection: NameServerSelection,
     // broker name -> BrokerData
     broker_address_map: HashMap<String, BrokerData>,
     broker_version_map: HashMap<String, HashMap<String, i32>>,
     // topic name -> TopicRouteData
     route_data_map: HashMap<String, TopicRouteData>,
+    // topic name -> last time the route was fetched
+    route_updated_at: HashMap<String, Instant>,
+    route_ttl: Option<Duration>,
 }
 
 #[derive(Debug, Clone)]
@@ -30,93 +95,477 @@ pub struct NameServer<NR: NsResolver + Clone> {
     inner: Arc<Mutex<NameServerInner>>,
     resolver: NR,
     remoting_client: RemotingClient,
+    route_changes: broadcast::Sender<(String, TopicRouteData)>,
 }
 
 impl<NR: NsResolver + Clone> NameServer<NR> {
+    /// Create a name server client, addresses are resolved asynchronously on first use
+    /// and by `update_name_server_address`
     pub fn new<C: Into<Option<Credentials>>>(resolver: NR, credentials: C) -> Result<Self, Error> {
         let inner = NameServerInner {
             servers: Vec::new(),
             index: 0,
+            health: HashMap::new(),
+            weights: HashMap::new(),
+            selection: NameServerSelection::default(),
             broker_address_map: HashMap::new(),
             broker_version_map: HashMap::new(),
             route_data_map: HashMap::new(),
+            route_updated_at: HashMap::new(),
+            route_ttl: None,
         };
         Ok(Self {
             inner: Arc::new(Mutex::new(inner)),
             resolver,
             remoting_client: RemotingClient::new(credentials),
+            route_changes: broadcast::channel(ROUTE_CHANGES_CAPACITY).0,
         })
     }
 
+    /// Header serialize type of requests to name servers
+    pub fn set_serialize_type(&mut self, serialize_type: HeaderCodecType) -> &mut Self {
+        self.remoting_client.set_serialize_type(serialize_type);
+        self
+    }
+
+    /// Language and version reported in requests to name servers
+    pub fn set_identity(&mut self, language: LanguageCode, version: i16) -> &mut Self {
+        self.remoting_client.set_identity(language, version);
+        self
+    }
+
+    /// Source of the credentials signing requests to name servers
+    pub fn set_credentials_provider(
+        &mut self,
+        provider: Option<Arc<dyn CredentialsProvider>>,
+    ) -> &mut Self {
+        self.remoting_client.set_credentials_provider(provider);
+        self
+    }
+
+    /// Algorithm of ACL signatures of requests to name servers
+    pub fn set_signature_method(&mut self, method: SignatureMethod) -> &mut Self {
+        self.remoting_client.set_signature_method(method);
+        self
+    }
+
+    /// Propagate trace context in requests to name servers
+    pub fn set_trace_propagation(&mut self, enabled: bool) -> &mut Self {
+        self.remoting_client.set_trace_propagation(enabled);
+        self
+    }
+
+    /// Runtime to open connections to name servers on, the current one if unset
+    pub fn set_runtime(&mut self, runtime: Option<Handle>) -> &mut Self {
+        self.remoting_client.set_runtime(runtime);
+        self
+    }
+
+    /// Timeout of requests to name servers
+    pub fn set_request_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.remoting_client.set_request_timeout(Some(timeout));
+        self
+    }
+
+    /// Connect to name servers over TLS
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: crate::remoting::TlsConfig) -> &mut Self {
+        self.remoting_client.set_tls(Some(tls));
+        self
+    }
+
+    /// Send connection events of name servers to the subscribers of `client`
+    pub(crate) fn share_connection_events(&mut self, client: &RemotingClient) -> &mut Self {
+        self.remoting_client.share_connection_events(client);
+        self
+    }
+
     pub fn address(&self) -> String {
+        self.candidate_servers()
+            .into_iter()
+            .next()
+            .unwrap_or_default()
+    }
+
+    /// Name servers in round-robin order, servers in cooldown are moved to the end
+    ///
+    /// With selection weights configured, the first server is picked at random by weight
+    /// among the healthy ones instead. Sticky selection starts from the current server
+    /// without rotating or weighting.
+    fn candidate_servers(&self) -> Vec<String> {
         let mut inner = self.inner.lock();
-        let addr = &inner.servers[inner.index].clone();
-        let mut index = inner.index + 1;
-        index %= inner.servers.len();
-        inner.index = index;
-        addr.trim_start_matches("http(s)://").to_string()
+        let len = inner.servers.len();
+        if len == 0 {
+            return Vec::new();
+        }
+        let start = inner.index % len;
+        let sticky = inner.selection == NameServerSelection::Sticky;
+        if !sticky {
+            inner.index = (start + 1) % len;
+        }
+        let now = Instant::now();
+        let (mut healthy, unhealthy): (Vec<String>, Vec<String>) = (0..len)
+            .map(|i| inner.servers[(start + i) % len].clone())
+            .partition(|addr| {
+                inner
+                    .health
+                    .get(addr)
+                    .map(|health| health.unhealthy_until <= now)
+                    .unwrap_or(true)
+            });
+        if !sticky && !inner.weights.is_empty() {
+            let weight = |addr: &String| inner.weights.get(addr).copied().unwrap_or(1);
+            if let Ok(dist) = WeightedIndex::new(healthy.iter().map(weight)) {
+                let first = dist.sample(&mut thread_rng());
+                healthy[..=first].rotate_right(1);
+            }
+        }
+        healthy.extend(unhealthy);
+        healthy
     }
 
-    pub fn broker_address_map(&self) -> HashMap<String, BrokerData> {
-        self.inner.lock().broker_address_map.clone()
+    /// Selection weights of name servers for heterogeneous capacity,
+    /// unlisted servers weigh 1 and servers weighing 0 are only used for failover
+    pub fn set_weights(&self, weights: HashMap<String, u32>) {
+        let weights = weights
+            .into_iter()
+            .map(|(addr, weight)| {
+                let addr = normalize_addrs(std::slice::from_ref(&addr))
+                    .ok()
+                    .and_then(|addrs| addrs.into_iter().next())
+                    .unwrap_or(addr);
+                (addr, weight)
+            })
+            .collect();
+        self.inner.lock().weights = weights;
     }
 
-    pub fn len(&self) -> usize {
-        self.inner.lock().servers.len()
+    /// Set how requests are spread across name servers, defaults to round robin
+    pub fn set_selection(&self, selection: NameServerSelection) {
+        self.inner.lock().selection = selection;
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.inner.lock().servers.is_empty()
+    fn record_success(&self, addr: &str) {
+        let mut inner = self.inner.lock();
+        if inner.health.remove(addr).is_some() {
+            info!(name_server = %addr, "name server recovered");
+        }
+        if inner.selection == NameServerSelection::Sticky {
+            if let Some(index) = inner.servers.iter().position(|server| server == addr) {
+                inner.index = index;
+            }
+        }
     }
 
-    pub async fn update_name_server_address(&self) -> Result<(), Error> {
-        if let Ok(servers) = self.resolver.resolve().await {
-            self.inner.lock().servers = servers;
+    fn record_failure(&self, addr: &str, err: &Error) {
+        let mut inner = self.inner.lock();
+        if inner.selection == NameServerSelection::Sticky {
+            let len = inner.servers.len();
+            if len > 0 && inner.servers[inner.index % len] == addr {
+                inner.index = (inner.index + 1) % len;
+            }
         }
-        Ok(())
+        let health = inner
+            .health
+            .entry(addr.to_string())
+            .or_insert(ServerHealth {
+                failures: 0,
+                unhealthy_until: Instant::now(),
+            });
+        health.failures += 1;
+        health.unhealthy_until = Instant::now() + UNHEALTHY_COOLDOWN;
+        warn!(name_server = %addr, failures = health.failures, error = ?err, "name server request failed");
     }
 
-    pub async fn query_topic_route_info(&self, topic: &str) -> Result<TopicRouteData, Error> {
-        let mut servers = self.inner.lock().servers.clone();
+    /// Candidate servers, resolving name server addresses first if there is none yet
+    async fn resolved_candidate_servers(&self) -> Result<Vec<String>, Error> {
+        let mut servers = self.candidate_servers();
         if servers.is_empty() {
             // Try update name servers
-            if let Ok(new_servers) = self.resolver.resolve().await {
-                servers = new_servers.clone();
-                self.inner.lock().servers = new_servers;
-            } else {
+            self.update_name_server_address().await?;
+            servers = self.candidate_servers();
+            if servers.is_empty() {
                 return Err(Error::EmptyNameServers);
             }
         }
+        Ok(servers)
+    }
+
+    /// Send a request to every name server, fails if any of them failed
+    async fn invoke_all(&self, cmd: RemotingCommand) -> Result<(), Error> {
+        let servers = self.resolved_candidate_servers().await?;
+        let mut last_err = None;
+        for addr in &servers {
+            match self.remoting_client.invoke(addr, cmd.clone()).await {
+                Ok(res) => {
+                    self.record_success(addr);
+                    if res.code() != ResponseCode::Success {
+                        last_err = Some(Error::ResponseError {
+                            code: res.code(),
+                            message: res.header.remark.clone(),
+                        });
+                    }
+                }
+                Err(err) => {
+                    self.record_failure(addr, &err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        match last_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Send a request to the name servers, failing over to the next one on connection errors
+    async fn invoke(&self, cmd: RemotingCommand) -> Result<RemotingCommand, Error> {
+        let servers = self.resolved_candidate_servers().await?;
+        let mut last_err = None;
+        for addr in &servers {
+            match self.remoting_client.invoke(addr, cmd.clone()).await {
+                Ok(res) => {
+                    self.record_success(addr);
+                    return Ok(res);
+                }
+                Err(err) => {
+                    self.record_failure(addr, &err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap_or(Error::EmptyNameServers))
+    }
+
+    /// Subscribe to topic route changes detected when fetching routes
+    pub fn subscribe_route_changes(&self) -> broadcast::Receiver<(String, TopicRouteData)> {
+        self.route_changes.subscribe()
+    }
+
+    fn notify_route_change(&self, topic: &str, route_data: &TopicRouteData) {
+        // no receivers is fine
+        let _ = self
+            .route_changes
+            .send((topic.to_string(), route_data.clone()));
+    }
+
+    /// Expire cached topic routes not fetched within `ttl`, see `remove_expired_routes`
+    pub fn set_route_ttl(&self, ttl: Option<Duration>) {
+        self.inner.lock().route_ttl = ttl;
+    }
+
+    pub(crate) fn register_rpc_hook(&self, hook: Arc<dyn RpcHook>) {
+        self.remoting_client.register_rpc_hook(hook);
+    }
+
+    pub(crate) fn remoting_client(&self) -> &RemotingClient {
+        &self.remoting_client
+    }
+
+    pub fn broker_address_map(&self) -> HashMap<String, BrokerData> {
+        self.inner.lock().broker_address_map.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().servers.is_empty()
+    }
+
+    /// Re-resolve name server addresses, returns whether the set of addresses changed
+    ///
+    /// Connections to removed name servers are closed.
+    pub async fn update_name_server_address(&self) -> Result<bool, Error> {
+        let servers = normalize_addrs(&self.resolver.resolve().await?)?;
+        if servers.is_empty() {
+            return Ok(false);
+        }
+        let old_servers = {
+            let mut inner = self.inner.lock();
+            let old_servers = std::mem::replace(&mut inner.servers, servers.clone());
+            if old_servers.is_empty() || inner.index >= inner.servers.len() {
+                // start at a random position to spread clients across name servers
+                inner.index = thread_rng().gen_range(0..inner.servers.len());
+            }
+            let NameServerInner {
+                servers, health, ..
+            } = &mut *inner;
+            health.retain(|addr, _| servers.contains(addr));
+            old_servers
+        };
+        let old: HashSet<&String> = old_servers.iter().collect();
+        let new: HashSet<&String> = servers.iter().collect();
+        if old == new {
+            return Ok(false);
+        }
+        info!(old = ?old_servers, new = ?servers, "name server addresses changed");
+        for removed in old.difference(&new) {
+            self.remoting_client.close_connection(removed);
+        }
+        Ok(true)
+    }
+
+    pub async fn query_topic_route_info(&self, topic: &str) -> Result<TopicRouteData, Error> {
         let header = GetRouteInfoRequestHeader {
             topic: topic.to_string(),
         };
-        for addr in &servers {
-            let cmd = RemotingCommand::with_header(
-                RequestCode::GetRouteInfoByTopic,
-                header.clone(),
-                Vec::new(),
-            );
-            let res = self.remoting_client.invoke(addr, cmd).await;
-            if let Ok(res) = res {
-                return match ResponseCode::from_code(res.header.code)? {
-                    ResponseCode::Success => {
-                        if res.body.is_empty() {
-                            // FIXME: error
-                        }
-                        let route_data = TopicRouteData::from_bytes(&res.body)?;
-                        Ok(route_data)
+        let cmd =
+            RemotingCommand::with_header(RequestCode::GetRouteInfoByTopic, header, Vec::new());
+        let res = self.invoke(cmd).await?;
+        match ResponseCode::from_code(res.header.code) {
+            ResponseCode::Success => {
+                if res.body.is_empty() {
+                    warn!(topic = %topic, "name server returned empty route data");
+                    return Err(Error::EmptyRouteBody(topic.to_string()));
+                }
+                TopicRouteData::from_bytes(&res.body).map_err(|err| {
+                    warn!(topic = %topic, error = ?err, "decode route data failed");
+                    Error::RouteDecode {
+                        topic: topic.to_string(),
+                        source: Box::new(err),
                     }
-                    ResponseCode::TopicNotExist => Err(Error::TopicNotExist(topic.to_string())),
-                    _ => Err(Error::ResponseError {
-                        code: res.header.code,
-                        message: res.header.remark.clone(),
-                    }),
-                };
-            } else {
-                println!("{:?}", res);
+                })
             }
+            ResponseCode::TopicNotExist => Err(Error::TopicNotExist(topic.to_string())),
+            _ => Err(Error::ResponseError {
+                code: res.header.code,
+                message: res.header.remark.clone(),
+            }),
+        }
+    }
+
+    /// Send a request without header to the name servers, returns the response body
+    async fn invoke_for_body(&self, code: RequestCode) -> Result<Bytes, Error> {
+        let cmd = RemotingCommand::new(code, String::new(), HashMap::new(), Vec::new());
+        let res = self.invoke(cmd).await?;
+        match ResponseCode::from_code(res.header.code) {
+            ResponseCode::Success => Ok(res.body),
+            _ => Err(Error::ResponseError {
+                code: res.header.code,
+                message: res.header.remark.clone(),
+            }),
+        }
+    }
+
+    /// Get brokers and clusters registered in the name servers
+    pub async fn get_broker_cluster_info(&self) -> Result<ClusterInfo, Error> {
+        let body = self
+            .invoke_for_body(RequestCode::GetBrokerClusterInfo)
+            .await?;
+        ClusterInfo::from_bytes(&body)
+    }
+
+    /// Get all topics registered in the name servers
+    pub async fn get_all_topic_list(&self) -> Result<TopicList, Error> {
+        let body = self
+            .invoke_for_body(RequestCode::GetAllTopicListFromNameServer)
+            .await?;
+        TopicList::from_bytes(&body)
+    }
+
+    /// Delete the route of `topic` from all name servers
+    pub async fn delete_topic(&self, topic: &str) -> Result<(), Error> {
+        let header = DeleteTopicRequestHeader {
+            topic: topic.to_string(),
+        };
+        let cmd =
+            RemotingCommand::with_header(RequestCode::DeleteTopicInNameServer, header, Vec::new());
+        self.invoke_all(cmd).await
+    }
+
+    /// Remove the write permission of `broker_name` from the routes in all name servers,
+    /// returns the number of topics affected or the error by name server address
+    ///
+    /// Every name server is tried even if some of them fail.
+    pub async fn wipe_write_perm_of_broker(
+        &self,
+        broker_name: &str,
+    ) -> Result<HashMap<String, Result<i32, Error>>, Error> {
+        let header = WipeWritePermOfBrokerRequestHeader {
+            broker_name: broker_name.to_string(),
+        };
+        let cmd =
+            RemotingCommand::with_header(RequestCode::WipeWritePermOfBroker, header, Vec::new());
+        let servers = self.resolved_candidate_servers().await?;
+        let mut counts = HashMap::with_capacity(servers.len());
+        for addr in servers {
+            let count = match self.remoting_client.invoke(&addr, cmd.clone()).await {
+                Ok(res) => {
+                    self.record_success(&addr);
+                    decode_response_header::<WipeWritePermOfBrokerResponseHeader>(&res)
+                        .map(|header| header.wipe_topic_count)
+                }
+                Err(err) => {
+                    self.record_failure(&addr, &err);
+                    Err(err)
+                }
+            };
+            counts.insert(addr, count);
+        }
+        Ok(counts)
+    }
+
+    /// Put a KV config to all name servers
+    pub async fn put_kv_config(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let header = PutKvConfigRequestHeader {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let cmd = RemotingCommand::with_header(RequestCode::PutKvConfig, header, Vec::new());
+        self.invoke_all(cmd).await
+    }
+
+    /// Get a KV config, returns `None` if not exists
+    pub async fn get_kv_config(&self, namespace: &str, key: &str) -> Result<Option<String>, Error> {
+        let header = KvConfigRequestHeader {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+        };
+        let cmd = RemotingCommand::with_header(RequestCode::GetKvConfig, header, Vec::new());
+        let res = self.invoke(cmd).await?;
+        match ResponseCode::from_code(res.header.code) {
+            ResponseCode::Success => Ok(res.ext_fields().get("value").cloned()),
+            ResponseCode::QueryNotFound => Ok(None),
+            _ => Err(Error::ResponseError {
+                code: res.header.code,
+                message: res.header.remark.clone(),
+            }),
+        }
+    }
+
+    /// Delete a KV config from all name servers
+    pub async fn delete_kv_config(&self, namespace: &str, key: &str) -> Result<(), Error> {
+        let header = KvConfigRequestHeader {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+        };
+        let cmd = RemotingCommand::with_header(RequestCode::DeleteKvConfig, header, Vec::new());
+        self.invoke_all(cmd).await
+    }
+
+    /// Get all KV configs of `namespace`
+    pub async fn get_kv_list_by_namespace(
+        &self,
+        namespace: &str,
+    ) -> Result<HashMap<String, String>, Error> {
+        let header = GetKvListByNamespaceRequestHeader {
+            namespace: namespace.to_string(),
+        };
+        let cmd =
+            RemotingCommand::with_header(RequestCode::GetKvListByNamespace, header, Vec::new());
+        let res = self.invoke(cmd).await?;
+        match ResponseCode::from_code(res.header.code) {
+            ResponseCode::Success => Ok(KvTable::from_bytes(&res.body)?.table),
+            ResponseCode::QueryNotFound => Ok(HashMap::new()),
+            _ => Err(Error::ResponseError {
+                code: res.header.code,
+                message: res.header.remark.clone(),
+            }),
         }
-        Err(Error::EmptyRouteData)
     }
 
     pub async fn update_topic_route_info(
@@ -128,6 +577,10 @@ impl<NR: NsResolver + Clone> NameServer<NR> {
             .await?)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "update_topic_route_info", skip(self))
+    )]
     pub async fn update_topic_route_info_with_default(
         &self,
         topic: &str,
@@ -149,6 +602,9 @@ impl<NR: NsResolver + Clone> NameServer<NR> {
             }
         }
         let mut inner = self.inner.lock();
+        inner
+            .route_updated_at
+            .insert(topic.to_string(), Instant::now());
         let changed = inner
             .route_data_map
             .get(topic)
@@ -163,6 +619,8 @@ impl<NR: NsResolver + Clone> NameServer<NR> {
             inner
                 .route_data_map
                 .insert(topic.to_string(), route_data.clone());
+            drop(inner);
+            self.notify_route_change(topic, &route_data);
         }
         Ok((route_data, changed))
     }
@@ -182,24 +640,7 @@ impl<NR: NsResolver + Clone> NameServer<NR> {
         topic: &str,
     ) -> Result<Vec<MessageQueue>, Error> {
         let route_data = self.query_topic_route_info(topic).await?;
-        let mqs: Vec<MessageQueue> = route_data
-            .queue_datas
-            .into_iter()
-            .flat_map(|queue_data| {
-                let mut mqs = Vec::new();
-                if queue_data.perm.is_readable() {
-                    for i in 0..queue_data.read_queue_nums {
-                        mqs.push(MessageQueue {
-                            topic: topic.to_string(),
-                            broker_name: queue_data.broker_name.clone(),
-                            queue_id: i as u32,
-                        })
-                    }
-                }
-                mqs
-            })
-            .collect();
-        Ok(mqs)
+        Ok(route_data.to_subscribe_message_queues(topic))
     }
 
     pub async fn fetch_publish_message_queues(
@@ -218,12 +659,17 @@ impl<NR: NsResolver + Clone> NameServer<NR> {
             inner
                 .route_data_map
                 .insert(topic.to_string(), route_data.clone());
+            inner
+                .route_updated_at
+                .insert(topic.to_string(), Instant::now());
             // Add brokers
             for broker_data in &route_data.broker_datas {
                 inner
                     .broker_address_map
                     .insert(broker_data.broker_name.to_string(), broker_data.clone());
             }
+            drop(inner);
+            self.notify_route_change(topic, &route_data);
             let publish_info = route_data.to_publish_info(topic);
             Ok(publish_info.message_queues)
         }
@@ -258,6 +704,157 @@ impl<NR: NsResolver + Clone> NameServer<NR> {
             .and_then(|broker_data| broker_data.broker_addrs.get(&MASTER_ID).cloned())
     }
 
+    /// Find an address of broker `broker_name`
+    ///
+    /// Returns the master address, or a slave address when `prefer_slave` is set.
+    /// Unless `only_this_broker` is set, falls back to the other role when no address
+    /// of the preferred one is available, e.g. pulling from a slave while the master is down.
+    pub fn find_broker_addr(
+        &self,
+        broker_name: &str,
+        prefer_slave: bool,
+        only_this_broker: bool,
+    ) -> Option<FindBrokerResult> {
+        self.find_broker_addrs(broker_name, prefer_slave)
+            .into_iter()
+            .find(|found| !only_this_broker || found.slave == prefer_slave)
+    }
+
+    /// All addresses of broker `broker_name`, the master first or the slaves first
+    /// when `prefer_slave` is set, slaves ordered by broker id
+    pub fn find_broker_addrs(
+        &self,
+        broker_name: &str,
+        prefer_slave: bool,
+    ) -> Vec<FindBrokerResult> {
+        let inner = self.inner.lock();
+        let broker_data = match inner.broker_address_map.get(broker_name) {
+            Some(broker_data) => broker_data,
+            None => return Vec::new(),
+        };
+        let mut addrs: Vec<_> = broker_data
+            .broker_addrs
+            .iter()
+            .filter(|(_, addr)| !addr.is_empty())
+            .collect();
+        addrs.sort_by_key(|(id, _)| (prefer_slave == (**id == MASTER_ID), **id));
+        addrs
+            .into_iter()
+            .map(|(id, addr)| FindBrokerResult {
+                addr: addr.clone(),
+                slave: *id != MASTER_ID,
+            })
+            .collect()
+    }
+
+    /// Remove the cached route of `topic` and brokers no longer referenced by any topic,
+    /// returns the removed broker addresses
+    pub fn remove_topic_route(&self, topic: &str) -> Vec<String> {
+        {
+            let mut inner = self.inner.lock();
+            inner.route_updated_at.remove(topic);
+            if inner.route_data_map.remove(topic).is_none() {
+                return Vec::new();
+            }
+        }
+        self.clean_offline_broker()
+    }
+
+    /// Remove cached routes not fetched within the route TTL, returns the removed topics
+    ///
+    /// Brokers of removed routes are cleaned up by `clean_offline_broker`.
+    pub fn remove_expired_routes(&self) -> Vec<String> {
+        let mut inner = self.inner.lock();
+        let ttl = match inner.route_ttl {
+            Some(ttl) => ttl,
+            None => return Vec::new(),
+        };
+        let now = Instant::now();
+        let expired: Vec<String> = inner
+            .route_updated_at
+            .iter()
+            .filter(|(_, updated_at)| now.duration_since(**updated_at) >= ttl)
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        for topic in &expired {
+            inner.route_updated_at.remove(topic);
+            inner.route_data_map.remove(topic);
+        }
+        expired
+    }
+
+    /// Remove broker addresses which are no longer present in any topic route,
+    /// returns the removed addresses
+    pub fn clean_offline_broker(&self) -> Vec<String> {
+        let mut inner = self.inner.lock();
+        let NameServerInner {
+            broker_address_map,
+            broker_version_map,
+            route_data_map,
+            ..
+        } = &mut *inner;
+        let is_addr_in_route = |addr: &str| {
+            route_data_map.values().any(|route_data| {
+                route_data
+                    .broker_datas
+                    .iter()
+                    .any(|bd| bd.broker_addrs.values().any(|a| a == addr))
+            })
+        };
+        let mut removed = Vec::new();
+        broker_address_map.retain(|broker_name, broker_data| {
+            broker_data.broker_addrs.retain(|_, addr| {
+                if is_addr_in_route(addr) {
+                    true
+                } else {
+                    info!(broker_name = %broker_name, broker_addr = %addr, "remove offline broker address");
+                    removed.push(addr.clone());
+                    false
+                }
+            });
+            if broker_data.broker_addrs.is_empty() {
+                info!(broker_name = %broker_name, "remove offline broker");
+                broker_version_map.remove(broker_name);
+                false
+            } else {
+                true
+            }
+        });
+        for addr in &removed {
+            for versions in broker_version_map.values_mut() {
+                versions.remove(addr);
+            }
+        }
+        removed
+    }
+
+    /// Version of the broker at `broker_addr` reported in its last response, if known
+    pub fn broker_version(&self, broker_addr: &str) -> Option<i32> {
+        self.inner
+            .lock()
+            .broker_version_map
+            .values()
+            .find_map(|versions| versions.get(broker_addr).copied())
+    }
+
+    /// Record the version of a response from `broker_addr`, responses from addresses
+    /// which aren't known brokers are ignored
+    pub(crate) fn update_broker_version(&self, broker_addr: &str, version: i32) {
+        let mut inner = self.inner.lock();
+        let broker_name = inner
+            .broker_address_map
+            .iter()
+            .find(|(_, data)| data.broker_addrs.values().any(|addr| addr == broker_addr))
+            .map(|(name, _)| name.clone());
+        if let Some(broker_name) = broker_name {
+            inner
+                .broker_version_map
+                .entry(broker_name)
+                .or_default()
+                .insert(broker_addr.to_string(), version);
+        }
+    }
+
     pub fn add_broker_version(&self, broker_name: &str, broker_addr: &str, version: i32) {
         self.inner
             .lock()
@@ -270,7 +867,12 @@ impl<NR: NsResolver + Clone> NameServer<NR> {
 
 #[cfg(test)]
 mod test {
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
     use super::*;
+    use crate::protocol::MqCodec;
     use crate::resolver::StaticResolver;
 
     const TOPIC: &'static str = "DefaultCluster";
@@ -296,14 +898,39 @@ mod test {
 
     #[tokio::test]
     async fn test_query_topic_route_info_with_credentials() {
-        let namesrv = NameServer::new(
-            StaticResolver::new(vec!["localhost:9876".to_string()]),
-            Credentials::new("rocketmq", "12345678"),
-        )
-        .unwrap();
-        let res = namesrv.query_topic_route_info(TOPIC).await;
-        println!("{:?}", res);
-        assert!(!res.is_err());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, MqCodec::default());
+            let mut requests = Vec::new();
+            while let Some(Ok(req)) = framed.next().await {
+                let body = br#"{"queueDatas":[],"brokerDatas":[],"filterServerTable":{}}"#;
+                let mut res = req.response(ResponseCode::Success, "", HashMap::new());
+                res.set_body(body.to_vec());
+                requests.push(req);
+                framed.send(res).await.unwrap();
+                if requests.len() == 2 {
+                    break;
+                }
+            }
+            requests
+        });
+
+        let mut credentials = Credentials::new("ak", "sk");
+        credentials.security_token = Some("token".to_string());
+        let namesrv = NameServer::new(StaticResolver::new(vec![addr]), credentials).unwrap();
+        namesrv.query_topic_route_info(TOPIC).await.unwrap();
+        namesrv.put_kv_config("ns", "key", "value").await.unwrap();
+
+        let requests = server.await.unwrap();
+        assert_eq!(2, requests.len());
+        for req in &requests {
+            let fields = req.ext_fields();
+            assert_eq!("ak", fields["AccessKey"]);
+            assert_eq!("token", fields["SecurityToken"]);
+            assert!(!fields["Signature"].is_empty());
+        }
     }
 
     #[tokio::test]
@@ -364,4 +991,340 @@ mod test {
         let addr = namesrv.find_broker_addr_by_name(broker_name).unwrap();
         assert!(addr.ends_with(":10911"));
     }
+
+    #[tokio::test]
+    async fn test_get_broker_cluster_info() {
+        let namesrv = NameServer::new(
+            StaticResolver::new(vec!["localhost:9876".to_string()]),
+            None,
+        )
+        .unwrap();
+        let info = namesrv.get_broker_cluster_info().await.unwrap();
+        assert!(!info.cluster_brokers("DefaultCluster").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_all_topic_list() {
+        let namesrv = NameServer::new(
+            StaticResolver::new(vec!["localhost:9876".to_string()]),
+            None,
+        )
+        .unwrap();
+        let topics = namesrv.get_all_topic_list().await.unwrap();
+        assert!(topics.topic_list.iter().any(|topic| topic == "TBW102"));
+    }
+
+    #[tokio::test]
+    async fn test_kv_config() {
+        let namesrv = NameServer::new(
+            StaticResolver::new(vec!["localhost:9876".to_string()]),
+            None,
+        )
+        .unwrap();
+        let namespace = "RUST_CLIENT_TEST";
+        namesrv
+            .put_kv_config(namespace, "key", "value")
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("value".to_string()),
+            namesrv.get_kv_config(namespace, "key").await.unwrap()
+        );
+        let table = namesrv.get_kv_list_by_namespace(namespace).await.unwrap();
+        assert_eq!(Some(&"value".to_string()), table.get("key"));
+        namesrv.delete_kv_config(namespace, "key").await.unwrap();
+        assert_eq!(None, namesrv.get_kv_config(namespace, "key").await.unwrap());
+    }
+
+    #[test]
+    fn test_kv_table_from_bytes() {
+        let table = KvTable::from_bytes(br#"{"table":{"key":"value"}}"#).unwrap();
+        assert_eq!("value", table.table["key"]);
+    }
+
+    #[tokio::test]
+    async fn test_update_name_server_address() {
+        let servers = vec!["10.0.0.2:9876".to_string(), "10.0.0.1:9876".to_string()];
+        let namesrv = NameServer::new(StaticResolver::new(servers.clone()), None).unwrap();
+        assert!(namesrv.update_name_server_address().await.unwrap());
+        // same set in a different order
+        namesrv.inner.lock().servers = servers.iter().rev().cloned().collect();
+        assert!(!namesrv.update_name_server_address().await.unwrap());
+
+        {
+            let mut inner = namesrv.inner.lock();
+            inner.servers = vec!["10.0.0.3:9876".to_string()];
+            inner.index = 0;
+        }
+        assert!(namesrv.update_name_server_address().await.unwrap());
+        assert_eq!(servers, namesrv.inner.lock().servers);
+    }
+
+    #[tokio::test]
+    async fn test_name_server_failover() {
+        let servers = vec!["127.0.0.1:1".to_string(), "127.0.0.1:2".to_string()];
+        let namesrv = NameServer::new(StaticResolver::new(servers), None).unwrap();
+        namesrv.update_name_server_address().await.unwrap();
+        assert!(namesrv.query_topic_route_info(TOPIC).await.is_err());
+        assert!(namesrv.query_topic_route_info(TOPIC).await.is_err());
+        let health = namesrv.inner.lock().health.clone();
+        assert_eq!(2, health["127.0.0.1:1"].failures);
+        assert_eq!(2, health["127.0.0.1:2"].failures);
+    }
+
+    #[test]
+    fn test_candidate_servers_skip_unhealthy() {
+        let namesrv = NameServer::new(StaticResolver::new(vec![]), None).unwrap();
+        namesrv.inner.lock().servers = vec![
+            "10.0.0.1:9876".to_string(),
+            "10.0.0.2:9876".to_string(),
+            "10.0.0.3:9876".to_string(),
+        ];
+        assert_eq!("10.0.0.1:9876", namesrv.address());
+        assert_eq!("10.0.0.2:9876", namesrv.address());
+
+        namesrv.record_failure("10.0.0.3:9876", &Error::EmptyNameServers);
+        assert_eq!(
+            vec!["10.0.0.1:9876", "10.0.0.2:9876", "10.0.0.3:9876"],
+            namesrv.candidate_servers()
+        );
+        assert_eq!("10.0.0.1:9876", namesrv.address());
+
+        namesrv.record_success("10.0.0.3:9876");
+        assert_eq!(
+            vec!["10.0.0.2:9876", "10.0.0.3:9876", "10.0.0.1:9876"],
+            namesrv.candidate_servers()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_route_changes() {
+        let namesrv = NameServer::new(StaticResolver::new(vec![]), None).unwrap();
+        let mut changes = namesrv.subscribe_route_changes();
+        let route_data = TopicRouteData {
+            order_topic_conf: String::new(),
+            queue_datas: Vec::new(),
+            broker_datas: Vec::new(),
+            filter_server_table: HashMap::new(),
+        };
+        namesrv.notify_route_change(TOPIC, &route_data);
+        assert_eq!(
+            (TOPIC.to_string(), route_data),
+            changes.recv().await.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_weighted_candidate_servers() {
+        let namesrv = NameServer::new(StaticResolver::new(vec![]), None).unwrap();
+        namesrv.inner.lock().servers = vec![
+            "10.0.0.1:9876".to_string(),
+            "10.0.0.2:9876".to_string(),
+            "10.0.0.3:9876".to_string(),
+        ];
+        namesrv.set_weights(
+            [
+                ("10.0.0.1".to_string(), 0),
+                ("10.0.0.2:9876".to_string(), 0),
+            ]
+            .into(),
+        );
+        for _ in 0..10 {
+            let servers = namesrv.candidate_servers();
+            assert_eq!(3, servers.len());
+            assert_eq!("10.0.0.3:9876", servers[0]);
+        }
+    }
+
+    #[test]
+    fn test_sticky_candidate_servers() {
+        let namesrv = NameServer::new(StaticResolver::new(vec![]), None).unwrap();
+        namesrv.set_selection(NameServerSelection::Sticky);
+        namesrv.inner.lock().servers = vec![
+            "10.0.0.1:9876".to_string(),
+            "10.0.0.2:9876".to_string(),
+            "10.0.0.3:9876".to_string(),
+        ];
+        assert_eq!("10.0.0.1:9876", namesrv.address());
+        assert_eq!("10.0.0.1:9876", namesrv.address());
+
+        let err = Error::EmptyNameServers;
+        namesrv.record_failure("10.0.0.1:9876", &err);
+        let servers = namesrv.candidate_servers();
+        assert_eq!(
+            vec!["10.0.0.2:9876", "10.0.0.3:9876", "10.0.0.1:9876"],
+            servers
+        );
+        namesrv.record_failure("10.0.0.2:9876", &err);
+        namesrv.record_success("10.0.0.3:9876");
+        assert_eq!("10.0.0.3:9876", namesrv.address());
+        assert_eq!("10.0.0.3:9876", namesrv.address());
+    }
+
+    #[tokio::test]
+    async fn test_random_start_index() {
+        let servers: Vec<String> = (1..=100).map(|i| format!("10.0.0.{}:9876", i)).collect();
+        let mut starts = HashSet::new();
+        for _ in 0..5 {
+            let namesrv = NameServer::new(StaticResolver::new(servers.clone()), None).unwrap();
+            namesrv.update_name_server_address().await.unwrap();
+            starts.insert(namesrv.address());
+        }
+        assert!(starts.len() > 1);
+    }
+
+    #[test]
+    fn test_find_broker_addr() {
+        let namesrv = NameServer::new(StaticResolver::new(vec![]), None).unwrap();
+        let mut broker_data = BrokerData {
+            cluster: "DefaultCluster".to_string(),
+            broker_name: "broker-a".to_string(),
+            broker_addrs: [
+                (MASTER_ID, "10.0.0.1:10911".to_string()),
+                (2, "10.0.0.3:10911".to_string()),
+                (1, "10.0.0.2:10911".to_string()),
+            ]
+            .into(),
+        };
+        namesrv
+            .inner
+            .lock()
+            .broker_address_map
+            .insert("broker-a".to_string(), broker_data.clone());
+        let found = |prefer_slave, only_this_broker| {
+            namesrv
+                .find_broker_addr("broker-a", prefer_slave, only_this_broker)
+                .map(|res| (res.addr, res.slave))
+        };
+        assert_eq!(
+            Some(("10.0.0.1:10911".to_string(), false)),
+            found(false, false)
+        );
+        assert_eq!(
+            Some(("10.0.0.2:10911".to_string(), true)),
+            found(true, true)
+        );
+        assert!(namesrv.find_broker_addr("broker-b", false, false).is_none());
+        let addrs: Vec<_> = namesrv
+            .find_broker_addrs("broker-a", true)
+            .into_iter()
+            .map(|res| res.addr)
+            .collect();
+        assert_eq!(
+            vec!["10.0.0.2:10911", "10.0.0.3:10911", "10.0.0.1:10911"],
+            addrs
+        );
+
+        // master is down
+        broker_data.broker_addrs.remove(&MASTER_ID);
+        namesrv
+            .inner
+            .lock()
+            .broker_address_map
+            .insert("broker-a".to_string(), broker_data);
+        assert_eq!(
+            Some(("10.0.0.2:10911".to_string(), true)),
+            found(false, false)
+        );
+        assert_eq!(None, found(false, true));
+    }
+
+    #[test]
+    fn test_remove_topic_route() {
+        let namesrv = NameServer::new(StaticResolver::new(vec![]), None).unwrap();
+        let broker_data = BrokerData {
+            cluster: "DefaultCluster".to_string(),
+            broker_name: "broker-a".to_string(),
+            broker_addrs: [(MASTER_ID, "10.0.0.1:10911".to_string())].into(),
+        };
+        let route_data = TopicRouteData {
+            order_topic_conf: String::new(),
+            queue_datas: Vec::new(),
+            broker_datas: vec![broker_data.clone()],
+            filter_server_table: HashMap::new(),
+        };
+        {
+            let mut inner = namesrv.inner.lock();
+            inner
+                .broker_address_map
+                .insert("broker-a".to_string(), broker_data);
+            for topic in ["topic-a", "topic-b"] {
+                inner
+                    .route_data_map
+                    .insert(topic.to_string(), route_data.clone());
+                inner
+                    .route_updated_at
+                    .insert(topic.to_string(), Instant::now());
+            }
+        }
+        assert!(namesrv.remove_topic_route("topic-a").is_empty());
+        assert!(namesrv.remove_topic_route("topic-a").is_empty());
+        assert_eq!(1, namesrv.broker_address_map().len());
+
+        assert!(namesrv.remove_expired_routes().is_empty());
+        namesrv.set_route_ttl(Some(Duration::ZERO));
+        assert_eq!(vec!["topic-b"], namesrv.remove_expired_routes());
+        assert_eq!(vec!["10.0.0.1:10911"], namesrv.clean_offline_broker());
+        assert!(namesrv.broker_address_map().is_empty());
+    }
+
+    #[test]
+    fn test_clean_offline_broker() {
+        let namesrv = NameServer::new(StaticResolver::new(vec![]), None).unwrap();
+        let broker_data = |name: &str, addrs: &[&str]| BrokerData {
+            cluster: "DefaultCluster".to_string(),
+            broker_name: name.to_string(),
+            broker_addrs: addrs
+                .iter()
+                .enumerate()
+                .map(|(id, addr)| (id as i64, addr.to_string()))
+                .collect(),
+        };
+        {
+            let mut inner = namesrv.inner.lock();
+            inner.broker_address_map.insert(
+                "broker-a".to_string(),
+                broker_data("broker-a", &["10.0.0.1:10911", "10.0.0.2:10911"]),
+            );
+            inner.broker_address_map.insert(
+                "broker-b".to_string(),
+                broker_data("broker-b", &["10.0.0.3:10911"]),
+            );
+            inner.route_data_map.insert(
+                TOPIC.to_string(),
+                TopicRouteData {
+                    order_topic_conf: String::new(),
+                    queue_datas: Vec::new(),
+                    broker_datas: vec![broker_data("broker-a", &["10.0.0.1:10911"])],
+                    filter_server_table: HashMap::new(),
+                },
+            );
+        }
+        namesrv.add_broker_version("broker-b", "10.0.0.3:10911", 1);
+        let mut removed = namesrv.clean_offline_broker();
+        removed.sort();
+        assert_eq!(removed, vec!["10.0.0.2:10911", "10.0.0.3:10911"]);
+        let brokers = namesrv.broker_address_map();
+        assert_eq!(1, brokers.len());
+        assert_eq!(1, brokers["broker-a"].broker_addrs.len());
+        assert!(namesrv.inner.lock().broker_version_map.is_empty());
+    }
+
+    #[test]
+    fn test_update_broker_version() {
+        let namesrv = NameServer::new(StaticResolver::new(vec![]), None).unwrap();
+        namesrv.inner.lock().broker_address_map.insert(
+            "broker-a".to_string(),
+            BrokerData {
+                cluster: "DefaultCluster".to_string(),
+                broker_name: "broker-a".to_string(),
+                broker_addrs: [(0, "10.0.0.1:10911".to_string())].into_iter().collect(),
+            },
+        );
+        assert_eq!(None, namesrv.broker_version("10.0.0.1:10911"));
+        namesrv.update_broker_version("10.0.0.1:10911", 413);
+        namesrv.update_broker_version("127.0.0.1:9876", 413);
+        assert_eq!(Some(413), namesrv.broker_version("10.0.0.1:10911"));
+        assert_eq!(None, namesrv.broker_version("127.0.0.1:9876"));
+    }
 }
@@ -4,6 +4,7 @@ use bitflags::bitflags;
 use bitflags_serde_shim::impl_serde_for_bitflags;
 
 bitflags! {
+    /// Read and write permission of a topic or broker
     pub struct Permission: i32 {
         const PRIORITY = 0x1 << 3;
         const READ = 0x1 << 2;
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::io::{self, Write};
+use std::fmt;
+use std::io::Write;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -8,23 +8,35 @@ use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use parking_lot::Mutex;
 use time::OffsetDateTime;
+use tokio::sync::broadcast;
+use tokio::time::{sleep, Instant};
+use tracing::warn;
 
-use crate::client::{Client, ClientOptions, ClientState};
-use crate::error::{ClientError, Error};
-use crate::message::{Message, MessageQueue, MessageSysFlag, Property};
-use crate::namesrv::NameServer;
+use crate::client::{
+    Client, ClientMetrics, ClientOptions, ClientState, Credentials, MQClientManager,
+};
+use crate::error::{ClientError, Error, ErrorKind};
+use crate::message::{Message, MessageExt, MessageQueue, MessageSysFlag, Property};
+use crate::namespace::{without_namespace, wrap_namespace};
 use crate::producer::selector::QueueSelect;
 use crate::protocol::{
-    request::{SendMessageRequestHeader, SendMessageRequestV2Header},
-    RemotingCommand, RequestCode, ResponseCode,
+    request::{EndTransactionRequestHeader, SendMessageRequestHeader, SendMessageRequestV2Header},
+    version, RemotingCommand, RequestCode, ResponseCode,
 };
+use crate::remoting::{ConnectionEvent, RpcHook};
 use crate::resolver::{HttpResolver, PassthroughResolver, Resolver};
-use crate::route::TopicPublishInfo;
+use crate::route::{TopicPublishInfo, TopicRouteData};
 use crate::Error::TopicNotExist;
 use selector::QueueSelector;
+use transaction::TransactionChecker;
+pub use transaction::{LocalTransactionState, TransactionSendResult};
 
 /// Message queue selector
 pub mod selector;
+mod transaction;
+
+/// Max messages of one broker returned by a unique key query
+const UNIQUE_KEY_QUERY_MAX_NUM: i32 = 32;
 
 /// Message send status
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -98,6 +110,20 @@ impl ProducerOptions {
         &self.client_options.group_name
     }
 
+    /// Set ACL credentials
+    pub fn set_credentials(&mut self, credentials: Credentials) -> &mut Self {
+        self.client_options.set_credentials(credentials);
+        self
+    }
+
+    /// Times to resend a message failed with an error retried by the retry policy of
+    /// the client options, defaults to 2
+    pub fn set_max_retries(&mut self, retries: usize) -> &mut Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Time budget of sending a message including its retries, defaults to 3s
     pub fn set_send_msg_timeout(&mut self, timeout: Duration) -> &mut Self {
         self.send_msg_timeout = timeout;
         self
@@ -135,18 +161,31 @@ impl ProducerOptions {
     }
 }
 
-#[derive(Debug)]
 pub(crate) struct ProducerInner {
     publish_info: HashMap<String, TopicPublishInfo>,
+    transaction_checker: Option<TransactionChecker>,
+}
+
+impl fmt::Debug for ProducerInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProducerInner")
+            .field("publish_info", &self.publish_info)
+            .finish()
+    }
 }
 
 impl ProducerInner {
     fn new() -> Self {
         Self {
             publish_info: HashMap::new(),
+            transaction_checker: None,
         }
     }
 
+    pub(crate) fn transaction_checker(&self) -> Option<TransactionChecker> {
+        self.transaction_checker.clone()
+    }
+
     pub(crate) fn publish_topic_list(&self) -> Vec<String> {
         self.publish_info.keys().cloned().collect()
     }
@@ -163,10 +202,6 @@ impl ProducerInner {
             .map(|info| info.message_queues.is_empty())
             .unwrap_or(true)
     }
-
-    pub(crate) fn is_unit_mode(&self) -> bool {
-        false
-    }
 }
 
 /// RocketMQ producer
@@ -182,28 +217,129 @@ impl Producer {
         Self::with_options(ProducerOptions::default())
     }
 
-    pub fn with_options(options: ProducerOptions) -> Result<Self, Error> {
-        let client_options = options.client_options.clone();
-        let name_server =
-            NameServer::new(options.resolver.clone(), client_options.credentials.clone())?;
+    pub fn with_options(mut options: ProducerOptions) -> Result<Self, Error> {
+        options.client_options.make_instance_name_unique();
+        let client = MQClientManager::get_or_create_client(
+            options.client_options.clone(),
+            options.resolver.clone(),
+        )?;
         Ok(Self {
             inner: Arc::new(Mutex::new(ProducerInner::new())),
             options,
-            client: Client::new(client_options, name_server),
+            client,
         })
     }
 
-    pub fn start(&self) {
+    /// Start the producer, fails if the underlying client can not be started
+    pub async fn start(&self) -> Result<(), Error> {
+        let group = self.group();
+        MQClientManager::register_client(&self.client, &self.options.resolver)?;
         self.client
-            .register_producer(&self.options.group_name(), Arc::clone(&self.inner));
-        self.client.start();
+            .register_producer(&group, Arc::clone(&self.inner))?;
+        if let Err(err) = self.client.start().await {
+            self.client.unregister_producer(&group, &self.inner);
+            return Err(err);
+        }
+        Ok(())
     }
 
     pub fn shutdown(&self) {
-        self.client.unregister_producer(&self.options.group_name());
+        self.client.unregister_producer(&self.group(), &self.inner);
         self.client.shutdown();
     }
 
+    /// Producer group prefixed with the namespace
+    fn group(&self) -> String {
+        wrap_namespace(
+            &self.options.client_options.resource_namespace(),
+            self.options.group_name(),
+        )
+    }
+
+    /// Register a hook invoked around every request of the underlying client
+    pub fn register_rpc_hook(&self, hook: impl RpcHook + 'static) {
+        self.client.register_rpc_hook(hook);
+    }
+
+    /// Get a snapshot of the connection, request and failure metrics of the underlying client
+    pub fn client_metrics(&self) -> ClientMetrics {
+        self.client.metrics()
+    }
+
+    /// Subscribe to connection events of broker and name server addresses, e.g. to
+    /// track their availability
+    pub fn subscribe_connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.client.subscribe_connection_events()
+    }
+
+    /// Connect to the master brokers of `topic` to avoid paying for it on the first send
+    pub async fn warm_up(&self, topic: &str) -> Result<(), Error> {
+        let topic = wrap_namespace(&self.options.client_options.resource_namespace(), topic);
+        self.client.warm_up(&topic).await
+    }
+
+    /// Subscribe to topic route changes, topics are prefixed with the namespace if any
+    pub fn subscribe_route_changes(&self) -> broadcast::Receiver<(String, TopicRouteData)> {
+        self.client.subscribe_route_changes()
+    }
+
+    /// Query up to `max_num` messages of every broker of `topic` having `key` among
+    /// their keys and stored between the timestamps in milliseconds
+    pub async fn query_message(
+        &self,
+        topic: &str,
+        key: &str,
+        max_num: i32,
+        begin_timestamp: i64,
+        end_timestamp: i64,
+    ) -> Result<Vec<MessageExt>, Error> {
+        self.check_state()?;
+        let topic = wrap_namespace(&self.options.client_options.resource_namespace(), topic);
+        let msgs = self
+            .client
+            .query_message_by_key(&topic, key, max_num, begin_timestamp, end_timestamp, false)
+            .await?;
+        Ok(msgs
+            .into_iter()
+            .map(|msg| self.message_without_namespace(msg))
+            .collect())
+    }
+
+    /// Query the messages of `topic` sent with the unique message id `msg_id`,
+    /// more than one if the send was retried
+    pub async fn query_message_by_unique_key(
+        &self,
+        topic: &str,
+        msg_id: &str,
+    ) -> Result<Vec<MessageExt>, Error> {
+        self.check_state()?;
+        let topic = wrap_namespace(&self.options.client_options.resource_namespace(), topic);
+        let msgs = self
+            .client
+            .query_message_by_key(&topic, msg_id, UNIQUE_KEY_QUERY_MAX_NUM, 0, i64::MAX, true)
+            .await?;
+        Ok(msgs
+            .into_iter()
+            .map(|msg| self.message_without_namespace(msg))
+            .collect())
+    }
+
+    /// Fetch a message by its offset message id
+    pub async fn view_message(&self, offset_msg_id: &str) -> Result<MessageExt, Error> {
+        self.check_state()?;
+        let msg = self.client.view_message(offset_msg_id).await?;
+        Ok(self.message_without_namespace(msg))
+    }
+
+    /// Strip the namespace off the topic of a fetched message
+    fn message_without_namespace(&self, mut msg: MessageExt) -> MessageExt {
+        msg.message.topic = without_namespace(
+            &self.options.client_options.resource_namespace(),
+            &msg.message.topic,
+        );
+        msg
+    }
+
     fn check_state(&self) -> Result<(), Error> {
         match self.client.state() {
             ClientState::Created => Err(Error::Client(ClientError::NotStarted)),
@@ -213,13 +349,64 @@ impl Producer {
         }
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "send",
+            skip_all,
+            fields(topic = %msg.topic, group = %self.options.group_name(), broker)
+        )
+    )]
     pub async fn send(&self, msg: Message) -> Result<SendResult, Error> {
         self.check_state()?;
         let mut msg = msg;
-        let namespace = &self.options.client_options.namespace;
-        if !namespace.is_empty() {
-            msg.topic = format!("{}%{}", namespace, msg.topic);
+        msg.topic = wrap_namespace(
+            &self.options.client_options.resource_namespace(),
+            &msg.topic,
+        );
+        // retries carry the same message id so that consumers can deduplicate them
+        msg.set_default_unique_key();
+        let policy = &self.options.client_options.retry_policy;
+        let deadline = Instant::now() + self.options.send_msg_timeout;
+        let mut retry = 0;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let err = match self.send_once(msg.clone(), timeout).await {
+                Err(err) if retry < self.options.max_retries && policy.is_retriable_error(&err) => {
+                    err
+                }
+                res => return res,
+            };
+            retry += 1;
+            let delay = policy.backoff(retry as u32);
+            if Instant::now() + delay >= deadline {
+                return Err(err);
+            }
+            warn!(
+                topic = %msg.topic,
+                retry = retry,
+                error = %err,
+                "send message failed, retry in {:?}",
+                delay
+            );
+            if err.kind() == ErrorKind::NotLeader {
+                // pick a queue of the new leader
+                self.refresh_publish_info(&msg.topic).await;
+            }
+            sleep(delay).await;
+        }
+    }
+
+    async fn refresh_publish_info(&self, topic: &str) {
+        match self.client.name_server.update_topic_route_info(topic).await {
+            Ok((route_data, changed)) => {
+                self.client.update_publish_info(topic, route_data, changed)
+            }
+            Err(err) => warn!(topic = %topic, error = %err, "refresh topic route failed"),
         }
+    }
+
+    async fn send_once(&self, mut msg: Message, timeout: Duration) -> Result<SendResult, Error> {
         let mq = self
             .select_message_queue(&msg)
             .await?
@@ -229,14 +416,94 @@ impl Producer {
             .name_server
             .find_broker_addr_by_name(&mq.broker_name)
             .ok_or(Error::EmptyRouteData)?;
-        let cmd = self.build_send_request(&mq, &mut msg)?;
-        let res = tokio::time::timeout(
-            self.options.send_msg_timeout.clone(),
-            self.client.invoke(&addr, cmd),
-        )
-        .await
-        .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))??;
-        Self::process_send_response(&mq.broker_name, res, &[msg])
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("broker", addr.as_str());
+        let cmd = self.build_send_request(&addr, &mq, &mut msg)?;
+        let res = self.client.invoke_timeout(&addr, cmd, timeout).await?;
+        let mut result = Self::process_send_response(&mq.broker_name, res, &[msg])?;
+        result.message_queue.topic = without_namespace(
+            &self.options.client_options.resource_namespace(),
+            &result.message_queue.topic,
+        );
+        Ok(result)
+    }
+
+    /// Send `msg` as a prepared message, then commit or roll it back by the state
+    /// `executor` returns for the local transaction
+    ///
+    /// Prepared messages left in the `Unknown` state are checked later by the
+    /// broker with the checker set by `set_transaction_checker`.
+    pub async fn send_message_in_transaction<F>(
+        &self,
+        msg: Message,
+        executor: F,
+    ) -> Result<TransactionSendResult, Error>
+    where
+        F: FnOnce(&Message) -> LocalTransactionState,
+    {
+        let mut msg = msg;
+        msg.set_default_unique_key();
+        msg.set_property(
+            Property::TRANSACTION_PREPARED.to_string(),
+            "true".to_string(),
+        );
+        msg.set_property(Property::PRODUCER_GROUP.to_string(), self.group());
+        let send_result = self.send(msg.clone()).await?;
+        let local_state = match send_result.status {
+            SendStatus::Ok => executor(&msg),
+            _ => LocalTransactionState::RollbackMessage,
+        };
+        if let Err(err) = self.end_transaction(&send_result, local_state).await {
+            warn!(
+                msg_id = %send_result.msg_id,
+                error = %err,
+                "end transaction failed, the broker checks its state later"
+            );
+        }
+        Ok(TransactionSendResult {
+            send_result,
+            local_state,
+        })
+    }
+
+    /// Set the checker answering brokers asking for the local transaction state of
+    /// prepared messages whose transaction did not end
+    pub fn set_transaction_checker<F>(&self, checker: F)
+    where
+        F: Fn(&MessageExt) -> LocalTransactionState + Send + Sync + 'static,
+    {
+        let namespace = self.options.client_options.resource_namespace();
+        self.inner.lock().transaction_checker = Some(Arc::new(move |msg: &MessageExt| {
+            let mut msg = msg.clone();
+            msg.message.topic = without_namespace(&namespace, &msg.message.topic);
+            checker(&msg)
+        }));
+    }
+
+    /// Commit or roll back the prepared message of `send_result` with a one-way request
+    async fn end_transaction(
+        &self,
+        send_result: &SendResult,
+        state: LocalTransactionState,
+    ) -> Result<(), Error> {
+        let (_, commit_log_offset) = MessageExt::decode_message_id(&send_result.offset_msg_id)?;
+        let addr = self
+            .client
+            .name_server
+            .find_broker_addr_by_name(&send_result.message_queue.broker_name)
+            .ok_or(Error::EmptyRouteData)?;
+        let header = EndTransactionRequestHeader {
+            producer_group: self.group(),
+            tran_state_table_offset: send_result.queue_offset,
+            commit_log_offset,
+            commit_or_rollback: state.sys_flag(),
+            from_transaction_check: false,
+            msg_id: send_result.msg_id.clone(),
+            transaction_id: send_result.transaction_id.clone(),
+        };
+        self.client
+            .invoke_oneway(&addr, header.into_command(String::new()))
+            .await
     }
 
     pub async fn send_batch(&self, msgs: &[Message]) -> Result<SendResult, Error> {
@@ -244,13 +511,21 @@ impl Producer {
         Ok(self.send(msg).await?)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "send_oneway",
+            skip_all,
+            fields(topic = %msg.topic, group = %self.options.group_name(), broker)
+        )
+    )]
     pub async fn send_oneway(&self, msg: Message) -> Result<(), Error> {
         self.check_state()?;
         let mut msg = msg;
-        let namespace = &self.options.client_options.namespace;
-        if !namespace.is_empty() {
-            msg.topic = format!("{}%{}", namespace, msg.topic);
-        }
+        msg.topic = wrap_namespace(
+            &self.options.client_options.resource_namespace(),
+            &msg.topic,
+        );
         let mq = self
             .select_message_queue(&msg)
             .await?
@@ -260,7 +535,9 @@ impl Producer {
             .name_server
             .find_broker_addr_by_name(&mq.broker_name)
             .ok_or(Error::EmptyRouteData)?;
-        let cmd = self.build_send_request(&mq, &mut msg)?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("broker", addr.as_str());
+        let cmd = self.build_send_request(&addr, &mq, &mut msg)?;
         Ok(self.client.invoke_oneway(&addr, cmd).await?)
     }
 
@@ -269,8 +546,11 @@ impl Producer {
         Ok(self.send_oneway(msg).await?)
     }
 
+    /// Build the send command, choosing the request code and header the broker at
+    /// `addr` supports by its version
     fn build_send_request(
         &self,
+        addr: &str,
         mq: &MessageQueue,
         msg: &mut Message,
     ) -> Result<RemotingCommand, Error> {
@@ -295,7 +575,7 @@ impl Producer {
                     encoder.write_all(&msg.body)?;
                     let compressed = encoder.finish()?;
                     msg.sys_flag |= compressed_flag;
-                    compressed
+                    compressed.into()
                 } else {
                     msg.body.clone()
                 }
@@ -303,41 +583,37 @@ impl Producer {
         } else {
             msg.body.clone()
         };
+        let header = SendMessageRequestHeader {
+            producer_group: self.group(),
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+            sys_flag,
+            born_timestamp: (OffsetDateTime::now_utc() - OffsetDateTime::UNIX_EPOCH)
+                .whole_milliseconds() as i64,
+            flag: msg.flag,
+            properties: msg.dump_properties(),
+            reconsume_times: 0,
+            unit_mode: self.options.client_options.unit_mode,
+            max_reconsume_times: 0,
+            batch: msg.batch,
+            default_topic: self.options.create_topic_key.clone(),
+            default_topic_queue_nums: self.options.default_topic_queue_nums,
+        };
+        let broker_version = self.client.broker_version(addr);
         let cmd = if msg.batch {
-            let header = SendMessageRequestV2Header {
-                producer_group: self.options.group_name().to_string(),
-                topic: mq.topic.clone(),
-                queue_id: mq.queue_id,
-                sys_flag,
-                born_timestamp: (OffsetDateTime::now_utc() - OffsetDateTime::UNIX_EPOCH)
-                    .whole_milliseconds() as i64,
-                flag: msg.flag,
-                properties: msg.dump_properties(),
-                reconsume_times: 0,
-                unit_mode: self.options.client_options.unit_mode,
-                max_reconsume_times: 0,
-                batch: msg.batch,
-                default_topic: self.options.create_topic_key.clone(),
-                default_topic_queue_nums: self.options.default_topic_queue_nums,
-            };
+            if !version::supports_batch(broker_version) {
+                return Err(Error::UnsupportedByBroker {
+                    addr: addr.to_string(),
+                    version: broker_version.unwrap_or_default(),
+                    feature: "batch messages",
+                });
+            }
+            let header = SendMessageRequestV2Header::from(header);
+            RemotingCommand::with_header(RequestCode::SendBatchMessage, header, body)
+        } else if version::supports_send_v2(broker_version) {
+            let header = SendMessageRequestV2Header::from(header);
             RemotingCommand::with_header(RequestCode::SendMessageV2, header, body)
         } else {
-            let header = SendMessageRequestHeader {
-                producer_group: self.options.group_name().to_string(),
-                topic: mq.topic.clone(),
-                queue_id: mq.queue_id,
-                sys_flag,
-                born_timestamp: (OffsetDateTime::now_utc() - OffsetDateTime::UNIX_EPOCH)
-                    .whole_milliseconds() as i64,
-                flag: msg.flag,
-                properties: msg.dump_properties(),
-                reconsume_times: 0,
-                unit_mode: self.options.client_options.unit_mode,
-                max_reconsume_times: 0,
-                batch: msg.batch,
-                default_topic: self.options.create_topic_key.clone(),
-                default_topic_queue_nums: self.options.default_topic_queue_nums,
-            };
             RemotingCommand::with_header(RequestCode::SendMessage, header, body)
         };
         Ok(cmd)
@@ -348,7 +624,7 @@ impl Producer {
         cmd: RemotingCommand,
         msgs: &[Message],
     ) -> Result<SendResult, Error> {
-        let status = match ResponseCode::try_from(cmd.code()).unwrap_or(ResponseCode::SystemError) {
+        let status = match ResponseCode::from_code(cmd.code()) {
             ResponseCode::FlushDiskTimeout => SendStatus::FlushDiskTimeout,
             ResponseCode::FlushSlaveTimeout => SendStatus::FlushDiskTimeout,
             ResponseCode::SlaveNotAvailable => SendStatus::SlaveNotAvailable,
@@ -377,8 +653,8 @@ impl Producer {
             .get(Property::TRACE_SWITCH)
             .map(|prop| !prop.is_empty() && prop != "false")
             .unwrap_or(false);
-        let queue_id: u32 = cmd.header.ext_fields["queueId"].parse().unwrap();
-        let queue_offset: i64 = cmd.header.ext_fields["queueOffset"].parse().unwrap();
+        let queue_id: u32 = cmd.ext("queueId")?;
+        let queue_offset = cmd.ext_i64("queueOffset")?;
         let result = SendResult {
             status,
             msg_id: uniq_msg_id,
@@ -389,7 +665,7 @@ impl Producer {
             },
             queue_offset,
             transaction_id: cmd.header.ext_fields.get("transactionId").cloned(),
-            offset_msg_id: cmd.header.ext_fields["msgId"].clone(),
+            offset_msg_id: cmd.ext_str("msgId")?.to_string(),
             region_id,
             trace_on,
         };
@@ -449,7 +725,7 @@ impl Drop for Producer {
 mod test {
     use super::{Producer, ProducerOptions, SendStatus};
     use crate::error::{ClientError, Error};
-    use crate::message::{Message, MessageQueue};
+    use crate::message::{Message, MessageExt, MessageQueue};
 
     #[tokio::test]
     async fn test_producer_send_error_not_started() {
@@ -472,7 +748,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let msg = Message::new(
             "SELF_TEST_TOPIC".to_string(),
             String::new(),
@@ -491,7 +767,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let body = b"test-compressed".to_vec().repeat(1024);
         let msg = Message::new(
             "SELF_TEST_TOPIC".to_string(),
@@ -510,7 +786,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let ret = producer.send_batch(&[]).await;
         assert!(matches!(ret.unwrap_err(), Error::EmptyBatchMessage));
     }
@@ -521,7 +797,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let msg = Message::new(
             "SELF_TEST_TOPIC".to_string(),
             String::new(),
@@ -540,7 +816,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let msgs = [
             Message::new(
                 "SELF_TEST_TOPIC".to_string(),
@@ -569,7 +845,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let msg = Message::new(
             "SELF_TEST_TOPIC".to_string(),
             String::new(),
@@ -586,7 +862,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let ret = producer.send_batch_oneway(&[]).await;
         assert!(matches!(ret.unwrap_err(), Error::EmptyBatchMessage));
     }
@@ -597,7 +873,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let msg = Message::new(
             "SELF_TEST_TOPIC".to_string(),
             String::new(),
@@ -615,7 +891,7 @@ mod test {
         let mut options = ProducerOptions::default();
         options.set_name_server(vec!["localhost:9876".to_string()]);
         let producer = Producer::with_options(options).unwrap();
-        producer.start();
+        producer.start().await.unwrap();
         let msgs = [
             Message::new(
                 "SELF_TEST_TOPIC".to_string(),
@@ -654,10 +930,25 @@ mod test {
             broker_name: "DefaultCluster".to_string(),
             queue_id: 0,
         };
-        let cmd = producer.build_send_request(&mq, &mut msg).unwrap();
+        let cmd = producer
+            .build_send_request("127.0.0.1:10911", &mq, &mut msg)
+            .unwrap();
         assert_eq!(body, cmd.body);
     }
 
+    #[test]
+    fn test_producer_message_without_namespace() {
+        let mut options = ProducerOptions::default();
+        options.client_options.set_namespace("ns");
+        let producer = Producer::with_options(options).unwrap();
+        let mut msg = MessageExt::default();
+        msg.message.topic = "ns%test".to_string();
+        assert_eq!(
+            "test",
+            producer.message_without_namespace(msg).message.topic
+        );
+    }
+
     #[test]
     fn test_producer_build_send_request_compressed() {
         let producer = Producer::new().unwrap();
@@ -675,7 +966,53 @@ mod test {
             broker_name: "DefaultCluster".to_string(),
             queue_id: 0,
         };
-        let cmd = producer.build_send_request(&mq, &mut msg).unwrap();
+        let cmd = producer
+            .build_send_request("127.0.0.1:10911", &mq, &mut msg)
+            .unwrap();
         assert_ne!(body, cmd.body);
     }
+
+    #[tokio::test]
+    async fn test_producer_duplicate_group() {
+        let mut options = ProducerOptions::default();
+        options.set_name_server(vec!["localhost:9876".to_string()]);
+        options
+            .client_options
+            .set_group_name("test_duplicate_group")
+            .set_instance_name("test_producer_duplicate_group");
+        let producer1 = Producer::with_options(options.clone()).unwrap();
+        let producer2 = Producer::with_options(options.clone()).unwrap();
+        producer1.start().await.unwrap();
+        // starting twice is fine
+        producer1.start().await.unwrap();
+        let err = producer2.start().await.unwrap_err();
+        assert!(matches!(err, Error::DuplicateGroup(_)));
+        // dropping the rejected producer keeps the registered one
+        drop(producer2);
+        assert!(producer1
+            .client
+            .producers
+            .lock()
+            .contains_key("test_duplicate_group"));
+
+        options.client_options.set_group_name("another_group");
+        let producer3 = Producer::with_options(options).unwrap();
+        producer3.start().await.unwrap();
+        assert_eq!(2, producer1.client.producers.lock().len());
+    }
+
+    #[tokio::test]
+    async fn test_producer_restart() {
+        let mut options = ProducerOptions::default();
+        options.set_name_server(vec!["localhost:9876".to_string()]);
+        let producer = Producer::with_options(options).unwrap();
+        producer.start().await.unwrap();
+        producer.shutdown();
+        assert!(matches!(
+            producer.check_state(),
+            Err(Error::Client(ClientError::Shutdown))
+        ));
+        producer.start().await.unwrap();
+        assert!(producer.check_state().is_ok());
+    }
 }
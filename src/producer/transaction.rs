@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use crate::message::{MessageExt, MessageSysFlag};
+use crate::producer::SendResult;
+
+/// State of a local transaction, deciding whether its prepared message is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalTransactionState {
+    /// Deliver the prepared message to consumers
+    CommitMessage,
+    /// Discard the prepared message
+    RollbackMessage,
+    /// Not decided yet, the broker checks the state again later
+    Unknown,
+}
+
+impl LocalTransactionState {
+    /// `commitOrRollback` flag of the end transaction request
+    pub(crate) fn sys_flag(self) -> i32 {
+        match self {
+            LocalTransactionState::CommitMessage => MessageSysFlag::TransactionCommitType.into(),
+            LocalTransactionState::RollbackMessage => {
+                MessageSysFlag::TransactionRollbackType.into()
+            }
+            LocalTransactionState::Unknown => MessageSysFlag::TransactionNotType.into(),
+        }
+    }
+}
+
+/// Result of sending a transactional message
+#[derive(Debug, Clone)]
+pub struct TransactionSendResult {
+    pub send_result: SendResult,
+    pub local_state: LocalTransactionState,
+}
+
+/// Checker of the local transaction state of a prepared message, asked by brokers
+pub(crate) type TransactionChecker =
+    Arc<dyn Fn(&MessageExt) -> LocalTransactionState + Send + Sync>;
+
+#[cfg(test)]
+mod test {
+    use super::LocalTransactionState;
+
+    #[test]
+    fn test_local_transaction_state_sys_flag() {
+        assert_eq!(0x8, LocalTransactionState::CommitMessage.sys_flag());
+        assert_eq!(0xc, LocalTransactionState::RollbackMessage.sys_flag());
+        assert_eq!(0, LocalTransactionState::Unknown.sys_flag());
+    }
+}
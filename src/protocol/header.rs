@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, Read};
 use std::str::FromStr;
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt};
+use bytes::{BufMut, BytesMut};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
@@ -29,6 +30,7 @@ pub enum LanguageCode {
     GO = 9,
     PHP = 10,
     OMS = 11,
+    RUST = 12,
 }
 
 impl FromStr for LanguageCode {
@@ -47,6 +49,7 @@ impl FromStr for LanguageCode {
             "GO" => Self::GO,
             "PHP" => Self::PHP,
             "OMS" => Self::OMS,
+            "RUST" => Self::RUST,
             _ => Self::OTHER,
         };
         Ok(code)
@@ -68,6 +71,7 @@ impl fmt::Display for LanguageCode {
             LanguageCode::GO => write!(f, "GO"),
             LanguageCode::PHP => write!(f, "PHP"),
             LanguageCode::OMS => write!(f, "OMS"),
+            LanguageCode::RUST => write!(f, "RUST"),
         }
     }
 }
@@ -87,7 +91,17 @@ pub struct Header {
 
 pub trait HeaderCodec {
     fn codec_type(&self) -> HeaderCodecType;
-    fn encode(&self, header: &Header) -> Result<Vec<u8>, Error>;
+
+    /// Append the encoded `header` to `dst`
+    fn encode_into(&self, header: &Header, dst: &mut BytesMut) -> Result<(), Error>;
+
+    #[cfg(test)]
+    fn encode(&self, header: &Header) -> Result<Vec<u8>, Error> {
+        let mut buf = BytesMut::new();
+        self.encode_into(header, &mut buf)?;
+        Ok(buf.to_vec())
+    }
+
     fn decode(&self, buf: &[u8]) -> Result<Header, Error>;
 }
 
@@ -99,9 +113,10 @@ impl HeaderCodec for JsonHeaderCodec {
         HeaderCodecType::Json
     }
 
-    fn encode(&self, header: &Header) -> Result<Vec<u8>, Error> {
-        Ok(serde_json::to_vec(header)?)
+    fn encode_into(&self, header: &Header, dst: &mut BytesMut) -> Result<(), Error> {
+        Ok(serde_json::to_writer(dst.writer(), header)?)
     }
+
     fn decode(&self, buf: &[u8]) -> Result<Header, Error> {
         Ok(serde_json::from_slice(buf)?)
     }
@@ -110,52 +125,40 @@ impl HeaderCodec for JsonHeaderCodec {
 #[derive(Debug, PartialEq)]
 pub struct RocketMQHeaderCodec;
 
-impl RocketMQHeaderCodec {
-    fn encode_map(&self, map: &HashMap<String, String>) -> Result<Vec<u8>, Error> {
-        let mut buf = Vec::new();
-        if map.is_empty() {
-            return Ok(buf);
-        }
-        for (key, value) in map.iter() {
-            buf.write_i16::<BigEndian>(key.len() as _)?;
-            buf.write_all(key.as_bytes())?;
-            buf.write_i32::<BigEndian>(value.len() as _)?;
-            buf.write_all(value.as_bytes())?;
-        }
-        Ok(buf)
-    }
-}
-
 impl HeaderCodec for RocketMQHeaderCodec {
     fn codec_type(&self) -> HeaderCodecType {
         HeaderCodecType::RocketMQ
     }
 
-    fn encode(&self, header: &Header) -> Result<Vec<u8>, Error> {
-        let ext_bytes = self.encode_map(&header.ext_fields)?;
-        let length = HEADER_FIXED_LENGTH + header.remark.len() + ext_bytes.len();
-        let mut buf = Vec::with_capacity(length);
+    fn encode_into(&self, header: &Header, dst: &mut BytesMut) -> Result<(), Error> {
+        let ext_len: usize = header
+            .ext_fields
+            .iter()
+            .map(|(key, value)| 2 + key.len() + 4 + value.len())
+            .sum();
+        dst.reserve(2 + 1 + 2 + 4 + 4 + 4 + header.remark.len() + 4 + ext_len);
         // request code, 2 bytes
-        buf.write_i16::<BigEndian>(header.code as _)?;
+        dst.put_i16(header.code);
         // language flag, 1 byte
-        buf.write_u8(LanguageCode::OTHER.into())?;
+        dst.put_u8(header.language.into());
         // version flag, 2 bytes
-        buf.write_i16::<BigEndian>(header.version as _)?;
+        dst.put_i16(header.version);
         // opaque flag, 4 bytes
-        buf.write_i32::<BigEndian>(header.opaque)?;
+        dst.put_i32(header.opaque);
         // request flag, 4 bytes
-        buf.write_i32::<BigEndian>(header.flag as _)?;
+        dst.put_i32(header.flag);
         // remark length flag, 4 bytes
-        buf.write_i32::<BigEndian>(header.remark.len() as _)?;
-        if !header.remark.is_empty() {
-            // write remark
-            buf.write_all(header.remark.as_bytes())?;
+        dst.put_i32(header.remark.len() as _);
+        dst.put_slice(header.remark.as_bytes());
+        // ext fields length, 4 bytes
+        dst.put_i32(ext_len as _);
+        for (key, value) in &header.ext_fields {
+            dst.put_i16(key.len() as _);
+            dst.put_slice(key.as_bytes());
+            dst.put_i32(value.len() as _);
+            dst.put_slice(value.as_bytes());
         }
-        buf.write_i32::<BigEndian>(ext_bytes.len() as _)?;
-        if !ext_bytes.is_empty() {
-            buf.write_all(&ext_bytes)?;
-        }
-        Ok(buf)
+        Ok(())
     }
 
     fn decode(&self, buf: &[u8]) -> Result<Header, Error> {
@@ -172,29 +175,26 @@ impl HeaderCodec for RocketMQHeaderCodec {
         // request flag
         let flag = rdr.read_i32::<BigEndian>()?;
         // remark
-        let remark_len = rdr.read_i32::<BigEndian>()? as usize;
+        let remark_len = rdr.read_i32::<BigEndian>()?;
         let remark = if remark_len > 0 {
-            let mut remark_bytes = vec![0; remark_len];
-            rdr.read_exact(&mut remark_bytes)?;
-            String::from_utf8(remark_bytes)?
+            String::from_utf8(read_bytes(&mut rdr, remark_len, "remark")?)?
         } else {
             String::new()
         };
         // ext_fields
-        let ext_len = rdr.read_i32::<BigEndian>()? as usize;
+        let ext_len = rdr.read_i32::<BigEndian>()?;
         let ext_fields = if ext_len > 0 {
+            let ext_len = ext_len as usize;
             let mut map = HashMap::new();
             let mut bytes_read = 0;
             while bytes_read < ext_len {
-                let key_len = rdr.read_i16::<BigEndian>()? as usize;
-                let mut key_bytes = vec![0; key_len];
-                rdr.read_exact(&mut key_bytes)?;
-                bytes_read += 2 + key_len;
+                let key_len = rdr.read_i16::<BigEndian>()?;
+                let key_bytes = read_bytes(&mut rdr, key_len.into(), "ext field key")?;
+                bytes_read += 2 + key_bytes.len();
 
-                let val_len = rdr.read_i32::<BigEndian>()? as usize;
-                let mut val_bytes = vec![0; val_len];
-                rdr.read_exact(&mut val_bytes)?;
-                bytes_read += 4 + val_len;
+                let val_len = rdr.read_i32::<BigEndian>()?;
+                let val_bytes = read_bytes(&mut rdr, val_len, "ext field value")?;
+                bytes_read += 4 + val_bytes.len();
 
                 let key = String::from_utf8(key_bytes)?;
                 let val = String::from_utf8(val_bytes)?;
@@ -217,9 +217,25 @@ impl HeaderCodec for RocketMQHeaderCodec {
     }
 }
 
+/// Read `len` bytes, failing on negative lengths or lengths past the end of the header
+fn read_bytes(rdr: &mut Cursor<&[u8]>, len: i32, what: &str) -> Result<Vec<u8>, Error> {
+    let remaining = rdr.get_ref().len() as u64 - rdr.position().min(rdr.get_ref().len() as u64);
+    if len < 0 || len as u64 > remaining {
+        return Err(Error::InvalidHeader(format!(
+            "{} length {} exceeds the remaining {} bytes",
+            what, len, remaining
+        )));
+    }
+    let mut bytes = vec![0; len as usize];
+    rdr.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Serialize type of command headers
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, IntoPrimitive, TryFromPrimitive)]
 pub enum HeaderCodecType {
     Json = 0,
+    #[default]
     RocketMQ = 1,
 }
@@ -1,74 +1,185 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::io::Read;
 
 use byteorder::{BigEndian, ReadBytesExt};
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
 
 mod header;
 pub mod request;
 pub mod response;
+pub(crate) mod version;
 
+use crate::error::DecodeError;
 use crate::Error;
-use header::{Header, HeaderCodec, LanguageCode, HEADER_FIXED_LENGTH};
-pub use header::{HeaderCodecType, JsonHeaderCodec, RocketMQHeaderCodec};
+pub(crate) use header::HeaderCodec;
+use header::{Header, HEADER_FIXED_LENGTH};
+pub use header::{HeaderCodecType, JsonHeaderCodec, LanguageCode, RocketMQHeaderCodec};
 use request::EncodeRequestHeader;
-pub use request::RequestCode;
+pub use request::{HeaderValue, RequestCode};
 pub use response::ResponseCode;
 
-const _LENGTH: usize = 4;
+const LENGTH: usize = 4;
+/// Default maximum frame size, the same as the Java client
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+/// Language reported in outgoing commands by default
+pub const DEFAULT_LANGUAGE: LanguageCode = LanguageCode::RUST;
+/// Version reported in outgoing commands by default, the ordinal of the Java `MQVersion` it matches
+pub const DEFAULT_VERSION: i16 = 317;
 const RESPONSE_TYPE: i32 = 1;
+const RPC_ONEWAY: i32 = 1 << 1;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RemotingCommand {
     pub(crate) header: Header,
-    pub(crate) body: Vec<u8>,
+    pub(crate) body: Bytes,
 }
 
 impl RemotingCommand {
+    /// Build a request command
     pub fn new(
+        code: RequestCode,
+        remark: String,
+        ext_fields: HashMap<String, String>,
+        body: impl Into<Bytes>,
+    ) -> Self {
+        Self::with_code(code.into(), 0, remark, ext_fields, body)
+    }
+
+    /// Build a response command
+    pub fn new_response(
+        code: ResponseCode,
+        remark: String,
+        ext_fields: HashMap<String, String>,
+        body: impl Into<Bytes>,
+    ) -> Self {
+        Self::with_code(code.into(), RESPONSE_TYPE, remark, ext_fields, body)
+    }
+
+    /// Build the response to this request, carrying its opaque so the sender can
+    /// match it to the request
+    pub fn response(
+        &self,
+        code: ResponseCode,
+        remark: impl Into<String>,
+        ext_fields: HashMap<String, String>,
+    ) -> Self {
+        let mut res = Self::new_response(code, remark.into(), ext_fields, Bytes::new());
+        res.header.opaque = self.header.opaque;
+        res
+    }
+
+    pub fn with_header<H: EncodeRequestHeader>(
+        code: RequestCode,
+        header: H,
+        body: impl Into<Bytes>,
+    ) -> Self {
+        let ext_fields = header.encode();
+        Self::new(code, String::new(), ext_fields, body)
+    }
+
+    fn with_code(
         code: i16,
         flag: i32,
         remark: String,
         ext_fields: HashMap<String, String>,
-        body: Vec<u8>,
+        body: impl Into<Bytes>,
     ) -> Self {
         Self {
             header: Header {
                 code,
-                language: LanguageCode::OTHER,
-                version: 317,
+                language: DEFAULT_LANGUAGE,
+                version: DEFAULT_VERSION,
                 opaque: 0,
                 flag,
                 remark,
                 ext_fields,
             },
-            body,
+            body: body.into(),
         }
     }
 
-    pub fn with_header<H: EncodeRequestHeader>(
-        code: RequestCode,
-        header: H,
-        body: Vec<u8>,
-    ) -> Self {
-        let ext_fields = header.encode();
-        Self::new(code.into(), 0, String::new(), ext_fields, body)
+    pub fn code(&self) -> i16 {
+        self.header.code
     }
 
-    fn encode_codec_type(source: i32, codec: impl HeaderCodec) -> [u8; 4] {
-        let codec_type: u8 = codec.codec_type().into();
-        [
-            codec_type,
-            ((source >> 16) & 0xff) as u8,
-            ((source >> 8) & 0xff) as u8,
-            (source & 0xff) as u8,
-        ]
+    pub fn opaque(&self) -> i32 {
+        self.header.opaque
     }
 
-    pub fn code(&self) -> i16 {
-        self.header.code
+    /// Version of the client or server which sent the command
+    pub fn version(&self) -> i16 {
+        self.header.version
+    }
+
+    /// Language of the client or server which sent the command
+    pub fn language(&self) -> LanguageCode {
+        self.header.language
+    }
+
+    /// Identify the sender of the command as `language` client of `version`
+    pub fn set_identity(&mut self, language: LanguageCode, version: i16) -> &mut Self {
+        self.header.language = language;
+        self.header.version = version;
+        self
+    }
+
+    pub fn remark(&self) -> &str {
+        &self.header.remark
+    }
+
+    pub fn ext_fields(&self) -> &HashMap<String, String> {
+        &self.header.ext_fields
+    }
+
+    pub fn ext_fields_mut(&mut self) -> &mut HashMap<String, String> {
+        &mut self.header.ext_fields
+    }
+
+    /// Extension field `name`, an error naming the field if it's missing
+    pub fn ext_str(&self, name: &str) -> Result<&str, Error> {
+        self.header
+            .ext_fields
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| Error::InvalidHeader(format!("missing {}", name)))
+    }
+
+    /// Extension field `name` parsed as `T`, an error naming the field if it's missing
+    /// or invalid
+    pub fn ext<T: HeaderValue>(&self, name: &str) -> Result<T, Error> {
+        let value = self.ext_str(name)?;
+        T::from_header_value(value)
+            .ok_or_else(|| Error::InvalidHeader(format!("invalid value of {}: {}", name, value)))
+    }
+
+    /// Extension field `name` parsed as an `i32`, see `ext`
+    pub fn ext_i32(&self, name: &str) -> Result<i32, Error> {
+        self.ext(name)
+    }
+
+    /// Extension field `name` parsed as an `i64`, see `ext`
+    pub fn ext_i64(&self, name: &str) -> Result<i64, Error> {
+        self.ext(name)
+    }
+
+    /// Extension field `name` parsed as a `bool`, see `ext`
+    pub fn ext_bool(&self, name: &str) -> Result<bool, Error> {
+        self.ext(name)
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn set_body(&mut self, body: impl Into<Bytes>) -> &mut Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Take the body out of the command without copying
+    pub fn into_body(self) -> Bytes {
+        self.body
     }
 
     pub fn is_response_type(&self) -> bool {
@@ -79,30 +190,75 @@ impl RemotingCommand {
         self.header.flag |= RESPONSE_TYPE
     }
 
-    fn encode_into(&self, wtr: &mut BytesMut, codec: impl HeaderCodec) -> Result<(), Error> {
-        let header_bytes = codec.encode(&self.header)?;
-        let header_len = header_bytes.len();
-        let length = HEADER_FIXED_LENGTH + header_len + self.body.len();
-        wtr.put_i32(length as i32);
-        let header_len_with_codec = Self::encode_codec_type(header_len as i32, codec);
-        wtr.put(&header_len_with_codec[..]);
-        wtr.put(&header_bytes[..]);
-        if !self.body.is_empty() {
-            wtr.put(&self.body[..]);
+    /// Whether the sender expects no response
+    pub fn is_oneway_rpc(&self) -> bool {
+        self.header.flag & RPC_ONEWAY == RPC_ONEWAY
+    }
+
+    pub fn mark_oneway_rpc(&mut self) {
+        self.header.flag |= RPC_ONEWAY
+    }
+
+    /// Append the frame of this command to `dst`, the length prefixes are reserved
+    /// up front and filled in once the header is written
+    fn encode_into(&self, dst: &mut BytesMut, codec: impl HeaderCodec) -> Result<(), Error> {
+        let start = dst.len();
+        dst.reserve(LENGTH + HEADER_FIXED_LENGTH + self.body.len());
+        dst.put_bytes(0, LENGTH + HEADER_FIXED_LENGTH);
+        if let Err(err) = codec.encode_into(&self.header, dst) {
+            // drop the partial frame so that the buffer stays a sequence of whole frames
+            dst.truncate(start);
+            return Err(err);
         }
+        let header_len = dst.len() - start - LENGTH - HEADER_FIXED_LENGTH;
+        let length = HEADER_FIXED_LENGTH + header_len + self.body.len();
+        let codec_type: u8 = codec.codec_type().into();
+        dst[start..start + LENGTH].copy_from_slice(&(length as i32).to_be_bytes());
+        dst[start + LENGTH..start + LENGTH + HEADER_FIXED_LENGTH].copy_from_slice(
+            &((header_len as u32 & 0xffffff) | (codec_type as u32) << 24).to_be_bytes(),
+        );
+        dst.put_slice(&self.body);
         Ok(())
     }
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct MqCodec;
+pub(crate) struct MqCodec {
+    max_frame_size: usize,
+    serialize_type: HeaderCodecType,
+}
+
+impl MqCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            serialize_type: HeaderCodecType::default(),
+        }
+    }
+
+    /// Header codec of outgoing commands, incoming ones are decoded with the codec they declare
+    pub fn with_serialize_type(serialize_type: HeaderCodecType) -> Self {
+        Self {
+            serialize_type,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for MqCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
 
 impl Encoder<RemotingCommand> for MqCodec {
     type Error = Error;
 
     fn encode(&mut self, item: RemotingCommand, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        item.encode_into(dst, RocketMQHeaderCodec)?;
-        Ok(())
+        match self.serialize_type {
+            HeaderCodecType::Json => item.encode_into(dst, JsonHeaderCodec),
+            HeaderCodecType::RocketMQ => item.encode_into(dst, RocketMQHeaderCodec),
+        }
     }
 }
 
@@ -116,17 +272,37 @@ impl Decoder for MqCodec {
             src.reserve(HEADER_FIXED_LENGTH);
             return Ok(None);
         }
-        let length = buf.read_i32::<BigEndian>()?;
-        if buf.len() < length as usize {
-            src.reserve(length as usize);
+        let raw_length = buf.read_i32::<BigEndian>()?;
+        if raw_length < HEADER_FIXED_LENGTH as i32 {
+            return Err(DecodeError::InvalidFrameLength(raw_length).into());
+        }
+        let length = raw_length as usize;
+        if length > self.max_frame_size {
+            return Err(DecodeError::FrameTooLarge {
+                length,
+                max: self.max_frame_size,
+            }
+            .into());
+        }
+        if buf.len() < length {
+            src.reserve(length - buf.len());
             return Ok(None);
         }
         let origin_header_len = buf.read_i32::<BigEndian>()?;
-        let header_len = origin_header_len & 0xffffff;
-        let mut header_buf = vec![0; header_len as usize];
-        buf.read_exact(&mut header_buf)?;
+        let header_len = (origin_header_len & 0xffffff) as usize;
+        if header_len > length - HEADER_FIXED_LENGTH {
+            return Err(DecodeError::InvalidHeaderLength {
+                header_len,
+                frame_len: length,
+            }
+            .into());
+        }
         let codec_type = HeaderCodecType::try_from(((origin_header_len >> 24) & 0xff) as u8)
             .map_err(|_| Error::InvalidHeaderCodec)?;
+        // Split the frame off the read buffer, the body is sliced from it without copying
+        let mut frame = src.split_to(LENGTH + length).freeze();
+        frame.advance(LENGTH + HEADER_FIXED_LENGTH);
+        let header_buf = frame.split_to(header_len);
         let header = match codec_type {
             HeaderCodecType::Json => {
                 let codec = JsonHeaderCodec;
@@ -137,26 +313,24 @@ impl Decoder for MqCodec {
                 codec.decode(&header_buf)?
             }
         };
-        let body_len = length as usize - HEADER_FIXED_LENGTH - header_len as usize;
-        let body = {
-            if body_len > 0 {
-                let mut body_buf = vec![0; body_len];
-                buf.read_exact(&mut body_buf)?;
-                body_buf
-            } else {
-                Vec::new()
-            }
-        };
-        src.advance(HEADER_FIXED_LENGTH + length as usize);
-        Ok(Some(RemotingCommand { header, body }))
+        Ok(Some(RemotingCommand {
+            header,
+            body: frame,
+        }))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::header::{JsonHeaderCodec, RocketMQHeaderCodec};
-    use super::{MqCodec, RemotingCommand};
+    use super::header::{Header, HeaderCodec, JsonHeaderCodec, RocketMQHeaderCodec};
+    use super::{
+        HeaderCodecType, LanguageCode, MqCodec, RemotingCommand, RequestCode, ResponseCode,
+    };
+    use crate::error::DecodeError;
+    use crate::Error;
     use bytes::BytesMut;
+    use proptest::collection::{hash_map, vec};
+    use proptest::prelude::*;
     use std::collections::HashMap;
     use tokio_util::codec::{Decoder, Encoder};
 
@@ -165,25 +339,68 @@ mod test {
         let mut fields = HashMap::new();
         fields.insert("messageId".to_string(), "123".to_string());
         fields.insert("offset".to_string(), "456".to_string());
-        let cmd =
-            RemotingCommand::new(10, 0, "remark".to_string(), fields, b"Hello World".to_vec());
+        let cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            "remark".to_string(),
+            fields,
+            b"Hello World".to_vec(),
+        );
         let mut encoded = BytesMut::new();
         cmd.encode_into(&mut encoded, JsonHeaderCodec).unwrap();
-        let mut decoder = MqCodec;
+        let mut decoder = MqCodec::default();
         let decoded = decoder.decode(&mut encoded).unwrap().unwrap();
         assert_eq!(cmd, decoded);
     }
 
+    struct FailingHeaderCodec;
+
+    impl HeaderCodec for FailingHeaderCodec {
+        fn codec_type(&self) -> HeaderCodecType {
+            HeaderCodecType::Json
+        }
+
+        fn encode_into(&self, _header: &Header, dst: &mut BytesMut) -> Result<(), Error> {
+            dst.extend_from_slice(b"{\"partial");
+            Err(Error::InvalidHeader("encode failed".to_string()))
+        }
+
+        fn decode(&self, _buf: &[u8]) -> Result<Header, Error> {
+            Err(Error::InvalidHeader("decode failed".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_encode_error_keeps_previous_frames() {
+        let cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            String::new(),
+            HashMap::new(),
+            b"body".to_vec(),
+        );
+        let mut encoded = BytesMut::new();
+        cmd.encode_into(&mut encoded, JsonHeaderCodec).unwrap();
+        let len = encoded.len();
+        assert!(cmd.encode_into(&mut encoded, FailingHeaderCodec).is_err());
+        assert_eq!(len, encoded.len());
+        let decoded = MqCodec::default().decode(&mut encoded).unwrap().unwrap();
+        assert_eq!(cmd, decoded);
+        assert!(encoded.is_empty());
+    }
+
     #[test]
     fn test_remote_command_rocketmq_encode_decode_roundtrip() {
         let mut fields = HashMap::new();
         fields.insert("messageId".to_string(), "123".to_string());
         fields.insert("offset".to_string(), "456".to_string());
-        let cmd =
-            RemotingCommand::new(10, 0, "remark".to_string(), fields, b"Hello World".to_vec());
+        let cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            "remark".to_string(),
+            fields,
+            b"Hello World".to_vec(),
+        );
         let mut encoded = BytesMut::new();
         cmd.encode_into(&mut encoded, RocketMQHeaderCodec).unwrap();
-        let mut decoder = MqCodec;
+        let mut decoder = MqCodec::default();
         let decoded = decoder.decode(&mut encoded).unwrap().unwrap();
         assert_eq!(cmd, decoded);
     }
@@ -193,25 +410,280 @@ mod test {
         let mut fields = HashMap::new();
         fields.insert("messageId".to_string(), "123".to_string());
         fields.insert("offset".to_string(), "456".to_string());
-        let cmd =
-            RemotingCommand::new(10, 0, "remark".to_string(), fields, b"Hello World".to_vec());
-        let mut codec = MqCodec;
+        let cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            "remark".to_string(),
+            fields,
+            b"Hello World".to_vec(),
+        );
+        let mut codec = MqCodec::default();
         let mut encoded = BytesMut::new();
         codec.encode(cmd.clone(), &mut encoded).unwrap();
         let decoded = codec.decode(&mut encoded).unwrap().unwrap();
         assert_eq!(cmd, decoded);
     }
 
+    #[test]
+    fn test_codec_serialize_type() {
+        let cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            "remark".to_string(),
+            HashMap::new(),
+            b"Hello World".to_vec(),
+        );
+        let mut codec = MqCodec::with_serialize_type(HeaderCodecType::Json);
+        let mut encoded = BytesMut::new();
+        codec.encode(cmd.clone(), &mut encoded).unwrap();
+        assert_eq!(u8::from(HeaderCodecType::Json), encoded[4]);
+        assert_eq!(
+            cmd,
+            MqCodec::default().decode(&mut encoded).unwrap().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rocketmq_header_layout() {
+        let mut fields = HashMap::new();
+        fields.insert("k".to_string(), "v".to_string());
+        let mut cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            "r".to_string(),
+            fields,
+            b"b".to_vec(),
+        );
+        cmd.header.opaque = 7;
+        let mut encoded = BytesMut::from(&b"prev"[..]);
+        MqCodec::default().encode(cmd, &mut encoded).unwrap();
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            b'p', b'r', b'e', b'v',
+            0, 0, 0, 35, // frame length
+            1, 0, 0, 30, // codec type and header length
+            0, 10, 12, 1, 61, 0, 0, 0, 7, 0, 0, 0, 0, // code, language, version, opaque, flag
+            0, 0, 0, 1, b'r', // remark
+            0, 0, 0, 8, 0, 1, b'k', 0, 0, 0, 1, b'v', // ext fields
+            b'b', // body
+        ];
+        assert_eq!(expected, &encoded[..]);
+    }
+
+    #[test]
+    fn test_codec_identity() {
+        let mut cmd = RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        assert_eq!(LanguageCode::RUST, cmd.language());
+        cmd.set_identity(LanguageCode::JAVA, 453);
+        for serialize_type in [HeaderCodecType::RocketMQ, HeaderCodecType::Json] {
+            let mut codec = MqCodec::with_serialize_type(serialize_type);
+            let mut encoded = BytesMut::new();
+            codec.encode(cmd.clone(), &mut encoded).unwrap();
+            let decoded = codec.decode(&mut encoded).unwrap().unwrap();
+            assert_eq!(LanguageCode::JAVA, decoded.language());
+            assert_eq!(453, decoded.version());
+        }
+    }
+
+    #[test]
+    fn test_decode_partial_and_consecutive_frames() {
+        let first = RemotingCommand::new(
+            RequestCode::SendMessage,
+            String::new(),
+            HashMap::new(),
+            b"first".to_vec(),
+        );
+        let second = RemotingCommand::new(
+            RequestCode::PullMessage,
+            String::new(),
+            HashMap::new(),
+            b"second".to_vec(),
+        );
+        let mut codec = MqCodec::default();
+        let mut encoded = BytesMut::new();
+        codec.encode(first.clone(), &mut encoded).unwrap();
+        codec.encode(second.clone(), &mut encoded).unwrap();
+
+        let mut partial = BytesMut::from(&encoded[..10]);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        assert_eq!(10, partial.len());
+
+        assert_eq!(first, codec.decode(&mut encoded).unwrap().unwrap());
+        assert_eq!(second, codec.decode(&mut encoded).unwrap().unwrap());
+        assert!(encoded.is_empty());
+    }
+
+    #[test]
+    fn test_decode_malformed_frames() {
+        let mut codec = MqCodec::new(1024);
+        let mut negative = BytesMut::from(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0][..]);
+        assert!(matches!(
+            codec.decode(&mut negative),
+            Err(Error::Decode(DecodeError::InvalidFrameLength(-1)))
+        ));
+
+        let mut huge = BytesMut::from(&[0x7f, 0xff, 0xff, 0xff, 0, 0, 0, 0][..]);
+        assert!(matches!(
+            codec.decode(&mut huge),
+            Err(Error::Decode(DecodeError::FrameTooLarge { max: 1024, .. }))
+        ));
+
+        let mut bad_header = BytesMut::from(&[0, 0, 0, 8, 1, 0, 0, 100, 0, 0, 0, 0][..]);
+        assert!(matches!(
+            codec.decode(&mut bad_header),
+            Err(Error::Decode(DecodeError::InvalidHeaderLength {
+                header_len: 100,
+                frame_len: 8
+            }))
+        ));
+
+        // remark length points past the end of the header
+        let cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            "remark".to_string(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let mut encoded = BytesMut::new();
+        codec.encode(cmd, &mut encoded).unwrap();
+        encoded[4 + 4 + 13..4 + 4 + 17].copy_from_slice(&i32::MAX.to_be_bytes());
+        assert!(matches!(
+            codec.decode(&mut encoded),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_ext_field_accessors() {
+        let mut fields = HashMap::new();
+        fields.insert("queueOffset".to_string(), "1024".to_string());
+        fields.insert("queueId".to_string(), "-1".to_string());
+        fields.insert("traceOn".to_string(), "true".to_string());
+        let res =
+            RemotingCommand::new_response(ResponseCode::Success, String::new(), fields, Vec::new());
+        assert_eq!(1024, res.ext_i64("queueOffset").unwrap());
+        assert_eq!(-1, res.ext_i32("queueId").unwrap());
+        assert!(res.ext_bool("traceOn").unwrap());
+        assert_eq!("1024", res.ext_str("queueOffset").unwrap());
+        assert!(matches!(
+            res.ext::<u32>("queueId"),
+            Err(Error::InvalidHeader(msg)) if msg == "invalid value of queueId: -1"
+        ));
+        assert!(matches!(
+            res.ext_i64("msgId"),
+            Err(Error::InvalidHeader(msg)) if msg == "missing msgId"
+        ));
+    }
+
     #[test]
     fn test_remote_command_type() {
         let mut fields = HashMap::new();
         fields.insert("messageId".to_string(), "123".to_string());
         fields.insert("offset".to_string(), "456".to_string());
-        let mut cmd =
-            RemotingCommand::new(10, 0, "remark".to_string(), fields, b"Hello World".to_vec());
+        let mut cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            "remark".to_string(),
+            fields,
+            b"Hello World".to_vec(),
+        );
         assert!(!cmd.is_response_type());
 
         cmd.mark_response_type();
         assert!(cmd.is_response_type());
+
+        let res = RemotingCommand::new_response(
+            ResponseCode::Success,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        assert!(res.is_response_type());
+        assert_eq!(0, res.code());
+
+        cmd.header.opaque = 42;
+        let mut res = cmd.response(ResponseCode::SystemError, "failed", HashMap::new());
+        res.set_body(b"body".to_vec());
+        assert!(res.is_response_type());
+        assert!(!res.is_oneway_rpc());
+        assert_eq!(42, res.opaque());
+        assert_eq!(1, res.code());
+        assert_eq!("failed", res.remark());
+        assert_eq!(b"body", res.body());
+    }
+
+    fn arb_header() -> impl Strategy<Value = Header> {
+        (
+            any::<i16>(),
+            prop_oneof![Just(LanguageCode::JAVA), Just(LanguageCode::RUST)],
+            any::<i16>(),
+            any::<i32>(),
+            any::<i32>(),
+            ".{0,32}",
+            hash_map(".{1,16}", ".{0,32}", 0..8),
+        )
+            .prop_map(
+                |(code, language, version, opaque, flag, remark, ext_fields)| Header {
+                    code,
+                    language,
+                    version,
+                    opaque,
+                    flag,
+                    remark,
+                    ext_fields,
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn prop_header_codec_roundtrip(header in arb_header()) {
+            let encoded = JsonHeaderCodec.encode(&header).unwrap();
+            prop_assert_eq!(&header, &JsonHeaderCodec.decode(&encoded).unwrap());
+            let encoded = RocketMQHeaderCodec.encode(&header).unwrap();
+            prop_assert_eq!(&header, &RocketMQHeaderCodec.decode(&encoded).unwrap());
+        }
+
+        #[test]
+        fn prop_header_codec_arbitrary_bytes(data in vec(any::<u8>(), 0..256)) {
+            let _ = JsonHeaderCodec.decode(&data);
+            let _ = RocketMQHeaderCodec.decode(&data);
+        }
+
+        #[test]
+        fn prop_codec_roundtrip(
+            header in arb_header(),
+            body in vec(any::<u8>(), 0..256),
+            json in any::<bool>(),
+        ) {
+            let cmd = RemotingCommand { header, body: body.into() };
+            let serialize_type = if json { HeaderCodecType::Json } else { HeaderCodecType::RocketMQ };
+            let mut codec = MqCodec::with_serialize_type(serialize_type);
+            let mut encoded = BytesMut::new();
+            codec.encode(cmd.clone(), &mut encoded).unwrap();
+
+            // every truncated frame waits for more data
+            for len in 0..encoded.len() {
+                let mut partial = BytesMut::from(&encoded[..len]);
+                prop_assert!(codec.decode(&mut partial).unwrap().is_none());
+            }
+            prop_assert_eq!(cmd, codec.decode(&mut encoded).unwrap().unwrap());
+            prop_assert!(encoded.is_empty());
+        }
+
+        #[test]
+        fn prop_codec_arbitrary_frames(
+            length in any::<i32>(),
+            header_len in any::<i32>(),
+            data in vec(any::<u8>(), 0..256),
+        ) {
+            let mut buf = BytesMut::new();
+            buf.extend_from_slice(&length.to_be_bytes());
+            buf.extend_from_slice(&header_len.to_be_bytes());
+            buf.extend_from_slice(&data);
+            let mut codec = MqCodec::new(1024);
+            while let Ok(Some(_)) = codec.decode(&mut buf) {}
+        }
     }
 }
@@ -2,6 +2,10 @@ use std::collections::HashMap;
 use std::time::Duration;
 
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use rocketmq_derive::RequestHeader;
+
+use super::RemotingCommand;
+use crate::Error;
 
 #[repr(i16)]
 #[derive(Debug, Copy, Clone, PartialEq, IntoPrimitive, TryFromPrimitive)]
@@ -114,7 +118,63 @@ impl EncodeRequestHeader for HashMap<String, String> {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Decode a request header from the ext fields of a command
+pub trait DecodeRequestHeader: Sized {
+    fn decode(fields: &HashMap<String, String>) -> Result<Self, Error>;
+}
+
+/// Value of a request header field, encoded as a string
+pub trait HeaderValue: Sized {
+    /// Encode the value as a header field
+    fn to_header_value(self) -> String;
+    /// Parse a header field, `None` if it's invalid
+    fn from_header_value(value: &str) -> Option<Self>;
+}
+
+impl HeaderValue for String {
+    fn to_header_value(self) -> String {
+        self
+    }
+
+    fn from_header_value(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+macro_rules! impl_header_value {
+    ($($ty:ty),*) => {
+        $(
+            impl HeaderValue for $ty {
+                fn to_header_value(self) -> String {
+                    self.to_string()
+                }
+
+                fn from_header_value(value: &str) -> Option<Self> {
+                    value.parse().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_header_value!(bool, i32, u32, i64, u64);
+
+/// Durations are encoded in milliseconds
+impl HeaderValue for Duration {
+    fn to_header_value(self) -> String {
+        self.as_millis().to_string()
+    }
+
+    fn from_header_value(value: &str) -> Option<Self> {
+        value.parse().ok().map(Duration::from_millis)
+    }
+}
+
+fn is_unset(value: &i32) -> bool {
+    *value == -1
+}
+
+#[derive(Debug, Clone, RequestHeader)]
 pub struct SendMessageRequestHeader {
     pub producer_group: String,
     pub topic: String,
@@ -131,73 +191,57 @@ pub struct SendMessageRequestHeader {
     pub default_topic_queue_nums: i32,
 }
 
-impl EncodeRequestHeader for SendMessageRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("producerGroup".to_string(), self.producer_group);
-        map.insert("topic".to_string(), self.topic);
-        map.insert("queueId".to_string(), self.queue_id.to_string());
-        map.insert("sysFlag".to_string(), self.sys_flag.to_string());
-        map.insert("bornTimestamp".to_string(), self.born_timestamp.to_string());
-        map.insert("flag".to_string(), self.flag.to_string());
-        map.insert(
-            "reconsumeTimes".to_string(),
-            self.reconsume_times.to_string(),
-        );
-        map.insert("unitMode".to_string(), self.unit_mode.to_string());
-        map.insert(
-            "maxReconsumeTimes".to_string(),
-            self.max_reconsume_times.to_string(),
-        );
-        map.insert("defaultTopic".to_string(), self.default_topic);
-        map.insert(
-            "defaultTopicQueueNums".to_string(),
-            self.default_topic_queue_nums.to_string(),
-        );
-        map.insert("batch".to_string(), self.batch.to_string());
-        map.insert("properties".to_string(), self.properties);
-        map
-    }
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct SendMessageRequestV2Header {
+    #[rename = "a"]
     pub producer_group: String,
+    #[rename = "b"]
     pub topic: String,
+    #[rename = "e"]
     pub queue_id: u32,
+    #[rename = "f"]
     pub sys_flag: i32,
+    #[rename = "g"]
     pub born_timestamp: i64,
+    #[rename = "h"]
     pub flag: i32,
+    #[rename = "i"]
     pub properties: String,
+    #[rename = "j"]
     pub reconsume_times: i32,
+    #[rename = "k"]
     pub unit_mode: bool,
+    #[rename = "l"]
     pub max_reconsume_times: i32,
+    #[rename = "m"]
     pub batch: bool,
+    #[rename = "c"]
     pub default_topic: String,
+    #[rename = "d"]
     pub default_topic_queue_nums: i32,
 }
 
-impl EncodeRequestHeader for SendMessageRequestV2Header {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("a".to_string(), self.producer_group);
-        map.insert("b".to_string(), self.topic);
-        map.insert("c".to_string(), self.default_topic);
-        map.insert("d".to_string(), self.default_topic_queue_nums.to_string());
-        map.insert("e".to_string(), self.queue_id.to_string());
-        map.insert("f".to_string(), self.sys_flag.to_string());
-        map.insert("g".to_string(), self.born_timestamp.to_string());
-        map.insert("h".to_string(), self.flag.to_string());
-        map.insert("i".to_string(), self.properties);
-        map.insert("j".to_string(), self.reconsume_times.to_string());
-        map.insert("k".to_string(), self.unit_mode.to_string());
-        map.insert("l".to_string(), self.max_reconsume_times.to_string());
-        map.insert("m".to_string(), self.batch.to_string());
-        map
+impl From<SendMessageRequestHeader> for SendMessageRequestV2Header {
+    fn from(header: SendMessageRequestHeader) -> Self {
+        Self {
+            producer_group: header.producer_group,
+            topic: header.topic,
+            queue_id: header.queue_id,
+            sys_flag: header.sys_flag,
+            born_timestamp: header.born_timestamp,
+            flag: header.flag,
+            properties: header.properties,
+            reconsume_times: header.reconsume_times,
+            unit_mode: header.unit_mode,
+            max_reconsume_times: header.max_reconsume_times,
+            batch: header.batch,
+            default_topic: header.default_topic,
+            default_topic_queue_nums: header.default_topic_queue_nums,
+        }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct CheckTransactionStateRequestHeader {
     pub tran_state_table_offset: i64,
     pub commit_log_offset: i64,
@@ -206,38 +250,40 @@ pub struct CheckTransactionStateRequestHeader {
     pub offset_msg_id: String,
 }
 
-impl EncodeRequestHeader for CheckTransactionStateRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert(
-            "tranStateTableOffset".to_string(),
-            self.tran_state_table_offset.to_string(),
-        );
-        map.insert(
-            "commitLogOffset".to_string(),
-            self.commit_log_offset.to_string(),
+/// Header of the request committing or rolling back a prepared transactional message
+#[derive(Debug, Clone, RequestHeader)]
+pub struct EndTransactionRequestHeader {
+    pub producer_group: String,
+    pub tran_state_table_offset: i64,
+    pub commit_log_offset: i64,
+    /// `MessageSysFlag::TransactionCommitType`, `TransactionRollbackType` or `TransactionNotType`
+    pub commit_or_rollback: i32,
+    pub from_transaction_check: bool,
+    pub msg_id: String,
+    pub transaction_id: Option<String>,
+}
+
+impl EndTransactionRequestHeader {
+    /// Build the one-way end transaction command, `remark` describes how the
+    /// transaction state was decided
+    pub fn into_command(self, remark: String) -> RemotingCommand {
+        let mut cmd = RemotingCommand::new(
+            RequestCode::EndTransaction,
+            remark,
+            self.encode(),
+            Vec::new(),
         );
-        map.insert("msgId".to_string(), self.msg_id);
-        map.insert("transactionId".to_string(), self.transaction_id);
-        map.insert("offsetMsgId".to_string(), self.offset_msg_id);
-        map
+        cmd.mark_oneway_rpc();
+        cmd
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct GetRouteInfoRequestHeader {
     pub topic: String,
 }
 
-impl EncodeRequestHeader for GetRouteInfoRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("topic".to_string(), self.topic);
-        map
-    }
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct PullMessageRequestHeader {
     pub consumer_group: String,
     pub topic: String,
@@ -247,193 +293,312 @@ pub struct PullMessageRequestHeader {
     pub sys_flag: i32,
     pub commit_offset: i64,
     pub suspend_timeout_millis: Duration,
+    #[rename = "subscription"]
     pub sub_expression: String,
     pub sub_version: i64,
     pub expression_type: String,
 }
 
-impl EncodeRequestHeader for PullMessageRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("consumerGroup".to_string(), self.consumer_group);
-        map.insert("topic".to_string(), self.topic);
-        map.insert("queueId".to_string(), self.queue_id.to_string());
-        map.insert("queueOffset".to_string(), self.queue_offset.to_string());
-        map.insert("maxMsgNums".to_string(), self.max_msg_nums.to_string());
-        map.insert("sysFlag".to_string(), self.sys_flag.to_string());
-        map.insert("commitOffset".to_string(), self.commit_offset.to_string());
-        map.insert(
-            "suspendTimeoutMillis".to_string(),
-            self.suspend_timeout_millis.as_millis().to_string(),
-        );
-        map.insert("subscription".to_string(), self.sub_expression);
-        map.insert("subVersion".to_string(), self.sub_version.to_string());
-        map.insert("expressionType".to_string(), self.expression_type);
-        map
-    }
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct UnregisterClientRequestHeader {
+    #[rename = "clientID"]
     pub client_id: String,
     pub producer_group: String,
     pub consumer_group: String,
 }
 
-impl EncodeRequestHeader for UnregisterClientRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("clientID".to_string(), self.client_id);
-        map.insert("producerGroup".to_string(), self.producer_group);
-        map.insert("consumerGroup".to_string(), self.consumer_group);
-        map
-    }
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct CreateTopicRequestHeader {
     pub topic: String,
     pub default_topic: String,
     pub read_queue_nums: u32,
     pub write_queue_nums: u32,
+    #[rename = "perm"]
     pub permission: i32,
     pub topic_filter_type: String,
     pub topic_sys_flag: i32,
     pub order: bool,
 }
 
-impl EncodeRequestHeader for CreateTopicRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("topic".to_string(), self.topic);
-        map.insert("defaultTopic".to_string(), self.default_topic);
-        map.insert(
-            "readQueueNums".to_string(),
-            self.read_queue_nums.to_string(),
-        );
-        map.insert(
-            "writeQueueNums".to_string(),
-            self.write_queue_nums.to_string(),
-        );
-        map.insert("perm".to_string(), self.permission.to_string());
-        map.insert("topicFilterType".to_string(), self.topic_filter_type);
-        map.insert("topicSysFlag".to_string(), self.topic_sys_flag.to_string());
-        map.insert("order".to_string(), self.order.to_string());
-        map
-    }
+/// Header of deleting a topic from a broker or the name servers
+#[derive(Debug, Clone, RequestHeader)]
+pub struct DeleteTopicRequestHeader {
+    pub topic: String,
 }
 
-#[derive(Debug, Clone)]
-pub struct QueryConsumerOffsetRequestHeader {
+#[derive(Debug, Clone, RequestHeader)]
+pub struct DeleteSubscriptionGroupRequestHeader {
+    pub group_name: String,
+}
+
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetTopicStatsInfoRequestHeader {
+    pub topic: String,
+}
+
+/// Header of querying consume stats of a group, of all its topics if `topic` is `None`
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetConsumeStatsRequestHeader {
+    pub consumer_group: String,
+    pub topic: Option<String>,
+}
+
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetConsumerConnectionListRequestHeader {
     pub consumer_group: String,
+}
+
+/// Header of resetting the offsets of a group on a topic to the first message stored
+/// at or after `timestamp`, offsets are only moved backwards unless `is_force` is set
+#[derive(Debug, Clone, RequestHeader)]
+pub struct ResetOffsetRequestHeader {
     pub topic: String,
-    pub queue_id: u32,
+    pub group: String,
+    pub timestamp: i64,
+    pub is_force: bool,
 }
 
-impl EncodeRequestHeader for QueryConsumerOffsetRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("consumerGroup".to_string(), self.consumer_group);
-        map.insert("topic".to_string(), self.topic);
-        map.insert("queueId".to_string(), self.queue_id.to_string());
-        map
-    }
+/// Header of removing write permission of a broker from the name servers
+#[derive(Debug, Clone, RequestHeader)]
+pub struct WipeWritePermOfBrokerRequestHeader {
+    pub broker_name: String,
 }
 
-#[derive(Debug, Clone)]
-pub struct UpdateConsumerOffsetRequestHeader {
+#[derive(Debug, Clone, RequestHeader)]
+pub struct QueryConsumerOffsetRequestHeader {
     pub consumer_group: String,
     pub topic: String,
     pub queue_id: u32,
-    pub commit_offset: i64,
 }
 
-impl EncodeRequestHeader for UpdateConsumerOffsetRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("consumerGroup".to_string(), self.consumer_group);
-        map.insert("topic".to_string(), self.topic);
-        map.insert("queueId".to_string(), self.queue_id.to_string());
-        map.insert("commitOffset".to_string(), self.commit_offset.to_string());
-        map
-    }
+#[derive(Debug, Clone, RequestHeader)]
+pub struct UpdateConsumerOffsetRequestHeader {
+    pub consumer_group: String,
+    pub topic: String,
+    pub queue_id: u32,
+    pub commit_offset: i64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct GetConsumerListRequestHeader {
     pub consumer_group: String,
 }
 
-impl EncodeRequestHeader for GetConsumerListRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("consumerGroup".to_string(), self.consumer_group);
-        map
-    }
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct GetMaxOffsetRequestHeader {
     pub topic: String,
     pub queue_id: u32,
 }
 
-impl EncodeRequestHeader for GetMaxOffsetRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("topic".to_string(), self.topic);
-        map.insert("queueId".to_string(), self.queue_id.to_string());
-        map
-    }
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetMinOffsetRequestHeader {
+    pub topic: String,
+    pub queue_id: u32,
 }
 
-#[derive(Debug, Clone)]
-pub struct SearchOffsetByTimestampRequestHeader {
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetEarliestMsgStoreTimeRequestHeader {
     pub topic: String,
     pub queue_id: u32,
-    pub timestamp: i64,
 }
 
-impl EncodeRequestHeader for SearchOffsetByTimestampRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("topic".to_string(), self.topic);
-        map.insert("queueId".to_string(), self.queue_id.to_string());
-        map.insert("timestamp".to_string(), self.timestamp.to_string());
-        map
-    }
+/// Header of searching the offset of a message queue by store timestamp
+#[derive(Debug, Clone, RequestHeader)]
+pub struct SearchOffsetRequestHeader {
+    pub topic: String,
+    pub queue_id: u32,
+    pub timestamp: i64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RequestHeader)]
 pub struct ConsumerSendMsgBackRequestHeader {
     pub offset: i64,
     pub group: String,
     pub delay_level: i32,
+    #[skip_if = "String::is_empty"]
     pub origin_msg_id: String,
+    #[skip_if = "String::is_empty"]
     pub origin_topic: String,
     pub unit_mode: bool,
+    #[skip_if = "is_unset"]
+    #[default = "-1"]
     pub max_reconsume_times: i32,
 }
 
-impl EncodeRequestHeader for ConsumerSendMsgBackRequestHeader {
-    fn encode(self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("offset".to_string(), self.offset.to_string());
-        map.insert("group".to_string(), self.group);
-        map.insert("delayLevel".to_string(), self.delay_level.to_string());
-        map.insert("unitMode".to_string(), self.unit_mode.to_string());
-        if !self.origin_msg_id.is_empty() {
-            map.insert("originMsgId".to_string(), self.origin_msg_id);
-        }
-        if !self.origin_topic.is_empty() {
-            map.insert("originTopic".to_string(), self.origin_topic);
-        }
-        if self.max_reconsume_times != -1 {
-            map.insert(
-                "maxReconsumeTimes".to_string(),
-                self.max_reconsume_times.to_string(),
-            );
-        }
-        map
+/// Header of querying messages by key within a time range
+#[derive(Debug, Clone, RequestHeader)]
+pub struct QueryMessageRequestHeader {
+    pub topic: String,
+    pub key: String,
+    pub max_num: i32,
+    pub begin_timestamp: i64,
+    pub end_timestamp: i64,
+    /// Query by the unique message id instead of message keys
+    #[rename = "_UNIQUE_KEY_QUERY"]
+    pub unique_key_query: bool,
+}
+
+/// Header of viewing a message by its commit log offset
+#[derive(Debug, Clone, RequestHeader)]
+pub struct ViewMessageRequestHeader {
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, RequestHeader)]
+pub struct PutKvConfigRequestHeader {
+    pub namespace: String,
+    pub key: String,
+    pub value: String,
+}
+
+/// Header of get and delete KV config requests
+#[derive(Debug, Clone, RequestHeader)]
+pub struct KvConfigRequestHeader {
+    pub namespace: String,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetKvListByNamespaceRequestHeader {
+    pub namespace: String,
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::message::MessageSysFlag;
+
+    #[test]
+    fn test_encode_request_header() {
+        let header = PullMessageRequestHeader {
+            consumer_group: "group".to_string(),
+            topic: "topic".to_string(),
+            queue_id: 1,
+            queue_offset: 100,
+            max_msg_nums: 32,
+            sys_flag: 0,
+            commit_offset: 0,
+            suspend_timeout_millis: Duration::from_secs(20),
+            sub_expression: "*".to_string(),
+            sub_version: 0,
+            expression_type: "TAG".to_string(),
+        };
+        let fields = header.encode();
+        assert_eq!(11, fields.len());
+        assert_eq!("group", fields["consumerGroup"]);
+        assert_eq!("32", fields["maxMsgNums"]);
+        assert_eq!("20000", fields["suspendTimeoutMillis"]);
+        assert_eq!("*", fields["subscription"]);
+
+        let header = PullMessageRequestHeader::decode(&fields).unwrap();
+        assert_eq!(100, header.queue_offset);
+        assert_eq!(Duration::from_secs(20), header.suspend_timeout_millis);
+        assert_eq!("TAG", header.expression_type);
+    }
+
+    #[test]
+    fn test_end_transaction_command() {
+        let header = EndTransactionRequestHeader {
+            producer_group: "group".to_string(),
+            tran_state_table_offset: 10,
+            commit_log_offset: 20,
+            commit_or_rollback: MessageSysFlag::TransactionCommitType.into(),
+            from_transaction_check: true,
+            msg_id: "msg".to_string(),
+            transaction_id: None,
+        };
+        let cmd = header.into_command("check".to_string());
+        assert_eq!(RequestCode::EndTransaction as i16, cmd.code());
+        assert!(cmd.is_oneway_rpc());
+        assert!(!cmd.is_response_type());
+        assert_eq!("check", cmd.remark());
+        let fields = cmd.ext_fields();
+        assert_eq!(6, fields.len());
+        assert_eq!("8", fields["commitOrRollback"]);
+        assert_eq!("true", fields["fromTransactionCheck"]);
+        assert_eq!("20", fields["commitLogOffset"]);
+        assert!(!fields.contains_key("transactionId"));
+    }
+
+    #[test]
+    fn test_query_message_header() {
+        let header = QueryMessageRequestHeader {
+            topic: "topic".to_string(),
+            key: "key".to_string(),
+            max_num: 32,
+            begin_timestamp: 0,
+            end_timestamp: i64::MAX,
+            unique_key_query: true,
+        };
+        let fields = header.encode();
+        assert_eq!("32", fields["maxNum"]);
+        assert_eq!(i64::MAX.to_string(), fields["endTimestamp"]);
+        assert_eq!("true", fields["_UNIQUE_KEY_QUERY"]);
+    }
+
+    #[test]
+    fn test_skip_header_fields() {
+        let header = ConsumerSendMsgBackRequestHeader {
+            offset: 1,
+            group: "group".to_string(),
+            delay_level: 0,
+            origin_msg_id: String::new(),
+            origin_topic: "topic".to_string(),
+            unit_mode: false,
+            max_reconsume_times: -1,
+        };
+        let fields = header.encode();
+        assert_eq!(5, fields.len());
+        assert!(!fields.contains_key("originMsgId"));
+        assert!(!fields.contains_key("maxReconsumeTimes"));
+        assert_eq!("topic", fields["originTopic"]);
+
+        let header = ConsumerSendMsgBackRequestHeader::decode(&fields).unwrap();
+        assert_eq!(-1, header.max_reconsume_times);
+        assert_eq!("", header.origin_msg_id);
+        assert_eq!("topic", header.origin_topic);
+    }
+
+    #[derive(Debug, RequestHeader)]
+    struct OptionalHeader {
+        topic: Option<String>,
+        queue_id: Option<u32>,
+    }
+
+    #[test]
+    fn test_optional_header_fields() {
+        let header = OptionalHeader {
+            topic: None,
+            queue_id: Some(3),
+        };
+        let fields = header.encode();
+        assert_eq!(1, fields.len());
+        assert_eq!("3", fields["queueId"]);
+        let header = OptionalHeader::decode(&fields).unwrap();
+        assert_eq!(None, header.topic);
+        assert_eq!(Some(3), header.queue_id);
+    }
+
+    #[test]
+    fn test_decode_request_header() {
+        let mut fields = HashMap::new();
+        fields.insert("tranStateTableOffset".to_string(), "1".to_string());
+        fields.insert("commitLogOffset".to_string(), "2".to_string());
+        fields.insert("msgId".to_string(), "msg".to_string());
+        fields.insert("transactionId".to_string(), "tx".to_string());
+        assert!(matches!(
+            CheckTransactionStateRequestHeader::decode(&fields),
+            Err(Error::InvalidHeader(_))
+        ));
+        fields.insert("offsetMsgId".to_string(), "offset".to_string());
+        let header = CheckTransactionStateRequestHeader::decode(&fields).unwrap();
+        assert_eq!(2, header.commit_log_offset);
+        assert_eq!("tx", header.transaction_id);
+
+        fields.insert("commitLogOffset".to_string(), "x".to_string());
+        assert!(CheckTransactionStateRequestHeader::decode(&fields).is_err());
     }
 }
@@ -1,11 +1,12 @@
-use std::convert::TryFrom;
-
-use num_enum::{IntoPrimitive, TryFromPrimitive};
+use num_enum::{FromPrimitive, IntoPrimitive};
+use rocketmq_derive::RequestHeader;
 
+use super::request::DecodeRequestHeader;
+use super::RemotingCommand;
 use crate::Error;
 
 #[repr(i16)]
-#[derive(Debug, Copy, Clone, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, IntoPrimitive, FromPrimitive)]
 pub enum ResponseCode {
     /// success response from broker
     Success = 0,
@@ -51,21 +52,62 @@ pub enum ResponseCode {
     TransactionStateGroupWrong = 203,
     ConsumerNotOnline = 206,
     ConsumeMsgTimeout = 207,
+    /// broker is not the leader of the queue any more
+    NotLeaderForQueue = 501,
+    /// code unknown to this client, kept as is
+    #[num_enum(catch_all)]
+    Other(i16),
 }
 
 impl ResponseCode {
-    pub fn from_code(code: i16) -> Result<Self, Error> {
-        ResponseCode::try_from(code).map_err(|_| Error::ResponseError {
-            code,
-            message: format!("unknown response code {}", code),
-        })
+    pub fn from_code(code: i16) -> Self {
+        ResponseCode::from(code)
     }
 }
 
 impl PartialEq<ResponseCode> for i16 {
     fn eq(&self, other: &ResponseCode) -> bool {
-        *self == *other as i16
+        *self == i16::from(*other)
+    }
+}
+
+/// Decode the header of a successful response, other responses are returned as errors
+pub fn decode_response_header<H: DecodeRequestHeader>(res: &RemotingCommand) -> Result<H, Error> {
+    if res.code() != ResponseCode::Success {
+        return Err(Error::ResponseError {
+            code: res.code(),
+            message: res.remark().to_string(),
+        });
     }
+    H::decode(res.ext_fields())
+}
+
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetMaxOffsetResponseHeader {
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetMinOffsetResponseHeader {
+    pub offset: i64,
+}
+
+/// Store timestamp in milliseconds of the earliest message of a queue
+#[derive(Debug, Clone, RequestHeader)]
+pub struct GetEarliestMsgStoreTimeResponseHeader {
+    pub timestamp: i64,
+}
+
+/// Response header of searching offset by timestamp
+#[derive(Debug, Clone, RequestHeader)]
+pub struct SearchOffsetResponseHeader {
+    pub offset: i64,
+}
+
+/// Number of topics whose write permission was removed by a name server
+#[derive(Debug, Clone, RequestHeader)]
+pub struct WipeWritePermOfBrokerResponseHeader {
+    pub wipe_topic_count: i32,
 }
 
 #[derive(Debug, Clone)]
@@ -84,3 +126,62 @@ pub struct PullMessageResponse {
     pub min_offset: i64,
     pub max_offset: i64,
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{
+        decode_response_header, GetEarliestMsgStoreTimeResponseHeader, ResponseCode,
+        SearchOffsetResponseHeader,
+    };
+    use crate::protocol::RemotingCommand;
+    use crate::Error;
+
+    #[test]
+    fn test_unknown_response_code() {
+        assert_eq!(ResponseCode::PullNotFound, ResponseCode::from_code(19));
+        assert_eq!(ResponseCode::Other(1024), ResponseCode::from_code(1024));
+        assert_eq!(1024, i16::from(ResponseCode::Other(1024)));
+        assert!(1024 == ResponseCode::Other(1024));
+    }
+
+    #[test]
+    fn test_decode_response_header() {
+        let mut fields = HashMap::new();
+        fields.insert("offset".to_string(), "1024".to_string());
+        let res =
+            RemotingCommand::new_response(ResponseCode::Success, String::new(), fields, Vec::new());
+        let header: SearchOffsetResponseHeader = decode_response_header(&res).unwrap();
+        assert_eq!(1024, header.offset);
+
+        let res = RemotingCommand::new_response(
+            ResponseCode::Success,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        assert!(matches!(
+            decode_response_header::<SearchOffsetResponseHeader>(&res),
+            Err(Error::InvalidHeader(_))
+        ));
+
+        let mut fields = HashMap::new();
+        fields.insert("timestamp".to_string(), "1600000000000".to_string());
+        let res =
+            RemotingCommand::new_response(ResponseCode::Success, String::new(), fields, Vec::new());
+        let header: GetEarliestMsgStoreTimeResponseHeader = decode_response_header(&res).unwrap();
+        assert_eq!(1600000000000, header.timestamp);
+
+        let res = RemotingCommand::new_response(
+            ResponseCode::SystemError,
+            "error".to_string(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        assert!(matches!(
+            decode_response_header::<SearchOffsetResponseHeader>(&res),
+            Err(Error::ResponseError { code: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,31 @@
+//! Server versions carried in the `version` field of commands, which are the
+//! ordinals of the Java `MQVersion.Version` enum
+
+pub const V4_0_0: i32 = 227;
+pub const V4_1_0: i32 = 247;
+
+/// Whether a broker of `version` accepts `SendMessageV2` with the compact header,
+/// brokers of unknown version are assumed to
+pub fn supports_send_v2(version: Option<i32>) -> bool {
+    version.map(|v| v >= V4_0_0).unwrap_or(true)
+}
+
+/// Whether a broker of `version` accepts `SendBatchMessage`, brokers of unknown
+/// version are assumed to
+pub fn supports_batch(version: Option<i32>) -> bool {
+    version.map(|v| v >= V4_1_0).unwrap_or(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_version_gating() {
+        assert!(supports_send_v2(None));
+        assert!(supports_send_v2(Some(V4_1_0 + 180)));
+        assert!(!supports_send_v2(Some(V4_0_0 - 1)));
+        assert!(supports_batch(Some(V4_1_0)));
+        assert!(!supports_batch(Some(V4_0_0)));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::error::{ConnectionError, Error};
+
+#[derive(Debug, Default)]
+struct State {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Stops connecting to an address for `cooldown` after `threshold` consecutive
+/// failed attempts, so that a broker group being down doesn't cause reconnect
+/// storms. Once the cooldown is over a single failure opens it again.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    states: Mutex<HashMap<String, State>>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(10))
+    }
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of zero disables the breaker
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fails with `ConnectionError::CircuitOpen` while connecting to `addr` is suspended
+    pub fn check(&self, addr: &str) -> Result<(), Error> {
+        match self
+            .states
+            .lock()
+            .get(addr)
+            .and_then(|state| state.open_until)
+        {
+            Some(open_until) if Instant::now() < open_until => {
+                Err(Error::Connection(ConnectionError::CircuitOpen))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_success(&self, addr: &str) {
+        self.states.lock().remove(addr);
+    }
+
+    /// Returns the cooldown if connecting to `addr` is suspended now
+    pub fn record_failure(&self, addr: &str) -> Option<Duration> {
+        if self.threshold == 0 {
+            return None;
+        }
+        let mut states = self.states.lock();
+        let state = states.entry(addr.to_string()).or_default();
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.threshold {
+            warn!(
+                addr = %addr,
+                failures = state.failures,
+                "connecting failed repeatedly, suspend connecting for {:?}",
+                self.cooldown
+            );
+            state.open_until = Some(Instant::now() + self.cooldown);
+            return Some(self.cooldown);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::CircuitBreaker;
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        let cooldown = Duration::from_millis(50);
+        let breaker = CircuitBreaker::new(2, cooldown);
+        let addr = "127.0.0.1:10911";
+        assert_eq!(None, breaker.record_failure(addr));
+        assert!(breaker.check(addr).is_ok());
+        assert_eq!(Some(cooldown), breaker.record_failure(addr));
+        assert!(breaker.check(addr).is_err());
+        assert!(breaker.check("127.0.0.1:10921").is_ok());
+
+        tokio::time::sleep(cooldown).await;
+        assert!(breaker.check(addr).is_ok());
+        // still failing after the cooldown
+        breaker.record_failure(addr);
+        assert!(breaker.check(addr).is_err());
+
+        tokio::time::sleep(cooldown).await;
+        breaker.record_success(addr);
+        breaker.record_failure(addr);
+        assert!(breaker.check(addr).is_ok());
+
+        let disabled = CircuitBreaker::new(0, cooldown);
+        for _ in 0..10 {
+            disabled.record_failure(addr);
+        }
+        assert!(disabled.check(addr).is_ok());
+    }
+}
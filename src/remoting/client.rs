@@ -1,27 +1,93 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
+use futures::future;
+use hmac::digest::KeyInit;
 use hmac::{Hmac, Mac};
 use parking_lot::Mutex;
-use tokio::sync::oneshot;
+use tokio::runtime::Handle;
+use tokio::sync::{broadcast, oneshot};
+use tokio::time::{self, Instant};
+use tracing::{info, warn};
 
+use super::breaker::CircuitBreaker;
 use super::connection::Connection;
-use crate::client::Credentials;
+use super::event::{ConnectionEvent, DisconnectReason, CONNECTION_EVENTS_CAPACITY};
+use super::hook::{Hooks, RpcHook};
+use super::processor::{Processors, RequestProcessor};
+use super::socket::SocketOptions;
+use super::stats::{ConnectionStats, RemotingStats};
+use super::trace::{TraceContext, TRACE_ID};
+use crate::client::{ConnectionMetrics, Credentials, CredentialsProvider, SignatureMethod};
 use crate::error::{ConnectionError, Error};
-use crate::protocol::RemotingCommand;
+use crate::protocol::{
+    HeaderCodecType, LanguageCode, RemotingCommand, RequestCode, DEFAULT_LANGUAGE, DEFAULT_VERSION,
+};
+use crate::utils::spawn;
 
 type HmacSha1 = Hmac<sha1::Sha1>;
+type HmacSha256 = Hmac<sha2::Sha256>;
+
+/// Server address and the index of one of the connections to it
+type ConnectionKey = (String, usize);
+
+/// Caller waiting for a connect attempt of another one
+type Waiter = oneshot::Sender<Result<Arc<Connection>, Error>>;
 
 enum ConnectionStatus {
     Connected(Arc<Connection>),
-    Connecting(Vec<oneshot::Sender<Result<Arc<Connection>, Error>>>),
+    /// Id of the connect attempt in progress and the callers waiting for it
+    Connecting(u64, Vec<Waiter>),
+    /// Closed by `close_connection` while connecting, the attempt drops its connection
+    Closed,
+}
+
+/// Remove the entry of the connect `attempt` to `key` and return its waiters, `None` if
+/// the connection was closed or replaced meanwhile
+fn finish_connecting(
+    connections: &mut HashMap<ConnectionKey, ConnectionStatus>,
+    key: &ConnectionKey,
+    attempt: u64,
+) -> Option<Vec<Waiter>> {
+    match connections.get(key) {
+        Some(ConnectionStatus::Connecting(id, _)) if *id == attempt => {}
+        Some(ConnectionStatus::Closed) => {
+            connections.remove(key);
+            return None;
+        }
+        _ => return None,
+    }
+    match connections.remove(key) {
+        Some(ConnectionStatus::Connecting(_, waiters)) => Some(waiters),
+        _ => None,
+    }
 }
 
 #[derive(Clone)]
 pub struct RemotingClient {
-    connections: Arc<Mutex<HashMap<String, ConnectionStatus>>>,
-    credentials: Option<Credentials>,
+    connections: Arc<Mutex<HashMap<ConnectionKey, ConnectionStatus>>>,
+    connect_attempts: Arc<AtomicU64>,
+    credentials: Option<Arc<dyn CredentialsProvider>>,
+    signature_method: SignatureMethod,
+    stats: Arc<RemotingStats>,
+    hooks: Hooks,
+    processors: Processors,
+    serialize_type: HeaderCodecType,
+    trace_propagation: bool,
+    language: LanguageCode,
+    version: i16,
+    socket: SocketOptions,
+    connections_per_addr: usize,
+    multiplex_threshold: usize,
+    breaker: Arc<CircuitBreaker>,
+    runtime: Option<Handle>,
+    request_timeout: Option<Duration>,
+    events: broadcast::Sender<ConnectionEvent>,
+    #[cfg(feature = "tls")]
+    tls: Option<super::TlsConfig>,
 }
 
 impl fmt::Debug for RemotingClient {
@@ -42,66 +108,463 @@ impl RemotingClient {
     pub fn new<C: Into<Option<Credentials>>>(credentials: C) -> Self {
         Self {
             connections: Arc::new(Mutex::new(HashMap::new())),
-            credentials: credentials.into(),
+            connect_attempts: Arc::new(AtomicU64::new(0)),
+            credentials: credentials
+                .into()
+                .map(|credentials| Arc::new(credentials) as Arc<dyn CredentialsProvider>),
+            signature_method: SignatureMethod::default(),
+            stats: Arc::new(RemotingStats::default()),
+            hooks: Hooks::default(),
+            processors: Processors::default(),
+            serialize_type: HeaderCodecType::default(),
+            trace_propagation: false,
+            language: DEFAULT_LANGUAGE,
+            version: DEFAULT_VERSION,
+            socket: SocketOptions::default(),
+            connections_per_addr: 1,
+            multiplex_threshold: 64,
+            breaker: Arc::new(CircuitBreaker::default()),
+            runtime: None,
+            request_timeout: Some(Duration::from_secs(3)),
+            events: broadcast::channel(CONNECTION_EVENTS_CAPACITY).0,
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 
+    /// Header serialize type of outgoing commands, only affects new connections
+    pub fn set_serialize_type(&mut self, serialize_type: HeaderCodecType) -> &mut Self {
+        self.serialize_type = serialize_type;
+        self
+    }
+
+    /// Source of the credentials signing every request, replacing the static ones
+    pub fn set_credentials_provider(
+        &mut self,
+        provider: Option<Arc<dyn CredentialsProvider>>,
+    ) -> &mut Self {
+        self.credentials = provider;
+        self
+    }
+
+    /// Algorithm of ACL request signatures
+    pub fn set_signature_method(&mut self, method: SignatureMethod) -> &mut Self {
+        self.signature_method = method;
+        self
+    }
+
+    /// Language and version reported in every request
+    pub fn set_identity(&mut self, language: LanguageCode, version: i16) -> &mut Self {
+        self.language = language;
+        self.version = version;
+        self
+    }
+
+    /// Inject a trace id and `traceparent` into every request, taken from the
+    /// `TraceContext` of the current task or else a new one
+    pub fn set_trace_propagation(&mut self, enabled: bool) -> &mut Self {
+        self.trace_propagation = enabled;
+        self
+    }
+
+    /// TCP options of new connections
+    pub(crate) fn set_socket_options(&mut self, socket: SocketOptions) -> &mut Self {
+        self.socket = socket;
+        self
+    }
+
+    /// Runtime to open connections and run their tasks on, the current one if unset
+    pub fn set_runtime(&mut self, runtime: Option<Handle>) -> &mut Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Timeout of `invoke` requests, `None` waits for responses until the connection
+    /// is closed, defaults to 3 seconds
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Suspend connecting to an address for `cooldown` after `failures` consecutive
+    /// failures, zero failures disables it
+    pub fn set_circuit_breaker(&mut self, failures: u32, cooldown: Duration) -> &mut Self {
+        self.breaker = Arc::new(CircuitBreaker::new(failures, cooldown));
+        self
+    }
+
+    /// Maximum number of connections to one address, another one is opened when
+    /// requests in flight on all of them reach `multiplex_threshold`
+    pub fn set_connections_per_addr(
+        &mut self,
+        connections: usize,
+        multiplex_threshold: usize,
+    ) -> &mut Self {
+        self.connections_per_addr = connections.max(1);
+        self.multiplex_threshold = multiplex_threshold.max(1);
+        self
+    }
+
+    /// Connect over TLS, only affects new connections
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: Option<super::TlsConfig>) -> &mut Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Send a request and wait for its response, failing with `ConnectionError::Timeout`
+    /// after the request timeout
     pub async fn invoke(&self, addr: &str, cmd: RemotingCommand) -> Result<RemotingCommand, Error> {
+        if let Some(timeout) = self.request_timeout {
+            return self.invoke_timeout(addr, cmd, timeout).await;
+        }
         let conn = self.get_connection(addr).await?;
+        self.invoke_on(&conn, addr, cmd, None).await
+    }
+
+    /// Like `invoke`, failing with `ConnectionError::Timeout` when connecting and
+    /// waiting for the response take longer than `timeout`
+    pub async fn invoke_timeout(
+        &self,
+        addr: &str,
+        cmd: RemotingCommand,
+        timeout: Duration,
+    ) -> Result<RemotingCommand, Error> {
+        let deadline = Instant::now() + timeout;
+        let conn = time::timeout(timeout, self.get_connection(addr))
+            .await
+            .map_err(|_| Error::Connection(ConnectionError::Timeout))??;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        self.invoke_on(&conn, addr, cmd, Some(remaining)).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "invoke",
+            skip_all,
+            fields(broker = %addr, code = cmd.code(), size = cmd.body().len(), opaque, elapsed_ms)
+        )
+    )]
+    async fn invoke_on(
+        &self,
+        conn: &Connection,
+        addr: &str,
+        cmd: RemotingCommand,
+        timeout: Option<Duration>,
+    ) -> Result<RemotingCommand, Error> {
         let sender = conn.sender();
-        Ok(sender.send(self.add_signature(cmd)).await?)
+        let code = cmd.code();
+        self.stats.record_request(code);
+        let cmd = self.before_request(addr, cmd).await;
+        let trace_id = cmd.ext_fields().get(TRACE_ID).cloned();
+        let stats = self.stats.connection(addr);
+        let start = Instant::now();
+        let res = sender.send(cmd, timeout).await;
+        let elapsed = start.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("elapsed_ms", elapsed.as_millis() as u64);
+        let mut res = res?;
+        stats.record_latency(elapsed);
+        if let Some(trace_id) = trace_id {
+            // Correlate the response with its request when the server doesn't echo it
+            res.ext_fields_mut()
+                .entry(TRACE_ID.to_string())
+                .or_insert(trace_id);
+        }
+        self.stats.record_response(res.code());
+        for hook in self.hooks.read().iter() {
+            hook.after_response(addr, code, &res);
+        }
+        Ok(res)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "invoke_oneway",
+            skip_all,
+            fields(broker = %addr, code = cmd.code(), size = cmd.body().len(), opaque, elapsed_ms)
+        )
+    )]
     pub async fn invoke_oneway(&self, addr: &str, cmd: RemotingCommand) -> Result<(), Error> {
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
         let conn = self.get_connection(addr).await?;
         let sender = conn.sender();
-        Ok(sender.send_oneway(self.add_signature(cmd)).await?)
+        self.stats.record_request(cmd.code());
+        let cmd = self.before_request(addr, cmd).await;
+        let res = sender.send_oneway(cmd).await;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("elapsed_ms", start.elapsed().as_millis() as u64);
+        res
     }
 
     pub async fn get_connection(&self, addr: &str) -> Result<Arc<Connection>, Error> {
-        let rx = {
-            match self.connections.lock().get_mut(addr) {
-                None => None,
-                Some(ConnectionStatus::Connected(conn)) => return Ok(conn.clone()),
-                Some(ConnectionStatus::Connecting(ref mut v)) => {
-                    let (tx, rx) = oneshot::channel();
-                    v.push(tx);
-                    Some(rx)
+        let key = (addr.to_string(), self.pick_connection(addr));
+        self.get_connection_at(key).await
+    }
+
+    /// Open every connection to each of `addrs` ahead of the first request, fails
+    /// with the last error after trying all of them
+    pub async fn pre_connect(&self, addrs: &[String]) -> Result<(), Error> {
+        let keys: Vec<ConnectionKey> = addrs
+            .iter()
+            .flat_map(|addr| (0..self.connections_per_addr).map(move |index| (addr.clone(), index)))
+            .collect();
+        let results =
+            future::join_all(keys.iter().map(|key| self.get_connection_at(key.clone()))).await;
+        let mut res = Ok(());
+        for ((addr, _), result) in keys.into_iter().zip(results) {
+            if let Err(err) = result {
+                warn!(addr = %addr, "pre-connect to server failed: {:?}", err);
+                res = Err(err);
+            }
+        }
+        res
+    }
+
+    async fn get_connection_at(&self, key: ConnectionKey) -> Result<Arc<Connection>, Error> {
+        if let Some(ConnectionStatus::Connected(conn)) = self.connections.lock().get(&key) {
+            return Ok(conn.clone());
+        }
+        self.connect(key).await
+    }
+
+    /// Index of the connection to `addr` to send the next request on: the first one
+    /// below the multiplex threshold, a new one if all are above, or else the least
+    /// busy one
+    fn pick_connection(&self, addr: &str) -> usize {
+        let connections = self.connections.lock();
+        let mut least_busy = (0, usize::MAX);
+        for index in 0..self.connections_per_addr {
+            match connections.get(&(addr.to_string(), index)) {
+                Some(ConnectionStatus::Connected(conn)) => {
+                    let in_flight = conn.in_flight();
+                    if in_flight < self.multiplex_threshold {
+                        return index;
+                    }
+                    if in_flight < least_busy.1 {
+                        least_busy = (index, in_flight);
+                    }
+                }
+                Some(ConnectionStatus::Connecting(..) | ConnectionStatus::Closed) | None => {
+                    return index
                 }
             }
-        };
-        match rx {
-            None => self.connect(addr).await,
-            Some(rx) => match rx.await {
-                Ok(res) => res,
-                Err(_) => Err(Error::Connection(ConnectionError::Canceled)),
-            },
+        }
+        least_busy.0
+    }
+
+    /// Close the connections to `addr` if any, connections being opened are dropped
+    /// once connected
+    pub fn close_connection(&self, addr: &str) {
+        let mut connected = 0;
+        self.connections.lock().retain(|(conn_addr, _), status| {
+            if conn_addr != addr {
+                return true;
+            }
+            match status {
+                ConnectionStatus::Connected(_) => {
+                    connected += 1;
+                    false
+                }
+                ConnectionStatus::Connecting(_, waiters) => {
+                    for tx in waiters.drain(..) {
+                        let _ = tx.send(Err(Error::Connection(ConnectionError::Canceled)));
+                    }
+                    *status = ConnectionStatus::Closed;
+                    true
+                }
+                ConnectionStatus::Closed => true,
+            }
+        });
+        self.stats.record_closed(connected);
+        self.stats.remove_connection(addr);
+        for _ in 0..connected {
+            self.notify(ConnectionEvent::Disconnected {
+                addr: addr.to_string(),
+                reason: DisconnectReason::Shutdown,
+            });
+        }
+    }
+
+    /// Subscribe to connection events of every address
+    pub fn subscribe_connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send connection events to the subscribers of `other` instead
+    pub(crate) fn share_connection_events(&mut self, other: &RemotingClient) -> &mut Self {
+        self.events = other.events.clone();
+        self
+    }
+
+    fn notify(&self, event: ConnectionEvent) {
+        // no receivers is fine
+        let _ = self.events.send(event);
+    }
+
+    /// Ping every connection which received nothing for `idle` and close the ones
+    /// not responding within `timeout`, so they are reconnected on next use
+    pub async fn check_connections(&self, idle: Duration, timeout: Duration) {
+        let idle_connections: Vec<_> = self
+            .connections
+            .lock()
+            .iter()
+            .filter_map(|(key, status)| match status {
+                ConnectionStatus::Connected(conn) if conn.idle_time() >= idle => {
+                    Some((key.clone(), Arc::clone(conn)))
+                }
+                _ => None,
+            })
+            .collect();
+        let checks = idle_connections.into_iter().map(|(key, conn)| async move {
+            let addr = &key.0;
+            // any response, even an error, proves the connection alive
+            let ping = RemotingCommand::new(
+                RequestCode::GetBrokerRuntimeInfo,
+                String::new(),
+                HashMap::new(),
+                Vec::new(),
+            );
+            if let Err(err) = self.invoke_on(&conn, addr, ping, Some(timeout)).await {
+                warn!(addr = %addr, error = %err, "connection failed health check, closing it");
+                self.evict(&key, &conn, DisconnectReason::HealthCheckFailed);
+            }
+        });
+        futures::future::join_all(checks).await;
+    }
+
+    /// Remove `conn` unless it was replaced by another connection meanwhile
+    fn evict(&self, key: &ConnectionKey, conn: &Arc<Connection>, reason: DisconnectReason) {
+        let mut connections = self.connections.lock();
+        if matches!(connections.get(key), Some(ConnectionStatus::Connected(c)) if Arc::ptr_eq(c, conn))
+        {
+            connections.remove(key);
+            self.stats.record_closed(1);
+            self.notify(ConnectionEvent::Disconnected {
+                addr: key.0.clone(),
+                reason,
+            });
         }
     }
 
     pub fn shutdown(&self) {
         let mut connections = self.connections.lock();
+        let connected: Vec<String> = connections
+            .iter()
+            .filter(|(_, status)| matches!(status, ConnectionStatus::Connected(_)))
+            .map(|((addr, _), _)| addr.clone())
+            .collect();
         connections.clear();
+        self.stats.record_closed(connected.len());
+        for addr in connected {
+            self.notify(ConnectionEvent::Disconnected {
+                addr,
+                reason: DisconnectReason::Shutdown,
+            });
+        }
+    }
+
+    /// Like `shutdown`, but first waits up to `timeout` for the commands queued on
+    /// every connection, like oneway offset commits, to be written
+    pub async fn shutdown_gracefully(&self, timeout: Duration) {
+        let connections: Vec<Arc<Connection>> = self
+            .connections
+            .lock()
+            .values()
+            .filter_map(|status| match status {
+                ConnectionStatus::Connected(conn) => Some(conn.clone()),
+                ConnectionStatus::Connecting(..) | ConnectionStatus::Closed => None,
+            })
+            .collect();
+        let drained = future::join_all(connections.iter().map(|conn| conn.drain()));
+        if time::timeout(timeout, drained).await.is_err() {
+            warn!("timed out writing queued commands on shutdown");
+        }
+        drop(connections);
+        self.shutdown();
+    }
+
+    /// Register a hook invoked around every request
+    pub fn register_rpc_hook(&self, hook: Arc<dyn RpcHook>) {
+        self.hooks.write().push(hook);
+    }
+
+    /// Register the processor of requests of `code` initiated by servers,
+    /// replacing the previous one
+    ///
+    /// Requests without a processor are answered with `RequestCodeNotSupported`.
+    pub fn register_processor(&self, code: RequestCode, processor: Arc<dyn RequestProcessor>) {
+        self.processors.write().insert(code.into(), processor);
+    }
+
+    pub(crate) fn stats(&self) -> &RemotingStats {
+        &self.stats
+    }
+
+    /// Snapshot of the stats of every address, with the requests in flight on
+    /// its open connections
+    pub(crate) fn connection_metrics(&self) -> HashMap<String, ConnectionMetrics> {
+        let mut in_flight: HashMap<String, usize> = HashMap::new();
+        for ((addr, _), status) in self.connections.lock().iter() {
+            if let ConnectionStatus::Connected(conn) = status {
+                *in_flight.entry(addr.clone()).or_default() += conn.in_flight();
+            }
+        }
+        self.stats.connections(&in_flight)
+    }
+
+    /// Open a connection to `addr`, over TLS if configured
+    async fn open(&self, addr: &str, stats: Arc<ConnectionStats>) -> Result<Connection, Error> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return Connection::new_tls(
+                addr,
+                tls,
+                self.serialize_type,
+                &self.socket,
+                self.processors.clone(),
+                self.hooks.clone(),
+                stats,
+                self.runtime.clone(),
+            )
+            .await;
+        }
+        Connection::new(
+            addr,
+            self.serialize_type,
+            &self.socket,
+            self.processors.clone(),
+            self.hooks.clone(),
+            stats,
+            self.runtime.clone(),
+        )
+        .await
     }
 
-    async fn connect(&self, addr: &str) -> Result<Arc<Connection>, Error> {
+    async fn connect(&self, key: ConnectionKey) -> Result<Arc<Connection>, Error> {
+        let addr = &key.0[..];
+        self.breaker.check(addr)?;
+        let attempt = self.connect_attempts.fetch_add(1, Ordering::Relaxed);
         let rx = {
-            match self
-                .connections
-                .lock()
-                .entry(addr.to_string())
-                .or_insert_with(|| ConnectionStatus::Connecting(Vec::new()))
-            {
-                ConnectionStatus::Connecting(ref mut v) => {
-                    if v.is_empty() {
-                        None
-                    } else {
-                        let (tx, rx) = oneshot::channel();
-                        v.push(tx);
-                        Some(rx)
-                    }
+            let mut connections = self.connections.lock();
+            match connections.get_mut(&key) {
+                Some(ConnectionStatus::Connected(conn)) => return Ok(conn.clone()),
+                // another caller is connecting
+                Some(ConnectionStatus::Connecting(_, waiters)) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    Some(rx)
+                }
+                Some(ConnectionStatus::Closed) | None => {
+                    connections.insert(
+                        key.clone(),
+                        ConnectionStatus::Connecting(attempt, Vec::new()),
+                    );
+                    None
                 }
-                ConnectionStatus::Connected(_) => None,
             }
         };
         if let Some(rx) = rx {
@@ -110,27 +573,97 @@ impl RemotingClient {
                 Err(_) => Err(Error::Connection(ConnectionError::Canceled)),
             };
         }
-        // FIXME: connection backoff
-        let conn = Connection::new(addr).await?;
+        let stats = self.stats.connection(addr);
+        let conn = match &self.runtime {
+            // sockets are bound to the runtime they are opened on
+            Some(runtime) => {
+                let client = self.clone();
+                let addr = addr.to_string();
+                let stats = stats.clone();
+                runtime
+                    .spawn(async move { client.open(&addr, stats).await })
+                    .await
+                    .unwrap_or_else(|_| Err(Error::Connection(ConnectionError::Canceled)))
+            }
+            None => self.open(addr, stats.clone()).await,
+        };
+        let conn = match conn {
+            Ok(conn) => {
+                self.breaker.record_success(addr);
+                conn
+            }
+            Err(err) => {
+                if let Some(after) = self.breaker.record_failure(addr) {
+                    self.notify(ConnectionEvent::ReconnectScheduled {
+                        addr: addr.to_string(),
+                        after,
+                    });
+                }
+                // wake up waiters so that they don't wait forever
+                let waiters = finish_connecting(&mut self.connections.lock(), &key, attempt);
+                for tx in waiters.into_iter().flatten() {
+                    let _ = tx.send(Err(Error::Connection(ConnectionError::Disconnected)));
+                }
+                return Err(err);
+            }
+        };
         let c = Arc::new(conn);
-        let old = self.connections.lock().insert(
-            addr.to_string(),
-            ConnectionStatus::Connected(Arc::clone(&c)),
-        );
-        match old {
-            Some(ConnectionStatus::Connecting(mut v)) => {
-                for tx in v.drain(..) {
-                    let _ = tx.send(Ok(c.clone()));
+        let waiters = {
+            let mut connections = self.connections.lock();
+            match finish_connecting(&mut connections, &key, attempt) {
+                Some(waiters) => {
+                    connections.insert(key.clone(), ConnectionStatus::Connected(Arc::clone(&c)));
+                    waiters
+                }
+                None => {
+                    info!(addr = %addr, "connection closed while connecting, dropping it");
+                    return Err(Error::Connection(ConnectionError::Canceled));
                 }
             }
-            Some(ConnectionStatus::Connected(_)) => {}
-            None => {}
+        };
+        self.notify(ConnectionEvent::Connected {
+            addr: addr.to_string(),
+        });
+        // evict the connection once it's closed by the server or a read error
+        let closed = c.closed();
+        let weak = Arc::downgrade(&c);
+        let client = self.clone();
+        spawn(self.runtime.as_ref(), async move {
+            closed.await;
+            if let Some(conn) = weak.upgrade() {
+                warn!(addr = %key.0, "connection closed, evicting it");
+                client.evict(&key, &conn, DisconnectReason::Closed);
+            }
+        });
+        self.stats.record_connected();
+        stats.record_connected();
+        for tx in waiters {
+            let _ = tx.send(Ok(c.clone()));
         }
         Ok(c)
     }
 
-    fn add_signature(&self, mut cmd: RemotingCommand) -> RemotingCommand {
-        if let Some(credentials) = &self.credentials {
+    async fn before_request(&self, addr: &str, mut cmd: RemotingCommand) -> RemotingCommand {
+        cmd.set_identity(self.language, self.version);
+        if self.trace_propagation {
+            let ctx = TraceContext::current()
+                .map(|ctx| ctx.child())
+                .unwrap_or_default();
+            ctx.inject(&mut cmd);
+        }
+        for hook in self.hooks.read().iter() {
+            hook.before_request(addr, &mut cmd);
+        }
+        let mut cmd = self.add_signature(cmd).await;
+        for hook in self.hooks.read().iter() {
+            hook.after_signature(addr, &mut cmd);
+        }
+        cmd
+    }
+
+    async fn add_signature(&self, mut cmd: RemotingCommand) -> RemotingCommand {
+        if let Some(provider) = &self.credentials {
+            let credentials = provider.get().await;
             let size = cmd.header.ext_fields.len() + 1;
             let mut m = HashMap::with_capacity(size);
             m.insert("AccessKey".to_string(), &credentials.access_key);
@@ -144,6 +677,13 @@ impl RemotingClient {
                         .insert("SecurityToken".to_string(), security_token.clone());
                 }
             }
+            if self.signature_method != SignatureMethod::HmacSha1 {
+                // brokers assume HMAC-SHA1 without it
+                cmd.header.ext_fields.insert(
+                    "SignatureMethod".to_string(),
+                    self.signature_method.as_str().to_string(),
+                );
+            }
             for (k, v) in &cmd.header.ext_fields {
                 m.insert(k.clone(), v);
                 order.push(&k[..]);
@@ -156,7 +696,11 @@ impl RemotingClient {
             content.extend_from_slice(&cmd.body);
             cmd.header.ext_fields.insert(
                 "Signature".to_string(),
-                Self::calculate_signature(&content, credentials.secret_key.as_bytes()),
+                Self::calculate_signature(
+                    self.signature_method,
+                    &content,
+                    credentials.secret_key.as_bytes(),
+                ),
             );
             cmd.header
                 .ext_fields
@@ -165,8 +709,15 @@ impl RemotingClient {
         cmd
     }
 
-    fn calculate_signature(data: &[u8], key: &[u8]) -> String {
-        let mut mac = HmacSha1::new_from_slice(key).unwrap();
+    fn calculate_signature(method: SignatureMethod, data: &[u8], key: &[u8]) -> String {
+        match method {
+            SignatureMethod::HmacSha1 => Self::hmac::<HmacSha1>(data, key),
+            SignatureMethod::HmacSha256 => Self::hmac::<HmacSha256>(data, key),
+        }
+    }
+
+    fn hmac<M: Mac + KeyInit>(data: &[u8], key: &[u8]) -> String {
+        let mut mac = <M as Mac>::new_from_slice(key).unwrap();
         mac.update(data);
         let result = mac.finalize().into_bytes();
         base64::encode(&result)
@@ -175,14 +726,395 @@ impl RemotingClient {
 
 #[cfg(test)]
 mod test {
-    use super::RemotingClient;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use async_trait::async_trait;
+
+    use super::{RemotingClient, RpcHook};
+    use crate::client::{Credentials, CredentialsProvider, SignatureMethod};
+    use crate::error::{ConnectionError, Error};
+    use crate::protocol::{RemotingCommand, RequestCode};
+    use crate::remoting::{ConnectionEvent, DisconnectReason, TraceContext};
+
+    struct HeaderHook;
+
+    impl RpcHook for HeaderHook {
+        fn before_request(&self, addr: &str, request: &mut RemotingCommand) {
+            request
+                .ext_fields_mut()
+                .insert("Addr".to_string(), addr.to_string());
+        }
+
+        fn after_signature(&self, _addr: &str, request: &mut RemotingCommand) {
+            request
+                .ext_fields_mut()
+                .insert("TenantId".to_string(), "tenant".to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rpc_hook_before_signature() {
+        let client = RemotingClient::new(Credentials::new("ak", "sk"));
+        client.register_rpc_hook(Arc::new(HeaderHook));
+        let cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let cmd = client.before_request("127.0.0.1:10911", cmd).await;
+        assert_eq!("127.0.0.1:10911", cmd.ext_fields()["Addr"]);
+        assert_eq!("tenant", cmd.ext_fields()["TenantId"]);
+        let signature = cmd.ext_fields()["Signature"].clone();
+
+        let unhooked = RemotingClient::new(Credentials::new("ak", "sk"));
+        let mut cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        cmd.ext_fields_mut()
+            .insert("Addr".to_string(), "127.0.0.1:10911".to_string());
+        let cmd = unhooked.add_signature(cmd).await;
+        assert_eq!(signature, cmd.ext_fields()["Signature"]);
+    }
+
+    #[tokio::test]
+    async fn test_signature_method() {
+        let mut client = RemotingClient::new(Credentials::new("ak", "sk"));
+        let new_request = || {
+            RemotingCommand::new(
+                RequestCode::SendMessage,
+                String::new(),
+                HashMap::new(),
+                Vec::new(),
+            )
+        };
+        let cmd = client.add_signature(new_request()).await;
+        assert!(!cmd.ext_fields().contains_key("SignatureMethod"));
+        let sha1 = cmd.ext_fields()["Signature"].clone();
+
+        client.set_signature_method(SignatureMethod::HmacSha256);
+        let cmd = client.add_signature(new_request()).await;
+        assert_eq!("HmacSHA256", cmd.ext_fields()["SignatureMethod"]);
+        assert_ne!(sha1, cmd.ext_fields()["Signature"]);
+        // the method is covered by the signature
+        let expected = RemotingClient::calculate_signature(
+            SignatureMethod::HmacSha256,
+            b"akHmacSHA256",
+            b"sk",
+        );
+        assert_eq!(expected, cmd.ext_fields()["Signature"]);
+    }
+
+    #[tokio::test]
+    async fn test_credentials_provider() {
+        struct Rotating(AtomicUsize);
+
+        #[async_trait]
+        impl CredentialsProvider for Rotating {
+            async fn get(&self) -> Credentials {
+                let n = self.0.fetch_add(1, Ordering::SeqCst);
+                let mut credentials = Credentials::new("ak", "sk");
+                credentials.security_token = Some(format!("token-{}", n));
+                credentials
+            }
+        }
+
+        let mut client = RemotingClient::new(None);
+        client.set_credentials_provider(Some(Arc::new(Rotating(AtomicUsize::new(0)))));
+        let new_request = || {
+            RemotingCommand::new(
+                RequestCode::SendMessage,
+                String::new(),
+                HashMap::new(),
+                Vec::new(),
+            )
+        };
+        let cmd = client.add_signature(new_request()).await;
+        assert_eq!("token-0", cmd.ext_fields()["SecurityToken"]);
+        let cmd = client.add_signature(new_request()).await;
+        assert_eq!("token-1", cmd.ext_fields()["SecurityToken"]);
+    }
+
+    #[tokio::test]
+    async fn test_trace_propagation() {
+        let new_request = || {
+            RemotingCommand::new(
+                RequestCode::SendMessage,
+                String::new(),
+                HashMap::new(),
+                Vec::new(),
+            )
+        };
+        let mut client = RemotingClient::default();
+        let cmd = client
+            .before_request("127.0.0.1:10911", new_request())
+            .await;
+        assert!(TraceContext::from_command(&cmd).is_none());
+
+        client.set_trace_propagation(true);
+        let cmd = client
+            .before_request("127.0.0.1:10911", new_request())
+            .await;
+        assert!(TraceContext::from_command(&cmd).is_some());
+
+        let ctx = TraceContext::new();
+        let cmd = ctx
+            .clone()
+            .scope(client.before_request("127.0.0.1:10911", new_request()))
+            .await;
+        let propagated = TraceContext::from_command(&cmd).unwrap();
+        assert_eq!(ctx.trace_id(), propagated.trace_id());
+        assert_ne!(ctx, propagated);
+    }
+
+    #[tokio::test]
+    async fn test_check_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Accept the connection but never respond, like a half-open one
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let client = RemotingClient::default();
+        client.get_connection(&addr).await.unwrap();
+        let _stream = server.await.unwrap();
+        assert_eq!(1, client.stats().open_connections());
+
+        client
+            .check_connections(Duration::from_secs(60), Duration::from_millis(50))
+            .await;
+        assert_eq!(1, client.stats().open_connections());
+
+        client
+            .check_connections(Duration::ZERO, Duration::from_millis(50))
+            .await;
+        assert_eq!(0, client.stats().open_connections());
+        assert!(client.connections.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_connects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let client = RemotingClient::default();
+        let (first, second) =
+            futures::join!(client.get_connection(&addr), client.get_connection(&addr));
+        assert!(Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+        assert_eq!(1, client.stats().open_connections());
+        let _stream = server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_close_connection_while_connecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let client = RemotingClient::default();
+        let mut connecting = Box::pin(client.get_connection(&addr));
+        assert!(futures::poll!(&mut connecting).is_pending());
+        client.close_connection(&addr);
+        let (_stream, _) = listener.accept().await.unwrap();
+        assert!(matches!(
+            connecting.await,
+            Err(Error::Connection(ConnectionError::Canceled))
+        ));
+        assert_eq!(0, client.stats().open_connections());
+        assert!(client.connections.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // the server never responds
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let mut client = RemotingClient::default();
+        client.set_request_timeout(Some(Duration::from_millis(50)));
+        let request = RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let err = client.invoke(&addr, request).await.unwrap_err();
+        assert!(matches!(err, Error::Connection(ConnectionError::Timeout)));
+        let _stream = server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_multiple_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let mut streams = Vec::new();
+            for _ in 0..2 {
+                streams.push(listener.accept().await.unwrap());
+            }
+            streams
+        });
+        let mut client = RemotingClient::default();
+        client.set_connections_per_addr(2, 1);
+        let first = client.get_connection(&addr).await.unwrap();
+        assert!(Arc::ptr_eq(
+            &first,
+            &client.get_connection(&addr).await.unwrap()
+        ));
+
+        // the server never responds, keeping the request in flight
+        let request = RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let sender = Arc::clone(&first);
+        let pending = tokio::spawn(async move { sender.sender().send(request, None).await });
+        while first.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+        let second = client.get_connection(&addr).await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(2, client.stats().open_connections());
+
+        // all connections are busy, the least busy one is used
+        let least_busy = client.get_connection(&addr).await.unwrap();
+        assert!(Arc::ptr_eq(&second, &least_busy));
+
+        client.close_connection(&addr);
+        assert_eq!(0, client.stats().open_connections());
+        assert!(client.connection_metrics().is_empty());
+        pending.abort();
+        let _streams = server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pre_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let mut streams = Vec::new();
+            for _ in 0..2 {
+                streams.push(listener.accept().await.unwrap());
+            }
+            streams
+        });
+        let mut client = RemotingClient::default();
+        client.set_connections_per_addr(2, 1);
+        client
+            .pre_connect(std::slice::from_ref(&addr))
+            .await
+            .unwrap();
+        assert_eq!(2, client.stats().open_connections());
+        let _streams = server.await.unwrap();
+
+        // existing connections are reused, closed ports fail
+        assert!(client.pre_connect(&[addr]).await.is_ok());
+        let unused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused = unused.local_addr().unwrap().to_string();
+        drop(unused);
+        assert!(client.pre_connect(&[refused]).await.is_err());
+    }
+
+    #[test]
+    fn test_runtime() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = runtime.spawn(async move { listener.accept().await.unwrap() });
+        let mut client = RemotingClient::default();
+        client.set_runtime(Some(runtime.handle().clone()));
+        // no runtime is entered on this thread
+        futures::executor::block_on(client.get_connection(&addr)).unwrap();
+        let _stream = runtime.block_on(server).unwrap();
+        assert_eq!(1, client.stats().open_connections());
+    }
+
+    #[tokio::test]
+    async fn test_connection_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let client = RemotingClient::default();
+        let mut events = client.subscribe_connection_events();
+        let conn = client.get_connection(&addr).await.unwrap();
+        let stream = server.await.unwrap();
+        assert_eq!(
+            ConnectionEvent::Connected { addr: addr.clone() },
+            events.recv().await.unwrap()
+        );
+
+        let request = RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let sender = Arc::clone(&conn);
+        let pending = tokio::spawn(async move { sender.sender().send(request, None).await });
+        while conn.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+        drop(stream);
+        let err = pending.await.unwrap().unwrap_err();
+        assert!(matches!(err, Error::Connection(ConnectionError::Closed)));
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while client.stats().open_connections() > 0 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(client.connections.lock().is_empty());
+        assert_eq!(
+            ConnectionEvent::Disconnected {
+                addr,
+                reason: DisconnectReason::Closed
+            },
+            events.recv().await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_share_connection_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let broker = RemotingClient::default();
+        let mut name_server = RemotingClient::default();
+        name_server.share_connection_events(&broker);
+        let mut events = broker.subscribe_connection_events();
+        name_server.get_connection(&addr).await.unwrap();
+        let _stream = server.await.unwrap();
+        assert_eq!(
+            ConnectionEvent::Connected { addr },
+            events.recv().await.unwrap()
+        );
+    }
 
     #[test]
     fn test_calculate_signature() {
         let signature = RemotingClient::calculate_signature(
+            SignatureMethod::HmacSha1,
             b"Hello RocketMQ Client ACL Feature",
             b"adiaushdiaushd",
         );
         assert_eq!(signature, "tAb/54Rwwcq+pbH8Loi7FWX4QSQ=");
+        let signature = RemotingClient::calculate_signature(
+            SignatureMethod::HmacSha256,
+            b"Hello RocketMQ Client ACL Feature",
+            b"adiaushdiaushd",
+        );
+        assert_eq!(signature, "+ys9Aq0DrbFchbjaSOOnsxe7lEQVmGbXu5UcRbb88uc=");
     }
 }
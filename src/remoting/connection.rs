@@ -1,25 +1,130 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use futures::{
     task::{Context, Poll},
     Future, Sink, SinkExt, Stream, StreamExt,
 };
+use parking_lot::Mutex;
 use tokio::net::TcpStream;
-use tokio::sync::{mpsc, oneshot};
-use tracing::{debug, info};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{self, Instant};
+use tracing::{debug, info, warn};
 
+#[cfg(feature = "test-util")]
+use super::fault::FaultyStream;
+use super::hook::Hooks;
+use super::processor::Processors;
+use super::socket::SocketOptions;
+use super::stats::{ConnectionStats, CountingStream};
 use crate::error::{ConnectionError, Error};
-use crate::protocol::{MqCodec, RemotingCommand};
+use crate::protocol::{HeaderCodecType, MqCodec, RemotingCommand, ResponseCode};
+use crate::utils::spawn;
+
+/// Maximum number of queued commands written with a single flush
+const MAX_WRITE_BATCH: usize = 64;
+
+/// Write `first` and the commands queued after it, flushing once to save syscalls
+/// under high throughput
+async fn write_batch<S>(
+    sink: &mut S,
+    rx: &mut mpsc::UnboundedReceiver<RemotingCommand>,
+    first: RemotingCommand,
+) -> Result<(), Error>
+where
+    S: Sink<RemotingCommand, Error = Error> + Unpin,
+{
+    sink.feed(first).await?;
+    for _ in 1..MAX_WRITE_BATCH {
+        match rx.try_recv() {
+            Ok(msg) => sink.feed(msg).await?,
+            Err(_) => break,
+        }
+    }
+    sink.flush().await
+}
+
+/// Time of the last inbound frame of a connection
+#[derive(Debug)]
+struct Activity {
+    since: Instant,
+    // milliseconds after `since`
+    last_read: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            last_read: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.since.elapsed().as_millis() as u64;
+        self.last_read.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn idle_time(&self) -> Duration {
+        let last_read = Duration::from_millis(self.last_read.load(Ordering::Relaxed));
+        self.since.elapsed().saturating_sub(last_read)
+    }
+}
+
+/// A request waiting for its response, deregistered when dropped before the
+/// response arrived, e.g. on timeout or when the caller drops the `send` future
+struct PendingRequest<'a> {
+    sender: &'a ConnectionSender,
+    opaque: i32,
+    completed: bool,
+}
+
+impl<'a> PendingRequest<'a> {
+    fn new(sender: &'a ConnectionSender, opaque: i32) -> Self {
+        sender.in_flight.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::increment_gauge!("rocketmq_client_requests_in_flight", 1.0, "addr" => sender.addr.clone());
+        Self {
+            sender,
+            opaque,
+            completed: false,
+        }
+    }
+}
+
+impl Drop for PendingRequest<'_> {
+    fn drop(&mut self) {
+        self.sender.in_flight.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::decrement_gauge!("rocketmq_client_requests_in_flight", 1.0, "addr" => self.sender.addr.clone());
+        if !self.completed {
+            let _ = self
+                .sender
+                .registrations_tx
+                .send(Registration::Deregister(self.opaque));
+        }
+    }
+}
+
+/// Changes to the requests waiting for a response on a connection
+enum Registration {
+    Register(i32, oneshot::Sender<Result<RemotingCommand, Error>>),
+    /// The request with the opaque gave up waiting
+    Deregister(i32),
+}
 
 pub struct ConnectionSender {
     addr: String,
     tx: mpsc::UnboundedSender<RemotingCommand>,
-    registrations_tx: mpsc::UnboundedSender<(i32, oneshot::Sender<RemotingCommand>)>,
+    registrations_tx: mpsc::UnboundedSender<Registration>,
     receiver_shutdown: Option<oneshot::Sender<()>>,
     opaque_id: AtomicI32,
+    in_flight: AtomicUsize,
 }
 
 impl fmt::Debug for ConnectionSender {
@@ -31,10 +136,10 @@ impl fmt::Debug for ConnectionSender {
 }
 
 impl ConnectionSender {
-    pub fn new(
+    fn new(
         addr: String,
         tx: mpsc::UnboundedSender<RemotingCommand>,
-        registrations_tx: mpsc::UnboundedSender<(i32, oneshot::Sender<RemotingCommand>)>,
+        registrations_tx: mpsc::UnboundedSender<Registration>,
         receiver_shutdown: oneshot::Sender<()>,
     ) -> Self {
         Self {
@@ -43,14 +148,22 @@ impl ConnectionSender {
             registrations_tx,
             receiver_shutdown: Some(receiver_shutdown),
             opaque_id: AtomicI32::new(1),
+            in_flight: AtomicUsize::new(0),
         }
     }
 
-    #[tracing::instrument(skip(self, cmd))]
-    pub async fn send(&self, cmd: RemotingCommand) -> Result<RemotingCommand, Error> {
+    /// Send a request and wait for its response, for at most `timeout` if set,
+    /// the opaque is recorded on the current span
+    pub async fn send(
+        &self,
+        cmd: RemotingCommand,
+        timeout: Option<Duration>,
+    ) -> Result<RemotingCommand, Error> {
         let (sender, receiver) = oneshot::channel();
         let mut cmd = cmd;
         cmd.header.opaque = self.opaque_id.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("opaque", cmd.header.opaque);
         debug!(
             code = cmd.code(),
             opaque = cmd.header.opaque,
@@ -58,20 +171,35 @@ impl ConnectionSender {
             "sending remoting command to {}",
             &self.addr
         );
-        match (
-            self.registrations_tx.send((cmd.header.opaque, sender)),
-            self.tx.send(cmd),
-        ) {
-            (Ok(_), Ok(_)) => receiver
-                .await
-                .map_err(|_err| Error::Connection(ConnectionError::Disconnected)),
-            _ => Err(Error::Connection(ConnectionError::Disconnected)),
+        let opaque = cmd.header.opaque;
+        if self
+            .registrations_tx
+            .send(Registration::Register(opaque, sender))
+            .is_err()
+        {
+            return Err(Error::Connection(ConnectionError::Disconnected));
+        }
+        let mut pending = PendingRequest::new(self, opaque);
+        if self.tx.send(cmd).is_err() {
+            return Err(Error::Connection(ConnectionError::Disconnected));
         }
+        let res = match timeout {
+            Some(timeout) => time::timeout(timeout, receiver)
+                .await
+                .map_err(|_| Error::Connection(ConnectionError::Timeout))?,
+            None => receiver.await,
+        };
+        // the receiver removed the registration when resolving it
+        pending.completed = true;
+        res.map_err(|_err| Error::Connection(ConnectionError::Disconnected))?
     }
 
     pub async fn send_oneway(&self, cmd: RemotingCommand) -> Result<(), Error> {
         let mut cmd = cmd;
         cmd.header.opaque = self.opaque_id.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("opaque", cmd.header.opaque);
+        cmd.mark_oneway_rpc();
         self.tx
             .send(cmd)
             .map_err(|_| Error::Connection(ConnectionError::Disconnected))?;
@@ -83,29 +211,73 @@ struct Receiver<S: Stream<Item = Result<RemotingCommand, Error>>> {
     addr: String,
     inbound: Pin<Box<S>>,
     // internal sender
-    // outbound: mpsc::UnboundedSender<RemotingCommand>,
-    pending_requests: HashMap<i32, oneshot::Sender<RemotingCommand>>,
-    registrations: Pin<Box<mpsc::UnboundedReceiver<(i32, oneshot::Sender<RemotingCommand>)>>>,
+    outbound: mpsc::UnboundedSender<RemotingCommand>,
+    processors: Processors,
+    hooks: Hooks,
+    pending_requests: HashMap<i32, oneshot::Sender<Result<RemotingCommand, Error>>>,
+    registrations: Pin<Box<mpsc::UnboundedReceiver<Registration>>>,
     shutdown: Pin<Box<oneshot::Receiver<()>>>,
+    // resolved when writing to the connection failed, `None` once the writer stopped
+    write_failed: Option<Pin<Box<oneshot::Receiver<()>>>>,
+    activity: Arc<Activity>,
+    stats: Arc<ConnectionStats>,
+    runtime: Option<Handle>,
+    // dropped along with the receiver to signal that the connection is closed
+    _closed: watch::Sender<()>,
+}
+
+impl<S: Stream<Item = Result<RemotingCommand, Error>>> Drop for Receiver<S> {
+    fn drop(&mut self) {
+        for (_, resolver) in self.pending_requests.drain() {
+            let _ = resolver.send(Err(Error::Connection(ConnectionError::Closed)));
+        }
+    }
 }
 
 impl<S: Stream<Item = Result<RemotingCommand, Error>>> Receiver<S> {
-    pub fn new(
-        addr: String,
-        inbound: S,
-        // outbound: mpsc::UnboundedSender<RemotingCommand>,
-        registrations: mpsc::UnboundedReceiver<(i32, oneshot::Sender<RemotingCommand>)>,
-        shutdown: oneshot::Receiver<()>,
-    ) -> Receiver<S> {
-        Self {
-            addr,
-            inbound: Box::pin(inbound),
-            // outbound,
-            pending_requests: HashMap::new(),
-            registrations: Box::pin(registrations),
-            shutdown: Box::pin(shutdown),
+    fn orphan_response(&self, response: RemotingCommand) {
+        warn!(
+            code = response.code(),
+            opaque = response.header.opaque,
+            remark = %response.header.remark,
+            "dropping response of unknown request from {}",
+            &self.addr
+        );
+        self.stats.record_orphan_response();
+        for hook in self.hooks.read().iter() {
+            hook.on_orphan_response(&self.addr, &response);
         }
     }
+
+    /// Process a server initiated request in a new task and send back its response
+    fn process_request(&self, request: RemotingCommand) {
+        let code = request.code();
+        let processor = self.processors.read().get(&code).cloned();
+        let outbound = self.outbound.clone();
+        let addr = self.addr.clone();
+        spawn(self.runtime.as_ref(), async move {
+            let opaque = request.opaque();
+            let oneway = request.is_oneway_rpc();
+            let response = match processor {
+                Some(processor) => processor.process(&addr, request).await,
+                None => {
+                    warn!(code = code, "no processor of request from {}", &addr);
+                    Some(request.response(
+                        ResponseCode::RequestCodeNotSupported,
+                        format!("request code {} not supported", code),
+                        HashMap::new(),
+                    ))
+                }
+            };
+            if let Some(mut response) = response {
+                if !oneway {
+                    response.header.opaque = opaque;
+                    response.mark_response_type();
+                    let _ = outbound.send(response);
+                }
+            }
+        });
+    }
 }
 
 impl<S: Stream<Item = Result<RemotingCommand, Error>>> Future for Receiver<S> {
@@ -120,17 +292,32 @@ impl<S: Stream<Item = Result<RemotingCommand, Error>>> Future for Receiver<S> {
         }
         loop {
             match self.registrations.as_mut().poll_recv(ctx) {
-                Poll::Ready(Some((opaque, resolver))) => {
+                Poll::Ready(Some(Registration::Register(opaque, resolver))) => {
                     self.pending_requests.insert(opaque, resolver);
                 }
+                Poll::Ready(Some(Registration::Deregister(opaque))) => {
+                    self.pending_requests.remove(&opaque);
+                }
                 Poll::Ready(None) => return Poll::Ready(Err(())),
                 Poll::Pending => break,
             }
         }
-        #[allow(clippy::never_loop)]
+        if let Some(write_failed) = self.write_failed.as_mut() {
+            match write_failed.as_mut().poll(ctx) {
+                Poll::Ready(Ok(())) => {
+                    // pending requests fail as closed
+                    warn!("closing connection to {} on write error", &self.addr);
+                    return Poll::Ready(Err(()));
+                }
+                // the writer stopped after draining
+                Poll::Ready(Err(..)) => self.write_failed = None,
+                Poll::Pending => {}
+            }
+        }
         loop {
             match self.inbound.as_mut().poll_next(ctx) {
                 Poll::Ready(Some(Ok(msg))) => {
+                    self.activity.touch();
                     debug!(
                         code = msg.code(),
                         opaque = msg.header.opaque,
@@ -140,16 +327,28 @@ impl<S: Stream<Item = Result<RemotingCommand, Error>>> Future for Receiver<S> {
                         &self.addr
                     );
                     if msg.is_response_type() {
-                        if let Some(resolver) = self.pending_requests.remove(&msg.header.opaque) {
-                            let _ = resolver.send(msg);
+                        match self.pending_requests.remove(&msg.header.opaque) {
+                            Some(resolver) => {
+                                let _ = resolver.send(Ok(msg));
+                            }
+                            // timed out, cancelled or never sent by us
+                            None => self.orphan_response(msg),
                         }
                     } else {
-                        // FIXME: what to do?
+                        self.process_request(msg);
                     }
                 }
                 Poll::Ready(None) => return Poll::Ready(Err(())),
                 Poll::Pending => return Poll::Pending,
-                Poll::Ready(Some(Err(_e))) => return Poll::Ready(Err(())),
+                Poll::Ready(Some(Err(err))) => {
+                    if !matches!(err, Error::Io(_)) {
+                        self.stats.record_decode_error();
+                    }
+                    // the stream can't be resynchronized after a malformed frame,
+                    // pending requests fail as disconnected
+                    warn!(error = %err, "closing connection to {} on read error", &self.addr);
+                    return Poll::Ready(Err(()));
+                }
             }
         }
     }
@@ -158,33 +357,127 @@ impl<S: Stream<Item = Result<RemotingCommand, Error>>> Future for Receiver<S> {
 pub struct Connection {
     addr: String,
     sender: ConnectionSender,
+    activity: Arc<Activity>,
+    closed: watch::Receiver<()>,
+    // asks the writer to stop accepting commands once the queued ones are written
+    drain: Mutex<Option<oneshot::Sender<()>>>,
+    drained: watch::Receiver<()>,
 }
 
 impl Connection {
-    pub async fn new(addr: &str) -> Result<Self, Error> {
-        let sender = Connection::prepare_stream(addr.to_string()).await?;
-        Ok(Self {
-            addr: addr.to_string(),
-            sender,
-        })
+    /// Connect to `addr`, requests initiated by the server are handled by `processors`
+    ///
+    /// The reader and writer tasks run on `runtime`, or on the current one if unset.
+    pub async fn new(
+        addr: &str,
+        serialize_type: HeaderCodecType,
+        socket: &SocketOptions,
+        processors: Processors,
+        hooks: Hooks,
+        stats: Arc<ConnectionStats>,
+        runtime: Option<Handle>,
+    ) -> Result<Self, Error> {
+        let stream =
+            Connection::prepare_stream(addr, serialize_type, socket, stats.clone()).await?;
+        #[cfg(feature = "test-util")]
+        if let Some(faults) = &socket.faults {
+            let stream = FaultyStream::new(stream, faults);
+            return Ok(Connection::connect(
+                addr.to_string(),
+                stream,
+                processors,
+                hooks,
+                stats,
+                runtime,
+            ));
+        }
+        Ok(Connection::connect(
+            addr.to_string(),
+            stream,
+            processors,
+            hooks,
+            stats,
+            runtime,
+        ))
+    }
+
+    /// Connect to `addr` over TLS
+    #[cfg(feature = "tls")]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new_tls(
+        addr: &str,
+        tls: &super::TlsConfig,
+        serialize_type: HeaderCodecType,
+        socket: &SocketOptions,
+        processors: Processors,
+        hooks: Hooks,
+        stats: Arc<ConnectionStats>,
+        runtime: Option<Handle>,
+    ) -> Result<Self, Error> {
+        info!(addr = %addr, "connecting to server over TLS");
+        let stream = socket.connect(addr).await?;
+        let stream = CountingStream::new(stream, stats.clone());
+        let stream = tls.connect(addr, stream).await.map(|stream| {
+            tokio_util::codec::Framed::new(stream, MqCodec::with_serialize_type(serialize_type))
+        })?;
+        info!(addr = %addr, "server connected");
+        #[cfg(feature = "test-util")]
+        if let Some(faults) = &socket.faults {
+            let stream = FaultyStream::new(stream, faults);
+            return Ok(Connection::connect(
+                addr.to_string(),
+                stream,
+                processors,
+                hooks,
+                stats,
+                runtime,
+            ));
+        }
+        Ok(Connection::connect(
+            addr.to_string(),
+            stream,
+            processors,
+            hooks,
+            stats,
+            runtime,
+        ))
     }
 
-    #[tracing::instrument(name = "connect")]
-    async fn prepare_stream(addr: String) -> Result<ConnectionSender, Error> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "connect", skip(stats))
+    )]
+    async fn prepare_stream(
+        addr: &str,
+        serialize_type: HeaderCodecType,
+        socket: &SocketOptions,
+        stats: Arc<ConnectionStats>,
+    ) -> Result<tokio_util::codec::Framed<CountingStream<TcpStream>, MqCodec>, Error> {
         info!("connecting to server");
-        let stream = TcpStream::connect(&addr)
-            .await
-            .map(|stream| tokio_util::codec::Framed::new(stream, MqCodec))?;
+        let stream = socket.connect(addr).await.map(|stream| {
+            tokio_util::codec::Framed::new(
+                CountingStream::new(stream, stats),
+                MqCodec::with_serialize_type(serialize_type),
+            )
+        })?;
         info!("server connected");
-        Connection::connect(addr, stream).await
+        Ok(stream)
     }
 
-    async fn connect<S>(addr: String, stream: S) -> Result<ConnectionSender, Error>
+    fn connect<S>(
+        addr: String,
+        stream: S,
+        processors: Processors,
+        hooks: Hooks,
+        stats: Arc<ConnectionStats>,
+        runtime: Option<Handle>,
+    ) -> Self
     where
         S: Stream<Item = Result<RemotingCommand, Error>>,
         S: Sink<RemotingCommand, Error = Error>,
         S: Send + std::marker::Unpin + 'static,
     {
+        let activity = Arc::new(Activity::new());
         let (mut sink, stream) = stream.split();
         let (tx, mut rx): (
             mpsc::UnboundedSender<RemotingCommand>,
@@ -192,28 +485,105 @@ impl Connection {
         ) = mpsc::unbounded_channel();
         let (registrations_tx, registrations_rx) = mpsc::unbounded_channel();
         let (receiver_shutdown_tx, receiver_shutdown_rx) = oneshot::channel();
-        tokio::spawn(Box::pin(Receiver::new(
-            addr.clone(),
-            stream,
-            // tx.clone(),
-            registrations_rx,
-            receiver_shutdown_rx,
-        )));
-        tokio::spawn(Box::pin(async move {
-            while let Some(msg) = rx.recv().await {
-                if let Err(_e) = sink.send(msg).await {
-                    // FIXME: error handling
-                    break;
+        let (closed_tx, closed) = watch::channel(());
+        let (write_failed_tx, write_failed_rx) = oneshot::channel();
+        let receiver = Receiver {
+            addr: addr.clone(),
+            inbound: Box::pin(stream),
+            outbound: tx.clone(),
+            processors,
+            hooks,
+            pending_requests: HashMap::new(),
+            registrations: Box::pin(registrations_rx),
+            shutdown: Box::pin(receiver_shutdown_rx),
+            write_failed: Some(Box::pin(write_failed_rx)),
+            activity: activity.clone(),
+            stats,
+            runtime: runtime.clone(),
+            _closed: closed_tx,
+        };
+        spawn(runtime.as_ref(), Box::pin(receiver));
+        let (drain_tx, drain_rx) = oneshot::channel();
+        let (drained_tx, drained) = watch::channel(());
+        let writer_addr = addr.clone();
+        spawn(
+            runtime.as_ref(),
+            Box::pin(async move {
+                let mut drain = Some(drain_rx);
+                loop {
+                    let msg = match drain.as_mut() {
+                        Some(drain_rx) => tokio::select! {
+                            msg = rx.recv() => msg,
+                            res = drain_rx => {
+                                if res.is_ok() {
+                                    // commands already queued are still received
+                                    rx.close();
+                                }
+                                drain = None;
+                                continue;
+                            }
+                        },
+                        None => rx.recv().await,
+                    };
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    };
+                    if let Err(err) = write_batch(&mut sink, &mut rx, msg).await {
+                        warn!(error = %err, "write to {} failed", &writer_addr);
+                        // the receiver closes the connection and fails pending requests
+                        let _ = write_failed_tx.send(());
+                        break;
+                    }
                 }
-            }
-        }));
-        let sender = ConnectionSender::new(addr, tx, registrations_tx, receiver_shutdown_tx);
-        Ok(sender)
+                drop(drained_tx);
+            }),
+        );
+        let sender =
+            ConnectionSender::new(addr.clone(), tx, registrations_tx, receiver_shutdown_tx);
+        Self {
+            addr,
+            sender,
+            activity,
+            closed,
+            drain: Mutex::new(Some(drain_tx)),
+            drained,
+        }
     }
 
     pub fn sender(&self) -> &ConnectionSender {
         &self.sender
     }
+
+    /// Resolves once the connection is closed, by the server, a read error or shutdown
+    pub fn closed(&self) -> impl Future<Output = ()> {
+        let mut closed = self.closed.clone();
+        async move {
+            // the sender is never used, only dropped
+            let _ = closed.changed().await;
+        }
+    }
+
+    /// Stop accepting new commands, resolves once the queued ones are written
+    pub fn drain(&self) -> impl Future<Output = ()> {
+        if let Some(drain) = self.drain.lock().take() {
+            let _ = drain.send(());
+        }
+        let mut drained = self.drained.clone();
+        async move {
+            let _ = drained.changed().await;
+        }
+    }
+
+    /// Number of requests waiting for their response
+    pub fn in_flight(&self) -> usize {
+        self.sender.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Time since the last frame was received, or since connecting if none was
+    pub fn idle_time(&self) -> Duration {
+        self.activity.idle_time()
+    }
 }
 
 impl Drop for Connection {
@@ -224,3 +594,354 @@ impl Drop for Connection {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use futures::{stream, Sink, SinkExt, Stream, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::{mpsc, oneshot, watch};
+    use tokio_util::codec::Framed;
+
+    use super::{write_batch, Activity, Connection, ConnectionSender, Receiver, MAX_WRITE_BATCH};
+    use crate::error::ConnectionError;
+    use crate::protocol::{HeaderCodecType, MqCodec, RemotingCommand, RequestCode, ResponseCode};
+    use crate::remoting::hook::Hooks;
+    use crate::remoting::processor::Processors;
+    use crate::remoting::stats::RemotingStats;
+    use crate::remoting::{RequestProcessor, RpcHook, SocketOptions};
+    use crate::Error;
+
+    struct EchoProcessor;
+
+    #[async_trait]
+    impl RequestProcessor for EchoProcessor {
+        async fn process(&self, addr: &str, request: RemotingCommand) -> Option<RemotingCommand> {
+            Some(request.response(ResponseCode::Success, addr, HashMap::new()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Accept the connection but never respond
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let conn = Connection::new(
+            &addr,
+            HeaderCodecType::Json,
+            &SocketOptions::default(),
+            Processors::default(),
+            Hooks::default(),
+            RemotingStats::default().connection(&addr),
+            None,
+        )
+        .await
+        .unwrap();
+        let _stream = server.await.unwrap();
+        let cmd = RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let err = conn
+            .sender()
+            .send(cmd, Some(Duration::from_millis(50)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Connection(ConnectionError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_process_server_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let processors = Processors::default();
+        processors.write().insert(
+            RequestCode::CheckTransactionState.into(),
+            Arc::new(EchoProcessor),
+        );
+        let _conn = Connection::new(
+            &addr,
+            HeaderCodecType::Json,
+            &SocketOptions::default(),
+            processors,
+            Hooks::default(),
+            RemotingStats::default().connection(&addr),
+            None,
+        )
+        .await
+        .unwrap();
+        let (stream, _) = server.await.unwrap();
+        let mut server = Framed::new(stream, MqCodec::default());
+
+        for (code, opaque) in [
+            (RequestCode::CheckTransactionState, 42),
+            (RequestCode::GetConsumerRunningInfo, 43),
+        ] {
+            let mut request = RemotingCommand::new(code, String::new(), HashMap::new(), Vec::new());
+            request.header.opaque = opaque;
+            server.send(request).await.unwrap();
+        }
+        let mut responses = HashMap::new();
+        for _ in 0..2 {
+            let response = server.next().await.unwrap().unwrap();
+            assert!(response.is_response_type());
+            responses.insert(response.opaque(), response);
+        }
+        assert_eq!(i16::from(ResponseCode::Success), responses[&42].code());
+        assert_eq!(addr, responses[&42].remark());
+        assert_eq!(
+            i16::from(ResponseCode::RequestCodeNotSupported),
+            responses[&43].code()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_orphan_response() {
+        struct Orphans(mpsc::UnboundedSender<(String, i32)>);
+
+        impl RpcHook for Orphans {
+            fn on_orphan_response(&self, _addr: &str, response: &RemotingCommand) {
+                let _ = self
+                    .0
+                    .send((response.remark().to_string(), response.opaque()));
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let (tx, mut orphans) = mpsc::unbounded_channel();
+        let hooks = Hooks::default();
+        hooks.write().push(Arc::new(Orphans(tx)));
+        let stats = RemotingStats::default().connection(&addr);
+        let _conn = Connection::new(
+            &addr,
+            HeaderCodecType::Json,
+            &SocketOptions::default(),
+            Processors::default(),
+            hooks,
+            stats.clone(),
+            None,
+        )
+        .await
+        .unwrap();
+        let (stream, _) = server.await.unwrap();
+        let mut server = Framed::new(stream, MqCodec::default());
+
+        let mut response = RemotingCommand::new_response(
+            ResponseCode::SystemError,
+            "late".to_string(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        response.header.opaque = 42;
+        server.send(response).await.unwrap();
+        assert_eq!(("late".to_string(), 42), orphans.recv().await.unwrap());
+        assert_eq!(1, stats.snapshot(0).orphan_responses);
+    }
+
+    #[tokio::test]
+    async fn test_write_batch() {
+        struct CountingSink {
+            items: usize,
+            flushes: usize,
+        }
+
+        impl Sink<RemotingCommand> for CountingSink {
+            type Error = Error;
+
+            fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn start_send(mut self: Pin<&mut Self>, _: RemotingCommand) -> Result<(), Error> {
+                self.items += 1;
+                Ok(())
+            }
+
+            fn poll_flush(
+                mut self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+            ) -> Poll<Result<(), Error>> {
+                self.flushes += 1;
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        let new_request = || {
+            RemotingCommand::new(
+                RequestCode::UpdateConsumerOffset,
+                String::new(),
+                HashMap::new(),
+                Vec::new(),
+            )
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        for _ in 0..MAX_WRITE_BATCH + 9 {
+            tx.send(new_request()).unwrap();
+        }
+        let mut sink = CountingSink {
+            items: 0,
+            flushes: 0,
+        };
+        write_batch(&mut sink, &mut rx, new_request())
+            .await
+            .unwrap();
+        assert_eq!((MAX_WRITE_BATCH, 1), (sink.items, sink.flushes));
+        let first = rx.recv().await.unwrap();
+        write_batch(&mut sink, &mut rx, first).await.unwrap();
+        assert_eq!((MAX_WRITE_BATCH + 10, 2), (sink.items, sink.flushes));
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let conn = Connection::new(
+            &addr,
+            HeaderCodecType::Json,
+            &SocketOptions::default(),
+            Processors::default(),
+            Hooks::default(),
+            RemotingStats::default().connection(&addr),
+            None,
+        )
+        .await
+        .unwrap();
+        let (stream, _) = server.await.unwrap();
+        let mut server = Framed::new(stream, MqCodec::default());
+
+        let new_request = || {
+            RemotingCommand::new(
+                RequestCode::UpdateConsumerOffset,
+                String::new(),
+                HashMap::new(),
+                Vec::new(),
+            )
+        };
+        for _ in 0..3 {
+            conn.sender().send_oneway(new_request()).await.unwrap();
+        }
+        tokio::time::timeout(Duration::from_secs(1), conn.drain())
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            let request = server.next().await.unwrap().unwrap();
+            assert!(request.is_oneway_rpc());
+        }
+        assert!(conn.sender().send_oneway(new_request()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_error_closes_connection() {
+        struct BrokenPipe;
+
+        impl Stream for BrokenPipe {
+            type Item = Result<RemotingCommand, Error>;
+
+            fn poll_next(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                Poll::Pending
+            }
+        }
+
+        impl Sink<RemotingCommand> for BrokenPipe {
+            type Error = Error;
+
+            fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn start_send(self: Pin<&mut Self>, _: RemotingCommand) -> Result<(), Error> {
+                Err(io::Error::from(io::ErrorKind::BrokenPipe).into())
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        let conn = Connection::connect(
+            "test".to_string(),
+            BrokenPipe,
+            Processors::default(),
+            Hooks::default(),
+            RemotingStats::default().connection("test"),
+            None,
+        );
+        let request = RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let err = tokio::time::timeout(Duration::from_secs(1), conn.sender().send(request, None))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(matches!(err, Error::Connection(ConnectionError::Closed)));
+        tokio::time::timeout(Duration::from_secs(1), conn.closed())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_send_deregisters() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (registrations_tx, registrations_rx) = mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let sender = ConnectionSender::new("test".to_string(), tx, registrations_tx, shutdown_tx);
+        let (closed_tx, _closed) = watch::channel(());
+        let mut receiver = Receiver {
+            addr: "test".to_string(),
+            inbound: Box::pin(stream::pending::<Result<RemotingCommand, Error>>()),
+            outbound: mpsc::unbounded_channel().0,
+            processors: Processors::default(),
+            hooks: Hooks::default(),
+            pending_requests: HashMap::new(),
+            registrations: Box::pin(registrations_rx),
+            shutdown: Box::pin(shutdown_rx),
+            write_failed: None,
+            activity: Arc::new(Activity::new()),
+            stats: RemotingStats::default().connection("test"),
+            runtime: None,
+            _closed: closed_tx,
+        };
+
+        let request = RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        let mut send = Box::pin(sender.send(request, None));
+        assert!(futures::poll!(&mut send).is_pending());
+        assert!(futures::poll!(&mut receiver).is_pending());
+        assert_eq!(1, receiver.pending_requests.len());
+
+        drop(send);
+        assert!(futures::poll!(&mut receiver).is_pending());
+        assert!(receiver.pending_requests.is_empty());
+        assert_eq!(0, sender.in_flight.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+/// Capacity of the connection event channel, slow receivers miss older events
+pub(crate) const CONNECTION_EVENTS_CAPACITY: usize = 64;
+
+/// Change of a connection to a broker or name server address
+///
+/// Events are sent for every connection, an address has several when multiple
+/// connections per broker are enabled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    Connected {
+        addr: String,
+    },
+    Disconnected {
+        addr: String,
+        reason: DisconnectReason,
+    },
+    /// Connecting failed repeatedly, it's retried on next use after `after`
+    ReconnectScheduled {
+        addr: String,
+        after: Duration,
+    },
+}
+
+impl ConnectionEvent {
+    pub fn addr(&self) -> &str {
+        match self {
+            ConnectionEvent::Connected { addr }
+            | ConnectionEvent::Disconnected { addr, .. }
+            | ConnectionEvent::ReconnectScheduled { addr, .. } => addr,
+        }
+    }
+}
+
+/// Why a connection was closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// Closed by the server or after a read error
+    Closed,
+    /// Didn't respond to a health check ping in time
+    HealthCheckFailed,
+    /// Closed by the client, e.g. on shutdown
+    Shutdown,
+}
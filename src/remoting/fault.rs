@@ -0,0 +1,263 @@
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::{
+    task::{Context, Poll},
+    Future, Sink, Stream,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::time::{self, Sleep};
+
+use crate::error::Error;
+use crate::protocol::RemotingCommand;
+
+/// Faults injected into broker connections to verify retry and reconnect behavior
+///
+/// Faults apply to received frames. Rates are probabilities between 0 and 1,
+/// set a seed to get the same faults on every run.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FaultInjection {
+    max_delay: Option<Duration>,
+    drop_response_rate: f64,
+    read_error_rate: f64,
+    disconnect_after: Option<usize>,
+    seed: Option<u64>,
+}
+
+impl FaultInjection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay every received frame by a random duration up to `delay`
+    pub fn set_max_delay(&mut self, delay: Duration) -> &mut Self {
+        self.max_delay = Some(delay);
+        self
+    }
+
+    /// Probability of a response being dropped, its request times out
+    pub fn set_drop_response_rate(&mut self, rate: f64) -> &mut Self {
+        self.drop_response_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Probability of a decoded frame being replaced by an unexpected EOF error, which
+    /// closes the connection
+    pub fn set_read_error_rate(&mut self, rate: f64) -> &mut Self {
+        self.read_error_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Close connections after receiving `frames` frames, the last one is discarded
+    pub fn set_disconnect_after(&mut self, frames: usize) -> &mut Self {
+        self.disconnect_after = Some(frames);
+        self
+    }
+
+    /// Seed of the random faults, every connection starts with the same seed
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// Command stream of a connection with faults injected into it
+pub(crate) struct FaultyStream<S> {
+    inner: S,
+    faults: FaultInjection,
+    rng: StdRng,
+    received: usize,
+    delayed: Option<(Pin<Box<Sleep>>, RemotingCommand)>,
+    closed: bool,
+}
+
+impl<S> FaultyStream<S> {
+    pub fn new(inner: S, faults: &FaultInjection) -> Self {
+        let rng = match faults.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            inner,
+            faults: faults.clone(),
+            rng,
+            received: 0,
+            delayed: None,
+            closed: false,
+        }
+    }
+}
+
+impl<S> Stream for FaultyStream<S>
+where
+    S: Stream<Item = Result<RemotingCommand, Error>> + Unpin,
+{
+    type Item = Result<RemotingCommand, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if let Some((sleep, _)) = this.delayed.as_mut() {
+            if sleep.as_mut().poll(ctx).is_pending() {
+                return Poll::Pending;
+            }
+            let (_, msg) = this.delayed.take().unwrap();
+            return Poll::Ready(Some(Ok(msg)));
+        }
+        loop {
+            if this.closed {
+                return Poll::Ready(None);
+            }
+            let msg = match Pin::new(&mut this.inner).poll_next(ctx) {
+                Poll::Ready(Some(Ok(msg))) => msg,
+                other => return other,
+            };
+            this.received += 1;
+            if matches!(this.faults.disconnect_after, Some(frames) if this.received >= frames) {
+                this.closed = true;
+                continue;
+            }
+            if msg.is_response_type() && this.rng.gen_bool(this.faults.drop_response_rate) {
+                continue;
+            }
+            if this.rng.gen_bool(this.faults.read_error_rate) {
+                this.closed = true;
+                return Poll::Ready(Some(Err(Error::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "read error injected by fault injection",
+                )))));
+            }
+            if let Some(max_delay) = this.faults.max_delay {
+                let delay = this.rng.gen_range(Duration::ZERO..=max_delay);
+                let mut sleep = Box::pin(time::sleep(delay));
+                if sleep.as_mut().poll(ctx).is_pending() {
+                    this.delayed = Some((sleep, msg));
+                    return Poll::Pending;
+                }
+            }
+            return Poll::Ready(Some(Ok(msg)));
+        }
+    }
+}
+
+impl<S> Sink<RemotingCommand> for FaultyStream<S>
+where
+    S: Sink<RemotingCommand, Error = Error> + Unpin,
+{
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_ready(ctx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: RemotingCommand) -> Result<(), Error> {
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_flush(ctx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_close(ctx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    use super::FaultInjection;
+    use crate::error::ConnectionError;
+    use crate::protocol::{MqCodec, RemotingCommand, RequestCode, ResponseCode};
+    use crate::remoting::{RemotingClient, SocketOptions};
+    use crate::Error;
+
+    /// Responds with success to every request on every accepted connection
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut framed = Framed::new(stream, MqCodec::default());
+                    while let Some(Ok(request)) = framed.next().await {
+                        let response =
+                            request.response(ResponseCode::Success, String::new(), HashMap::new());
+                        if framed.send(response).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    fn faulty_client(faults: &FaultInjection) -> RemotingClient {
+        let mut client = RemotingClient::default();
+        client.set_socket_options(SocketOptions {
+            faults: Some(faults.clone()),
+            ..Default::default()
+        });
+        client
+    }
+
+    fn request() -> RemotingCommand {
+        RemotingCommand::new(
+            RequestCode::Heartbeat,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_drop_response() {
+        let addr = serve().await;
+        let client = faulty_client(FaultInjection::new().set_drop_response_rate(1.0));
+        let err = client
+            .invoke_timeout(&addr, request(), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Connection(ConnectionError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_read_error_and_reconnect() {
+        let addr = serve().await;
+        let client = faulty_client(FaultInjection::new().set_read_error_rate(1.0));
+        let err = client.invoke(&addr, request()).await.unwrap_err();
+        assert!(err.is_retriable());
+
+        // a new connection is opened for the retry
+        let client = faulty_client(FaultInjection::new().set_disconnect_after(2));
+        let response = client.invoke(&addr, request()).await.unwrap();
+        assert_eq!(i16::from(ResponseCode::Success), response.code());
+        let err = client.invoke(&addr, request()).await.unwrap_err();
+        assert!(err.is_retriable());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let response = client.invoke(&addr, request()).await.unwrap();
+        assert_eq!(i16::from(ResponseCode::Success), response.code());
+    }
+
+    #[tokio::test]
+    async fn test_delay() {
+        let addr = serve().await;
+        let client = faulty_client(
+            FaultInjection::new()
+                .set_max_delay(Duration::from_millis(200))
+                .set_seed(7),
+        );
+        for _ in 0..3 {
+            let response = client.invoke(&addr, request()).await.unwrap();
+            assert_eq!(i16::from(ResponseCode::Success), response.code());
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::protocol::RemotingCommand;
+
+/// Hooks registered on a remoting client, shared with its connections
+pub(crate) type Hooks = Arc<RwLock<Vec<Arc<dyn RpcHook>>>>;
+
+/// Hook invoked around every remoting request, e.g. to add custom headers,
+/// audit requests or track latencies
+///
+/// Requests are signed after `before_request` runs, so headers added there are
+/// covered by the ACL signature, while headers added in `after_signature` are not,
+/// e.g. for gateways which strip them before the broker verifies the signature.
+pub trait RpcHook: Send + Sync {
+    /// Called before sending `request` to `addr`
+    fn before_request(&self, addr: &str, request: &mut RemotingCommand) {
+        let _ = (addr, request);
+    }
+
+    /// Called after signing `request`, right before sending it to `addr`
+    fn after_signature(&self, addr: &str, request: &mut RemotingCommand) {
+        let _ = (addr, request);
+    }
+
+    /// Called after receiving `response` from `addr` for a request of `request_code`
+    fn after_response(&self, addr: &str, request_code: i16, response: &RemotingCommand) {
+        let _ = (addr, request_code, response);
+    }
+
+    /// Called when `response` from `addr` matches no pending request, usually as
+    /// it arrived after its request timed out
+    fn on_orphan_response(&self, addr: &str, response: &RemotingCommand) {
+        let _ = (addr, response);
+    }
+}
@@ -1,4 +1,24 @@
+mod breaker;
 mod client;
 mod connection;
+mod event;
+#[cfg(feature = "test-util")]
+mod fault;
+mod hook;
+mod processor;
+mod socket;
+mod stats;
+#[cfg(feature = "tls")]
+mod tls;
+mod trace;
 
 pub use client::RemotingClient;
+pub use event::{ConnectionEvent, DisconnectReason};
+#[cfg(feature = "test-util")]
+pub use fault::FaultInjection;
+pub use hook::RpcHook;
+pub use processor::RequestProcessor;
+pub(crate) use socket::SocketOptions;
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+pub use trace::TraceContext;
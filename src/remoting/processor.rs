@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::RwLock;
+
+use crate::protocol::RemotingCommand;
+
+/// Processors of server initiated requests by request code
+pub(crate) type Processors = Arc<RwLock<HashMap<i16, Arc<dyn RequestProcessor>>>>;
+
+/// Handler of requests initiated by a server, like transaction state checks,
+/// consumer id change notifications and running info queries
+#[async_trait]
+pub trait RequestProcessor: Send + Sync {
+    /// Process `request` received from `addr`
+    ///
+    /// The returned response is sent back over the same connection, unless the
+    /// request is oneway. Its opaque is set to the one of the request.
+    async fn process(&self, addr: &str, request: RemotingCommand) -> Option<RemotingCommand>;
+}
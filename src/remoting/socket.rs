@@ -0,0 +1,103 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+use tokio::time;
+
+/// TCP options of connections, the OS defaults apply to unset buffer sizes
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SocketOptions {
+    pub nodelay: bool,
+    /// Idle time before keepalive probes are sent, `None` disables keepalive
+    pub keepalive: Option<Duration>,
+    pub connect_timeout: Duration,
+    pub send_buffer_size: Option<u32>,
+    pub recv_buffer_size: Option<u32>,
+    #[cfg(feature = "test-util")]
+    pub faults: Option<super::FaultInjection>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(60)),
+            connect_timeout: Duration::from_secs(3),
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            #[cfg(feature = "test-util")]
+            faults: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Connect to the first address `addr` resolves to that accepts the connection
+    pub async fn connect(&self, addr: &str) -> io::Result<TcpStream> {
+        let stream = time::timeout(self.connect_timeout, async {
+            let mut last_err = None;
+            for addr in lookup_host(addr).await? {
+                match self.connect_addr(addr).await {
+                    Ok(stream) => return Ok(stream),
+                    Err(err) => last_err = Some(err),
+                }
+            }
+            Err(last_err.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+            }))
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(time) = self.keepalive {
+            SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        Ok(stream)
+    }
+
+    async fn connect_addr(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        // buffer sizes must be set before connecting to affect the TCP window scale
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        socket.connect(addr).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use super::SocketOptions;
+
+    #[tokio::test]
+    async fn test_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let options = SocketOptions {
+            nodelay: false,
+            keepalive: None,
+            connect_timeout: Duration::from_secs(1),
+            send_buffer_size: Some(64 * 1024),
+            recv_buffer_size: Some(64 * 1024),
+            ..Default::default()
+        };
+        let stream = options.connect(&addr).await.unwrap();
+        assert!(!stream.nodelay().unwrap());
+
+        let stream = SocketOptions::default().connect(&addr).await.unwrap();
+        assert!(stream.nodelay().unwrap());
+    }
+}
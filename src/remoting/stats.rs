@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::client::ConnectionMetrics;
+
+/// Counters of a `RemotingClient`
+#[derive(Debug, Default)]
+pub(crate) struct RemotingStats {
+    open_connections: AtomicUsize,
+    // request code -> count
+    requests: Mutex<HashMap<i16, u64>>,
+    // response code -> count
+    responses: Mutex<HashMap<i16, u64>>,
+    // server address -> stats
+    connections: Mutex<HashMap<String, Arc<ConnectionStats>>>,
+}
+
+impl RemotingStats {
+    pub fn record_connected(&self) {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::increment_gauge!("rocketmq_client_open_connections", 1.0);
+    }
+
+    pub fn record_closed(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.open_connections.fetch_sub(count, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::decrement_gauge!("rocketmq_client_open_connections", count as f64);
+    }
+
+    pub fn record_request(&self, code: i16) {
+        *self.requests.lock().entry(code).or_default() += 1;
+        #[cfg(feature = "metrics")]
+        metrics::counter!("rocketmq_client_requests_total", 1, "code" => code.to_string());
+    }
+
+    pub fn record_response(&self, code: i16) {
+        *self.responses.lock().entry(code).or_default() += 1;
+        #[cfg(feature = "metrics")]
+        metrics::counter!("rocketmq_client_responses_total", 1, "code" => code.to_string());
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.open_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn requests(&self) -> HashMap<i16, u64> {
+        self.requests.lock().clone()
+    }
+
+    pub fn responses(&self) -> HashMap<i16, u64> {
+        self.responses.lock().clone()
+    }
+
+    /// Stats of the connections to `addr`, kept across reconnects
+    pub fn connection(&self, addr: &str) -> Arc<ConnectionStats> {
+        self.connections
+            .lock()
+            .entry(addr.to_string())
+            .or_insert_with(|| Arc::new(ConnectionStats::new(addr)))
+            .clone()
+    }
+
+    /// Drop the stats of `addr`, once it is no longer connected to
+    pub fn remove_connection(&self, addr: &str) {
+        self.connections.lock().remove(addr);
+    }
+
+    /// Snapshot of the stats of every address, given the number of requests in
+    /// flight to each one
+    pub fn connections(
+        &self,
+        in_flight: &HashMap<String, usize>,
+    ) -> HashMap<String, ConnectionMetrics> {
+        self.connections
+            .lock()
+            .iter()
+            .map(|(addr, stats)| {
+                let in_flight = in_flight.get(addr).copied().unwrap_or_default();
+                (addr.clone(), stats.snapshot(in_flight))
+            })
+            .collect()
+    }
+}
+
+/// Counters of the connections to one server address
+#[derive(Debug)]
+pub(crate) struct ConnectionStats {
+    // label of the exported metrics
+    #[cfg(feature = "metrics")]
+    addr: String,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    connects: AtomicU64,
+    decode_errors: AtomicU64,
+    orphan_responses: AtomicU64,
+    responses: AtomicU64,
+    // microseconds
+    total_latency: AtomicU64,
+    max_latency: AtomicU64,
+}
+
+impl ConnectionStats {
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn new(addr: &str) -> Self {
+        Self {
+            #[cfg(feature = "metrics")]
+            addr: addr.to_string(),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            connects: AtomicU64::new(0),
+            decode_errors: AtomicU64::new(0),
+            orphan_responses: AtomicU64::new(0),
+            responses: AtomicU64::new(0),
+            total_latency: AtomicU64::new(0),
+            max_latency: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_connected(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("rocketmq_client_connects_total", 1, "addr" => self.addr.clone());
+    }
+
+    pub fn record_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("rocketmq_client_received_bytes_total", bytes as u64, "addr" => self.addr.clone());
+    }
+
+    pub fn record_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("rocketmq_client_sent_bytes_total", bytes as u64, "addr" => self.addr.clone());
+    }
+
+    pub fn record_decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("rocketmq_client_decode_errors_total", 1, "addr" => self.addr.clone());
+    }
+
+    pub fn record_orphan_response(&self) {
+        self.orphan_responses.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("rocketmq_client_orphan_responses_total", 1, "addr" => self.addr.clone());
+    }
+
+    pub fn record_latency(&self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        self.responses.fetch_add(1, Ordering::Relaxed);
+        self.total_latency.fetch_add(micros, Ordering::Relaxed);
+        self.max_latency.fetch_max(micros, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::histogram!(
+            "rocketmq_client_request_duration_seconds",
+            latency.as_secs_f64(),
+            "addr" => self.addr.clone(),
+        );
+    }
+
+    /// Snapshot of the counters, `in_flight` is the number of requests waiting on
+    /// the connections to the address
+    pub fn snapshot(&self, in_flight: usize) -> ConnectionMetrics {
+        let responses = self.responses.load(Ordering::Relaxed);
+        let total_latency = self.total_latency.load(Ordering::Relaxed);
+        ConnectionMetrics {
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            in_flight,
+            reconnects: self.connects.load(Ordering::Relaxed).saturating_sub(1),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            orphan_responses: self.orphan_responses.load(Ordering::Relaxed),
+            responses,
+            avg_latency: Duration::from_micros(total_latency.checked_div(responses).unwrap_or(0)),
+            max_latency: Duration::from_micros(self.max_latency.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Stream counting the bytes read and written into `ConnectionStats`
+pub(crate) struct CountingStream<S> {
+    inner: S,
+    stats: Arc<ConnectionStats>,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, stats: Arc<ConnectionStats>) -> Self {
+        Self { inner, stats }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - filled;
+        if read > 0 {
+            self.stats.record_received(read);
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = res {
+            self.stats.record_sent(written);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::RemotingStats;
+
+    #[test]
+    fn test_remoting_stats() {
+        let stats = RemotingStats::default();
+        stats.record_connected();
+        stats.record_connected();
+        stats.record_closed(1);
+        assert_eq!(1, stats.open_connections());
+        stats.record_request(10);
+        stats.record_request(10);
+        stats.record_response(0);
+        assert_eq!(Some(&2), stats.requests().get(&10));
+        assert_eq!(Some(&1), stats.responses().get(&0));
+    }
+
+    #[test]
+    fn test_connection_stats() {
+        let stats = RemotingStats::default();
+        let conn = stats.connection("127.0.0.1:10911");
+        conn.record_connected();
+        conn.record_connected();
+        conn.record_sent(100);
+        conn.record_received(60);
+        conn.record_decode_error();
+        conn.record_latency(Duration::from_millis(10));
+        conn.record_latency(Duration::from_millis(30));
+
+        let in_flight = HashMap::from([("127.0.0.1:10911".to_string(), 1)]);
+        let metrics = &stats.connections(&in_flight)["127.0.0.1:10911"];
+        assert_eq!(100, metrics.bytes_sent);
+        assert_eq!(60, metrics.bytes_received);
+        assert_eq!(1, metrics.in_flight);
+        assert_eq!(1, metrics.reconnects);
+        assert_eq!(1, metrics.decode_errors);
+        assert_eq!(2, metrics.responses);
+        assert_eq!(Duration::from_millis(20), metrics.avg_latency);
+        assert_eq!(Duration::from_millis(30), metrics.max_latency);
+
+        stats.remove_connection("127.0.0.1:10911");
+        assert!(stats.connections(&in_flight).is_empty());
+    }
+}
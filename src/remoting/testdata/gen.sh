@@ -0,0 +1,27 @@
+#!/bin/sh
+# Regenerate the TLS test fixtures: a CA, a server certificate of broker.test
+# and a client certificate, both signed by the CA and valid for 100 years
+set -e
+cd "$(dirname "$0")"
+openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out ca-key.pem
+openssl req -x509 -new -key ca-key.pem -sha256 -days 36500 -subj "/CN=rocketmq-rs test CA" \
+  -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign" \
+  -out ca.pem
+
+sign() {
+  name=$1
+  subject=$2
+  extensions=$3
+  openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out "$name-key.pem"
+  openssl req -new -key "$name-key.pem" -subj "$subject" -out "$name.csr"
+  printf "$extensions" > "$name.ext"
+  openssl x509 -req -in "$name.csr" -CA ca.pem -CAkey ca-key.pem -CAcreateserial -days 36500 \
+    -sha256 -extfile "$name.ext" -out "$name.pem"
+  rm "$name.csr" "$name.ext"
+}
+
+sign server "/CN=broker.test" \
+  "basicConstraints=CA:FALSE\nkeyUsage=critical,digitalSignature\nextendedKeyUsage=serverAuth\nsubjectAltName=DNS:broker.test\n"
+sign client "/CN=rocketmq-rs client" \
+  "basicConstraints=CA:FALSE\nkeyUsage=critical,digitalSignature\nextendedKeyUsage=clientAuth\n"
+rm ca-key.pem ca.srl
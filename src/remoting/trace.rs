@@ -0,0 +1,192 @@
+use std::fmt;
+use std::future::Future;
+
+use rand::Rng;
+
+use crate::protocol::RemotingCommand;
+
+/// Ext field carrying the trace id of a request and its response
+pub(crate) const TRACE_ID: &str = "traceId";
+/// Ext field carrying the W3C `traceparent` of a request
+pub(crate) const TRACEPARENT: &str = "traceparent";
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// W3C trace context propagated in the ext fields of remoting commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: String,
+    parent_id: String,
+    sampled: bool,
+}
+
+impl TraceContext {
+    /// A new sampled trace with random ids
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            trace_id: format!("{:032x}", rng.gen_range(1..=u128::MAX)),
+            parent_id: format!("{:016x}", rng.gen_range(1..=u64::MAX)),
+            sampled: true,
+        }
+    }
+
+    /// Parse a `traceparent` like `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`
+    pub fn from_traceparent(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+        if version.len() != 2
+            || version == "ff"
+            || !is_hex_id(trace_id, 32)
+            || !is_hex_id(parent_id, 16)
+            || flags.len() != 2
+        {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+            sampled: flags & 0x1 == 0x1,
+        })
+    }
+
+    /// Trace context of `cmd`, from its `traceparent` or else its bare trace id
+    pub fn from_command(cmd: &RemotingCommand) -> Option<Self> {
+        let fields = cmd.ext_fields();
+        if let Some(ctx) = fields
+            .get(TRACEPARENT)
+            .and_then(|value| Self::from_traceparent(value))
+        {
+            return Some(ctx);
+        }
+        let trace_id = fields.get(TRACE_ID)?;
+        if !is_hex_id(trace_id, 32) {
+            return None;
+        }
+        Some(Self {
+            trace_id: trace_id.clone(),
+            ..Self::new()
+        })
+    }
+
+    /// The trace context set with `scope` for the current task, if any
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Run `f` with this trace context, requests sent from it carry its trace id
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// A child of this context with a new parent id, for the next outgoing request
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id.clone(),
+            parent_id: format!("{:016x}", rand::thread_rng().gen_range(1..=u64::MAX)),
+            sampled: self.sampled,
+        }
+    }
+
+    /// Write the trace id and `traceparent` into the ext fields of `cmd`,
+    /// keeping fields already set by the caller
+    pub(crate) fn inject(&self, cmd: &mut RemotingCommand) {
+        let fields = cmd.ext_fields_mut();
+        fields
+            .entry(TRACE_ID.to_string())
+            .or_insert_with(|| self.trace_id.clone());
+        fields
+            .entry(TRACEPARENT.to_string())
+            .or_insert_with(|| self.to_string());
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            self.trace_id, self.parent_id, self.sampled as u8
+        )
+    }
+}
+
+fn is_hex_id(id: &str, len: usize) -> bool {
+    id.len() == len
+        && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && id.bytes().any(|b| b != b'0')
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{TraceContext, TRACEPARENT, TRACE_ID};
+    use crate::protocol::{RemotingCommand, RequestCode};
+
+    #[test]
+    fn test_traceparent() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let ctx = TraceContext::from_traceparent(traceparent).unwrap();
+        assert_eq!("4bf92f3577b34da6a3ce929d0e0e4736", ctx.trace_id());
+        assert!(ctx.is_sampled());
+        assert_eq!(traceparent, ctx.to_string());
+        assert!(TraceContext::from_traceparent(
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+        )
+        .is_none());
+        assert!(TraceContext::from_traceparent("00-4bf92f35-00f067aa0ba902b7-01").is_none());
+
+        let child = ctx.child();
+        assert_eq!(ctx.trace_id(), child.trace_id());
+        assert_ne!(ctx.to_string(), child.to_string());
+        assert!(TraceContext::from_traceparent(&TraceContext::new().to_string()).is_some());
+    }
+
+    #[test]
+    fn test_inject_extract() {
+        let mut cmd = RemotingCommand::new(
+            RequestCode::SendMessage,
+            String::new(),
+            HashMap::new(),
+            Vec::new(),
+        );
+        assert!(TraceContext::from_command(&cmd).is_none());
+        let ctx = TraceContext::new();
+        ctx.inject(&mut cmd);
+        assert_eq!(ctx.trace_id(), cmd.ext_fields()[TRACE_ID]);
+        assert_eq!(Some(ctx.clone()), TraceContext::from_command(&cmd));
+
+        cmd.ext_fields_mut().remove(TRACEPARENT);
+        let extracted = TraceContext::from_command(&cmd).unwrap();
+        assert_eq!(ctx.trace_id(), extracted.trace_id());
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert!(TraceContext::current().is_none());
+        let ctx = TraceContext::new();
+        let current = ctx.clone().scope(async { TraceContext::current() }).await;
+        assert_eq!(Some(ctx), current);
+    }
+}
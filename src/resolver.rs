@@ -1,10 +1,79 @@
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use tracing::{info, warn};
 
 use crate::Error;
 
 const DEFAULT_NAMESRV_ADDR: &'static str = "http://jmenv.tbsite.net:8080/rocketmq/nsaddr";
+const DEFAULT_NAMESRV_PORT: u16 = 9876;
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Normalize resolved name server addresses to `host:port`
+///
+/// Strips `http://` and `https://` schemes, applies the default port 9876 when
+/// missing, removes duplicates and rejects malformed addresses.
+pub(crate) fn normalize_addrs(addrs: &[String]) -> Result<Vec<String>, Error> {
+    let mut normalized: Vec<String> = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let trimmed = addr.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let addr = normalize_addr(trimmed)
+            .ok_or_else(|| Error::InvalidNameServerAddress(trimmed.to_string()))?;
+        if !normalized.contains(&addr) {
+            normalized.push(addr);
+        }
+    }
+    Ok(normalized)
+}
+
+fn normalize_addr(addr: &str) -> Option<String> {
+    let lower = addr.to_ascii_lowercase();
+    let addr = if lower.starts_with("http://") {
+        &addr["http://".len()..]
+    } else if lower.starts_with("https://") {
+        &addr["https://".len()..]
+    } else {
+        addr
+    };
+    let addr = addr.trim_end_matches('/');
+    let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+        // [IPv6]:port
+        let (host, rest) = rest.split_once(']')?;
+        host.parse::<std::net::Ipv6Addr>().ok()?;
+        let port = match rest {
+            "" => None,
+            _ => Some(rest.strip_prefix(':')?),
+        };
+        (format!("[{}]", host), port)
+    } else if addr.matches(':').count() > 1 {
+        // bare IPv6 without port
+        let host = addr.parse::<std::net::Ipv6Addr>().ok()?;
+        (format!("[{}]", host), None)
+    } else {
+        let (host, port) = match addr.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (addr, None),
+        };
+        let valid_host = !host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+        if !valid_host {
+            return None;
+        }
+        (host.to_string(), port)
+    };
+    let port = match port {
+        Some(port) => port.parse::<u16>().ok().filter(|port| *port != 0)?,
+        None => DEFAULT_NAMESRV_PORT,
+    };
+    Some(format!("{}:{}", host, port))
+}
 
 /// RocketMQ name server resolver trait
 #[async_trait]
@@ -14,7 +83,7 @@ pub trait NsResolver {
 }
 
 /// Pre-defined name server resolvers
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Resolver {
     /// Environment variable resolver
     Env(EnvResolver),
@@ -24,6 +93,12 @@ pub enum Resolver {
     PassthroughHttp(PassthroughResolver<HttpResolver>),
     /// HTTP resolver
     Http(HttpResolver),
+    /// DNS SRV resolver
+    #[cfg(feature = "dns-srv")]
+    DnsSrv(DnsSrvResolver),
+    /// Kubernetes EndpointSlice resolver
+    #[cfg(feature = "kubernetes")]
+    Kubernetes(KubernetesResolver),
 }
 
 #[async_trait]
@@ -34,6 +109,10 @@ impl NsResolver for Resolver {
             Resolver::Static(inner) => inner.resolve().await?,
             Resolver::PassthroughHttp(inner) => inner.resolve().await?,
             Resolver::Http(inner) => inner.resolve().await?,
+            #[cfg(feature = "dns-srv")]
+            Resolver::DnsSrv(inner) => inner.resolve().await?,
+            #[cfg(feature = "kubernetes")]
+            Resolver::Kubernetes(inner) => inner.resolve().await?,
         })
     }
 
@@ -43,12 +122,16 @@ impl NsResolver for Resolver {
             Resolver::Static(inner) => inner.description(),
             Resolver::PassthroughHttp(inner) => inner.description(),
             Resolver::Http(inner) => inner.description(),
+            #[cfg(feature = "dns-srv")]
+            Resolver::DnsSrv(inner) => inner.description(),
+            #[cfg(feature = "kubernetes")]
+            Resolver::Kubernetes(inner) => inner.description(),
         }
     }
 }
 
 /// Environment variable resolver
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct EnvResolver;
 
 #[async_trait]
@@ -65,7 +148,7 @@ impl NsResolver for EnvResolver {
 }
 
 /// Static configuration resolver
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StaticResolver {
     addrs: Vec<String>,
 }
@@ -88,7 +171,7 @@ impl NsResolver for StaticResolver {
 }
 
 /// Passthrough HTTP resolver
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PassthroughResolver<T: NsResolver> {
     addrs: Vec<String>,
     fallback: T,
@@ -116,56 +199,567 @@ impl<T: NsResolver + Clone + Send + Sync> NsResolver for PassthroughResolver<T>
 }
 
 /// HTTP resolver
+///
+/// The last fetched addresses are saved to a snapshot file, which is used when both
+/// the HTTP endpoint and the `NAMESRV_ADDR` environment variable are unavailable.
 #[derive(Debug, Clone)]
 pub struct HttpResolver {
     domain: String,
     instance: String,
+    unit_name: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
     http: reqwest::Client,
     fallback: EnvResolver,
+    snapshot_path: PathBuf,
+}
+
+// same endpoint and request settings, the HTTP client isn't compared
+impl PartialEq for HttpResolver {
+    fn eq(&self, other: &Self) -> bool {
+        self.domain == other.domain
+            && self.instance == other.instance
+            && self.unit_name == other.unit_name
+            && self.headers == other.headers
+            && self.timeout == other.timeout
+            && self.snapshot_path == other.snapshot_path
+    }
 }
 
 impl HttpResolver {
     pub fn new(instance: String) -> Self {
-        Self {
-            domain: DEFAULT_NAMESRV_ADDR.to_string(),
-            instance,
-            http: reqwest::Client::new(),
-            fallback: EnvResolver,
-        }
+        Self::with_domain(instance, DEFAULT_NAMESRV_ADDR.to_string())
     }
 
     pub fn with_domain(instance: String, domain: String) -> Self {
+        let snapshot_path = PathBuf::from(env::var("HOME").unwrap_or_default())
+            .join(".rocketmq_client_rust")
+            .join("snapshot")
+            .join(format!("nameserver_addr-{}", instance));
         Self {
             domain,
             instance,
+            unit_name: String::new(),
+            headers: Vec::new(),
+            timeout: DEFAULT_HTTP_TIMEOUT,
             http: reqwest::Client::new(),
             fallback: EnvResolver,
+            snapshot_path,
+        }
+    }
+
+    /// Path of the name server address snapshot file,
+    /// defaults to `$HOME/.rocketmq_client_rust/snapshot/nameserver_addr-<instance>`
+    pub fn set_snapshot_path<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.snapshot_path = path.into();
+        self
+    }
+
+    /// Unit name, the address list of unit `name` is fetched from `<domain>-<name>`
+    pub fn set_unit_name(&mut self, name: &str) -> &mut Self {
+        self.unit_name = name.to_string();
+        self
+    }
+
+    /// Send an extra header, e.g. an authentication token
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Timeout of fetching addresses, defaults to 3 seconds
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn url(&self) -> Result<reqwest::Url, String> {
+        let mut url = reqwest::Url::parse(&self.domain).map_err(|err| err.to_string())?;
+        if !self.unit_name.is_empty() {
+            let path = format!("{}-{}", url.path(), self.unit_name);
+            url.set_path(&path);
+            url.query_pairs_mut().append_pair("nofix", "1");
+        }
+        if !self.instance.is_empty() {
+            url.query_pairs_mut()
+                .append_pair("instanceName", &self.instance);
         }
+        Ok(url)
     }
 
-    async fn get(&self) -> Result<Vec<String>, Error> {
-        let resp = self.http.get(&self.domain).send().await;
-        if let Ok(res) = resp {
-            if let Ok(body) = res.text().await {
-                // TODO: save snapshot to file
-                return Ok(body.split(';').map(str::to_string).collect());
+    async fn fetch(&self) -> Result<String, String> {
+        let mut req = self.http.get(self.url()?).timeout(self.timeout);
+        for (name, value) in &self.headers {
+            req = req.header(name, value);
+        }
+        let res = req
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| err.to_string())?;
+        res.text().await.map_err(|err| err.to_string())
+    }
+
+    async fn get(&self) -> Vec<String> {
+        match self.fetch().await {
+            Ok(body) => {
+                let addrs = parse_addrs(&body);
+                if addrs.is_empty() {
+                    warn!(domain = %self.domain, "fetched empty name server addresses");
+                }
+                addrs
+            }
+            Err(err) => {
+                warn!(domain = %self.domain, error = %err, "fetch name server addresses failed");
+                Vec::new()
+            }
+        }
+    }
+
+    async fn save_snapshot(&self, addrs: &[String]) {
+        if let Some(dir) = self.snapshot_path.parent() {
+            if let Err(err) = tokio::fs::create_dir_all(dir).await {
+                warn!(
+                    "create name server snapshot dir {} failed: {:?}",
+                    dir.display(),
+                    err
+                );
+                return;
+            }
+        }
+        if let Err(err) = tokio::fs::write(&self.snapshot_path, addrs.join(";")).await {
+            warn!(
+                "save name server snapshot to {} failed: {:?}",
+                self.snapshot_path.display(),
+                err
+            );
+        }
+    }
+
+    async fn load_snapshot(&self) -> Vec<String> {
+        match tokio::fs::read_to_string(&self.snapshot_path).await {
+            Ok(data) => {
+                let addrs = parse_addrs(&data);
+                info!(addrs = ?addrs, "load name server addresses from snapshot");
+                addrs
+            }
+            Err(err) => {
+                warn!(
+                    "read name server snapshot {} failed: {:?}",
+                    self.snapshot_path.display(),
+                    err
+                );
+                Vec::new()
             }
         }
-        Ok(Vec::new())
     }
 }
 
+fn parse_addrs(s: &str) -> Vec<String> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 #[async_trait]
 impl NsResolver for HttpResolver {
     async fn resolve(&self) -> Result<Vec<String>, Error> {
-        if let Ok(addrs) = self.get().await {
-            Ok(addrs)
-        } else {
-            Ok(self.fallback.resolve().await?)
+        let addrs = self.get().await;
+        if !addrs.is_empty() {
+            self.save_snapshot(&addrs).await;
+            return Ok(addrs);
         }
+        let addrs = self.fallback.resolve().await?;
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+        Ok(self.load_snapshot().await)
     }
 
     fn description(&self) -> &'static str {
         "http resolver"
     }
 }
+
+/// DNS SRV resolver
+///
+/// Name servers are looked up from the SRV records of `<service>.<domain>`, ordered by
+/// priority and weight. Records are re-resolved whenever the client refreshes name
+/// server addresses, cached no longer than their TTL.
+#[cfg(feature = "dns-srv")]
+#[derive(Debug, Clone)]
+pub struct DnsSrvResolver {
+    name: String,
+    resolver: std::sync::Arc<hickory_resolver::TokioAsyncResolver>,
+}
+
+#[cfg(feature = "dns-srv")]
+impl PartialEq for DnsSrvResolver {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+#[cfg(feature = "dns-srv")]
+impl DnsSrvResolver {
+    /// Create a resolver using the system DNS configuration,
+    /// `service` is like `_rocketmq._tcp`
+    pub fn new(service: &str, domain: &str) -> Self {
+        use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+        use hickory_resolver::TokioAsyncResolver;
+
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|err| {
+            warn!(error = ?err, "read system DNS configuration failed, use default");
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
+        Self::with_resolver(service, domain, resolver)
+    }
+
+    pub fn with_resolver(
+        service: &str,
+        domain: &str,
+        resolver: hickory_resolver::TokioAsyncResolver,
+    ) -> Self {
+        Self {
+            name: format!(
+                "{}.{}",
+                service.trim_end_matches('.'),
+                domain.trim_end_matches('.')
+            ),
+            resolver: std::sync::Arc::new(resolver),
+        }
+    }
+}
+
+#[cfg(feature = "dns-srv")]
+#[async_trait]
+impl NsResolver for DnsSrvResolver {
+    async fn resolve(&self) -> Result<Vec<String>, Error> {
+        let lookup = self
+            .resolver
+            .srv_lookup(self.name.as_str())
+            .await
+            .map_err(|err| {
+                Error::Resolve(format!("SRV lookup of {} failed: {}", self.name, err))
+            })?;
+        let mut records: Vec<_> = lookup.iter().collect();
+        records.sort_by_key(|srv| (srv.priority(), std::cmp::Reverse(srv.weight())));
+        Ok(records
+            .into_iter()
+            .map(|srv| {
+                let target = srv.target().to_utf8();
+                format!("{}:{}", target.trim_end_matches('.'), srv.port())
+            })
+            .collect())
+    }
+
+    fn description(&self) -> &'static str {
+        "dns srv resolver"
+    }
+}
+
+/// Kubernetes EndpointSlice resolver
+///
+/// Watches the EndpointSlices of a name server Service and yields the addresses of
+/// its ready pods. The watch is started on the first resolve and stopped when the
+/// last clone of the resolver is dropped.
+#[cfg(feature = "kubernetes")]
+#[derive(Clone)]
+pub struct KubernetesResolver {
+    namespace: Option<String>,
+    service: String,
+    port_name: Option<String>,
+    watch: std::sync::Arc<tokio::sync::OnceCell<EndpointSliceWatch>>,
+}
+
+#[cfg(feature = "kubernetes")]
+const INITIAL_LIST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[cfg(feature = "kubernetes")]
+struct EndpointSliceWatch {
+    store: kube::runtime::reflector::Store<k8s_openapi::api::discovery::v1::EndpointSlice>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(feature = "kubernetes")]
+impl Drop for EndpointSliceWatch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(feature = "kubernetes")]
+impl std::fmt::Debug for KubernetesResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KubernetesResolver")
+            .field("namespace", &self.namespace)
+            .field("service", &self.service)
+            .field("port_name", &self.port_name)
+            .field("watching", &self.watch.initialized())
+            .finish()
+    }
+}
+
+#[cfg(feature = "kubernetes")]
+impl PartialEq for KubernetesResolver {
+    fn eq(&self, other: &Self) -> bool {
+        self.namespace == other.namespace
+            && self.service == other.service
+            && self.port_name == other.port_name
+    }
+}
+
+#[cfg(feature = "kubernetes")]
+impl KubernetesResolver {
+    /// Resolve the pods of `service` in the namespace of the current kube config
+    pub fn new(service: &str) -> Self {
+        Self {
+            namespace: None,
+            service: service.to_string(),
+            port_name: None,
+            watch: Default::default(),
+        }
+    }
+
+    /// Namespace of the Service, defaults to the namespace of the current kube config
+    pub fn set_namespace(&mut self, namespace: &str) -> &mut Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// Name of the Service port of the name server, defaults to the first port
+    pub fn set_port_name(&mut self, port_name: &str) -> &mut Self {
+        self.port_name = Some(port_name.to_string());
+        self
+    }
+
+    async fn start_watch(&self) -> Result<EndpointSliceWatch, Error> {
+        use futures::StreamExt;
+        use k8s_openapi::api::discovery::v1::EndpointSlice;
+        use kube::runtime::{reflector, watcher, WatchStreamExt};
+        use kube::Api;
+
+        let client = kube::Client::try_default()
+            .await
+            .map_err(|err| Error::Resolve(format!("create Kubernetes client failed: {}", err)))?;
+        let api: Api<EndpointSlice> = match &self.namespace {
+            Some(namespace) => Api::namespaced(client, namespace),
+            None => Api::default_namespaced(client),
+        };
+        let config = watcher::Config::default()
+            .labels(&format!("kubernetes.io/service-name={}", self.service));
+        let (store, writer) = reflector::store();
+        let stream = reflector(writer, watcher(api, config).default_backoff()).touched_objects();
+        let service = self.service.clone();
+        let task = tokio::spawn(async move {
+            stream
+                .for_each(|res| async {
+                    if let Err(err) = res {
+                        warn!(service = %service, error = ?err, "watch EndpointSlices failed");
+                    }
+                })
+                .await;
+        });
+        let watch = EndpointSliceWatch { store, task };
+        match tokio::time::timeout(INITIAL_LIST_TIMEOUT, watch.store.wait_until_ready()).await {
+            Ok(Ok(())) => Ok(watch),
+            Ok(Err(err)) => Err(Error::Resolve(format!(
+                "watch EndpointSlices failed: {}",
+                err
+            ))),
+            Err(_) => Err(Error::Resolve(format!(
+                "list EndpointSlices of service {} timed out",
+                self.service
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "kubernetes")]
+fn endpoint_slice_addrs(
+    slice: &k8s_openapi::api::discovery::v1::EndpointSlice,
+    port_name: Option<&str>,
+) -> Vec<String> {
+    let port = slice
+        .ports
+        .iter()
+        .flatten()
+        .find(|port| port_name.is_none() || port.name.as_deref() == port_name)
+        .and_then(|port| port.port);
+    let port = match port {
+        Some(port) => port,
+        None => return Vec::new(),
+    };
+    slice
+        .endpoints
+        .iter()
+        .filter(|endpoint| {
+            endpoint
+                .conditions
+                .as_ref()
+                .and_then(|conditions| conditions.ready)
+                .unwrap_or(true)
+        })
+        .flat_map(|endpoint| endpoint.addresses.iter())
+        .map(|addr| {
+            if slice.address_type == "IPv6" {
+                format!("[{}]:{}", addr, port)
+            } else {
+                format!("{}:{}", addr, port)
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "kubernetes")]
+#[async_trait]
+impl NsResolver for KubernetesResolver {
+    async fn resolve(&self) -> Result<Vec<String>, Error> {
+        let watch = self.watch.get_or_try_init(|| self.start_watch()).await?;
+        let mut addrs: Vec<String> = watch
+            .store
+            .state()
+            .iter()
+            .flat_map(|slice| endpoint_slice_addrs(slice, self.port_name.as_deref()))
+            .collect();
+        addrs.sort();
+        addrs.dedup();
+        Ok(addrs)
+    }
+
+    fn description(&self) -> &'static str {
+        "kubernetes resolver"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::{normalize_addrs, HttpResolver, NsResolver};
+    use crate::Error;
+
+    #[test]
+    fn test_normalize_addrs() {
+        let addrs: Vec<String> = [
+            "10.0.0.1:9876",
+            " http://10.0.0.2:9877/ ",
+            "HTTPS://namesrv.example.com",
+            "10.0.0.1",
+            "",
+            "[::1]:9876",
+            "::1",
+        ]
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect();
+        assert_eq!(
+            vec![
+                "10.0.0.1:9876",
+                "10.0.0.2:9877",
+                "namesrv.example.com:9876",
+                "[::1]:9876"
+            ],
+            normalize_addrs(&addrs).unwrap()
+        );
+
+        for invalid in [
+            "10.0.0.1:abc",
+            "10.0.0.1:0",
+            "http://",
+            "name server:9876",
+            "[::1",
+        ] {
+            match normalize_addrs(&[invalid.to_string()]) {
+                Err(Error::InvalidNameServerAddress(addr)) => assert_eq!(invalid, addr),
+                res => panic!("{} should be invalid, got {:?}", invalid, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_http_resolver_url() {
+        let mut resolver = HttpResolver::with_domain(
+            "DEFAULT".to_string(),
+            "http://jmenv.tbsite.net:8080/rocketmq/nsaddr".to_string(),
+        );
+        assert_eq!(
+            "http://jmenv.tbsite.net:8080/rocketmq/nsaddr?instanceName=DEFAULT",
+            resolver.url().unwrap().as_str()
+        );
+        resolver.set_unit_name("unit");
+        assert_eq!(
+            "http://jmenv.tbsite.net:8080/rocketmq/nsaddr-unit?nofix=1&instanceName=DEFAULT",
+            resolver.url().unwrap().as_str()
+        );
+        let resolver = HttpResolver::with_domain(String::new(), "not a url".to_string());
+        assert!(resolver.url().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_resolver_snapshot() {
+        let path = env::temp_dir().join(format!("rocketmq_nsaddr_test_{}", std::process::id()));
+        let mut resolver =
+            HttpResolver::with_domain("test".to_string(), "http://127.0.0.1:1/nsaddr".to_string());
+        resolver.set_snapshot_path(&path);
+        let addrs = vec!["10.0.0.1:9876".to_string(), "10.0.0.2:9876".to_string()];
+        resolver.save_snapshot(&addrs).await;
+        if env::var("NAMESRV_ADDR").is_err() {
+            assert_eq!(addrs, resolver.resolve().await.unwrap());
+        }
+        assert_eq!(addrs, resolver.load_snapshot().await);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(feature = "dns-srv")]
+    #[tokio::test]
+    async fn test_dns_srv_resolver_name() {
+        let resolver = super::DnsSrvResolver::new("_rocketmq._tcp", "example.com.");
+        assert_eq!("_rocketmq._tcp.example.com", resolver.name);
+    }
+
+    #[cfg(feature = "kubernetes")]
+    #[test]
+    fn test_endpoint_slice_addrs() {
+        use k8s_openapi::api::discovery::v1::{
+            Endpoint, EndpointConditions, EndpointPort, EndpointSlice,
+        };
+
+        let endpoint = |addr: &str, ready: bool| Endpoint {
+            addresses: vec![addr.to_string()],
+            conditions: Some(EndpointConditions {
+                ready: Some(ready),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let slice = EndpointSlice {
+            address_type: "IPv4".to_string(),
+            endpoints: vec![endpoint("10.0.0.1", true), endpoint("10.0.0.2", false)],
+            ports: Some(vec![
+                EndpointPort {
+                    name: Some("metrics".to_string()),
+                    port: Some(9100),
+                    ..Default::default()
+                },
+                EndpointPort {
+                    name: Some("namesrv".to_string()),
+                    port: Some(9876),
+                    ..Default::default()
+                },
+            ]),
+            metadata: Default::default(),
+        };
+        assert_eq!(
+            vec!["10.0.0.1:9876"],
+            super::endpoint_slice_addrs(&slice, Some("namesrv"))
+        );
+        assert_eq!(
+            vec!["10.0.0.1:9100"],
+            super::endpoint_slice_addrs(&slice, None)
+        );
+        assert!(super::endpoint_slice_addrs(&slice, Some("other")).is_empty());
+    }
+}
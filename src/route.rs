@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use serde::Deserialize;
 
+use crate::fastjson;
 use crate::message::MessageQueue;
 use crate::permission::Permission;
 use crate::Error;
@@ -44,11 +45,22 @@ pub struct TopicRouteData {
 
 impl TopicRouteData {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
-        let s = std::str::from_utf8(bytes).unwrap();
-        // fixup fastjson mess
-        let json = dirty_json::fix(s);
-        let data: TopicRouteData = serde_json::from_str(&json)?;
-        Ok(data)
+        fastjson::from_slice(bytes)
+    }
+
+    /// Readable message queues of `topic`
+    pub fn to_subscribe_message_queues(&self, topic: &str) -> Vec<MessageQueue> {
+        self.queue_datas
+            .iter()
+            .filter(|queue_data| queue_data.perm.is_readable())
+            .flat_map(|queue_data| {
+                (0..queue_data.read_queue_nums).map(move |i| MessageQueue {
+                    topic: topic.to_string(),
+                    broker_name: queue_data.broker_name.clone(),
+                    queue_id: i as u32,
+                })
+            })
+            .collect()
     }
 
     pub fn to_publish_info(&self, topic: &str) -> TopicPublishInfo {
@@ -111,6 +123,48 @@ impl TopicRouteData {
     }
 }
 
+/// Brokers and clusters registered in the name server
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct ClusterInfo {
+    /// broker name -> BrokerData
+    #[serde(default, rename = "brokerAddrTable")]
+    pub broker_addr_table: HashMap<String, BrokerData>,
+    /// cluster name -> broker names
+    #[serde(default, rename = "clusterAddrTable")]
+    pub cluster_addr_table: HashMap<String, Vec<String>>,
+}
+
+impl ClusterInfo {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fastjson::from_slice(bytes)
+    }
+
+    /// Broker addresses of `cluster`, by broker name
+    pub fn cluster_brokers(&self, cluster: &str) -> Vec<&BrokerData> {
+        self.cluster_addr_table
+            .get(cluster)
+            .into_iter()
+            .flatten()
+            .filter_map(|broker_name| self.broker_addr_table.get(broker_name))
+            .collect()
+    }
+}
+
+/// Topics registered in the name server
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct TopicList {
+    #[serde(default, rename = "topicList")]
+    pub topic_list: Vec<String>,
+    #[serde(default, rename = "brokerAddr")]
+    pub broker_addr: Option<String>,
+}
+
+impl TopicList {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fastjson::from_slice(bytes)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TopicPublishInfo {
     pub order_topic: bool,
@@ -119,3 +173,29 @@ pub struct TopicPublishInfo {
     pub route_data: TopicRouteData,
     pub queue_index: usize,
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ClusterInfo, TopicList};
+
+    #[test]
+    fn test_cluster_info_from_bytes() {
+        let body = br#"{"brokerAddrTable":{"broker-a":{"brokerAddrs":{0:"10.0.0.1:10911",1:"10.0.0.2:10911"},"brokerName":"broker-a","cluster":"DefaultCluster"}},"clusterAddrTable":{"DefaultCluster":["broker-a"]}}"#;
+        let info = ClusterInfo::from_bytes(body).unwrap();
+        assert_eq!(
+            "10.0.0.2:10911",
+            info.broker_addr_table["broker-a"].broker_addrs[&1]
+        );
+        let brokers = info.cluster_brokers("DefaultCluster");
+        assert_eq!(1, brokers.len());
+        assert_eq!("broker-a", brokers[0].broker_name);
+        assert!(info.cluster_brokers("OtherCluster").is_empty());
+    }
+
+    #[test]
+    fn test_topic_list_from_bytes() {
+        let list = TopicList::from_bytes(br#"{"topicList":["TBW102","test"]}"#).unwrap();
+        assert_eq!(vec!["TBW102", "test"], list.topic_list);
+        assert_eq!(None, list.broker_addr);
+    }
+}
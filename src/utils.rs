@@ -1,7 +1,26 @@
+use std::future::Future;
 use std::net::IpAddr;
 
 use if_addrs::get_if_addrs;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
 
+/// Spawn `future` on `runtime`, or on the current runtime if none
+pub fn spawn<F>(runtime: Option<&Handle>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match runtime {
+        Some(runtime) => runtime.spawn(future),
+        None => tokio::spawn(future),
+    }
+}
+
+/// Detect the ip address reported to brokers
+///
+/// Public IPv4 addresses are preferred, then private IPv4 ones. IPv6-only hosts
+/// fall back to a non link-local IPv6 address.
 pub fn client_ip_addr() -> Option<IpAddr> {
     let mut ipv4_addrs = Vec::new();
     let mut ipv6_addrs = Vec::new();
@@ -10,24 +29,24 @@ pub fn client_ip_addr() -> Option<IpAddr> {
             if addr.is_loopback() {
                 continue;
             }
-            let ip = addr.ip();
-            if ip.is_ipv4() {
-                let ip_str = ip.to_string();
-                if ip_str.starts_with("127.0") || ip_str.starts_with("192.") {
-                    ipv4_addrs.push(ip);
-                } else {
-                    return Some(ip);
+            match addr.ip() {
+                IpAddr::V4(v4) => {
+                    if v4.is_private() || v4.is_link_local() {
+                        ipv4_addrs.push(IpAddr::V4(v4));
+                    } else {
+                        return Some(IpAddr::V4(v4));
+                    }
+                }
+                IpAddr::V6(v6) => {
+                    // link-local fe80::/10 addresses are not reachable without a scope id
+                    if (v6.segments()[0] & 0xffc0) != 0xfe80 {
+                        ipv6_addrs.push(IpAddr::V6(v6));
+                    }
                 }
-            } else {
-                ipv6_addrs.push(ip);
             }
         }
     }
-    // did not find ipv4 address, try ipv6
-    if let Some(addr) = ipv6_addrs.first() {
-        return Some(addr.clone());
-    }
-    ipv4_addrs.first().cloned()
+    ipv4_addrs.into_iter().chain(ipv6_addrs).next()
 }
 
 #[cfg(test)]